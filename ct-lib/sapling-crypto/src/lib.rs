@@ -6,6 +6,7 @@ extern crate ff;
 extern crate rand;
 extern crate byteorder;
 extern crate rlp;
+extern crate bech32;
 
 #[cfg(test)]
 #[macro_use]
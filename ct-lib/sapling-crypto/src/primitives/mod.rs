@@ -1,3 +1,4 @@
+use std::fmt;
 use std::hash::{Hash, Hasher};
 use ff::{Field, PrimeField, PrimeFieldRepr};
 
@@ -20,9 +21,12 @@ use jubjub::{
     JubjubParams,
     edwards,
     PrimeOrder,
+    Unknown,
     FixedGenerators
 };
 
+use bech32::{self, u5, Bech32};
+
 use blake2_rfc::blake2s::Blake2s;
 
 #[derive(Clone)]
@@ -205,6 +209,107 @@ impl<E: JubjubEngine> PaymentAddress<E> {
     }
 }
 
+/// Length of the `(diversifier, pk_d)` payload of an encoded payment address.
+const PAYMENT_ADDRESS_PAYLOAD_LENGTH: usize = 11 + 32;
+
+/// Errors when parsing a Bech32 payment address.
+#[derive(Debug, PartialEq)]
+pub enum AddrError {
+    /// The string is not valid Bech32, e.g. its checksum does not match.
+    Bech32(bech32::Error),
+    /// The payload does not have the length of a payment address.
+    InvalidLength(usize),
+    /// The payload is not a valid diversified transmission key.
+    InvalidPkD,
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddrError::Bech32(ref e) => write!(f, "Invalid Bech32 address: {}", e),
+            AddrError::InvalidLength(len) => write!(f, "Invalid payment address payload length {}", len),
+            AddrError::InvalidPkD => write!(f, "Invalid payment address transmission key"),
+        }
+    }
+}
+
+impl<E: JubjubEngine> PaymentAddress<E> {
+    /// Encodes the 43-byte `(diversifier, pk_d)` payload in Bech32 with
+    /// the given human-readable part.
+    ///
+    /// Panics if `hrp` is not a valid Bech32 human-readable part.
+    pub fn to_bech32(&self, hrp: &str) -> String {
+        let mut payload = Vec::with_capacity(PAYMENT_ADDRESS_PAYLOAD_LENGTH);
+        payload.extend_from_slice(&self.diversifier.0);
+        self.pk_d.write(&mut payload).unwrap();
+
+        Bech32::new_check_data(hrp.into(), bytes_to_u5(&payload))
+            .expect("hrp must be a valid Bech32 human-readable part")
+            .to_string()
+    }
+
+    /// Decodes a Bech32 payment address, returning its human-readable part
+    /// along with the address.
+    pub fn from_bech32(
+        s: &str,
+        params: &E::Params
+    ) -> Result<(String, Self), AddrError>
+    {
+        let parsed = s.parse::<Bech32>().map_err(AddrError::Bech32)?;
+        if parsed.data().len() != (PAYMENT_ADDRESS_PAYLOAD_LENGTH * 8 + 4) / 5 {
+            return Err(AddrError::InvalidLength(parsed.data().len() * 5 / 8));
+        }
+        let payload = u5_to_bytes(parsed.data());
+
+        let mut diversifier = Diversifier([0u8; 11]);
+        diversifier.0.copy_from_slice(&payload[0..11]);
+        let pk_d = edwards::Point::<E, Unknown>::read(&payload[11..], params)
+            .map_err(|_| AddrError::InvalidPkD)?
+            .as_prime_order(params)
+            .ok_or(AddrError::InvalidPkD)?;
+
+        Ok((parsed.hrp().to_string(), PaymentAddress {
+            pk_d: pk_d,
+            diversifier: diversifier
+        }))
+    }
+}
+
+/// Regroups bytes into 5-bit values, zero-padding the last one.
+fn bytes_to_u5(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for b in bytes {
+        acc = (acc << 8) | (*b as u32);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(((acc >> bits) & 0x1f) as u8);
+        }
+    }
+    if bits > 0 {
+        out.push(((acc << (5 - bits)) & 0x1f) as u8);
+    }
+    out
+}
+
+/// Regroups 5-bit values into bytes, dropping the padding bits.
+fn u5_to_bytes(data: &[u5]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for d in data {
+        acc = (acc << 5) | (d.to_u8() as u32);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push(((acc >> bits) & 0xff) as u8);
+        }
+    }
+    out
+}
+
 /// A note represents that a value v is spendable by the recipient who holds
 /// the spending key corresponding to a given shielded payment address.
 #[derive(Clone, Debug)]
@@ -307,4 +412,58 @@ impl<E: JubjubEngine> Note<E> {
         // commitment to the x-coordinate is an injective encoding.
         self.cm_full_point(params).into_xy().0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use pairing::bls12_381::Bls12;
+    use jubjub::JubjubBls12;
+    use bech32::{self, Bech32};
+    use super::{AddrError, PaymentAddress};
+
+    const ADDRESS: &'static str =
+        "ogo14j53eenhdjp85dlfctsttmtgav8sqkkttsl6qxvpmn74jk7edsyzp08r550dzu96hu9gwtzk9w2";
+
+    #[test]
+    fn bech32_round_trip_with_hrp() {
+        let params = JubjubBls12::new();
+        let (hrp, address) = PaymentAddress::<Bls12>::from_bech32(ADDRESS, &params).unwrap();
+        assert_eq!(hrp, "ogo");
+        assert_eq!(address.to_bech32("ogo"), ADDRESS);
+
+        let encoded = address.to_bech32("ogotest");
+        assert!(encoded.starts_with("ogotest1"));
+        assert_eq!(
+            PaymentAddress::<Bls12>::from_bech32(&encoded, &params).unwrap(),
+            ("ogotest".to_string(), address)
+        );
+    }
+
+    #[test]
+    fn bech32_rejects_bad_checksum() {
+        let params = JubjubBls12::new();
+        let mut address = ADDRESS.to_string();
+        address.pop();
+        address.push('3');
+        assert_eq!(
+            PaymentAddress::<Bls12>::from_bech32(&address, &params),
+            Err(AddrError::Bech32(bech32::Error::InvalidChecksum))
+        );
+    }
+
+    #[test]
+    fn bech32_rejects_wrong_payload_length() {
+        let params = JubjubBls12::new();
+        let short = Bech32::new_check_data("ogo".into(), vec![0u8; 10]).unwrap().to_string();
+        assert_eq!(
+            PaymentAddress::<Bls12>::from_bech32(&short, &params),
+            Err(AddrError::InvalidLength(6))
+        );
+
+        let long = Bech32::new_check_data("ogo".into(), vec![0u8; 70]).unwrap().to_string();
+        assert_eq!(
+            PaymentAddress::<Bls12>::from_bech32(&long, &params),
+            Err(AddrError::InvalidLength(43))
+        );
+    }
+}
@@ -126,6 +126,18 @@ impl<E: JubjubEngine> ExpandedSpendingKey<E> {
         Ok(())
     }
 
+    /// Parses an `ExpandedSpendingKey` from its 96-byte encoding, rejecting input of
+    /// any other length.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != 96 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expanded spending key must be 96 bytes",
+            ));
+        }
+        Self::read(bytes)
+    }
+
     pub fn to_bytes(&self) -> [u8; 96] {
         let mut result = [0u8; 96];
         self.write(&mut result[..])
@@ -209,3 +221,50 @@ impl<E: JubjubEngine> FullViewingKey<E> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pairing::bls12_381::Bls12;
+
+    use super::ExpandedSpendingKey;
+
+    #[test]
+    fn expanded_spending_key_round_trip() {
+        let expsk = ExpandedSpendingKey::<Bls12>::from_spending_key(&[7u8; 32]);
+        let encoded = expsk.to_bytes();
+
+        let mut written = vec![];
+        expsk.write(&mut written).unwrap();
+        assert_eq!(&written[..], &encoded[..]);
+
+        let decoded = ExpandedSpendingKey::<Bls12>::from_bytes(&encoded[..]).unwrap();
+        assert_eq!(&decoded.to_bytes()[..], &encoded[..]);
+        assert!(decoded.ask == expsk.ask);
+        assert!(decoded.nsk == expsk.nsk);
+        assert_eq!(decoded.ovk, expsk.ovk);
+
+        let read = ExpandedSpendingKey::<Bls12>::read(&encoded[..]).unwrap();
+        assert_eq!(&read.to_bytes()[..], &encoded[..]);
+    }
+
+    #[test]
+    fn expanded_spending_key_rejects_wrong_length() {
+        let encoded = ExpandedSpendingKey::<Bls12>::from_spending_key(&[7u8; 32]).to_bytes();
+
+        assert!(ExpandedSpendingKey::<Bls12>::from_bytes(&encoded[..95]).is_err());
+        assert!(ExpandedSpendingKey::<Bls12>::read(&encoded[..95]).is_err());
+
+        let mut long = encoded.to_vec();
+        long.push(0);
+        assert!(ExpandedSpendingKey::<Bls12>::from_bytes(&long).is_err());
+    }
+
+    #[test]
+    fn expanded_spending_key_rejects_non_canonical_scalar() {
+        let mut encoded = ExpandedSpendingKey::<Bls12>::from_spending_key(&[7u8; 32]).to_bytes();
+        for b in encoded[..32].iter_mut() {
+            *b = 0xff;
+        }
+        assert!(ExpandedSpendingKey::<Bls12>::from_bytes(&encoded[..]).is_err());
+    }
+}
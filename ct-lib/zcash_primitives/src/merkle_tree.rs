@@ -1,13 +1,18 @@
 //! Implementation of a Merkle tree of commitments used to prove the existence of notes.
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use heapsize::HeapSizeOf;
 use std::collections::VecDeque;
+use std::fmt;
 use std::io::{self, Read, Write};
 use std::iter;
 
 use serialize::{Optional, Vector};
 use sapling::SAPLING_COMMITMENT_TREE_DEPTH;
 
+/// Version of the serialization written by `IncrementalWitness::write_versioned`.
+pub const WITNESS_SERIALIZATION_VERSION: u8 = 1;
+
 /// A hashable node within a Merkle tree.
 pub trait Hashable: Clone + Copy {
     /// Parses a node from the given byte source.
@@ -44,6 +49,12 @@ impl<Node: Hashable> PathFiller<Node> {
     }
 }
 
+/// A Merkle tree generic over its node type.
+///
+/// The append, root and witness logic only relies on [`Hashable`], so any node type
+/// can reuse it; the Sapling commitment tree is `MerkleTree<sapling::Node>`.
+pub type MerkleTree<Node> = CommitmentTree<Node>;
+
 /// A Merkle tree of note commitments.
 ///
 /// The depth of the Merkle tree is fixed at 32, equal to the depth of the Sapling
@@ -53,6 +64,23 @@ pub struct CommitmentTree<Node: Hashable> {
     left: Option<Node>,
     right: Option<Node>,
     parents: Vec<Option<Node>>,
+    cached_root: Option<Node>,
+}
+
+impl<Node: Hashable + PartialEq> PartialEq for CommitmentTree<Node> {
+    fn eq(&self, other: &Self) -> bool {
+        // The cached root is derived from the frontier, so it is not compared.
+        self.left == other.left && self.right == other.right && self.parents == other.parents
+    }
+}
+
+impl<Node: Hashable + HeapSizeOf> HeapSizeOf for CommitmentTree<Node> {
+    fn heap_size_of_children(&self) -> usize {
+        self.left.heap_size_of_children()
+            + self.right.heap_size_of_children()
+            + self.parents.heap_size_of_children()
+            + self.cached_root.heap_size_of_children()
+    }
 }
 
 impl<Node: Hashable> CommitmentTree<Node> {
@@ -62,6 +90,7 @@ impl<Node: Hashable> CommitmentTree<Node> {
             left: None,
             right: None,
             parents: vec![],
+            cached_root: None,
         }
     }
 
@@ -75,6 +104,7 @@ impl<Node: Hashable> CommitmentTree<Node> {
             left,
             right,
             parents,
+            cached_root: None,
         })
     }
 
@@ -89,6 +119,14 @@ impl<Node: Hashable> CommitmentTree<Node> {
 
     /// Returns the number of notes in the tree.
     pub fn size(&self) -> usize {
+        self.leaf_count() as usize
+    }
+
+    /// Returns the number of notes in the tree.
+    ///
+    /// A full Sapling tree holds `2^32` notes, which does not fit in a 32-bit `usize`,
+    /// so positions are derived from this count rather than from `size`.
+    fn leaf_count(&self) -> u64 {
         self.parents.iter().enumerate().fold(
             match (self.left.is_some(), self.right.is_some()) {
                 (false, false) => 0,
@@ -98,12 +136,13 @@ impl<Node: Hashable> CommitmentTree<Node> {
             |acc, (i, p)| {
                 // Treat occupation of parents array as a binary number
                 // (right-shifted by 1)
-                acc + if p.is_some() { 1 << (i + 1) } else { 0 }
+                acc + if p.is_some() { 1u64 << (i + 1) } else { 0 }
             },
         )
     }
 
     fn is_complete(&self, depth: usize) -> bool {
+        assert!(depth > 0);
         self.left.is_some()
             && self.right.is_some()
             && self.parents.len() == depth - 1
@@ -123,6 +162,8 @@ impl<Node: Hashable> CommitmentTree<Node> {
             return Err(());
         }
 
+        self.cached_root = None;
+
         match (self.left, self.right) {
             (None, _) => self.left = Some(node),
             (_, None) => self.right = Some(node),
@@ -156,6 +197,33 @@ impl<Node: Hashable> CommitmentTree<Node> {
         self.root_inner(SAPLING_COMMITMENT_TREE_DEPTH, PathFiller::empty())
     }
 
+    /// Returns the root the tree would have after appending `nodes`, leaving the tree
+    /// unchanged.
+    ///
+    /// Only the frontier is copied, so this is cheap regardless of the tree size.
+    /// Returns an error if the nodes do not fit in the tree.
+    pub fn root_after(&self, nodes: &[Node]) -> Result<Node, ()> {
+        let mut tree = self.clone();
+        for node in nodes {
+            tree.append(*node)?;
+        }
+        Ok(tree.root())
+    }
+
+    /// Returns the current root of the tree, computing it only once between appends.
+    ///
+    /// This is meant for trees which are queried far more often than they are
+    /// modified, e.g. when checking the anchor of every transaction of a block.
+    pub fn root_cached(&mut self) -> Node {
+        if let Some(root) = self.cached_root {
+            return root;
+        }
+
+        let root = self.root();
+        self.cached_root = Some(root);
+        root
+    }
+
     fn root_inner(&self, depth: usize, mut filler: PathFiller<Node>) -> Node {
         assert!(depth > 0);
 
@@ -188,6 +256,315 @@ impl<Node: Hashable> CommitmentTree<Node> {
         ((self.parents.len() + 1)..depth)
             .fold(mid_root, |root, d| Node::combine(d, &root, &filler.next(d)))
     }
+
+    /// Exports this tree as a [`MerkleMountainRange`] over its notes.
+    pub fn to_mmr(&self) -> MerkleMountainRange<Node> {
+        // the pieces of the frontier, leftmost first, with their heights. The two leaves
+        // and the lowest parents are only hashed together on the next append, so the
+        // pieces of equal height are merged here.
+        let bottom = match (self.left, self.right) {
+            (Some(l), Some(r)) => Some((1, Node::combine(0, &l, &r))),
+            (Some(l), None) => Some((0, l)),
+            _ => None,
+        };
+        let pieces = self
+            .parents
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, p)| p.map(|p| (i + 1, p)))
+            .chain(bottom);
+
+        let mut peaks: Vec<(usize, Node)> = Vec::with_capacity(self.parents.len() + 1);
+        for (mut height, mut node) in pieces {
+            while let Some(&(top_height, top)) = peaks.last() {
+                if top_height != height {
+                    break;
+                }
+                peaks.pop();
+                node = Node::combine(height, &top, &node);
+                height += 1;
+            }
+            peaks.push((height, node));
+        }
+
+        MerkleMountainRange {
+            size: self.leaf_count(),
+            peaks: peaks.into_iter().map(|(_, node)| node).collect(),
+        }
+    }
+
+    /// Rebuilds a tree from a [`MerkleMountainRange`] over its notes, with the same size
+    /// and root.
+    ///
+    /// The range does not keep the nodes below its lowest peak, which the frontier of a
+    /// tree with an even number of notes holds. So the rebuilt tree has the same root after
+    /// any append, but no witness can be created for its last note. Returns `None` if the
+    /// range holds as many notes as a full tree.
+    pub fn from_mmr(mmr: &MerkleMountainRange<Node>) -> Option<Self> {
+        Self::from_mmr_inner(mmr, SAPLING_COMMITMENT_TREE_DEPTH)
+    }
+
+    fn from_mmr_inner(mmr: &MerkleMountainRange<Node>, depth: usize) -> Option<Self> {
+        if depth < 64 && mmr.size >> depth != 0 {
+            return None;
+        }
+
+        let mut tree = CommitmentTree::new();
+        for (height, peak) in mmr.heights().into_iter().zip(mmr.peaks.iter()) {
+            if height == 0 {
+                tree.left = Some(*peak);
+            } else {
+                if tree.parents.len() < height {
+                    tree.parents.resize(height, None);
+                }
+                tree.parents[height - 1] = Some(*peak);
+            }
+        }
+        Some(tree)
+    }
+}
+
+/// A [`CommitmentTree`] with a compressed frontier.
+///
+/// Instead of one `Option<Node>` per level of the frontier, only the non-empty parents
+/// are stored, along with a bitmap of the levels they occupy. Empty levels are filled
+/// with `empty_root` when the root is computed. This is the cheaper representation for
+/// trees kept in memory, and it exposes the same API as [`CommitmentTree`].
+#[derive(Clone, PartialEq)]
+pub struct CompactCommitmentTree<Node: Hashable> {
+    left: Option<Node>,
+    right: Option<Node>,
+    /// Bit `i` is set if the parent at level `i + 1` is present.
+    present: u64,
+    /// Number of levels of the frontier, present or not.
+    levels: usize,
+    /// The present parents, from the lowest level to the highest.
+    parents: Vec<Node>,
+}
+
+impl<Node: Hashable> CompactCommitmentTree<Node> {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        CompactCommitmentTree {
+            left: None,
+            right: None,
+            present: 0,
+            levels: 0,
+            parents: vec![],
+        }
+    }
+
+    /// Reads a tree from the serialized form of a [`CommitmentTree`].
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let tree = CommitmentTree::read(reader)?;
+        if tree.parents.len() > 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "commitment tree is too deep",
+            ));
+        }
+        Ok(CompactCommitmentTree::from(&tree))
+    }
+
+    /// Serializes this tree in the same form as a [`CommitmentTree`].
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.to_tree().write(writer)
+    }
+
+    /// Expands this tree into a [`CommitmentTree`].
+    pub fn to_tree(&self) -> CommitmentTree<Node> {
+        let mut parents = self.parents.iter();
+        CommitmentTree {
+            left: self.left,
+            right: self.right,
+            parents: (0..self.levels)
+                .map(|i| {
+                    if self.is_present(i) {
+                        parents.next().cloned()
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            cached_root: None,
+        }
+    }
+
+    fn is_present(&self, level: usize) -> bool {
+        self.present & (1 << level) != 0
+    }
+
+    /// Returns the number of notes in the tree.
+    pub fn size(&self) -> usize {
+        let leaves = match (self.left.is_some(), self.right.is_some()) {
+            (false, false) => 0,
+            (true, false) | (false, true) => 1,
+            (true, true) => 2,
+        };
+        // The bitmap is the occupation of the parents array as a binary number
+        // (right-shifted by 1).
+        leaves + ((self.present as usize) << 1)
+    }
+
+    fn is_complete(&self, depth: usize) -> bool {
+        self.left.is_some()
+            && self.right.is_some()
+            && self.levels == depth - 1
+            && self.parents.len() == self.levels
+    }
+
+    /// Adds a note to the tree.
+    ///
+    /// Returns an error if the tree is full.
+    pub fn append(&mut self, node: Node) -> Result<(), ()> {
+        self.append_inner(node, SAPLING_COMMITMENT_TREE_DEPTH)
+    }
+
+    fn append_inner(&mut self, node: Node, depth: usize) -> Result<(), ()> {
+        assert!(depth <= 64, "the bitmap holds at most 64 levels");
+        if self.is_complete(depth) {
+            // Tree is full
+            return Err(());
+        }
+
+        match (self.left, self.right) {
+            (None, _) => self.left = Some(node),
+            (_, None) => self.right = Some(node),
+            (Some(l), Some(r)) => {
+                let mut combined = Node::combine(0, &l, &r);
+                self.left = Some(node);
+                self.right = None;
+
+                // The present levels below the first empty one are carried into it;
+                // they are the lowest, so they sit at the front of `parents`.
+                let mut carried = 0;
+                while carried < self.levels && self.is_present(carried) {
+                    combined = Node::combine(carried + 1, &self.parents[carried], &combined);
+                    carried += 1;
+                }
+                self.parents.drain(..carried);
+                self.parents.insert(0, combined);
+                self.present = (self.present >> carried << carried) | (1 << carried);
+                if carried == self.levels {
+                    self.levels += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current root of the tree.
+    pub fn root(&self) -> Node {
+        self.root_inner(SAPLING_COMMITMENT_TREE_DEPTH)
+    }
+
+    fn root_inner(&self, depth: usize) -> Node {
+        assert!(depth > 0);
+
+        let leaf_root = Node::combine(
+            0,
+            &self.left.unwrap_or_else(|| Node::empty_root(0)),
+            &self.right.unwrap_or_else(|| Node::empty_root(0)),
+        );
+
+        let mut parents = self.parents.iter();
+        let mid_root = (0..self.levels).fold(leaf_root, |root, i| {
+            if self.is_present(i) {
+                let p = parents.next().expect("one parent per present level");
+                Node::combine(i + 1, p, &root)
+            } else {
+                Node::combine(i + 1, &root, &Node::empty_root(i + 1))
+            }
+        });
+
+        ((self.levels + 1)..depth)
+            .fold(mid_root, |root, d| Node::combine(d, &root, &Node::empty_root(d)))
+    }
+}
+
+impl<'a, Node: Hashable> From<&'a CommitmentTree<Node>> for CompactCommitmentTree<Node> {
+    fn from(tree: &'a CommitmentTree<Node>) -> Self {
+        assert!(tree.parents.len() <= 64, "the bitmap holds at most 64 levels");
+        let mut present = 0u64;
+        for (i, p) in tree.parents.iter().enumerate() {
+            if p.is_some() {
+                present |= 1 << i;
+            }
+        }
+        CompactCommitmentTree {
+            left: tree.left,
+            right: tree.right,
+            present,
+            levels: tree.parents.len(),
+            parents: tree.parents.iter().filter_map(|p| *p).collect(),
+        }
+    }
+}
+
+/// A Merkle mountain range over the notes of a [`CommitmentTree`], for tooling which
+/// expects one rather than a fixed-depth frontier.
+///
+/// The notes split into perfect subtrees, one per bit set in their number, the largest
+/// one first. The range keeps the root of each of them, its peaks, from the leftmost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleMountainRange<Node: Hashable> {
+    size: u64,
+    peaks: Vec<Node>,
+}
+
+impl<Node: Hashable> MerkleMountainRange<Node> {
+    /// Creates a range of `size` notes from its peaks, leftmost first, or returns `None`
+    /// if there is not one peak per bit set in `size`.
+    pub fn from_peaks(size: u64, peaks: Vec<Node>) -> Option<Self> {
+        if peaks.len() != size.count_ones() as usize {
+            return None;
+        }
+        Some(MerkleMountainRange { size, peaks })
+    }
+
+    /// Returns the number of notes in the range.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the peaks of the range, leftmost first.
+    pub fn peaks(&self) -> &[Node] {
+        &self.peaks
+    }
+
+    /// Returns the height of each peak, leftmost first. A peak of height `h` is the root
+    /// of `2^h` notes, which follow the notes of the peaks on its left.
+    pub fn heights(&self) -> Vec<usize> {
+        (0..64)
+            .rev()
+            .filter(|&h| (self.size >> h) & 1 == 1)
+            .collect()
+    }
+}
+
+/// Reason why `IncrementalWitness::from_path` cannot rebuild a witness.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WitnessError {
+    /// The path has a missing node, a position bit which disagrees with its position, or
+    /// does not have one node per level of the tree, or its root is not the root of the
+    /// tree at any point of the replay of the subsequent commitments.
+    MalformedPath,
+    /// The position does not fit in the tree.
+    PositionOutOfRange,
+    /// The tree fills up before every subsequent commitment is appended.
+    TreeFull,
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WitnessError::MalformedPath => write!(f, "malformed authentication path"),
+            WitnessError::PositionOutOfRange => write!(f, "position out of the tree"),
+            WitnessError::TreeFull => write!(f, "tree full before the last commitment"),
+        }
+    }
 }
 
 /// An updatable witness to a path from a position in a particular [`CommitmentTree`].
@@ -231,6 +608,14 @@ pub struct IncrementalWitness<Node: Hashable> {
     cursor: Option<CommitmentTree<Node>>,
 }
 
+impl<Node: Hashable + HeapSizeOf> HeapSizeOf for IncrementalWitness<Node> {
+    fn heap_size_of_children(&self) -> usize {
+        self.tree.heap_size_of_children()
+            + self.filled.heap_size_of_children()
+            + self.cursor.heap_size_of_children()
+    }
+}
+
 impl<Node: Hashable> IncrementalWitness<Node> {
     /// Creates an `IncrementalWitness` for the most recent commitment added to the given
     /// [`CommitmentTree`].
@@ -243,6 +628,96 @@ impl<Node: Hashable> IncrementalWitness<Node> {
         }
     }
 
+    /// Creates an `IncrementalWitness` for the most recent commitment added to `tree`, for
+    /// a wallet whose birthday is at tree size `birthday_size`.
+    ///
+    /// Only the frontier of the tree at the birthday is needed, as from a checkpoint: the
+    /// wallet appends the commitments mined since to it and witnesses its notes from there,
+    /// the commitments before the birthday are never replayed. Returns `None` for a note
+    /// from before the birthday, which the wallet does not track.
+    pub fn from_tree_at_birthday(
+        tree: &CommitmentTree<Node>,
+        birthday_size: u64,
+    ) -> Option<IncrementalWitness<Node>> {
+        if tree.leaf_count() <= birthday_size {
+            return None;
+        }
+        Some(IncrementalWitness::from_tree(tree))
+    }
+
+    /// Rebuilds the witness of the note `leaf` from an authentication path of it, such as
+    /// one a wallet persisted, and the commitments appended to the tree after the note.
+    ///
+    /// Only the nodes of the path on the left of the note are kept: they are the frontier
+    /// of the tree when the note was appended. The nodes on its right are rebuilt by
+    /// replaying `subsequent`, which must hold every commitment appended after the note,
+    /// in order, so that `path()` is what a witness updated all along would return.
+    ///
+    /// The path may have been taken after any number of the subsequent commitments were
+    /// appended, but its root must then be the root of the rebuilt tree, otherwise its
+    /// nodes on the right of the note disagree with `subsequent` and it is refused.
+    pub fn from_path(
+        path: &CommitmentTreeWitness<Node>,
+        leaf: Node,
+        subsequent: &[Node],
+    ) -> Result<IncrementalWitness<Node>, WitnessError> {
+        Self::from_path_inner(path, leaf, subsequent, SAPLING_COMMITMENT_TREE_DEPTH)
+    }
+
+    fn from_path_inner(
+        path: &CommitmentTreeWitness<Node>,
+        leaf: Node,
+        subsequent: &[Node],
+        depth: usize,
+    ) -> Result<IncrementalWitness<Node>, WitnessError> {
+        if path.auth_path.len() != depth {
+            return Err(WitnessError::MalformedPath);
+        }
+        if depth < 64 && path.position >> depth != 0 {
+            return Err(WitnessError::PositionOutOfRange);
+        }
+
+        let mut left_nodes = Vec::with_capacity(depth);
+        for (i, node) in path.auth_path.iter().enumerate() {
+            match *node {
+                Some((sibling, is_right)) if is_right == ((path.position >> i) & 1 == 1) => {
+                    left_nodes.push(if is_right { Some(sibling) } else { None });
+                }
+                _ => return Err(WitnessError::MalformedPath),
+            }
+        }
+
+        // the frontier of the tree just after the note was appended.
+        let (left, right) = match left_nodes[0] {
+            Some(sibling) => (Some(sibling), Some(leaf)),
+            None => (Some(leaf), None),
+        };
+        let mut parents = left_nodes.split_off(1);
+        while let Some(&None) = parents.last() {
+            parents.pop();
+        }
+        let tree = CommitmentTree {
+            left,
+            right,
+            parents,
+            cached_root: None,
+        };
+
+        let path_root = path.root(leaf).ok_or(WitnessError::MalformedPath)?;
+        let mut witness = IncrementalWitness::from_tree(&tree);
+        let mut matched = witness.root_inner(depth) == path_root;
+        for node in subsequent {
+            witness
+                .append_inner(*node, depth)
+                .map_err(|_| WitnessError::TreeFull)?;
+            matched = matched || witness.root_inner(depth) == path_root;
+        }
+        if !matched {
+            return Err(WitnessError::MalformedPath);
+        }
+        Ok(witness)
+    }
+
     /// Reads an `IncrementalWitness` from its serialized form.
     pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
         let tree = CommitmentTree::read(&mut reader)?;
@@ -268,9 +743,32 @@ impl<Node: Hashable> IncrementalWitness<Node> {
         Optional::write(&mut writer, &self.cursor, |w, t| t.write(w))
     }
 
-    /// Returns the position of the witnessed note in the commitment tree.
-    pub fn position(&self) -> usize {
-        self.tree.size() - 1
+    /// Reads an `IncrementalWitness` written by `write_versioned`, such as from a
+    /// wallet backup.
+    pub fn read_versioned<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = reader.read_u8()?;
+        if version != WITNESS_SERIALIZATION_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown witness serialization version {}", version),
+            ));
+        }
+        Self::read(reader)
+    }
+
+    /// Serializes this `IncrementalWitness` prefixed with the serialization version,
+    /// so that it can be restored by a later release.
+    pub fn write_versioned<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&[WITNESS_SERIALIZATION_VERSION])?;
+        self.write(writer)
+    }
+
+    /// Returns the absolute position of the witnessed note in the commitment tree.
+    ///
+    /// This only depends on the size of the tree the witness was created from, so it
+    /// does not change as further commitments are appended.
+    pub fn position(&self) -> u64 {
+        self.tree.leaf_count() - 1
     }
 
     fn filler(&self) -> PathFiller<Node> {
@@ -409,11 +907,120 @@ impl<Node: Hashable> IncrementalWitness<Node> {
 
         Some(CommitmentTreeWitness::from_path(
             auth_path,
-            self.position() as u64,
+            self.position(),
         ))
     }
 }
 
+/// The state of a witness cursor after a commitment has been appended to it.
+#[derive(Clone)]
+enum CursorUpdate<Node: Hashable> {
+    /// The cursor subtree is complete and its root is a filled node.
+    Filled(Node),
+    /// The cursor subtree still has room for more commitments.
+    Pending(CommitmentTree<Node>),
+}
+
+/// A set of [`IncrementalWitness`]es tracking notes of the same commitment tree, which
+/// are advanced together.
+///
+/// Witnesses whose cursors are in the same state (typically, witnesses to old notes
+/// which are all filling the same subtree to the right of them) share the hashing done
+/// for each appended commitment, instead of each recomputing it.
+#[derive(Clone)]
+pub struct WitnessSet<Node: Hashable> {
+    witnesses: Vec<IncrementalWitness<Node>>,
+}
+
+impl<Node: Hashable + PartialEq> WitnessSet<Node> {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        WitnessSet { witnesses: vec![] }
+    }
+
+    /// Starts tracking a witness, returning its index in the set.
+    pub fn insert(&mut self, witness: IncrementalWitness<Node>) -> usize {
+        self.witnesses.push(witness);
+        self.witnesses.len() - 1
+    }
+
+    /// Returns the tracked witnesses, in insertion order.
+    pub fn witnesses(&self) -> &[IncrementalWitness<Node>] {
+        &self.witnesses
+    }
+
+    /// Returns the number of tracked witnesses.
+    pub fn len(&self) -> usize {
+        self.witnesses.len()
+    }
+
+    /// Returns true if no witness is tracked.
+    pub fn is_empty(&self) -> bool {
+        self.witnesses.is_empty()
+    }
+
+    /// Advances every tracked witness with the notes that have been added to the
+    /// underlying tree.
+    ///
+    /// The result is identical to calling [`IncrementalWitness::append`] on each witness
+    /// for each commitment. Returns an error if the tree is full.
+    pub fn append_all(&mut self, new_commitments: &[Node]) -> Result<(), ()> {
+        self.append_all_inner(new_commitments, SAPLING_COMMITMENT_TREE_DEPTH)
+    }
+
+    fn append_all_inner(&mut self, new_commitments: &[Node], depth: usize) -> Result<(), ()> {
+        for node in new_commitments {
+            // Cursors already advanced with this node, keyed by their prior state.
+            let mut advanced: Vec<(usize, CommitmentTree<Node>, CursorUpdate<Node>)> = vec![];
+
+            for witness in self.witnesses.iter_mut() {
+                let cursor = match witness.cursor.take() {
+                    Some(cursor) => cursor,
+                    None => {
+                        witness.append_inner(*node, depth)?;
+                        continue;
+                    }
+                };
+
+                let cursor_depth = witness.cursor_depth;
+                let shared = advanced
+                    .iter()
+                    .find(|(d, prior, _)| *d == cursor_depth && *prior == cursor)
+                    .map(|(_, _, update)| update.clone());
+                let update = match shared {
+                    Some(update) => update,
+                    None => {
+                        let mut next = cursor.clone();
+                        next.append_inner(*node, depth)
+                            .expect("cursor should not be full");
+                        let update = if next.is_complete(cursor_depth) {
+                            CursorUpdate::Filled(next.root_inner(cursor_depth, PathFiller::empty()))
+                        } else {
+                            CursorUpdate::Pending(next)
+                        };
+                        advanced.push((cursor_depth, cursor, update.clone()));
+                        update
+                    }
+                };
+
+                match update {
+                    CursorUpdate::Filled(root) => witness.filled.push(root),
+                    CursorUpdate::Pending(next) => witness.cursor = Some(next),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<Node: Hashable + HeapSizeOf> WitnessSet<Node> {
+    /// Returns an estimate of the heap memory used by the tracked witnesses, in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.witnesses.heap_size_of_children()
+    }
+}
+
 /// A witness to a path from a position in a particular commitment tree to the root of
 /// that tree.
 #[derive(Clone, Debug, PartialEq)]
@@ -431,6 +1038,20 @@ impl<Node: Hashable> CommitmentTreeWitness<Node> {
         }
     }
 
+    /// Computes the root of the tree from the leaf this path authenticates, or `None` if a
+    /// node of the path is missing. Each node of the path is paired with `true` when the
+    /// leaf side is the right child at its depth.
+    pub fn root(&self, leaf: Node) -> Option<Node> {
+        self.auth_path
+            .iter()
+            .enumerate()
+            .try_fold(leaf, |cur, (depth, node)| match *node {
+                Some((ref sibling, true)) => Some(Node::combine(depth, sibling, &cur)),
+                Some((ref sibling, false)) => Some(Node::combine(depth, &cur, sibling)),
+                None => None,
+            })
+    }
+
     /// Reads a witness from its serialized form.
     pub fn from_slice(witness: &[u8]) -> Result<Self, ()> {
         Self::from_slice_with_depth(witness, SAPLING_COMMITMENT_TREE_DEPTH)
@@ -504,13 +1125,19 @@ impl<Node: Hashable> CommitmentTreeWitness<Node> {
 
 #[cfg(test)]
 mod tests {
-    use super::{CommitmentTree, CommitmentTreeWitness, Hashable, IncrementalWitness, PathFiller};
+    use super::{
+        CommitmentTree, CommitmentTreeWitness, CompactCommitmentTree, Hashable,
+        IncrementalWitness, MerkleMountainRange, MerkleTree, PathFiller, WitnessError,
+        WitnessSet,
+    };
     use crate::sapling::Node;
 
+    use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
     use ff::PrimeFieldRepr;
     extern crate hex;
     use pairing::bls12_381::FrRepr;
     use std::io::{self, Read, Write};
+    use std::mem;
 
     const HEX_EMPTY_ROOTS: [&str; 33] = [
         "0100000000000000000000000000000000000000000000000000000000000000",
@@ -548,6 +1175,11 @@ mod tests {
         "fbc2f4300c01f0b7820d00e3347c8da4ee614674376cbc45359daa54f9b5493e",
     ];
 
+    /// Depth of the trees built by the tests, small enough to fill completely.
+    ///
+    /// The tree types take their depth at each call of the `*_inner` methods, while
+    /// the public methods fix it to `SAPLING_COMMITMENT_TREE_DEPTH`. The wrappers
+    /// below fix it to this depth instead.
     const TESTING_DEPTH: usize = 4;
 
     struct TestCommitmentTree(CommitmentTree<Node>);
@@ -608,6 +1240,576 @@ mod tests {
         }
     }
 
+    struct TestWitnessSet(WitnessSet<Node>);
+
+    impl TestWitnessSet {
+        fn new() -> Self {
+            TestWitnessSet(WitnessSet::new())
+        }
+
+        fn insert(&mut self, witness: TestIncrementalWitness) -> usize {
+            self.0.insert(witness.0)
+        }
+
+        fn append_all(&mut self, new_commitments: &[Node]) -> Result<(), ()> {
+            self.0.append_all_inner(new_commitments, TESTING_DEPTH)
+        }
+    }
+
+    #[test]
+    fn compact_tree_matches_commitment_tree() {
+        let mut tree = TestCommitmentTree::new();
+        let mut compact = CompactCommitmentTree::new();
+        assert_eq!(compact.root_inner(TESTING_DEPTH), tree.root());
+
+        for i in 0..16u64 {
+            let leaf = Node::new(FrRepr::from(i + 1));
+            tree.append(leaf).unwrap();
+            compact.append_inner(leaf, TESTING_DEPTH).unwrap();
+
+            assert_eq!(compact.size(), tree.size());
+            assert_eq!(compact.root_inner(TESTING_DEPTH), tree.root());
+            assert!(compact.to_tree() == tree.0);
+            assert!(CompactCommitmentTree::from(&tree.0) == compact);
+        }
+
+        // Tree is full
+        let leaf = Node::new(FrRepr::from(17));
+        assert!(tree.append(leaf).is_err());
+        assert!(compact.append_inner(leaf, TESTING_DEPTH).is_err());
+
+        let mut encoded = vec![];
+        compact.write(&mut encoded).unwrap();
+        let mut expected = vec![];
+        tree.write(&mut expected).unwrap();
+        assert_eq!(encoded, expected);
+        assert!(CompactCommitmentTree::read(&encoded[..]).unwrap() == compact);
+    }
+
+    #[test]
+    fn compact_tree_memory_on_sparse_tree() {
+        use std::mem::size_of;
+
+        // A tree holding 2^31 + 1 notes only has the highest parent present.
+        let leaf = Node::new(FrRepr::from(1));
+        let mut parents = vec![None; 30];
+        parents.push(Some(Node::empty_root(31)));
+        let tree = CommitmentTree {
+            left: Some(leaf),
+            right: None,
+            parents,
+            cached_root: None,
+        };
+        let compact = CompactCommitmentTree::from(&tree);
+
+        assert_eq!(compact.size(), tree.size());
+        assert_eq!(compact.root(), tree.root());
+
+        let full_frontier = tree.parents.len() * size_of::<Option<Node>>();
+        let compact_frontier = compact.parents.len() * size_of::<Node>() + size_of::<u64>();
+        assert_eq!(full_frontier, 31 * size_of::<Option<Node>>());
+        assert_eq!(compact_frontier, size_of::<Node>() + size_of::<u64>());
+        assert!(compact_frontier * 10 < full_frontier);
+    }
+
+    #[test]
+    fn append_stops_at_depth_limit() {
+        for depth in 1..=TESTING_DEPTH {
+            let capacity = 1u64 << depth;
+            let mut tree = CommitmentTree::<Node>::new();
+            tree.append_inner(Node::new(FrRepr::from(1)), depth).unwrap();
+            let mut witness = IncrementalWitness::from_tree(&tree);
+
+            for i in 1..capacity {
+                let leaf = Node::new(FrRepr::from(i + 1));
+                assert!(tree.append_inner(leaf, depth).is_ok());
+                assert!(witness.append_inner(leaf, depth).is_ok());
+            }
+            assert!(tree.is_complete(depth));
+            assert_eq!(tree.leaf_count(), capacity);
+
+            // The last valid append was accepted, the next one leaves the tree untouched.
+            let root = tree.root_inner(depth, PathFiller::empty());
+            let leaf = Node::new(FrRepr::from(capacity + 1));
+            assert!(tree.append_inner(leaf, depth).is_err());
+            assert_eq!(tree.leaf_count(), capacity);
+            assert_eq!(tree.root_inner(depth, PathFiller::empty()), root);
+
+            assert!(witness.append_inner(leaf, depth).is_err());
+            assert_eq!(witness.position(), 0);
+            assert_eq!(witness.root_inner(depth), root);
+        }
+    }
+
+    #[test]
+    fn position_of_last_leaf_in_full_tree() {
+        let mut tree = CommitmentTree::<Node>::new();
+        for i in 0..(1u64 << TESTING_DEPTH) {
+            tree.append_inner(Node::new(FrRepr::from(i + 1)), TESTING_DEPTH).unwrap();
+        }
+        let witness = IncrementalWitness::from_tree(&tree);
+        assert_eq!(witness.position(), (1u64 << TESTING_DEPTH) - 1);
+    }
+
+    #[test]
+    fn path_authenticates_the_witnessed_leaf() {
+        let mut tree = CommitmentTree::<Node>::new();
+        for i in 0..3u64 {
+            tree.append_inner(Node::new(FrRepr::from(i + 1)), TESTING_DEPTH).unwrap();
+        }
+        let mut witness = IncrementalWitness::from_tree(&tree);
+        witness.append_inner(Node::new(FrRepr::from(4)), TESTING_DEPTH).unwrap();
+
+        let path = witness.path_inner(TESTING_DEPTH).unwrap();
+        let root = witness.root_inner(TESTING_DEPTH);
+        assert_eq!(path.root(Node::new(FrRepr::from(3))), Some(root));
+        assert_ne!(path.root(Node::new(FrRepr::from(4))), Some(root));
+
+        let mut incomplete = path.clone();
+        incomplete.auth_path[1] = None;
+        assert_eq!(incomplete.root(Node::new(FrRepr::from(3))), None);
+    }
+
+    #[test]
+    fn witness_rebuilt_from_path_matches_updated_witness() {
+        let leaves: Vec<_> = (0..14u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+
+        for position in 0..10 {
+            let mut tree = CommitmentTree::<Node>::new();
+            for leaf in &leaves[..=position] {
+                tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+            }
+            let mut reference = IncrementalWitness::from_tree(&tree);
+            let fresh_path = reference.path_inner(TESTING_DEPTH).unwrap();
+            let subsequent = &leaves[position + 1..];
+            for (i, leaf) in subsequent.iter().enumerate() {
+                reference.append_inner(*leaf, TESTING_DEPTH).unwrap();
+
+                // from the path taken when the note was appended, or a later one.
+                let later_path = reference.path_inner(TESTING_DEPTH).unwrap();
+                for path in &[fresh_path.clone(), later_path] {
+                    let rebuilt = IncrementalWitness::from_path_inner(
+                        path,
+                        leaves[position],
+                        &subsequent[..=i],
+                        TESTING_DEPTH,
+                    ).unwrap();
+                    assert_eq!(
+                        rebuilt.path_inner(TESTING_DEPTH),
+                        reference.path_inner(TESTING_DEPTH)
+                    );
+                    assert_eq!(
+                        rebuilt.root_inner(TESTING_DEPTH),
+                        reference.root_inner(TESTING_DEPTH)
+                    );
+                    assert_eq!(rebuilt.position(), position as u64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn witness_from_malformed_path_is_refused() {
+        let leaves: Vec<_> = (0..3u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+        let mut tree = CommitmentTree::<Node>::new();
+        for leaf in &leaves {
+            tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+        }
+        let path = IncrementalWitness::from_tree(&tree).path_inner(TESTING_DEPTH).unwrap();
+        let rebuild = |path: &CommitmentTreeWitness<Node>, subsequent: &[Node]| {
+            IncrementalWitness::from_path_inner(path, leaves[2], subsequent, TESTING_DEPTH)
+                .map(|_| ())
+        };
+        assert_eq!(rebuild(&path, &[]), Ok(()));
+
+        let mut missing = path.clone();
+        missing.auth_path[2] = None;
+        assert_eq!(rebuild(&missing, &[]), Err(WitnessError::MalformedPath));
+
+        let mut moved = path.clone();
+        moved.position = 3;
+        assert_eq!(rebuild(&moved, &[]), Err(WitnessError::MalformedPath));
+
+        let mut outside = path.clone();
+        outside.position = 1 << TESTING_DEPTH;
+        assert_eq!(rebuild(&outside, &[]), Err(WitnessError::PositionOutOfRange));
+
+        // the tree holds three notes, so it fills up after all but three commitments.
+        let commitments: Vec<_> = (0..(1u64 << TESTING_DEPTH) - 2)
+            .map(|i| Node::new(FrRepr::from(i)))
+            .collect();
+        let filling = &commitments[..commitments.len() - 1];
+        assert_eq!(rebuild(&path, filling), Ok(()));
+        assert_eq!(rebuild(&path, &commitments), Err(WitnessError::TreeFull));
+    }
+
+    #[test]
+    fn witness_from_path_with_another_root_is_refused() {
+        let leaves: Vec<_> = (0..4u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+        let mut tree = CommitmentTree::<Node>::new();
+        tree.append_inner(leaves[0], TESTING_DEPTH).unwrap();
+        let mut witness = IncrementalWitness::from_tree(&tree);
+        for leaf in &leaves[1..] {
+            witness.append_inner(*leaf, TESTING_DEPTH).unwrap();
+        }
+        let path = witness.path_inner(TESTING_DEPTH).unwrap();
+        let rebuild = |path: &CommitmentTreeWitness<Node>, subsequent: &[Node]| {
+            IncrementalWitness::from_path_inner(path, leaves[0], subsequent, TESTING_DEPTH)
+                .map(|_| ())
+        };
+        assert_eq!(rebuild(&path, &leaves[1..]), Ok(()));
+
+        // a node on the right of the note which is not built from the subsequent commitments.
+        let mut tampered = path.clone();
+        tampered.auth_path[1] = Some((Node::new(FrRepr::from(9)), false));
+        assert_eq!(rebuild(&tampered, &leaves[1..]), Err(WitnessError::MalformedPath));
+
+        // the path was taken after other commitments than the subsequent ones.
+        let others: Vec<_> = (5..8u64).map(|i| Node::new(FrRepr::from(i))).collect();
+        assert_eq!(rebuild(&path, &others), Err(WitnessError::MalformedPath));
+    }
+
+    #[test]
+    fn mmr_round_trips_with_the_same_root() {
+        let leaves: Vec<_> = (0..16u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+
+        for size in 0..16 {
+            let mut tree = CommitmentTree::<Node>::new();
+            for leaf in &leaves[..size] {
+                tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+            }
+
+            let mmr = tree.to_mmr();
+            assert_eq!(mmr.size(), size as u64);
+            assert_eq!(mmr.peaks().len(), size.count_ones() as usize);
+            let mut rebuilt = CommitmentTree::from_mmr_inner(&mmr, TESTING_DEPTH).unwrap();
+            assert_eq!(rebuilt.leaf_count(), size as u64);
+            assert_eq!(rebuilt.to_mmr(), mmr);
+
+            // the rebuilt tree keeps up with the original one.
+            for leaf in &leaves[size..] {
+                assert_eq!(
+                    rebuilt.root_inner(TESTING_DEPTH, PathFiller::empty()),
+                    tree.root_inner(TESTING_DEPTH, PathFiller::empty())
+                );
+                tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+                rebuilt.append_inner(*leaf, TESTING_DEPTH).unwrap();
+            }
+            assert_eq!(rebuilt.to_mmr(), tree.to_mmr());
+        }
+    }
+
+    #[test]
+    fn mmr_peaks_are_the_roots_of_perfect_subtrees() {
+        let leaves: Vec<_> = (0..16u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+        let mut tree = CommitmentTree::<Node>::new();
+        for leaf in &leaves[..6] {
+            tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+        }
+
+        let pair = |i: usize| Node::combine(0, &leaves[i], &leaves[i + 1]);
+        let mmr = tree.to_mmr();
+        assert_eq!(mmr.heights(), vec![2, 1]);
+        assert_eq!(
+            mmr.peaks(),
+            &[Node::combine(1, &pair(0), &pair(2)), pair(4)][..]
+        );
+        assert_eq!(MerkleMountainRange::from_peaks(6, mmr.peaks().to_vec()), Some(mmr));
+        assert_eq!(MerkleMountainRange::from_peaks(7, vec![pair(0)]), None);
+
+        // a full tree has no frontier to rebuild.
+        for leaf in &leaves[6..] {
+            tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+        }
+        let mmr = tree.to_mmr();
+        assert_eq!(mmr.peaks(), &[tree.root_inner(TESTING_DEPTH, PathFiller::empty())][..]);
+        assert!(CommitmentTree::from_mmr_inner(&mmr, TESTING_DEPTH).is_none());
+    }
+
+    #[test]
+    fn cached_root_follows_appends() {
+        let mut tree = CommitmentTree::<Node>::new();
+        assert_eq!(tree.root_cached(), tree.root());
+
+        for i in 0..5u64 {
+            tree.append(Node::new(FrRepr::from(i + 1))).unwrap();
+            assert_eq!(tree.root_cached(), tree.root());
+            assert_eq!(tree.root_cached(), tree.root());
+        }
+
+        // a tree read back has no cache, but equals the original.
+        let mut ser = vec![];
+        tree.write(&mut ser).unwrap();
+        let mut read = CommitmentTree::<Node>::read(&ser[..]).unwrap();
+        assert!(read == tree);
+        assert_eq!(read.root_cached(), tree.root());
+    }
+
+    #[test]
+    fn root_after_does_not_mutate() {
+        let mut tree = CommitmentTree::<Node>::new();
+        tree.append(Node::new(FrRepr::from(1))).unwrap();
+        let leaves: Vec<Node> = (2..6u64).map(|i| Node::new(FrRepr::from(i))).collect();
+
+        let root = tree.root();
+        let prospective = tree.root_after(&leaves).unwrap();
+        assert_eq!(tree.size(), 1);
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.root_after(&[]).unwrap(), root);
+
+        for leaf in &leaves {
+            tree.append(*leaf).unwrap();
+        }
+        assert_eq!(tree.root(), prospective);
+    }
+
+    #[test]
+    fn versioned_witness_round_trip() {
+        let leaves: Vec<Node> = (0..20u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+
+        let mut tree = CommitmentTree::<Node>::new();
+        for leaf in &leaves[..5] {
+            tree.append(*leaf).unwrap();
+        }
+        let mut witness = IncrementalWitness::from_tree(&tree);
+        for leaf in &leaves[5..9] {
+            witness.append(*leaf).unwrap();
+        }
+
+        let mut backup = vec![];
+        witness.write_versioned(&mut backup).unwrap();
+        assert_eq!(backup[0], super::WITNESS_SERIALIZATION_VERSION);
+        let mut restored = IncrementalWitness::<Node>::read_versioned(&backup[..]).unwrap();
+        assert_eq!(restored.path(), witness.path());
+
+        for leaf in &leaves[9..] {
+            witness.append(*leaf).unwrap();
+            restored.append(*leaf).unwrap();
+            assert_eq!(restored.root(), witness.root());
+            assert_eq!(restored.path(), witness.path());
+        }
+
+        backup[0] = 0;
+        assert!(IncrementalWitness::<Node>::read_versioned(&backup[..]).is_err());
+    }
+
+    #[test]
+    fn witness_position_survives_appends() {
+        let leaves: Vec<Node> = (0..12u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+
+        let mut tree = CommitmentTree::<Node>::new();
+        for leaf in &leaves[..7] {
+            tree.append(*leaf).unwrap();
+        }
+        let mut witness = IncrementalWitness::from_tree(&tree);
+        assert_eq!(witness.position(), 6);
+
+        for leaf in &leaves[7..] {
+            witness.append(*leaf).unwrap();
+            assert_eq!(witness.position(), 6);
+        }
+        assert_eq!(witness.path().unwrap().position, 6);
+    }
+
+    #[test]
+    fn birthday_witness_matches_full_history_witness() {
+        let leaves: Vec<Node> = (0..20u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+        let birthday = 9;
+
+        let mut full = CommitmentTree::<Node>::new();
+        for leaf in &leaves[..birthday] {
+            full.append(*leaf).unwrap();
+        }
+        // the wallet only gets the frontier at its birthday.
+        let mut frontier = Vec::new();
+        full.write(&mut frontier).unwrap();
+        let mut from_birthday = CommitmentTree::<Node>::read(&frontier[..]).unwrap();
+        assert!(IncrementalWitness::from_tree_at_birthday(&from_birthday, birthday as u64).is_none());
+
+        for leaf in &leaves[birthday..13] {
+            full.append(*leaf).unwrap();
+            from_birthday.append(*leaf).unwrap();
+        }
+        let mut expected = IncrementalWitness::from_tree(&full);
+        let mut witness = IncrementalWitness::from_tree_at_birthday(&from_birthday, birthday as u64).unwrap();
+
+        for leaf in &leaves[13..] {
+            full.append(*leaf).unwrap();
+            expected.append(*leaf).unwrap();
+            witness.append(*leaf).unwrap();
+        }
+        assert_eq!(witness.position(), 12);
+        assert_eq!(witness.root(), full.root());
+        assert_eq!(witness.path(), expected.path());
+    }
+
+    #[test]
+    fn witness_set_matches_independent_witnesses() {
+        let leaves: Vec<Node> = (0..15u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+        let tracked = [0usize, 1, 3, 5];
+
+        let mut tree = TestCommitmentTree::new();
+        let mut independent: Vec<TestIncrementalWitness> = vec![];
+        let mut set = WitnessSet::new();
+
+        // Track some notes while the tree grows one commitment at a time.
+        for (i, leaf) in leaves[..8].iter().enumerate() {
+            tree.append(*leaf).unwrap();
+            for witness in independent.iter_mut() {
+                witness.append(*leaf).unwrap();
+            }
+            set.append_all_inner(&[*leaf], TESTING_DEPTH).unwrap();
+
+            if tracked.contains(&i) {
+                independent.push(TestIncrementalWitness::from_tree(&tree));
+                set.insert(IncrementalWitness::from_tree(&tree.0));
+            }
+        }
+
+        // Then advance with a whole batch of commitments.
+        for leaf in &leaves[8..] {
+            tree.append(*leaf).unwrap();
+            for witness in independent.iter_mut() {
+                witness.append(*leaf).unwrap();
+            }
+        }
+        set.append_all_inner(&leaves[8..], TESTING_DEPTH).unwrap();
+
+        assert_eq!(set.len(), tracked.len());
+        for (expected, witness) in independent.iter().zip(set.witnesses()) {
+            assert_eq!(witness.position(), expected.0.position());
+            assert_eq!(witness.root_inner(TESTING_DEPTH), tree.root());
+            assert_eq!(witness.path_inner(TESTING_DEPTH), expected.path());
+        }
+    }
+
+    #[test]
+    fn witness_set_stops_at_depth_limit() {
+        let capacity = 1 << TESTING_DEPTH;
+        let leaves: Vec<Node> = (0..=capacity).map(|i| Node::new(FrRepr::from(i as u64 + 1))).collect();
+
+        let mut tree = TestCommitmentTree::new();
+        let mut set = TestWitnessSet::new();
+        for leaf in &leaves[..3] {
+            tree.append(*leaf).unwrap();
+            set.insert(TestIncrementalWitness::from_tree(&tree));
+        }
+
+        // Fill the tree in one batch, then try to go past it.
+        for leaf in &leaves[3..capacity] {
+            tree.append(*leaf).unwrap();
+        }
+        set.append_all(&leaves[3..capacity]).unwrap();
+        for (position, witness) in set.0.witnesses().iter().enumerate() {
+            assert_eq!(witness.position(), position as u64);
+            assert_eq!(witness.root_inner(TESTING_DEPTH), tree.root());
+        }
+
+        let overflow = &leaves[capacity..];
+        assert!(tree.append(overflow[0]).is_err());
+        assert!(set.append_all(overflow).is_err());
+    }
+
+    #[test]
+    fn witness_set_heap_size_grows_with_witnesses() {
+        let leaves: Vec<Node> = (0..8u64).map(|i| Node::new(FrRepr::from(i + 1))).collect();
+
+        let mut tree = CommitmentTree::<Node>::new();
+        let mut set = WitnessSet::new();
+        assert_eq!(set.heap_size(), 0);
+
+        for leaf in &leaves[..4] {
+            tree.append(*leaf).unwrap();
+            set.insert(IncrementalWitness::from_tree(&tree));
+        }
+        let tracked = set.heap_size();
+        assert!(tracked >= set.len() * mem::size_of::<IncrementalWitness<Node>>());
+
+        // Advancing the witnesses records the filled subtrees of each of them.
+        set.append_all(&leaves[4..]).unwrap();
+        assert!(set.heap_size() > tracked);
+    }
+
+    /// A non-cryptographic node whose parents are easy to compute by hand.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct MockNode(u64);
+
+    impl Hashable for MockNode {
+        fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+            reader.read_u64::<LittleEndian>().map(MockNode)
+        }
+
+        fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+            writer.write_u64::<LittleEndian>(self.0)
+        }
+
+        fn combine(depth: usize, lhs: &Self, rhs: &Self) -> Self {
+            MockNode(
+                lhs.0
+                    .wrapping_mul(31)
+                    .wrapping_add(rhs.0.wrapping_mul(17))
+                    .wrapping_add(depth as u64 + 1),
+            )
+        }
+
+        fn blank() -> Self {
+            MockNode(0)
+        }
+
+        fn empty_root(depth: usize) -> Self {
+            (0..depth).fold(Self::blank(), |node, d| Self::combine(d, &node, &node))
+        }
+    }
+
+    /// Computes the root of a tree of the given depth holding `leaves` directly.
+    fn naive_root(leaves: &[MockNode], depth: usize) -> MockNode {
+        let mut level: Vec<MockNode> = leaves.to_vec();
+        level.resize(1 << depth, MockNode::blank());
+        for d in 0..depth {
+            level = level
+                .chunks(2)
+                .map(|pair| MockNode::combine(d, &pair[0], &pair[1]))
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn merkle_tree_is_generic_over_hashable() {
+        let leaves: Vec<MockNode> = (1..=16u64).map(MockNode).collect();
+        let mut tree = MerkleTree::<MockNode>::new();
+        let mut witness: Option<IncrementalWitness<MockNode>> = None;
+        assert_eq!(
+            tree.root_inner(TESTING_DEPTH, PathFiller::empty()),
+            naive_root(&[], TESTING_DEPTH)
+        );
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            tree.append_inner(*leaf, TESTING_DEPTH).unwrap();
+            if let Some(ref mut witness) = witness {
+                witness.append_inner(*leaf, TESTING_DEPTH).unwrap();
+            }
+            if i == 5 {
+                witness = Some(IncrementalWitness::from_tree(&tree));
+            }
+
+            let expected = naive_root(&leaves[..=i], TESTING_DEPTH);
+            assert_eq!(tree.size(), i + 1);
+            assert_eq!(tree.root_inner(TESTING_DEPTH, PathFiller::empty()), expected);
+            if let Some(ref witness) = witness {
+                assert_eq!(witness.position(), 5);
+                assert_eq!(witness.root_inner(TESTING_DEPTH), expected);
+            }
+        }
+
+        // The tree is full.
+        assert!(tree.append_inner(MockNode(17), TESTING_DEPTH).is_err());
+
+        let mut encoded = vec![];
+        tree.write(&mut encoded).unwrap();
+        assert!(MerkleTree::<MockNode>::read(&encoded[..]).unwrap() == tree);
+    }
+
     #[test]
     fn empty_root_test_vectors() {
         let mut tmp = [0u8; 32];
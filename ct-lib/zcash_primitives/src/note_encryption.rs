@@ -17,12 +17,15 @@ use sapling_crypto::{
 use std::fmt;
 use std::str;
 
-use crate::{keys::OutgoingViewingKey, JUBJUB};
+use crate::{
+    keys::{prf_expand, OutgoingViewingKey},
+    JUBJUB,
+};
 
 pub const KDF_SAPLING_PERSONALIZATION: &'static [u8; 16] = b"Zcash_SaplingKDF";
 pub const PRF_OCK_PERSONALIZATION: &'static [u8; 16] = b"Zcash_Derive_ock";
 
-const COMPACT_NOTE_SIZE: usize = (
+pub const COMPACT_NOTE_SIZE: usize = (
     1  + // version
     11 + // diversifier
     8  + // value
@@ -148,6 +151,18 @@ pub fn generate_esk() -> Fs {
     Fs::to_uniform(&buffer[..])
 }
 
+/// Derives the ephemeral secret key of a note from its randomness seed, as
+/// `esk = ToScalar(PRF^expand(rseed, [0x05]))`.
+///
+/// An `rseed` must never be used for two notes: the same `esk` would encrypt
+/// both, and anyone seeing the two outputs could tell they came from the same
+/// sender and recover the relation between their plaintexts.
+///
+/// Implements section 4.6.2 of the Zcash Protocol Specification.
+pub fn derive_esk(rseed: &[u8; 32]) -> Fs {
+    Fs::to_uniform(prf_expand(rseed, &[0x05]).as_bytes())
+}
+
 /// Sapling key agreement for note encryption.
 ///
 /// Implements section 5.4.4.3 of the Zcash Protocol Specification.
@@ -269,7 +284,18 @@ impl SaplingNoteEncryption {
         to: PaymentAddress<Bls12>,
         memo: Memo,
     ) -> SaplingNoteEncryption {
-        let esk = generate_esk();
+        Self::new_with_esk(generate_esk(), ovk, note, to, memo)
+    }
+
+    /// Creates a new encryption context for the given note, using the
+    /// caller-provided ephemeral secret key.
+    pub fn new_with_esk(
+        esk: Fs,
+        ovk: OutgoingViewingKey,
+        note: Note<Bls12>,
+        to: PaymentAddress<Bls12>,
+        memo: Memo,
+    ) -> SaplingNoteEncryption {
         let epk = note.g_d.mul(esk, &JUBJUB);
 
         SaplingNoteEncryption {
@@ -349,33 +375,88 @@ impl SaplingNoteEncryption {
     }
 }
 
-fn parse_note_plaintext_minus_memo(
-    ivk: &Fs,
-    cmu: &Fr,
-    plaintext: &[u8],
-) -> Option<(Note<Bls12>, PaymentAddress<Bls12>)> {
-    let mut d = [0u8; 11];
-    d.copy_from_slice(&plaintext[1..12]);
+/// The note fields of a decrypted note plaintext, without the memo.
+#[derive(Clone, Debug)]
+pub struct NotePlaintext {
+    /// Diversifier of the recipient's payment address.
+    pub diversifier: Diversifier,
+    /// Value of the note.
+    pub value: u64,
+    /// Commitment trapdoor of the note.
+    pub rcm: Fs,
+}
 
-    let v = (&plaintext[12..20]).read_u64::<LittleEndian>().ok()?;
+impl NotePlaintext {
+    /// Parses the leading `COMPACT_NOTE_SIZE` bytes of a decrypted note plaintext.
+    pub fn from_bytes(plaintext: &[u8]) -> Option<Self> {
+        if plaintext.len() < COMPACT_NOTE_SIZE {
+            return None;
+        }
 
-    let mut rcm = FsRepr::default();
-    rcm.read_le(&plaintext[20..COMPACT_NOTE_SIZE]).ok()?;
-    let rcm = Fs::from_repr(rcm).ok()?;
+        let mut d = [0u8; 11];
+        d.copy_from_slice(&plaintext[1..12]);
 
-    let diversifier = Diversifier(d);
-    let pk_d = diversifier
-        .g_d::<Bls12>(&JUBJUB)?
-        .mul(ivk.into_repr(), &JUBJUB);
+        let value = (&plaintext[12..20]).read_u64::<LittleEndian>().ok()?;
 
-    let to = PaymentAddress { pk_d, diversifier };
-    let note = to.create_note(v, rcm, &JUBJUB).unwrap();
+        let mut rcm = FsRepr::default();
+        rcm.read_le(&plaintext[20..COMPACT_NOTE_SIZE]).ok()?;
+        let rcm = Fs::from_repr(rcm).ok()?;
+
+        Some(NotePlaintext {
+            diversifier: Diversifier(d),
+            value,
+            rcm,
+        })
+    }
+}
+
+/// Rebuilds the note of a decrypted plaintext sent to the recipient with the given
+/// `g_d` and `pk_d`, so that a wallet can spend it and compute its nullifier.
+///
+/// Returns `None` unless `g_d` is the one of the plaintext's diversifier and the note
+/// commits to `cmu`, the commitment published in the output: a note which does not is
+/// not in the commitment tree and can never be spent.
+pub fn note_from_plaintext(
+    plaintext: &NotePlaintext,
+    g_d: &edwards::Point<Bls12, PrimeOrder>,
+    pk_d: &edwards::Point<Bls12, PrimeOrder>,
+    cmu: &Fr,
+) -> Option<Note<Bls12>> {
+    if plaintext.diversifier.g_d::<Bls12>(&JUBJUB)? != *g_d {
+        return None;
+    }
+
+    let note = Note {
+        value: plaintext.value,
+        g_d: g_d.clone(),
+        pk_d: pk_d.clone(),
+        r: plaintext.rcm,
+    };
 
     if note.cm(&JUBJUB) != *cmu {
         // Published commitment doesn't match calculated commitment
         return None;
     }
 
+    Some(note)
+}
+
+fn parse_note_plaintext_minus_memo(
+    ivk: &Fs,
+    cmu: &Fr,
+    plaintext: &[u8],
+) -> Option<(Note<Bls12>, PaymentAddress<Bls12>)> {
+    let plaintext = NotePlaintext::from_bytes(plaintext)?;
+
+    let g_d = plaintext.diversifier.g_d::<Bls12>(&JUBJUB)?;
+    let pk_d = g_d.mul(ivk.into_repr(), &JUBJUB);
+
+    let note = note_from_plaintext(&plaintext, &g_d, &pk_d, cmu)?;
+    let to = PaymentAddress {
+        pk_d,
+        diversifier: plaintext.diversifier,
+    };
+
     Some((note, to))
 }
 
@@ -560,8 +641,9 @@ mod tests {
     };
 
     use super::{
-        kdf_sapling, prf_ock, sapling_ka_agree, try_sapling_compact_note_decryption,
-        try_sapling_note_decryption, try_sapling_output_recovery, Memo, SaplingNoteEncryption,
+        derive_esk, kdf_sapling, note_from_plaintext, prf_ock, sapling_ka_agree,
+        try_sapling_compact_note_decryption, try_sapling_note_decryption,
+        try_sapling_output_recovery, Memo, NotePlaintext, SaplingNoteEncryption,
         COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, OUT_CIPHERTEXT_SIZE,
     };
     use crate::{keys::OutgoingViewingKey, JUBJUB};
@@ -680,6 +762,39 @@ mod tests {
         assert_eq!(Memo::default().to_utf8(), None);
     }
 
+    #[test]
+    fn note_from_plaintext_checks_commitment() {
+        let mut rng = thread_rng();
+        let diversifier = Diversifier([0; 11]);
+        let g_d = diversifier.g_d::<Bls12>(&JUBJUB).unwrap();
+        let pk_d = g_d.mul(Fs::rand(&mut rng), &JUBJUB);
+        let plaintext = NotePlaintext {
+            diversifier,
+            value: 100,
+            rcm: Fs::rand(&mut rng),
+        };
+        let expected = PaymentAddress { diversifier, pk_d: pk_d.clone() }
+            .create_note(plaintext.value, plaintext.rcm, &JUBJUB)
+            .unwrap();
+        let cmu = expected.cm(&JUBJUB);
+
+        let note = note_from_plaintext(&plaintext, &g_d, &pk_d, &cmu).unwrap();
+        assert_eq!(note.cm(&JUBJUB), cmu);
+        assert_eq!(note.value, 100);
+
+        // commitment of another output.
+        assert!(note_from_plaintext(&plaintext, &g_d, &pk_d, &Fr::rand(&mut rng)).is_none());
+        // another recipient.
+        let other_pk_d = g_d.mul(Fs::rand(&mut rng), &JUBJUB);
+        assert!(note_from_plaintext(&plaintext, &g_d, &other_pk_d, &cmu).is_none());
+        // g_d of another diversifier.
+        let other_g_d = (1u8..)
+            .filter_map(|i| Diversifier([i; 11]).g_d::<Bls12>(&JUBJUB))
+            .next()
+            .unwrap();
+        assert!(note_from_plaintext(&plaintext, &other_g_d, &pk_d, &cmu).is_none());
+    }
+
     fn random_enc_ciphertext(
         mut rng: &mut Rng,
     ) -> (
@@ -742,6 +857,70 @@ mod tests {
         )
     }
 
+    #[test]
+    fn derived_esk_gives_stable_outputs() {
+        let mut rng = thread_rng();
+        let diversifier = Diversifier([0; 11]);
+        let ivk = Fs::rand(&mut rng);
+        let pk_d = diversifier.g_d::<Bls12>(&JUBJUB).unwrap().mul(ivk, &JUBJUB);
+        let pa = PaymentAddress { diversifier, pk_d };
+        let note = pa.create_note(100, Fs::rand(&mut rng), &JUBJUB).unwrap();
+        let ovk = OutgoingViewingKey([0; 32]);
+
+        let rseed = [7u8; 32];
+        assert!(derive_esk(&rseed) == derive_esk(&rseed));
+        assert!(derive_esk(&rseed) != derive_esk(&[8u8; 32]));
+
+        let encrypt = || {
+            let ne = SaplingNoteEncryption::new_with_esk(
+                derive_esk(&rseed),
+                ovk,
+                note.clone(),
+                pa.clone(),
+                Memo([0; 512]),
+            );
+            (ne.epk().clone(), ne.encrypt_note_plaintext().to_vec())
+        };
+        let (epk, enc_ciphertext) = encrypt();
+        let (epk2, enc_ciphertext2) = encrypt();
+        assert!(epk == epk2);
+        assert_eq!(enc_ciphertext, enc_ciphertext2);
+        assert!(try_sapling_note_decryption(&ivk, &epk, &note.cm(&JUBJUB), &enc_ciphertext).is_some());
+    }
+
+    #[test]
+    fn derive_esk_known_answers() {
+        // esk = ToScalar(BLAKE2b-512("Zcash_ExpandSeed", rseed || [0x05])).
+        let mut ascending = [0u8; 32];
+        for (i, b) in ascending.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let test_vectors = [
+            (
+                ascending,
+                [
+                    0x97, 0x7b, 0xf7, 0xe0, 0x5d, 0xce, 0x70, 0x5d, 0x7b, 0x93, 0xab, 0xe2, 0xad, 0xc8,
+                    0xe7, 0xf9, 0x27, 0xed, 0x86, 0xc6, 0x5a, 0xbc, 0xc3, 0x95, 0x48, 0x42, 0x7d, 0xab,
+                    0x9c, 0xb9, 0x77, 0x0e,
+                ],
+            ),
+            (
+                [7u8; 32],
+                [
+                    0x44, 0x7f, 0x83, 0x2b, 0x71, 0xd6, 0xec, 0x08, 0xf8, 0xd3, 0x23, 0x1c, 0xab, 0x70,
+                    0x8e, 0x5f, 0x27, 0xb5, 0xc4, 0xb6, 0x99, 0x80, 0x76, 0x24, 0xbe, 0x80, 0x51, 0xc2,
+                    0xa0, 0x84, 0xe3, 0x0b,
+                ],
+            ),
+        ];
+
+        for (rseed, expected) in test_vectors.iter() {
+            let mut esk = [0u8; 32];
+            derive_esk(rseed).into_repr().write_le(&mut esk[..]).unwrap();
+            assert_eq!(&esk, expected);
+        }
+    }
+
     #[test]
     fn decryption_with_invalid_ivk() {
         let mut rng = thread_rng();
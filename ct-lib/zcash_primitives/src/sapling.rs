@@ -6,9 +6,9 @@ use ff::{BitIterator, PrimeField, PrimeFieldRepr};
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use rand::OsRng;
 use sapling_crypto::{
-    jubjub::{fs::Fs, FixedGenerators, JubjubBls12},
+    jubjub::{edwards, fs::Fs, FixedGenerators, JubjubBls12, PrimeOrder, Unknown},
     pedersen_hash::{pedersen_hash, Personalization},
-    primitives::Note,
+    primitives::{Note, ValueCommitment},
     redjubjub::{PrivateKey, PublicKey, Signature},
 };
 use std::io::{self, Read, Write};
@@ -19,23 +19,26 @@ use JUBJUB;
 pub const SAPLING_COMMITMENT_TREE_DEPTH: usize =
     sapling_crypto::circuit::sapling::TREE_DEPTH;
 
+/// Length of a serialized `Node`.
+pub const NODE_SIZE: usize = 32;
+
+/// Returns the bits of `repr`, least significant first. `BitIterator` yields them most
+/// significant first, hence the reversal.
+fn le_bits(repr: &FrRepr) -> [bool; 256] {
+    let mut tmp = [false; 256];
+    for (a, b) in tmp.iter_mut().rev().zip(BitIterator::new(repr)) {
+        *a = b;
+    }
+    tmp
+}
+
 /// Compute a parent node in the Sapling commitment tree given its two children.
+///
+/// The Pedersen hash is fed the `Fr::NUM_BITS` bits of `lhs`, least significant first,
+/// then those of `rhs`.
 pub fn merkle_hash(depth: usize, lhs: &FrRepr, rhs: &FrRepr) -> FrRepr {
-    let lhs = {
-        let mut tmp = [false; 256];
-        for (a, b) in tmp.iter_mut().rev().zip(BitIterator::new(lhs)) {
-            *a = b;
-        }
-        tmp
-    };
-
-    let rhs = {
-        let mut tmp = [false; 256];
-        for (a, b) in tmp.iter_mut().rev().zip(BitIterator::new(rhs)) {
-            *a = b;
-        }
-        tmp
-    };
+    let lhs = le_bits(lhs);
+    let rhs = le_bits(rhs);
 
     pedersen_hash::<Bls12, _>(
         Personalization::MerkleTree(depth),
@@ -51,6 +54,12 @@ pub fn merkle_hash(depth: usize, lhs: &FrRepr, rhs: &FrRepr) -> FrRepr {
 }
 
 /// A node within the Sapling commitment tree.
+///
+/// The node is serialized by `Hashable::write` as its `FrRepr` in little-endian: the
+/// least significant limb first, each limb in little-endian, so byte `i` holds bits
+/// `8 * i` to `8 * i + 7`. Its bits, as returned by `to_le_bits` and hashed by
+/// `merkle_hash`, are least significant first, so bit `i` is bit `i % 8` of byte `i / 8`.
+/// Reading the bytes as big-endian, or the bits in `BitIterator` order, gives another node.
 #[derive(Clone, Copy, Eq, Debug, PartialEq)]
 pub struct Node {
     repr: FrRepr,
@@ -60,12 +69,116 @@ impl Node {
     pub fn new(repr: FrRepr) -> Self {
         Node { repr }
     }
+
+    /// Returns the `Fr::NUM_BITS` bits of this node, least significant first, in the
+    /// order in which `merkle_hash` and the Sapling circuit consume them.
+    pub fn to_le_bits(&self) -> Vec<bool> {
+        le_bits(&self.repr)
+            .iter()
+            .cloned()
+            .take(Fr::NUM_BITS as usize)
+            .collect()
+    }
+
+    /// Builds a node from bits in the order returned by `to_le_bits`. Returns `None`
+    /// unless exactly `Fr::NUM_BITS` bits are given and they encode a field element.
+    pub fn from_le_bits(bits: &[bool]) -> Option<Self> {
+        if bits.len() != Fr::NUM_BITS as usize {
+            return None;
+        }
+        let mut repr = FrRepr::default();
+        for (i, _) in bits.iter().enumerate().filter(|&(_, &bit)| bit) {
+            repr.0[i / 64] |= 1 << (i % 64);
+        }
+        Fr::from_repr(repr).ok().map(|_| Node::new(repr))
+    }
+
+    /// Describes the node in each of its encodings: the serialized bytes, the limbs of its
+    /// `FrRepr` and the first bits in hashing order. Meant for comparing a node across
+    /// implementations when one of them gets the byte or bit order wrong.
+    pub fn debug_endianness_report(&self) -> String {
+        let mut bytes = [0u8; NODE_SIZE];
+        self.repr
+            .write_le(&mut bytes[..])
+            .expect("a node is NODE_SIZE bytes");
+        let bytes: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let limbs: Vec<String> = self.repr.0.iter().map(|l| format!("{:#018x}", l)).collect();
+        let bits: String = self
+            .to_le_bits()
+            .iter()
+            .take(16)
+            .map(|&bit| if bit { '1' } else { '0' })
+            .collect();
+        format!(
+            "bytes (le): {}\nlimbs (least significant first): [{}]\nbits (least significant first): {}...",
+            bytes,
+            limbs.join(", "),
+            bits
+        )
+    }
+
+    /// Returns `true` if the nodes differ, comparing the most significant limb first.
+    ///
+    /// This is always `self != other`. Tree and witness code compares many unequal
+    /// nodes, and the high limb of a node derived from a Pedersen hash is as good as any
+    /// other at telling them apart, so most comparisons stop at the first limb checked.
+    /// Paths whose correctness depends on the result should keep using `PartialEq`.
+    #[inline]
+    pub fn quick_ne(&self, other: &Node) -> bool {
+        let (lhs, rhs) = (&self.repr.0, &other.repr.0);
+        lhs[3] != rhs[3] || lhs[2] != rhs[2] || lhs[1] != rhs[1] || lhs[0] != rhs[0]
+    }
+}
+
+/// Error returned by [`Node::combine_checked`].
+#[derive(Debug, PartialEq)]
+pub enum CombineError {
+    /// The children are not at the same level of the tree.
+    SiblingLevelMismatch { lhs: usize, rhs: usize },
+    /// The depth passed to the hash does not match the level of the children.
+    DepthMismatch { depth: usize, level: usize },
+    /// The parent would be above the root of the Sapling commitment tree.
+    AboveRoot(usize),
+}
+
+impl Node {
+    /// Returns the parent of two nodes like [`Hashable::combine`], checking that
+    /// `depth` is consistent with the known level of the children.
+    ///
+    /// Leaves are at level 0 and the parent of two nodes at level `l` is at level
+    /// `l + 1`, hashed with depth `l`. A wrong depth still yields a valid-looking
+    /// node, so callers which track levels should use this instead of `combine`.
+    pub fn combine_checked(
+        depth: usize,
+        lhs: &Self,
+        lhs_level: usize,
+        rhs: &Self,
+        rhs_level: usize,
+    ) -> Result<Self, CombineError> {
+        if lhs_level != rhs_level {
+            return Err(CombineError::SiblingLevelMismatch {
+                lhs: lhs_level,
+                rhs: rhs_level,
+            });
+        }
+        if depth != lhs_level {
+            return Err(CombineError::DepthMismatch {
+                depth,
+                level: lhs_level,
+            });
+        }
+        if depth >= SAPLING_COMMITMENT_TREE_DEPTH {
+            return Err(CombineError::AboveRoot(depth));
+        }
+        Ok(Node::combine(depth, lhs, rhs))
+    }
 }
 
 impl HeapSizeOf for Node {
-	fn heap_size_of_children(&self) -> usize {
-		256
-	}
+    fn heap_size_of_children(&self) -> usize {
+        // The field element is stored inline.
+        0
+    }
 }
 
 impl Hash for Node {
@@ -90,6 +203,11 @@ impl Hashable for Node {
     }
 
     fn combine(depth: usize, lhs: &Self, rhs: &Self) -> Self {
+        debug_assert!(
+            depth < SAPLING_COMMITMENT_TREE_DEPTH,
+            "combining nodes above the root, at depth {}",
+            depth
+        );
         Node {
             repr: merkle_hash(depth, &lhs.repr, &rhs.repr),
         }
@@ -123,6 +241,51 @@ lazy_static! {
     };
 }
 
+/// Compute the value commitment `cv` for the given value and commitment randomness.
+pub fn value_commitment(
+    value: u64,
+    rcv: Fs,
+    params: &JubjubBls12,
+) -> edwards::Point<Bls12, Unknown> {
+    ValueCommitment::<Bls12> {
+        value,
+        randomness: rcv,
+    }
+    .cm(params)
+    .into()
+}
+
+/// Compute the note commitment `cmu` of a note as a node of the commitment tree.
+pub fn note_commitment(
+    value: u64,
+    g_d: &edwards::Point<Bls12, PrimeOrder>,
+    pk_d: &edwards::Point<Bls12, PrimeOrder>,
+    rcm: Fs,
+    params: &JubjubBls12,
+) -> Node {
+    let note = Note {
+        value,
+        g_d: g_d.clone(),
+        pk_d: pk_d.clone(),
+        r: rcm,
+    };
+    Node::new(note.cm(params).into_repr())
+}
+
+/// Check that `rk` is the spend validating key `ak` randomized with `ar`.
+///
+/// A SpendDescription whose `rk` was computed with another randomizer than the one
+/// used for its spendAuthSig is rejected by the network, so wallets can use this to
+/// catch the mismatch before building the transaction.
+pub fn check_rk_consistency(
+    ak: &PublicKey<Bls12>,
+    ar: Fs,
+    rk: &PublicKey<Bls12>,
+    params: &JubjubBls12,
+) -> bool {
+    ak.randomize(ar, FixedGenerators::SpendingKeyGenerator, params).0 == rk.0
+}
+
 /// Create the spendAuthSig for a Sapling SpendDescription.
 pub fn spend_sig(
     ask: PrivateKey<Bls12>,
@@ -153,3 +316,300 @@ pub fn spend_sig(
         params,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use ff::{BitIterator, PrimeField, PrimeFieldRepr};
+    use pairing::bls12_381::{Bls12, Fr, FrRepr};
+    use rand::{thread_rng, Rand};
+    use sapling_crypto::{
+        jubjub::{
+            edwards,
+            fs::{Fs, FsRepr},
+            FixedGenerators, PrimeOrder, Unknown,
+        },
+        pedersen_hash::{pedersen_hash, Personalization},
+        primitives::{Diversifier, Note, ViewingKey},
+        redjubjub::{PrivateKey, PublicKey},
+    };
+
+    use super::{
+        check_rk_consistency, merkle_hash, spend_sig, CombineError, Node,
+        SAPLING_COMMITMENT_TREE_DEPTH, NODE_SIZE,
+    };
+    use crate::merkle_tree::Hashable;
+    use crate::test_vectors::sapling::{make_test_vectors, NoteVector};
+    use JUBJUB;
+
+    fn read_fr(bytes: &[u8; 32]) -> FrRepr {
+        let mut repr = FrRepr::default();
+        repr.read_le(&bytes[..]).unwrap();
+        repr
+    }
+
+    fn read_fs(bytes: &[u8; 32]) -> Fs {
+        let mut repr = FsRepr::default();
+        repr.read_le(&bytes[..]).unwrap();
+        Fs::from_repr(repr).unwrap()
+    }
+
+    fn read_point(bytes: &[u8; 32]) -> edwards::Point<Bls12, PrimeOrder> {
+        edwards::Point::<Bls12, Unknown>::read(&bytes[..], &JUBJUB)
+            .unwrap()
+            .as_prime_order(&JUBJUB)
+            .unwrap()
+    }
+
+    fn note(tv: &NoteVector) -> Note<Bls12> {
+        Note {
+            value: tv.v,
+            g_d: Diversifier(tv.d).g_d::<Bls12>(&JUBJUB).unwrap(),
+            pk_d: read_point(&tv.pk_d),
+            r: read_fs(&tv.rcm),
+        }
+    }
+
+    #[test]
+    fn rk_consistency() {
+        let mut rng = thread_rng();
+        let ask = PrivateKey::<Bls12>(Fs::rand(&mut rng));
+        let ak = PublicKey::from_private(&ask, FixedGenerators::SpendingKeyGenerator, &JUBJUB);
+        let ar = Fs::rand(&mut rng);
+
+        let rk = PublicKey::from_private(&ask.randomize(ar), FixedGenerators::SpendingKeyGenerator, &JUBJUB);
+        assert!(check_rk_consistency(&ak, ar, &rk, &JUBJUB));
+
+        let other_ar = Fs::rand(&mut rng);
+        assert!(!check_rk_consistency(&ak, other_ar, &rk, &JUBJUB));
+    }
+
+    #[test]
+    fn merkle_hash_test_vectors() {
+        for tv in make_test_vectors().merkle_hash {
+            let mut parent = [0u8; 32];
+            merkle_hash(tv.depth, &read_fr(&tv.lhs), &read_fr(&tv.rhs))
+                .write_le(&mut parent[..])
+                .unwrap();
+            assert_eq!(
+                parent, tv.parent,
+                "merkle_hash diverged at depth {}",
+                tv.depth
+            );
+        }
+    }
+
+    #[test]
+    fn node_le_bits_round_trip() {
+        for tv in make_test_vectors().merkle_hash {
+            let node = Node::new(read_fr(&tv.parent));
+            let bits = node.to_le_bits();
+            assert_eq!(bits.len(), Fr::NUM_BITS as usize);
+            assert_eq!(bits[0], tv.parent[0] & 1 == 1);
+            assert_eq!(bits[8], tv.parent[1] & 1 == 1);
+            assert_eq!(Node::from_le_bits(&bits), Some(node));
+        }
+        for depth in 0..4 {
+            let node = Node::empty_root(depth);
+            assert_eq!(Node::from_le_bits(&node.to_le_bits()), Some(node));
+        }
+    }
+
+    fn node_bytes(node: &Node) -> [u8; NODE_SIZE] {
+        let mut bytes = [0u8; NODE_SIZE];
+        node.write(&mut bytes[..]).unwrap();
+        bytes
+    }
+
+    fn bits_of_bytes(bytes: &[u8; NODE_SIZE]) -> Vec<bool> {
+        (0..Fr::NUM_BITS as usize)
+            .map(|i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+            .collect()
+    }
+
+    #[test]
+    fn node_bytes_are_little_endian() {
+        let node = Node::new(FrRepr([0x0807060504030201, 0, 0, 0x0011223344556677]));
+        let bytes = node_bytes(&node);
+        assert_eq!(&bytes[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&bytes[8..24], &[0u8; 16][..]);
+        assert_eq!(&bytes[24..], &[0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00]);
+        assert_eq!(Node::read(&bytes[..]).unwrap(), node);
+
+        // the same bytes read as big-endian are another number.
+        let mut be = FrRepr::default();
+        be.read_be(&bytes[..]).unwrap();
+        assert_ne!(Node::new(be), node);
+    }
+
+    #[test]
+    fn node_bits_follow_the_bytes() {
+        for tv in make_test_vectors().merkle_hash {
+            let node = Node::read(&tv.parent[..]).unwrap();
+            assert_eq!(node_bytes(&node), tv.parent);
+            assert_eq!(node.to_le_bits(), bits_of_bytes(&tv.parent));
+        }
+
+        // bit iteration is most significant first, the reverse of the hashing order.
+        let node = Node::new(FrRepr([1, 0, 0, 0]));
+        let msb_first: Vec<bool> = BitIterator::new(FrRepr([1, 0, 0, 0])).collect();
+        assert_eq!(msb_first.last(), Some(&true));
+        assert_eq!(node.to_le_bits()[0], true);
+        assert!(node.to_le_bits()[1..].iter().all(|&bit| !bit));
+    }
+
+    #[test]
+    fn merkle_hash_consumes_bits_least_significant_first() {
+        for tv in make_test_vectors().merkle_hash {
+            // bytes to bits by hand, then the hash of the concatenation.
+            let bits = bits_of_bytes(&tv.lhs)
+                .into_iter()
+                .chain(bits_of_bytes(&tv.rhs).into_iter());
+            let expected = pedersen_hash::<Bls12, _>(Personalization::MerkleTree(tv.depth), bits, &JUBJUB)
+                .into_xy()
+                .0
+                .into_repr();
+
+            assert_eq!(merkle_hash(tv.depth, &read_fr(&tv.lhs), &read_fr(&tv.rhs)), expected);
+            let lhs = Node::read(&tv.lhs[..]).unwrap();
+            let rhs = Node::read(&tv.rhs[..]).unwrap();
+            assert_eq!(node_bytes(&Node::combine(tv.depth, &lhs, &rhs)), tv.parent);
+        }
+    }
+
+    #[test]
+    fn endianness_report_shows_every_encoding() {
+        let report = Node::new(FrRepr([0x0201, 0, 0, 0])).debug_endianness_report();
+        assert_eq!(
+            report,
+            "bytes (le): 0102000000000000000000000000000000000000000000000000000000000000\n\
+             limbs (least significant first): [0x0000000000000201, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000]\n\
+             bits (least significant first): 1000000001000000..."
+        );
+    }
+
+    #[test]
+    fn quick_ne_agrees_with_eq() {
+        let nodes: Vec<_> = make_test_vectors()
+            .merkle_hash
+            .iter()
+            .map(|tv| Node::new(read_fr(&tv.parent)))
+            .chain((0..4).map(Node::empty_root))
+            .collect();
+        for a in &nodes {
+            for b in &nodes {
+                assert_eq!(a.quick_ne(b), a != b);
+            }
+        }
+
+        // differing in the low limb only.
+        let mut repr = read_fr(&make_test_vectors().merkle_hash[0].parent);
+        let node = Node::new(repr);
+        repr.0[0] ^= 1;
+        assert!(node.quick_ne(&Node::new(repr)));
+    }
+
+    #[test]
+    fn node_from_invalid_le_bits() {
+        let bits = Node::empty_root(1).to_le_bits();
+        assert_eq!(Node::from_le_bits(&bits[1..]), None);
+        let mut longer = bits.clone();
+        longer.push(false);
+        assert_eq!(Node::from_le_bits(&longer), None);
+
+        // all ones is above the field modulus.
+        assert_eq!(Node::from_le_bits(&[true; 255][..]), None);
+    }
+
+    #[test]
+    fn combine_checked_matches_combine() {
+        let leaf = Node::blank();
+        assert_eq!(
+            Node::combine_checked(0, &leaf, 0, &leaf, 0),
+            Ok(Node::empty_root(1))
+        );
+        let node = Node::empty_root(5);
+        assert_eq!(
+            Node::combine_checked(5, &node, 5, &node, 5),
+            Ok(Node::combine(5, &node, &node))
+        );
+    }
+
+    #[test]
+    fn combine_checked_rejects_wrong_depth() {
+        let node = Node::empty_root(3);
+        // the classic off-by-one, hashing with the level of the parent.
+        assert_eq!(
+            Node::combine_checked(4, &node, 3, &node, 3),
+            Err(CombineError::DepthMismatch { depth: 4, level: 3 })
+        );
+        assert_eq!(
+            Node::combine_checked(3, &node, 3, &Node::empty_root(2), 2),
+            Err(CombineError::SiblingLevelMismatch { lhs: 3, rhs: 2 })
+        );
+
+        let top = SAPLING_COMMITMENT_TREE_DEPTH;
+        let root = Node::empty_root(top);
+        assert_eq!(
+            Node::combine_checked(top, &root, top, &root, top),
+            Err(CombineError::AboveRoot(top))
+        );
+    }
+
+    #[test]
+    fn note_commitment_test_vectors() {
+        for (i, tv) in make_test_vectors().notes.iter().enumerate() {
+            let mut cm = [0u8; 32];
+            note(tv).cm(&JUBJUB).into_repr().write_le(&mut cm[..]).unwrap();
+            assert_eq!(cm, tv.cm, "note commitment diverged for notes[{}]", i);
+        }
+    }
+
+    #[test]
+    fn nullifier_test_vectors() {
+        for (i, tv) in make_test_vectors().notes.iter().enumerate() {
+            let vk = ViewingKey {
+                ak: read_point(&tv.ak),
+                nk: read_point(&tv.nk),
+            };
+            let nf = note(tv).nf(&vk, tv.pos, &JUBJUB);
+            assert_eq!(&nf[..], &tv.nf[..], "nullifier diverged for notes[{}]", i);
+        }
+    }
+
+    #[test]
+    fn spend_sig_test_vectors() {
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+        for (i, tv) in make_test_vectors().spend_sig.iter().enumerate() {
+            let ask = PrivateKey::<Bls12>(read_fs(&tv.ask));
+            let ak = PublicKey::from_private(&ask, p_g, &JUBJUB);
+            let mut ak_bytes = [0u8; 32];
+            ak.write(&mut ak_bytes[..]).unwrap();
+            assert_eq!(
+                ak_bytes, tv.ak,
+                "spend authorizing key diverged for spend_sig[{}]",
+                i
+            );
+
+            let ar = read_fs(&tv.ar);
+            let sig = spend_sig(ask, ar, &tv.sighash, &JUBJUB);
+
+            // The signature is over rk || sighash, rk being ak randomized by ar.
+            let rk = ak.randomize(ar, p_g, &JUBJUB);
+            let mut msg = [0u8; 64];
+            rk.write(&mut msg[0..32]).unwrap();
+            msg[32..64].copy_from_slice(&tv.sighash[..]);
+            assert!(
+                rk.verify(&msg, &sig, p_g, &JUBJUB),
+                "spend_sig does not verify for spend_sig[{}]",
+                i
+            );
+
+            msg[63] ^= 1;
+            assert!(
+                !rk.verify(&msg, &sig, p_g, &JUBJUB),
+                "spend_sig verifies another sighash for spend_sig[{}]",
+                i
+            );
+        }
+    }
+}
@@ -1 +1,2 @@
 pub(crate) mod note_encryption;
+pub(crate) mod sapling;
@@ -0,0 +1,137 @@
+//! Loader for the Sapling test vectors bundled in `sapling.json`.
+//!
+//! The note vectors are from
+//! https://github.com/zcash-hackworks/zcash-test-vectors/blob/master/sapling_key_components.py,
+//! the Merkle vectors hash the empty roots of the Sapling commitment tree, and the
+//! spend authorization vectors pair the spending keys of the former with fixed
+//! randomizers and sighashes.
+
+extern crate hex;
+extern crate serde_json;
+
+const SAPLING_JSON: &str = include_str!("sapling.json");
+
+pub(crate) struct MerkleHashVector {
+    pub depth: usize,
+    pub lhs: [u8; 32],
+    pub rhs: [u8; 32],
+    pub parent: [u8; 32],
+}
+
+pub(crate) struct NoteVector {
+    pub d: [u8; 11],
+    pub pk_d: [u8; 32],
+    pub v: u64,
+    pub rcm: [u8; 32],
+    pub ak: [u8; 32],
+    pub nk: [u8; 32],
+    pub pos: u64,
+    pub cm: [u8; 32],
+    pub nf: [u8; 32],
+}
+
+pub(crate) struct SpendSigVector {
+    pub ask: [u8; 32],
+    pub ak: [u8; 32],
+    pub ar: [u8; 32],
+    pub sighash: [u8; 32],
+}
+
+pub(crate) struct TestVectors {
+    pub merkle_hash: Vec<MerkleHashVector>,
+    pub notes: Vec<NoteVector>,
+    pub spend_sig: Vec<SpendSigVector>,
+}
+
+pub(crate) fn make_test_vectors() -> TestVectors {
+    let json: serde_json::Value =
+        serde_json::from_str(SAPLING_JSON).expect("sapling.json should be valid JSON");
+
+    let merkle_hash = vectors(&json, "merkle_hash")
+        .into_iter()
+        .map(|(path, v)| MerkleHashVector {
+            depth: read_u64(v, &path, "depth") as usize,
+            lhs: read_32(v, &path, "lhs"),
+            rhs: read_32(v, &path, "rhs"),
+            parent: read_32(v, &path, "parent"),
+        })
+        .collect();
+
+    let notes = vectors(&json, "notes")
+        .into_iter()
+        .map(|(path, v)| {
+            let mut d = [0u8; 11];
+            d.copy_from_slice(&read_bytes(v, &path, "d", 11));
+            NoteVector {
+                d,
+                pk_d: read_32(v, &path, "pk_d"),
+                v: read_u64(v, &path, "v"),
+                rcm: read_32(v, &path, "rcm"),
+                ak: read_32(v, &path, "ak"),
+                nk: read_32(v, &path, "nk"),
+                pos: read_u64(v, &path, "pos"),
+                cm: read_32(v, &path, "cm"),
+                nf: read_32(v, &path, "nf"),
+            }
+        })
+        .collect();
+
+    let spend_sig = vectors(&json, "spend_sig")
+        .into_iter()
+        .map(|(path, v)| SpendSigVector {
+            ask: read_32(v, &path, "ask"),
+            ak: read_32(v, &path, "ak"),
+            ar: read_32(v, &path, "ar"),
+            sighash: read_32(v, &path, "sighash"),
+        })
+        .collect();
+
+    TestVectors {
+        merkle_hash,
+        notes,
+        spend_sig,
+    }
+}
+
+/// Returns the vectors of the named group, each with its path for error messages.
+fn vectors<'a>(json: &'a serde_json::Value, group: &str) -> Vec<(String, &'a serde_json::Value)> {
+    let vectors = json[group]
+        .as_array()
+        .unwrap_or_else(|| panic!("sapling.json: `{}` should be an array", group));
+    assert!(!vectors.is_empty(), "sapling.json: `{}` should not be empty", group);
+
+    vectors
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (format!("{}[{}]", group, i), v))
+        .collect()
+}
+
+fn read_u64(v: &serde_json::Value, path: &str, field: &str) -> u64 {
+    v[field]
+        .as_u64()
+        .unwrap_or_else(|| panic!("sapling.json: {}.{} should be an unsigned integer", path, field))
+}
+
+fn read_bytes(v: &serde_json::Value, path: &str, field: &str, len: usize) -> Vec<u8> {
+    let s = v[field]
+        .as_str()
+        .unwrap_or_else(|| panic!("sapling.json: {}.{} should be a hex string", path, field));
+    let bytes = hex::decode(s)
+        .unwrap_or_else(|e| panic!("sapling.json: {}.{} is not valid hex: {:?}", path, field, e));
+    assert_eq!(
+        bytes.len(),
+        len,
+        "sapling.json: {}.{} should be {} bytes",
+        path,
+        field,
+        len
+    );
+    bytes
+}
+
+fn read_32(v: &serde_json::Value, path: &str, field: &str) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&read_bytes(v, path, field, 32));
+    out
+}
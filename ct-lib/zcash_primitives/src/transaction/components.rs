@@ -22,6 +22,69 @@ const ZC_NUM_JS_OUTPUTS: usize = 2;
 const COIN: i64 = 1_0000_0000;
 const MAX_MONEY: i64 = 21_000_000 * COIN;
 
+/// Returns whether `bytes` is the canonical little-endian encoding of an `Fr`, that is
+/// below the field modulus.
+///
+/// Anchors and note commitments received from peers must be canonical: an encoding of
+/// `x + r` names the same element as `x`, and implementations which reduce it instead
+/// of rejecting it would disagree on the validity of the transaction.
+pub fn validate_canonical(bytes: &[u8; 32]) -> bool {
+	let mut repr = FrRepr::default();
+	repr.read_le(&bytes[..]).is_ok() && Fr::from_repr(repr).is_ok()
+}
+
+/// Read a field element from an RLP item. `Fr::from_repr` rejects the encodings which
+/// are not below the field modulus, so only canonical encodings are accepted.
+fn decode_canonical_fr(bytes: &[u8], what: &'static str) -> Result<Fr, DecoderError> {
+	if bytes.len() != 32 {
+		return Err(DecoderError::Custom(what));
+	}
+	let mut repr = FrRepr::default();
+	repr.read_le(bytes).map_err(|_| DecoderError::Custom(what))?;
+	Fr::from_repr(repr).map_err(|_| DecoderError::Custom(what))
+}
+
+/// Read a point from an RLP item.
+///
+/// Non-canonical encodings are accepted, since blocks from before the fork which requires
+/// canonical points may hold them; see `is_canonical_point_encoding`.
+fn decode_point(bytes: &[u8], what: &'static str) -> Result<edwards::Point<Bls12, Unknown>, DecoderError> {
+	edwards::Point::<Bls12, Unknown>::read(bytes, &JUBJUB).map_err(|_| DecoderError::Custom(what))
+}
+
+/// Returns whether `bytes` is the encoding a Jubjub point is written back as.
+///
+/// Only the points with `x = 0`, that is `y = 1` and `y = -1`, have two encodings: their
+/// sign bit is meaningless and must be clear. This checks the bytes alone, so that it
+/// costs no second decompression of a point which was already read.
+pub fn is_canonical_point_encoding(bytes: &[u8]) -> bool {
+	if bytes.len() != 32 {
+		return false;
+	}
+	if bytes[31] & 0x80 == 0 {
+		return true;
+	}
+	let mut y = FrRepr::default();
+	let mut unsigned = [0u8; 32];
+	unsigned.copy_from_slice(bytes);
+	unsigned[31] &= 0x7f;
+	if y.read_le(&unsigned[..]).is_err() {
+		return false;
+	}
+	let mut minus_one = Fr::char();
+	minus_one.sub_noborrow(&FrRepr::from(1));
+	y != FrRepr::from(1) && y != minus_one
+}
+
+// Returns whether the items at `indices` of `d` are canonical point encodings.
+fn has_canonical_points_at(d: &Rlp, indices: &[usize]) -> bool {
+	indices.iter().all(|&i| {
+		d.at(i)
+			.and_then(|item| item.data().map(is_canonical_point_encoding))
+			.unwrap_or(false)
+	})
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Amount(pub i64);
 
@@ -183,22 +246,10 @@ impl rlp::Decodable for SpendDescription {
 			return Err(DecoderError::RlpIncorrectListLen);
 		}
 		let vec: Vec<u8> = d.val_at(0)?;
-		let cv = edwards::Point::<Bls12, Unknown>::read(&mut &vec[..], &JUBJUB);
-		let cv = match cv {
-			Ok(v) => v,
-			Err(_) => return Err(DecoderError::Custom("Invalid Point Rlp.")),
-		};
+		let cv = decode_point(&vec, "Invalid Point Rlp.")?;
 
-		let mut fr_repr = FrRepr::default();
 		let anchor_vec: Vec<u8> = d.val_at(1)?;
-		if anchor_vec.len() != 32 {
-			return Err(DecoderError::Custom("Invalid Anchor Rlp."));
-		}
-		fr_repr.read_le(&mut &anchor_vec[..]);
-		let anchor = match Fr::from_repr(fr_repr) {
-			Ok(fr) => fr,
-			Err(_) => return Err(DecoderError::Custom("Invalid prime field for anchor.")),
-		};
+		let anchor = decode_canonical_fr(&anchor_vec, "Invalid prime field for anchor.")?;
 
 		let mut nullifier: [u8; 32] = [0; 32];
 		let nullifier_vec: Vec<u8> = d.val_at(2)?;
@@ -208,11 +259,7 @@ impl rlp::Decodable for SpendDescription {
 		nullifier.copy_from_slice(&nullifier_vec);
 
 		let vec: Vec<u8> = d.val_at(3)?;
-		let rk = edwards::Point::<Bls12, Unknown>::read(&mut &vec[..], &JUBJUB);
-		let rk = match rk {
-			Ok(v) => v,
-			Err(_) => return Err(DecoderError::Custom("Invalid Point Rlp.")),
-		};
+		let rk = decode_point(&vec, "Invalid Point Rlp.")?;
 		let rk_pubkey = PublicKey::<Bls12>(rk);
 
 		let mut zkproof: [u8; GROTH_PROOF_SIZE] = [0; GROTH_PROOF_SIZE];
@@ -246,6 +293,12 @@ impl rlp::Encodable for SpendDescription {
 }
 
 impl SpendDescription {
+	/// Returns whether the points of the RLP encoded spend description `d` are
+	/// canonically encoded, see `is_canonical_point_encoding`.
+	pub fn has_canonical_points(d: &Rlp) -> bool {
+		has_canonical_points_at(d, &[0, 3])
+	}
+
 	fn rlp_append(&self, s: &mut RlpStream, include_sig: bool) {
 		s.begin_list(6);
 		let mut cv_vec = Vec::new();
@@ -368,29 +421,13 @@ impl rlp::Decodable for OutputDescription {
 			return Err(DecoderError::RlpIncorrectListLen);
 		}
 		let vec: Vec<u8> = d.val_at(0)?;
-		let cv = edwards::Point::<Bls12, Unknown>::read(&mut &vec[..], &JUBJUB);
-		let cv = match cv {
-			Ok(v) => v,
-			Err(_) => return Err(DecoderError::Custom("Invaild Point Rlp")),
-		};
+		let cv = decode_point(&vec, "Invaild Point Rlp")?;
 
-		let mut fr_repr = FrRepr::default();
 		let cmu_vec: Vec<u8> = d.val_at(1)?;
-		if cmu_vec.len() != 32 {
-			return Err(DecoderError::Custom("Invalid cmu Rlp."));
-		}
-		fr_repr.read_le(&mut &cmu_vec[..]);
-		let cmu = match Fr::from_repr(fr_repr) {
-			Ok(fr) => fr,
-			Err(_) => return Err(DecoderError::Custom("Invalid prime field for cmu.")),
-		};
+		let cmu = decode_canonical_fr(&cmu_vec, "Invalid prime field for cmu.")?;
 
 		let vec: Vec<u8> = d.val_at(2)?;
-		let ephemeral_key = edwards::Point::<Bls12, Unknown>::read(&mut &vec[..], &JUBJUB);
-		let ephemeral_key = match ephemeral_key {
-			Ok(v) => v,
-			Err(_) => return Err(DecoderError::Custom("Invaild Ephemeral_Key Rlp")),
-		};
+		let ephemeral_key = decode_point(&vec, "Invaild Ephemeral_Key Rlp")?;
 
 		let mut enc_ciphertext: [u8; 580] = [0; 580];
 		let enc_ciphertext_vec: Vec<u8> = d.val_at(3)?;
@@ -444,6 +481,12 @@ impl rlp::Encodable for OutputDescription {
 }
 
 impl OutputDescription {
+	/// Returns whether the points of the RLP encoded output description `d` are
+	/// canonically encoded, see `is_canonical_point_encoding`.
+	pub fn has_canonical_points(d: &Rlp) -> bool {
+		has_canonical_points_at(d, &[0, 2])
+	}
+
 	pub fn read<R: Read>(mut reader: &mut R) -> io::Result<Self> {
 		// Consensus rules (§4.5):
 		// - Canonical encoding is enforced here.
@@ -710,6 +753,96 @@ mod tests {
 		assert_eq!(spend, spend2);
 	}
 
+	fn modulus_plus(n: u64) -> [u8; 32] {
+		let mut repr = Fr::char();
+		repr.add_nocarry(&FrRepr::from(n));
+		let mut bytes = [0u8; 32];
+		repr.write_le(&mut bytes[..]).unwrap();
+		bytes
+	}
+
+	#[test]
+	fn rejects_non_canonical_field_elements() {
+		let mut below = Fr::char();
+		below.sub_noborrow(&FrRepr::from(1));
+		let mut below_bytes = [0u8; 32];
+		below.write_le(&mut below_bytes[..]).unwrap();
+		assert!(validate_canonical(&below_bytes));
+		assert!(validate_canonical(&[0u8; 32]));
+
+		assert!(!validate_canonical(&modulus_plus(0)));
+		assert!(!validate_canonical(&modulus_plus(1)));
+		assert!(!validate_canonical(&[0xffu8; 32]));
+	}
+
+	#[test]
+	fn rejects_descriptions_with_non_canonical_encodings() {
+		let output = OutputDescription {
+			cv: edwards::Point::zero(),
+			cmu: Fr::from_repr(FrRepr::from(1)).unwrap(),
+			ephemeral_key: edwards::Point::zero(),
+			enc_ciphertext: [0u8; 580],
+			out_ciphertext: [0u8; 80],
+			zkproof: [0u8; GROTH_PROOF_SIZE],
+		};
+		let mut cv = Vec::new();
+		output.cv.write(&mut cv).unwrap();
+		// the identity with the sign bit set is read as the identity.
+		let mut signed_cv = cv.clone();
+		signed_cv[31] |= 0x80;
+		assert!(edwards::Point::<Bls12, Unknown>::read(&signed_cv[..], &JUBJUB).is_ok());
+
+		let encode = |cv: &[u8], cmu: &[u8]| {
+			let mut s = RlpStream::new_list(6);
+			s.append(&cv);
+			s.append(&cmu);
+			s.append(&cv);
+			s.append(&&output.enc_ciphertext[..]);
+			s.append(&&output.out_ciphertext[..]);
+			s.append(&&output.zkproof[..]);
+			s.out()
+		};
+
+		let mut one = [0u8; 32];
+		one[0] = 1;
+		let decoded: OutputDescription = rlp::decode(&encode(&cv, &one)).unwrap();
+		assert_eq!(decoded, output);
+
+		assert!(OutputDescription::has_canonical_points(&Rlp::new(&encode(&cv, &one))));
+
+		// r + 1 names the same element as 1.
+		assert!(rlp::decode::<OutputDescription>(&encode(&cv, &modulus_plus(1))).is_err());
+		// non-canonical points are still read, it is up to the caller to reject them.
+		let signed = encode(&signed_cv, &one);
+		assert_eq!(rlp::decode::<OutputDescription>(&signed).unwrap(), output);
+		assert!(!OutputDescription::has_canonical_points(&Rlp::new(&signed)));
+	}
+
+	#[test]
+	fn only_points_with_zero_x_have_non_canonical_encodings() {
+		let mut one = [0u8; 32];
+		one[0] = 1;
+		let mut minus_one = Fr::char();
+		minus_one.sub_noborrow(&FrRepr::from(1));
+		let mut minus_one_bytes = [0u8; 32];
+		minus_one.write_le(&mut minus_one_bytes[..]).unwrap();
+		let mut two = [0u8; 32];
+		two[0] = 2;
+
+		for y in &[one, minus_one_bytes, two] {
+			assert!(is_canonical_point_encoding(&y[..]));
+		}
+		for y in &[one, minus_one_bytes] {
+			let mut signed = *y;
+			signed[31] |= 0x80;
+			assert!(!is_canonical_point_encoding(&signed[..]));
+		}
+		let mut signed_two = two;
+		signed_two[31] |= 0x80;
+		assert!(is_canonical_point_encoding(&signed_two[..]));
+		assert!(!is_canonical_point_encoding(&one[..31]));
+	}
+
 	#[test]
 	fn encode_decode_output_description() {
 		let output = OutputDescription {
@@ -5,7 +5,7 @@ use ff::Field;
 use fpe::ff1::{BinaryNumeralString, FF1};
 use pairing::bls12_381::Bls12;
 use sapling_crypto::{
-    jubjub::{fs::Fs, FixedGenerators, JubjubEngine, JubjubParams, ToUniform},
+    jubjub::{fs::Fs, FixedGenerators, JubjubBls12, JubjubEngine, JubjubParams, ToUniform},
     primitives::{Diversifier, PaymentAddress, ViewingKey},
 };
 use std::io::{self, Read, Write};
@@ -26,6 +26,12 @@ fn derive_child_ovk(parent: &OutgoingViewingKey, i_l: &[u8]) -> OutgoingViewingK
     OutgoingViewingKey(ovk)
 }
 
+/// Returns true if the diversifier hashes to a valid group element, without
+/// deriving the corresponding payment address.
+pub fn is_valid_diversifier(d: &[u8; 11], params: &JubjubBls12) -> bool {
+    Diversifier(*d).g_d::<Bls12>(params).is_some()
+}
+
 // ZIP 32 structures
 
 /// A Sapling full viewing key fingerprint
@@ -148,13 +154,11 @@ impl DiversifierKey {
             let d_j = Diversifier(d_j);
 
             // Return (j, d_j) if valid, else increment j and try again
-            match d_j.g_d::<Bls12>(&JUBJUB) {
-                Some(_) => return Ok((j, d_j)),
-                None => {
-                    if j.increment().is_err() {
-                        return Err(());
-                    }
-                }
+            if is_valid_diversifier(&d_j.0, &JUBJUB) {
+                return Ok((j, d_j));
+            }
+            if j.increment().is_err() {
+                return Err(());
             }
         }
     }
@@ -539,6 +543,23 @@ mod tests {
         assert_eq!(d_j.0, d_3);
     }
 
+    #[test]
+    fn valid_diversifier() {
+        let d_0 = [220, 231, 126, 188, 236, 10, 38, 175, 214, 153, 140];
+        let d_3 = [60, 253, 170, 8, 171, 147, 220, 31, 3, 144, 34];
+        assert!(is_valid_diversifier(&d_0, &JUBJUB));
+        assert!(is_valid_diversifier(&d_3, &JUBJUB));
+
+        let mut found_invalid = false;
+        for i in 0..=255u8 {
+            let d = [i, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            let valid = is_valid_diversifier(&d, &JUBJUB);
+            assert_eq!(valid, Diversifier(d).g_d::<Bls12>(&JUBJUB).is_some());
+            found_invalid |= !valid;
+        }
+        assert!(found_invalid);
+    }
+
     #[test]
     fn default_address() {
         let seed = [0; 32];
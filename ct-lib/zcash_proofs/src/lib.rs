@@ -3,11 +3,15 @@ extern crate blake2_rfc;
 extern crate byteorder;
 extern crate directories;
 extern crate ff;
+extern crate lru_cache;
 extern crate pairing;
 extern crate rand;
 extern crate sapling_crypto;
 extern crate zcash_primitives;
 
+#[cfg(test)]
+extern crate group;
+
 use bellman::groth16::{prepare_verifying_key, Parameters, PreparedVerifyingKey, VerifyingKey};
 use pairing::bls12_381::Bls12;
 use std::fs::File;
@@ -0,0 +1,270 @@
+//! Bounded cache of Groth16 proof verification results.
+//!
+//! The same proof may be verified more than once, for example when a transaction
+//! leaves a block retracted by a reorg and enters the pool again. Results are keyed
+//! by a hash of the circuit, its verifying key, the proof and its public inputs.
+//!
+//! The cache also remembers the transactions whose proofs and signatures all passed,
+//! so that the pool and block import skip them as a whole.
+
+use bellman::groth16::{Proof, VerifyingKey};
+use blake2_rfc::blake2b::Blake2b;
+use ff::{PrimeField, PrimeFieldRepr};
+use lru_cache::LruCache;
+use pairing::bls12_381::{Bls12, Fr};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+const PROOF_CACHE_PERSONALIZATION: &'static [u8; 16] = b"Origo_ProofCache";
+const VERIFYING_KEY_PERSONALIZATION: &'static [u8; 16] = b"Origo_VerifyKey_";
+
+/// Default number of verification results kept by a `ProofCache`.
+pub const DEFAULT_PROOF_CACHE_SIZE: usize = 4096;
+
+/// A `ProofCache` shared between verification contexts.
+pub type SharedProofCache = Arc<Mutex<ProofCache>>;
+
+/// The circuit a proof was created for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Circuit {
+    Spend,
+    Output,
+}
+
+/// Hash a verifying key, so that a cache can tell when the keys change.
+pub fn verifying_key_id(vk: &VerifyingKey<Bls12>) -> [u8; 32] {
+    let mut bytes = vec![];
+    vk.write(&mut bytes)
+        .expect("writing to a vector should not fail");
+
+    let mut h = Blake2b::with_params(32, &[], &[], VERIFYING_KEY_PERSONALIZATION);
+    h.update(&bytes);
+    let mut id = [0u8; 32];
+    id.copy_from_slice(h.finalize().as_bytes());
+    id
+}
+
+/// Least recently used cache of proof verification results, for one pair of spend
+/// and output verifying keys.
+pub struct ProofCache {
+    spend_vk: [u8; 32],
+    output_vk: [u8; 32],
+    results: LruCache<[u8; 32], bool>,
+    transactions: LruCache<[u8; 32], ()>,
+}
+
+impl ProofCache {
+    /// Create a cache holding up to `capacity` results and as many verified
+    /// transactions, for the verifying keys with the given `verifying_key_id`s.
+    pub fn new(capacity: usize, spend_vk: [u8; 32], output_vk: [u8; 32]) -> Self {
+        ProofCache {
+            spend_vk,
+            output_vk,
+            results: LruCache::new(capacity),
+            transactions: LruCache::new(capacity),
+        }
+    }
+
+    /// Switch to other verifying keys, dropping every result and verified transaction
+    /// if they differ from the current ones.
+    pub fn set_verifying_keys(&mut self, spend_vk: [u8; 32], output_vk: [u8; 32]) {
+        if spend_vk != self.spend_vk || output_vk != self.output_vk {
+            self.results.clear();
+            self.transactions.clear();
+            self.spend_vk = spend_vk;
+            self.output_vk = output_vk;
+        }
+    }
+
+    /// The key under which the result of verifying `proof` is cached.
+    pub fn key(&self, circuit: Circuit, proof: &Proof<Bls12>, public_inputs: &[Fr]) -> [u8; 32] {
+        let mut h = Blake2b::with_params(32, &[], &[], PROOF_CACHE_PERSONALIZATION);
+        match circuit {
+            Circuit::Spend => {
+                h.update(&[0]);
+                h.update(&self.spend_vk);
+            }
+            Circuit::Output => {
+                h.update(&[1]);
+                h.update(&self.output_vk);
+            }
+        }
+
+        let mut bytes = vec![];
+        proof
+            .write(&mut bytes)
+            .expect("writing to a vector should not fail");
+        h.update(&bytes);
+
+        for input in public_inputs {
+            let mut repr = [0u8; 32];
+            input
+                .into_repr()
+                .write_le(&mut repr[..])
+                .expect("field elements are 32 bytes");
+            h.update(&repr);
+        }
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(h.finalize().as_bytes());
+        key
+    }
+
+    /// Get a cached result, marking it as recently used.
+    pub fn get(&mut self, key: &[u8; 32]) -> Option<bool> {
+        self.results.get_mut(key).map(|result| *result)
+    }
+
+    /// Cache a result, evicting the least recently used one if the cache is full.
+    pub fn insert(&mut self, key: [u8; 32], result: bool) {
+        self.results.insert(key, result);
+    }
+
+    /// Whether every proof and signature of the transaction with the given hash
+    /// passed, marking it as recently used.
+    ///
+    /// The hash must cover the whole signed transaction. Its anchors and nullifiers
+    /// depend on the chain, so they are not covered and must be checked every time.
+    pub fn is_transaction_verified(&mut self, hash: &[u8; 32]) -> bool {
+        self.transactions.get_mut(hash).is_some()
+    }
+
+    /// Record that every proof and signature of the transaction with the given hash
+    /// passed, evicting the least recently used transaction if the cache is full.
+    pub fn insert_verified_transaction(&mut self, hash: [u8; 32]) {
+        self.transactions.insert(hash, ());
+    }
+
+    /// Number of cached results.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Whether no result is cached.
+    pub fn is_empty(&self) -> bool {
+        self.results.len() == 0
+    }
+}
+
+impl fmt::Debug for ProofCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProofCache")
+            .field("len", &self.results.len())
+            .field("capacity", &self.results.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellman::groth16::Proof;
+    use ff::PrimeField;
+    use group::CurveAffine;
+    use pairing::bls12_381::{Bls12, Fr, G1Affine, G2Affine};
+
+    use super::{Circuit, ProofCache};
+
+    fn proof() -> Proof<Bls12> {
+        Proof {
+            a: G1Affine::one(),
+            b: G2Affine::one(),
+            c: G1Affine::one(),
+        }
+    }
+
+    /// Stands in for `verify_proof`, accepting the public inputs which are even.
+    fn fresh_verification(input: u64, calls: &mut usize) -> bool {
+        *calls += 1;
+        input % 2 == 0
+    }
+
+    fn verify(cache: &mut ProofCache, circuit: Circuit, input: u64, calls: &mut usize) -> bool {
+        let inputs = [Fr::from_str(&input.to_string()).unwrap()];
+        let key = cache.key(circuit, &proof(), &inputs);
+        match cache.get(&key) {
+            Some(result) => result,
+            None => {
+                let result = fresh_verification(input, calls);
+                cache.insert(key, result);
+                result
+            }
+        }
+    }
+
+    #[test]
+    fn hit_matches_fresh_verification() {
+        let mut cache = ProofCache::new(4, [1; 32], [2; 32]);
+        let mut calls = 0;
+
+        for input in 1..3 {
+            let fresh = verify(&mut cache, Circuit::Spend, input, &mut calls);
+            let hit = verify(&mut cache, Circuit::Spend, input, &mut calls);
+            assert_eq!(hit, fresh);
+        }
+        assert_eq!(calls, 2);
+
+        // the same proof for the other circuit is another entry.
+        verify(&mut cache, Circuit::Output, 1, &mut calls);
+        assert_eq!(calls, 3);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn key_depends_on_proof() {
+        let cache = ProofCache::new(4, [1; 32], [2; 32]);
+        let inputs = [Fr::from_str("7").unwrap()];
+        let mut other = proof();
+        other.c = G1Affine::zero();
+        assert_ne!(
+            cache.key(Circuit::Spend, &proof(), &inputs),
+            cache.key(Circuit::Spend, &other, &inputs)
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ProofCache::new(2, [1; 32], [2; 32]);
+        let mut calls = 0;
+        verify(&mut cache, Circuit::Spend, 1, &mut calls);
+        verify(&mut cache, Circuit::Spend, 2, &mut calls);
+        // use the first one, so that the second one is evicted.
+        verify(&mut cache, Circuit::Spend, 1, &mut calls);
+        verify(&mut cache, Circuit::Spend, 3, &mut calls);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(calls, 3);
+
+        verify(&mut cache, Circuit::Spend, 1, &mut calls);
+        assert_eq!(calls, 3);
+        verify(&mut cache, Circuit::Spend, 2, &mut calls);
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn changing_verifying_keys_drops_results() {
+        let mut cache = ProofCache::new(4, [1; 32], [2; 32]);
+        let mut calls = 0;
+        verify(&mut cache, Circuit::Spend, 1, &mut calls);
+
+        cache.set_verifying_keys([1; 32], [2; 32]);
+        assert_eq!(cache.len(), 1);
+
+        cache.set_verifying_keys([3; 32], [2; 32]);
+        assert!(cache.is_empty());
+        verify(&mut cache, Circuit::Spend, 1, &mut calls);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn changing_verifying_keys_drops_verified_transactions() {
+        let mut cache = ProofCache::new(4, [1; 32], [2; 32]);
+        cache.insert_verified_transaction([5; 32]);
+        assert!(cache.is_transaction_verified(&[5; 32]));
+        assert!(!cache.is_transaction_verified(&[6; 32]));
+
+        cache.set_verifying_keys([1; 32], [2; 32]);
+        assert!(cache.is_transaction_verified(&[5; 32]));
+
+        cache.set_verifying_keys([1; 32], [3; 32]);
+        assert!(!cache.is_transaction_verified(&[5; 32]));
+    }
+}
@@ -3,11 +3,18 @@ use sapling_crypto::jubjub::{
     edwards, fs::FsRepr, FixedGenerators, JubjubBls12, JubjubParams, Unknown,
 };
 
+mod cache;
 mod prover;
 mod verifier;
 
+pub use self::cache::{
+    verifying_key_id, Circuit, ProofCache, SharedProofCache, DEFAULT_PROOF_CACHE_SIZE,
+};
 pub use self::prover::{ SaplingProvingContext};
-pub use self::verifier::SaplingVerificationContext;
+pub use self::verifier::{
+    output_public_inputs, spend_nullifier_matches_proof, spend_public_inputs,
+    verify_binding_signature, SaplingVerificationContext, ShieldedError,
+};
 
 // This function computes `value` in the exponent of the value commitment base
 fn compute_value_balance(
@@ -37,3 +44,81 @@ fn compute_value_balance(
     // Convert to unknown order point
     Some(value_balance.into())
 }
+
+/// Error returned by [`assert_value_conservation`].
+#[derive(Debug, PartialEq)]
+pub enum ConservationError {
+    /// A sum of values does not fit in 64 bits.
+    Overflow,
+    /// The spent value differs from the created value, by the given amount
+    /// (spent minus created).
+    Imbalance(i128),
+}
+
+/// Checks that the values of the spent notes match the values leaving the shielded
+/// pool: `inputs = outputs + fee + transparent_delta`, where a positive
+/// `transparent_delta` is value moved to the transparent side and a negative one is
+/// value brought in from it.
+///
+/// This is the equation the binding signature enforces, as plain checked arithmetic.
+pub fn assert_value_conservation(
+    inputs: &[u64],
+    outputs: &[u64],
+    transparent_delta: i64,
+    fee: u64,
+) -> Result<(), ConservationError> {
+    let sum = |values: &[u64]| {
+        values
+            .iter()
+            .try_fold(0u64, |acc, v| acc.checked_add(*v))
+            .ok_or(ConservationError::Overflow)
+    };
+
+    let spent = sum(inputs)? as i128;
+    let created = sum(outputs)?
+        .checked_add(fee)
+        .ok_or(ConservationError::Overflow)? as i128
+        + transparent_delta as i128;
+
+    if spent == created {
+        Ok(())
+    } else {
+        Err(ConservationError::Imbalance(spent - created))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_value_conservation, ConservationError};
+
+    #[test]
+    fn value_conservation() {
+        // shielded to shielded
+        assert_eq!(assert_value_conservation(&[300], &[200, 90], 0, 10), Ok(()));
+        // shielded to transparent
+        assert_eq!(assert_value_conservation(&[300], &[100], 200, 0), Ok(()));
+        // transparent to shielded
+        assert_eq!(assert_value_conservation(&[], &[250], -250, 0), Ok(()));
+
+        assert_eq!(
+            assert_value_conservation(&[300], &[200], 0, 10),
+            Err(ConservationError::Imbalance(90))
+        );
+        assert_eq!(
+            assert_value_conservation(&[100], &[200], 0, 0),
+            Err(ConservationError::Imbalance(-100))
+        );
+    }
+
+    #[test]
+    fn value_conservation_overflow() {
+        assert_eq!(
+            assert_value_conservation(&[u64::max_value(), 1], &[], 0, 0),
+            Err(ConservationError::Overflow)
+        );
+        assert_eq!(
+            assert_value_conservation(&[], &[u64::max_value()], 0, 1),
+            Err(ConservationError::Overflow)
+        );
+    }
+}
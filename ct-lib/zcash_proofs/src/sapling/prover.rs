@@ -196,6 +196,26 @@ impl SaplingProvingContext {
         // blinding factor `bsk` is accumulated in the context.
         let rcv = Fs::rand(&mut rng);
 
+        self.output_proof_with_rcv(esk, payment_address, rcm, rcv, value, proving_key, params)
+    }
+
+    /// Create the value commitment and proof for a Sapling OutputDescription,
+    /// using the caller-provided value commitment randomness `rcv`, while
+    /// accumulating its value commitment randomness and value commitment
+    /// into the context.
+    pub fn output_proof_with_rcv(
+        &mut self,
+        esk: Fs,
+        payment_address: PaymentAddress<Bls12>,
+        rcm: Fs,
+        rcv: Fs,
+        value: u64,
+        proving_key: &Parameters<Bls12>,
+        params: &JubjubBls12,
+    ) -> (Proof<Bls12>, edwards::Point<Bls12, Unknown>) {
+        // Initialize secure RNG
+        let mut rng = OsRng::new().expect("should be able to construct RNG");
+
         // Accumulate the value commitment randomness in the context
         {
             let mut tmp = rcv.clone();
@@ -1,21 +1,151 @@
 use bellman::groth16::{verify_proof, PreparedVerifyingKey, Proof};
-use ff::Field;
 use pairing::bls12_381::{Bls12, Fr};
+use std::error;
+use std::fmt;
 use sapling_crypto::{
     circuit::multipack,
     jubjub::{edwards, FixedGenerators, JubjubBls12, Unknown},
     redjubjub::{PublicKey, Signature},
 };
 
+use zcash_primitives::transaction::components::SpendDescription;
+
+use super::cache::{Circuit, SharedProofCache};
 use super::compute_value_balance;
 
+/// Reason why a shielded component fails verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShieldedError {
+    /// The zero-knowledge proof does not verify against the public inputs.
+    InvalidProof,
+    /// The spend authorization signature does not verify.
+    BadSignature,
+    /// The anchor of a spend is not the root of a known commitment tree.
+    UnknownAnchor,
+    /// The nullifier of a spend was already revealed.
+    DoubleSpend,
+    /// The value commitments do not balance with the value balance, so the binding
+    /// signature does not verify, or the value balance is out of range.
+    ValueBalance,
+    /// A value commitment, key or public key of a description has small order.
+    MalformedDescription,
+    /// A proof, signature or point is not validly encoded.
+    CanonicalEncoding,
+    /// A note commitment is not in a commitment tree of known root.
+    UnknownCommitment,
+    /// The transaction does not match the block it is claimed to be in.
+    NotInBlock,
+}
+
+impl fmt::Display for ShieldedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            ShieldedError::InvalidProof => "invalid proof",
+            ShieldedError::BadSignature => "bad spend authorization signature",
+            ShieldedError::UnknownAnchor => "unknown anchor",
+            ShieldedError::DoubleSpend => "nullifier already revealed",
+            ShieldedError::ValueBalance => "value balance does not match the value commitments",
+            ShieldedError::MalformedDescription => "small order value commitment or key",
+            ShieldedError::CanonicalEncoding => "invalid encoding",
+            ShieldedError::UnknownCommitment => "note commitment not in a known tree",
+            ShieldedError::NotInBlock => "transaction not in the block",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl error::Error for ShieldedError {
+    fn description(&self) -> &str {
+        "shielded verification error"
+    }
+}
+
 fn is_small_order<Order>(p: &edwards::Point<Bls12, Order>, params: &JubjubBls12) -> bool {
     p.double(params).double(params).double(params) == edwards::Point::zero()
 }
 
+/// Pack the public inputs of the Sapling spend circuit, in the order it expects:
+/// `rk`, `cv`, the anchor and the nullifier multipacked into two field elements.
+pub fn spend_public_inputs(
+    anchor: Fr,
+    cv: &edwards::Point<Bls12, Unknown>,
+    nullifier: &[u8; 32],
+    rk: &PublicKey<Bls12>,
+) -> Vec<Fr> {
+    let mut public_input = Vec::with_capacity(7);
+    {
+        let (x, y) = rk.0.into_xy();
+        public_input.push(x);
+        public_input.push(y);
+    }
+    {
+        let (x, y) = cv.into_xy();
+        public_input.push(x);
+        public_input.push(y);
+    }
+    public_input.push(anchor);
+
+    // Add the nullifier through multiscalar packing
+    {
+        let nullifier = multipack::bytes_to_bits_le(&nullifier[..]);
+        let nullifier = multipack::compute_multipacking::<Bls12>(&nullifier);
+
+        assert_eq!(nullifier.len(), 2);
+
+        public_input.extend(nullifier);
+    }
+
+    public_input
+}
+
+/// Pack the public inputs of the Sapling output circuit, in the order it expects:
+/// `cv`, `epk` and the note commitment.
+pub fn output_public_inputs(
+    cv: &edwards::Point<Bls12, Unknown>,
+    cm: Fr,
+    epk: &edwards::Point<Bls12, Unknown>,
+) -> Vec<Fr> {
+    let mut public_input = Vec::with_capacity(5);
+    {
+        let (x, y) = cv.into_xy();
+        public_input.push(x);
+        public_input.push(y);
+    }
+    {
+        let (x, y) = epk.into_xy();
+        public_input.push(x);
+        public_input.push(y);
+    }
+    public_input.push(cm);
+
+    public_input
+}
+
+/// Check that the nullifier declared by a SpendDescription is the one its proof was created
+/// for. The nullifier is a public input of the spend circuit, which derives it with PRF^nf
+/// from the spent note, so the proof only verifies against that nullifier. Any 32 bytes pack
+/// into the two field elements of the input, so no other check of the nullifier applies.
+///
+/// The proof is checked in full, but the spend authorization signature is not.
+pub fn spend_nullifier_matches_proof(
+    desc: &SpendDescription,
+    verifying_key: &PreparedVerifyingKey<Bls12>,
+) -> bool {
+    let zkproof = match Proof::<Bls12>::read(&desc.zkproof[..]) {
+        Ok(zkproof) => zkproof,
+        Err(_) => return false,
+    };
+    let public_input = spend_public_inputs(desc.anchor, &desc.cv, &desc.nullifier, &desc.rk);
+    match verify_proof(verifying_key, &zkproof, &public_input) {
+        Ok(true) => true,
+        _ => false,
+    }
+}
+
 /// A context object for verifying the Sapling components of a Zcash transaction.
 pub struct SaplingVerificationContext {
     bvk: edwards::Point<Bls12, Unknown>,
+    cache: Option<SharedProofCache>,
 }
 
 impl SaplingVerificationContext {
@@ -23,7 +153,50 @@ impl SaplingVerificationContext {
     pub fn new() -> Self {
         SaplingVerificationContext {
             bvk: edwards::Point::zero(),
+            cache: None,
+        }
+    }
+
+    /// Look up and record proof verification results in the given cache.
+    pub fn with_cache(mut self, cache: SharedProofCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    // Verify a proof, or return the cached result of an earlier verification.
+    // The cache is not locked while the proof is verified.
+    fn check_proof(
+        &self,
+        circuit: Circuit,
+        verifying_key: &PreparedVerifyingKey<Bls12>,
+        zkproof: &Proof<Bls12>,
+        public_input: &[Fr],
+    ) -> Result<(), ShieldedError> {
+        let key = match self.cache {
+            Some(ref cache) => {
+                let mut cache = cache.lock().expect("proof cache lock poisoned");
+                let key = cache.key(circuit, zkproof, public_input);
+                if let Some(result) = cache.get(&key) {
+                    return proof_result(result);
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        // No error, and proof verification successful. Any other case fails.
+        let result = match verify_proof(verifying_key, zkproof, public_input) {
+            Ok(true) => true,
+            _ => false,
+        };
+
+        if let (Some(cache), Some(key)) = (self.cache.as_ref(), key) {
+            cache
+                .lock()
+                .expect("proof cache lock poisoned")
+                .insert(key, result);
         }
+        proof_result(result)
     }
 
     /// Perform consensus checks on a Sapling SpendDescription, while
@@ -40,12 +213,39 @@ impl SaplingVerificationContext {
         verifying_key: &PreparedVerifyingKey<Bls12>,
         params: &JubjubBls12,
     ) -> bool {
+        self.verify_spend(
+            cv,
+            anchor,
+            nullifier,
+            rk,
+            sighash_value,
+            spend_auth_sig,
+            zkproof,
+            verifying_key,
+            params,
+        )
+        .is_ok()
+    }
+
+    /// Like `check_spend`, telling why the SpendDescription fails.
+    pub fn verify_spend(
+        &mut self,
+        cv: edwards::Point<Bls12, Unknown>,
+        anchor: Fr,
+        nullifier: &[u8; 32],
+        rk: PublicKey<Bls12>,
+        sighash_value: &[u8; 32],
+        spend_auth_sig: Signature,
+        zkproof: Proof<Bls12>,
+        verifying_key: &PreparedVerifyingKey<Bls12>,
+        params: &JubjubBls12,
+    ) -> Result<(), ShieldedError> {
         if is_small_order(&cv, params) {
-            return false;
+            return Err(ShieldedError::MalformedDescription);
         }
 
         if is_small_order(&rk.0, params) {
-            return false;
+            return Err(ShieldedError::MalformedDescription);
         }
 
         // Accumulate the value commitment in the context
@@ -57,9 +257,6 @@ impl SaplingVerificationContext {
             self.bvk = tmp;
         }
 
-        // Grab the nullifier as a sequence of bytes
-        let nullifier = &nullifier[..];
-
         // Compute the signature's message for rk/spend_auth_sig
         let mut data_to_be_signed = [0u8; 64];
         rk.0.write(&mut data_to_be_signed[0..32])
@@ -73,42 +270,14 @@ impl SaplingVerificationContext {
             FixedGenerators::SpendingKeyGenerator,
             params,
         ) {
-            return false;
+            return Err(ShieldedError::BadSignature);
         }
 
         // Construct public input for circuit
-        let mut public_input = [Fr::zero(); 7];
-        {
-            let (x, y) = rk.0.into_xy();
-            public_input[0] = x;
-            public_input[1] = y;
-        }
-        {
-            let (x, y) = cv.into_xy();
-            public_input[2] = x;
-            public_input[3] = y;
-        }
-        public_input[4] = anchor;
-
-        // Add the nullifier through multiscalar packing
-        {
-            let nullifier = multipack::bytes_to_bits_le(nullifier);
-            let nullifier = multipack::compute_multipacking::<Bls12>(&nullifier);
-
-            assert_eq!(nullifier.len(), 2);
-
-            public_input[5] = nullifier[0];
-            public_input[6] = nullifier[1];
-        }
+        let public_input = spend_public_inputs(anchor, &cv, nullifier, &rk);
 
         // Verify the proof
-        match verify_proof(verifying_key, &zkproof, &public_input[..]) {
-            // No error, and proof verification successful
-            Ok(true) => true,
-
-            // Any other case
-            _ => false,
-        }
+        self.check_proof(Circuit::Spend, verifying_key, &zkproof, &public_input[..])
     }
 
     /// Perform consensus checks on a Sapling OutputDescription, while
@@ -122,12 +291,26 @@ impl SaplingVerificationContext {
         verifying_key: &PreparedVerifyingKey<Bls12>,
         params: &JubjubBls12,
     ) -> bool {
+        self.verify_output(cv, cm, epk, zkproof, verifying_key, params)
+            .is_ok()
+    }
+
+    /// Like `check_output`, telling why the OutputDescription fails.
+    pub fn verify_output(
+        &mut self,
+        cv: edwards::Point<Bls12, Unknown>,
+        cm: Fr,
+        epk: edwards::Point<Bls12, Unknown>,
+        zkproof: Proof<Bls12>,
+        verifying_key: &PreparedVerifyingKey<Bls12>,
+        params: &JubjubBls12,
+    ) -> Result<(), ShieldedError> {
         if is_small_order(&cv, params) {
-            return false;
+            return Err(ShieldedError::MalformedDescription);
         }
 
         if is_small_order(&epk, params) {
-            return false;
+            return Err(ShieldedError::MalformedDescription);
         }
 
         // Accumulate the value commitment in the context
@@ -141,27 +324,17 @@ impl SaplingVerificationContext {
         }
 
         // Construct public input for circuit
-        let mut public_input = [Fr::zero(); 5];
-        {
-            let (x, y) = cv.into_xy();
-            public_input[0] = x;
-            public_input[1] = y;
-        }
-        {
-            let (x, y) = epk.into_xy();
-            public_input[2] = x;
-            public_input[3] = y;
-        }
-        public_input[4] = cm;
+        let public_input = output_public_inputs(&cv, cm, &epk);
 
         // Verify the proof
-        match verify_proof(verifying_key, &zkproof, &public_input[..]) {
-            // No error, and proof verification successful
-            Ok(true) => true,
+        self.check_proof(Circuit::Output, verifying_key, &zkproof, &public_input[..])
+    }
 
-            // Any other case
-            _ => false,
-        }
+    /// Add the value commitments accumulated by `other`, which checked other
+    /// descriptions of the same transaction, to this context. This lets the
+    /// descriptions be checked in separate contexts, for example on several threads.
+    pub fn merge(&mut self, other: &SaplingVerificationContext, params: &JubjubBls12) {
+        self.bvk = self.bvk.add(&other.bvk, params);
     }
 
     /// Perform consensus checks on the valueBalance and bindingSig parts of a
@@ -174,32 +347,336 @@ impl SaplingVerificationContext {
         binding_sig: Signature,
         params: &JubjubBls12,
     ) -> bool {
-        // Obtain current bvk from the context
-        let mut bvk = PublicKey(self.bvk.clone());
+        self.verify_final(value_balance, sighash_value, binding_sig, params)
+            .is_ok()
+    }
+
+    /// Like `final_check`, telling why the transaction fails.
+    pub fn verify_final(
+        &self,
+        value_balance: i64,
+        sighash_value: &[u8; 32],
+        binding_sig: Signature,
+        params: &JubjubBls12,
+    ) -> Result<(), ShieldedError> {
+        check_binding_signature(
+            self.bvk.clone(),
+            value_balance,
+            sighash_value,
+            &binding_sig,
+            params,
+        )
+    }
+}
+
+/// Verify the binding signature of a Sapling transaction from the value commitments
+/// of its spends and outputs and its claimed value balance.
+///
+/// `bvk` is the sum of the spend value commitments, minus the sum of the output
+/// value commitments, minus the value balance in the exponent of the value base.
+/// It is the key the transaction's `bsk` signed the sighash with only if the values
+/// balance. The proofs of the descriptions are not checked.
+pub fn verify_binding_signature(
+    spend_cvs: &[edwards::Point<Bls12, Unknown>],
+    output_cvs: &[edwards::Point<Bls12, Unknown>],
+    value_balance: i64,
+    sighash_value: &[u8; 32],
+    binding_sig: &Signature,
+    params: &JubjubBls12,
+) -> bool {
+    let mut bvk = edwards::Point::zero();
+    for cv in spend_cvs {
+        bvk = bvk.add(cv, params);
+    }
+    for cv in output_cvs {
+        bvk = bvk.add(&cv.negate(), params);
+    }
+
+    check_binding_signature(bvk, value_balance, sighash_value, binding_sig, params).is_ok()
+}
+
+fn check_binding_signature(
+    bvk: edwards::Point<Bls12, Unknown>,
+    value_balance: i64,
+    sighash_value: &[u8; 32],
+    binding_sig: &Signature,
+    params: &JubjubBls12,
+) -> Result<(), ShieldedError> {
+    let mut bvk = PublicKey(bvk);
+
+    // Compute value balance
+    let mut value_balance = match compute_value_balance(value_balance, params) {
+        Some(a) => a,
+        None => return Err(ShieldedError::ValueBalance),
+    };
+
+    // Subtract value_balance from current bvk to get final bvk
+    value_balance = value_balance.negate();
+    bvk.0 = bvk.0.add(&value_balance, params);
+
+    // Compute the signature's message for bvk/binding_sig
+    let mut data_to_be_signed = [0u8; 64];
+    bvk.0
+        .write(&mut data_to_be_signed[0..32])
+        .expect("bvk is 32 bytes");
+    (&mut data_to_be_signed[32..64]).copy_from_slice(&sighash_value[..]);
+
+    // Verify the binding_sig
+    if bvk.verify(
+        &data_to_be_signed,
+        binding_sig,
+        FixedGenerators::ValueCommitmentRandomness,
+        params,
+    ) {
+        Ok(())
+    } else {
+        Err(ShieldedError::ValueBalance)
+    }
+}
+
+fn proof_result(valid: bool) -> Result<(), ShieldedError> {
+    if valid {
+        Ok(())
+    } else {
+        Err(ShieldedError::InvalidProof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellman::groth16::{create_random_proof, generate_random_parameters, prepare_verifying_key};
+    use bellman::{Circuit, ConstraintSystem, SynthesisError};
+    use ff::{Field, PrimeField};
+    use pairing::bls12_381::{Bls12, Fr};
+    use rand::{OsRng, Rand};
+    use sapling_crypto::{
+        jubjub::{edwards, fs::Fs, FixedGenerators, Unknown},
+        primitives::ValueCommitment,
+        redjubjub::{PrivateKey, PublicKey, Signature},
+    };
+
+    use super::{
+        output_public_inputs, spend_nullifier_matches_proof, spend_public_inputs,
+        verify_binding_signature, SaplingVerificationContext, ShieldedError,
+    };
+    use zcash_primitives::transaction::components::{SpendDescription, GROTH_PROOF_SIZE};
+    use zcash_primitives::JUBJUB;
+
+    fn minus_one() -> Fr {
+        let mut fr = Fr::one();
+        fr.negate();
+        fr
+    }
 
-        // Compute value balance
-        let mut value_balance = match compute_value_balance(value_balance, params) {
-            Some(a) => a,
-            None => return false,
+    #[test]
+    fn spend_public_inputs_order() {
+        // (0, -1) is the point of order two, distinct from the identity (0, 1).
+        let rk = PublicKey::<Bls12>(edwards::Point::get_for_y(minus_one(), false, &JUBJUB).unwrap());
+        let cv = edwards::Point::zero();
+        let anchor = Fr::from_str("7").unwrap();
+        let mut nullifier = [0u8; 32];
+        nullifier[0] = 1;
+        nullifier[31] = 0x80;
+
+        assert_eq!(
+            spend_public_inputs(anchor, &cv, &nullifier, &rk),
+            vec![
+                Fr::zero(), minus_one(),
+                Fr::zero(), Fr::one(),
+                anchor,
+                // bit 0 in the first element, bit 255 is the second bit of the second one.
+                Fr::one(), Fr::from_str("2").unwrap(),
+            ]
+        );
+    }
+
+    /// Stands in for the spend circuit, exposing the given values as its public inputs.
+    struct PublicInputs(Vec<Fr>);
+
+    impl Circuit<Bls12> for PublicInputs {
+        fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+            for (i, input) in self.0.into_iter().enumerate() {
+                cs.alloc_input(|| format!("input {}", i), || Ok(input))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spend_nullifier_must_match_the_proof() {
+        let rng = &mut OsRng::new().unwrap();
+        let mut desc = SpendDescription {
+            cv: edwards::Point::zero(),
+            anchor: Fr::from_str("7").unwrap(),
+            nullifier: [3; 32],
+            rk: PublicKey::<Bls12>(edwards::Point::get_for_y(minus_one(), false, &JUBJUB).unwrap()),
+            zkproof: [0; GROTH_PROOF_SIZE],
+            spend_auth_sig: None,
         };
+        let public_input = spend_public_inputs(desc.anchor, &desc.cv, &desc.nullifier, &desc.rk);
+
+        let params = generate_random_parameters::<Bls12, _, _>(
+            PublicInputs(vec![Fr::zero(); public_input.len()]),
+            rng,
+        ).unwrap();
+        let pvk = prepare_verifying_key(&params.vk);
+        // undecodable proofs never match.
+        assert!(!spend_nullifier_matches_proof(&desc, &pvk));
 
-        // Subtract value_balance from current bvk to get final bvk
-        value_balance = value_balance.negate();
-        bvk.0 = bvk.0.add(&value_balance, params);
+        let proof = create_random_proof(PublicInputs(public_input), &params, rng).unwrap();
+        proof.write(&mut desc.zkproof[..]).unwrap();
+        assert!(spend_nullifier_matches_proof(&desc, &pvk));
+
+        desc.nullifier[31] ^= 1;
+        assert!(!spend_nullifier_matches_proof(&desc, &pvk));
+    }
 
-        // Compute the signature's message for bvk/binding_sig
+    #[test]
+    fn output_public_inputs_order() {
+        let cv = edwards::Point::get_for_y(minus_one(), false, &JUBJUB).unwrap();
+        let epk = edwards::Point::zero();
+        let cm = Fr::from_str("42").unwrap();
+
+        assert_eq!(
+            output_public_inputs(&cv, cm, &epk),
+            vec![Fr::zero(), minus_one(), Fr::zero(), Fr::one(), cm]
+        );
+    }
+
+    /// Commit to each value, returning the commitments and the sum of their randomness.
+    fn commit(values: &[u64], rng: &mut OsRng) -> (Vec<edwards::Point<Bls12, Unknown>>, Fs) {
+        let mut rcv_sum = Fs::zero();
+        let cvs = values
+            .iter()
+            .map(|value| {
+                let vc = ValueCommitment::<Bls12> {
+                    value: *value,
+                    randomness: Fs::rand(rng),
+                };
+                rcv_sum.add_assign(&vc.randomness);
+                vc.cm(&JUBJUB).into()
+            })
+            .collect();
+        (cvs, rcv_sum)
+    }
+
+    /// Sign `sighash` with `bsk`, as the prover does.
+    fn binding_sig(bsk: Fs, sighash: &[u8; 32], rng: &mut OsRng) -> Signature {
+        let bsk = PrivateKey::<Bls12>(bsk);
+        let bvk = PublicKey::from_private(&bsk, FixedGenerators::ValueCommitmentRandomness, &JUBJUB);
         let mut data_to_be_signed = [0u8; 64];
-        bvk.0
-            .write(&mut data_to_be_signed[0..32])
-            .expect("bvk is 32 bytes");
-        (&mut data_to_be_signed[32..64]).copy_from_slice(&sighash_value[..]);
+        bvk.0.write(&mut data_to_be_signed[0..32]).unwrap();
+        (&mut data_to_be_signed[32..64]).copy_from_slice(&sighash[..]);
+        bsk.sign(&data_to_be_signed, rng, FixedGenerators::ValueCommitmentRandomness, &JUBJUB)
+    }
 
-        // Verify the binding_sig
-        bvk.verify(
-            &data_to_be_signed,
-            &binding_sig,
-            FixedGenerators::ValueCommitmentRandomness,
-            params,
-        )
+    #[test]
+    fn binding_signature_checks_value_balance() {
+        let mut rng = OsRng::new().unwrap();
+        let sighash = [7u8; 32];
+
+        // 100 spent, 60 created, 40 leaving the shielded pool.
+        let (spend_cvs, spend_rcv) = commit(&[70, 30], &mut rng);
+        let (output_cvs, output_rcv) = commit(&[60], &mut rng);
+        let mut bsk = spend_rcv;
+        bsk.sub_assign(&output_rcv);
+        let sig = binding_sig(bsk, &sighash, &mut rng);
+
+        assert!(verify_binding_signature(&spend_cvs, &output_cvs, 40, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&spend_cvs, &output_cvs, 41, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&spend_cvs, &output_cvs, -40, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&output_cvs, &spend_cvs, 40, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&spend_cvs, &output_cvs, 40, &[8u8; 32], &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&spend_cvs[..1], &output_cvs, 40, &sighash, &sig, &JUBJUB));
+    }
+
+    #[test]
+    fn binding_signature_with_negative_value_balance() {
+        let mut rng = OsRng::new().unwrap();
+        let sighash = [9u8; 32];
+
+        // 250 brought in from the transparent side.
+        let (output_cvs, output_rcv) = commit(&[250], &mut rng);
+        let mut bsk = Fs::zero();
+        bsk.sub_assign(&output_rcv);
+        let sig = binding_sig(bsk, &sighash, &mut rng);
+
+        assert!(verify_binding_signature(&[], &output_cvs, -250, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&[], &output_cvs, 250, &sighash, &sig, &JUBJUB));
+        assert!(!verify_binding_signature(&[], &output_cvs, i64::min_value(), &sighash, &sig, &JUBJUB));
+    }
+
+    #[test]
+    fn verification_tells_why_a_spend_fails() {
+        let rng = &mut OsRng::new().unwrap();
+        let sighash = [5u8; 32];
+        let anchor = Fr::from_str("7").unwrap();
+        let nullifier = [3u8; 32];
+        let (cvs, _) = commit(&[10], rng);
+        let cv = cvs[0].clone();
+        let ask = PrivateKey::<Bls12>(Fs::rand(rng));
+        let rk = PublicKey::from_private(&ask, FixedGenerators::SpendingKeyGenerator, &JUBJUB);
+        let sign = |message: &[u8; 32], rng: &mut OsRng| {
+            let mut data_to_be_signed = [0u8; 64];
+            rk.0.write(&mut data_to_be_signed[0..32]).unwrap();
+            (&mut data_to_be_signed[32..64]).copy_from_slice(&message[..]);
+            ask.sign(&data_to_be_signed, rng, FixedGenerators::SpendingKeyGenerator, &JUBJUB)
+        };
+
+        let public_input = spend_public_inputs(anchor, &cv, &nullifier, &rk);
+        let params = generate_random_parameters::<Bls12, _, _>(
+            PublicInputs(vec![Fr::zero(); public_input.len()]),
+            rng,
+        ).unwrap();
+        let pvk = prepare_verifying_key(&params.vk);
+        let valid = create_random_proof(PublicInputs(public_input.clone()), &params, rng).unwrap();
+        let mut wrong_inputs = public_input;
+        wrong_inputs[4] = Fr::one();
+        let invalid = create_random_proof(PublicInputs(wrong_inputs), &params, rng).unwrap();
+
+        let verify = |cv: &edwards::Point<Bls12, Unknown>, sig: Signature, proof| {
+            SaplingVerificationContext::new().verify_spend(
+                cv.clone(), anchor, &nullifier, rk.clone(), &sighash, sig, proof, &pvk, &JUBJUB,
+            )
+        };
+
+        assert_eq!(verify(&cv, sign(&sighash, rng), valid.clone()), Ok(()));
+        assert_eq!(
+            verify(&cv, sign(&sighash, rng), invalid),
+            Err(ShieldedError::InvalidProof)
+        );
+        assert_eq!(
+            verify(&cv, sign(&[6u8; 32], rng), valid.clone()),
+            Err(ShieldedError::BadSignature)
+        );
+        assert_eq!(
+            verify(&edwards::Point::zero(), sign(&sighash, rng), valid),
+            Err(ShieldedError::MalformedDescription)
+        );
+    }
+
+    #[test]
+    fn verification_tells_why_the_value_balance_fails() {
+        let mut rng = OsRng::new().unwrap();
+        let sighash = [9u8; 32];
+        let (output_cvs, output_rcv) = commit(&[250], &mut rng);
+        let mut bsk = Fs::zero();
+        bsk.sub_assign(&output_rcv);
+        let sig = binding_sig(bsk, &sighash, &mut rng);
+
+        // as if the output had been checked.
+        let mut ctx = SaplingVerificationContext::new();
+        ctx.bvk = output_cvs[0].negate();
+
+        assert_eq!(ctx.verify_final(-250, &sighash, sig, &JUBJUB), Ok(()));
+        assert!(ctx.final_check(-250, &sighash, sig, &JUBJUB));
+        assert_eq!(
+            ctx.verify_final(250, &sighash, sig, &JUBJUB),
+            Err(ShieldedError::ValueBalance)
+        );
+        assert_eq!(
+            ctx.verify_final(i64::min_value(), &sighash, sig, &JUBJUB),
+            Err(ShieldedError::ValueBalance)
+        );
     }
 }
@@ -16,9 +16,12 @@
 
 //! Blockchain database.
 
+use std::cmp;
 use std::collections::{HashMap, HashSet, btree_map::BTreeMap};
+use std::fmt;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::thread;
 use std::{io, mem};
 
@@ -43,12 +46,15 @@ use common_types::BlockNumber;
 use ethcore_db::cache_manager::CacheManager;
 use ethcore_db::keys::{
 	BlockDetails, BlockReceipts, CommitmentTreeSerialization, EpochTransitions, TransactionAddress,
-	EPOCH_KEY_PREFIX,
+	TreeCheckpoint, EPOCH_KEY_PREFIX,
 };
-use ethcore_db::{self as db, CacheUpdatePolicy, Readable, Writable};
+#[cfg(feature = "commitment-index")]
+use ethcore_db::keys::CommitmentPosition;
+use ethcore_db::{self as db, CacheUpdatePolicy, Key, Readable, Writable};
 use ethereum_types::{Bloom, BloomRef, H256, U256};
 use heapsize::HeapSizeOf;
 use itertools::Itertools;
+use keccak_hash::keccak;
 use kvdb::{DBTransaction, KeyValueDB};
 use log::{info, trace, warn};
 use parity_bytes::Bytes;
@@ -64,11 +70,14 @@ use std::sync::RwLock as STD_RwLock;
 
 use crate::ff::PrimeField;
 use crate::wallet::wallet::{Wallet, WalletTxHashes, ReloadBlockInfo, WalletTxAddresses};
-use crate::zcash_primitives::merkle_tree::CommitmentTree;
-use crate::zcash_primitives::sapling::Node;
+use crate::zcash_primitives::merkle_tree::{CommitmentTree, Hashable, IncrementalWitness};
+use crate::zcash_primitives::sapling::{Node, SAPLING_COMMITMENT_TREE_DEPTH};
 
 use crate::best_block::{BestAncientBlock, BestBlock};
 use crate::block_info::{BlockInfo, BlockLocation, BranchBecomingCanonChainData};
+use crate::nullifier_set::{nullifier_set_hash, NullifierSet, NullifierSnapshot, SnapshotError};
+use crate::rescan::{RescanLimiter, TooManyRescans};
+use crate::shielded_checkpoint::{CheckpointError, ShieldedCheckpoint};
 use crate::update::{ExtrasInsert, ExtrasUpdate};
 use crate::{CacheSize, Config, ImportRoute};
 use crate::wallet::wallet_types::TxHash;
@@ -256,6 +265,80 @@ enum CacheId {
 	BlockReceipts(H256),
 }
 
+/// Number of blocks between two progress logs of `BlockChain::verify_commitment_tree`.
+const TREE_CHECK_LOG_INTERVAL: BlockNumber = 10_000;
+
+/// Default number of blocks between two commitment tree checkpoints.
+pub const DEFAULT_TREE_CHECKPOINT_INTERVAL: BlockNumber = 10_000;
+
+/// Maximum number of blocks whose commitment root is dropped by one `prune_commitment_roots` call.
+const COMMITMENT_ROOT_PRUNE_BATCH: BlockNumber = 1024;
+
+/// Number of nullifier writes after which `rebuild_nullifier_set` flushes its transaction.
+const NULLIFIER_REBUILD_BATCH: usize = 10_000;
+
+/// Root of the empty commitment tree, which the genesis block of every chain records as
+/// its anchor, whether or not the shielded pool is active at genesis.
+pub fn genesis_shielded_anchor() -> Node {
+	Node::empty_root(SAPLING_COMMITMENT_TREE_DEPTH)
+}
+
+/// Retention of the commitment roots which are accepted as anchors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnchorHistoryInfo {
+	/// Number of canonical blocks whose commitment root is still indexed.
+	pub retained: u64,
+	/// Lowest block number whose commitment root is still indexed.
+	pub oldest_retained: BlockNumber,
+}
+
+/// Reason why the persisted commitment tree does not match the canonical chain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeInconsistency {
+	/// No canonical block is known at the given height.
+	MissingBlock(BlockNumber),
+	/// No commitment tree is stored for the block at the given height.
+	MissingTree(BlockNumber),
+	/// The stored commitment tree root differs from the one recomputed from the blocks.
+	RootMismatch {
+		/// Height of the first mismatching block.
+		number: BlockNumber,
+		/// Root recomputed by replaying the shielded outputs.
+		expected: Node,
+		/// Root of the stored commitment tree.
+		stored: Node,
+	},
+	/// The stored root of the block at the given height is not mapped back to the block.
+	UnknownAnchor(BlockNumber),
+	/// The stored root of the block at the given height was dropped from the index by the
+	/// pruning of the anchor history, which is expected below the oldest retained root.
+	Pruned(BlockNumber),
+	/// The check was cancelled before reaching the given height.
+	Aborted(BlockNumber),
+}
+
+impl fmt::Display for TreeInconsistency {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TreeInconsistency::MissingBlock(n) => write!(f, "No canonical block #{}", n),
+			TreeInconsistency::MissingTree(n) => write!(f, "No commitment tree stored for block #{}", n),
+			TreeInconsistency::RootMismatch { number, .. } => write!(f, "Commitment tree root mismatch at block #{}", number),
+			TreeInconsistency::UnknownAnchor(n) => write!(f, "Commitment tree root of block #{} is not indexed", n),
+			TreeInconsistency::Pruned(n) => write!(f, "Commitment tree root of block #{} was pruned", n),
+			TreeInconsistency::Aborted(n) => write!(f, "Commitment tree check aborted at block #{}", n),
+		}
+	}
+}
+
+/// Change of the set of spent nullifiers on importing blocks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NullifierDelta {
+	/// Nullifiers revealed by the enacted blocks, in canonical order.
+	pub added: Vec<[u8; 32]>,
+	/// Nullifiers of the retracted blocks, in the reverse of the order they were added.
+	pub removed: Vec<[u8; 32]>,
+}
+
 /// Structure providing fast access to blockchain data.
 ///
 /// **Does not do input data verification.**
@@ -284,6 +367,14 @@ pub struct BlockChain {
 	block_hashes: RwLock<HashMap<BlockNumber, H256>>,
 	// store the map for commitment tree root to block hash.
 	commitment_root_blocks: RwLock<HashMap<Node, H256>>,
+	// lowest block number whose commitment root has not been pruned.
+	oldest_commitment_root: RwLock<BlockNumber>,
+	// blocks whose commitment root is never pruned.
+	pinned_blocks: RwLock<HashSet<H256>>,
+	// trusted shielded state, used when the tree of its block is not known.
+	shielded_checkpoint: RwLock<Option<ShieldedCheckpoint>>,
+	// number of blocks between two commitment tree checkpoints.
+	tree_checkpoint_interval: BlockNumber,
 	// store the map for block hash to commitment tree serialization
 	block_commitment_sers: RwLock<HashMap<H256, CommitmentTreeSerialization>>,
 	transaction_addresses: RwLock<HashMap<H256, TransactionAddress>>,
@@ -294,6 +385,10 @@ pub struct BlockChain {
 	rewards: RwLock<Rewards>,
 
 	pt_wallet: Arc<STD_RwLock<Wallet>>,
+	// bounds the wallet rescans walking the chain at the same time.
+	rescans: RescanLimiter,
+	// set when the rescan for newly imported addresses was put off for lack of a slot.
+	wallet_rescan_deferred: AtomicBool,
 
 	db: Arc<BlockChainDB>,
 
@@ -310,6 +405,9 @@ pub struct BlockChain {
 	pending_transaction_nullifiers: RwLock<HashMap<U256, Option<H256>>>,
 	pending_rewards: RwLock<Option<Rewards>>,
 
+	// notified once the senders of the sync transactions are all dropped.
+	sync_source: Arc<Mutex<SyncSourceWatch>>,
+
 	pending_routes: RwLock<ImportRoute>,
 }
 
@@ -638,6 +736,48 @@ impl<'a> Iterator for AncestryWithMetadataIter<'a> {
 	}
 }
 
+/// An iterator over the anchors of a range of canonical blocks, see `BlockChain::anchors`.
+pub struct AnchorIter<'a> {
+	chain: &'a BlockChain,
+	// the tree and root of the last block yielded, `None` once the iteration stopped.
+	state: Option<(CommitmentTree<Node>, Node)>,
+	next: BlockNumber,
+	to: BlockNumber,
+	started: bool,
+}
+
+impl<'a> Iterator for AnchorIter<'a> {
+	type Item = (BlockNumber, Node);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.next > self.to {
+			return None;
+		}
+		let number = self.next;
+		let chain = self.chain;
+		let (tree, root) = self.state.as_mut()?;
+
+		if self.started {
+			let block = match chain.block_hash(number).and_then(|hash| chain.block(&hash)) {
+				Some(block) => block,
+				None => {
+					self.state = None;
+					return None;
+				}
+			};
+			// a block without shielded outputs keeps the root of its parent.
+			if block.has_shielded_components() {
+				update_commitment_tree_with_txs(tree, &block.view().localized_private_txs());
+				*root = tree.root();
+			}
+		}
+
+		self.started = true;
+		self.next += 1;
+		Some((number, *root))
+	}
+}
+
 /// An iterator which walks all epoch transitions.
 /// Returns epoch transitions.
 pub struct EpochTransitionIter<'a> {
@@ -685,6 +825,32 @@ impl<'a> Iterator for EpochTransitionIter<'a> {
 	}
 }
 
+/// Whether the sync transaction source is lost, and what to run when it is.
+#[derive(Default)]
+struct SyncSourceWatch {
+	lost: bool,
+	callback: Option<Box<FnMut() + Send>>,
+}
+
+/// Drain the sync transactions until every sender is dropped, then run the
+/// `on_sync_source_lost` callback.
+fn drain_sync_transactions(rx: &Mutex<mpsc::Receiver<SyncTransaction>>, sync_source: &Mutex<SyncSourceWatch>) {
+	for _item in rx.lock().iter() {
+		//TODO(Kui): The receiver should be removed.
+		// ww.sync_transaction(&item);
+	}
+
+	let callback = {
+		let mut watch = sync_source.lock();
+		watch.lost = true;
+		watch.callback.take()
+	};
+	info!(target: "blockchain", "Sync transaction source lost");
+	if let Some(mut callback) = callback {
+		callback();
+	}
+}
+
 impl BlockChain {
 	/// Create new instance of blockchain from given Genesis.
 	pub fn new(
@@ -716,12 +882,18 @@ impl BlockChain {
 			block_details: RwLock::new(HashMap::new()),
 			block_hashes: RwLock::new(HashMap::new()),
 			commitment_root_blocks: RwLock::new(HashMap::new()),
+			oldest_commitment_root: RwLock::new(0),
+			pinned_blocks: RwLock::new(HashSet::new()),
+			shielded_checkpoint: RwLock::new(None),
+			tree_checkpoint_interval: config.tree_checkpoint_interval,
 			block_commitment_sers: RwLock::new(HashMap::new()),
 			transaction_addresses: RwLock::new(HashMap::new()),
 			transaction_nullifiers: RwLock::new(HashMap::new()),
 			block_receipts: RwLock::new(HashMap::new()),
 			rewards: RwLock::new(Rewards::new()),
 			pt_wallet: wallet,
+			rescans: RescanLimiter::new(config.max_concurrent_rescans),
+			wallet_rescan_deferred: AtomicBool::new(false),
 			pending_routes: RwLock::new(ImportRoute::none()),
 			db: db.clone(),
 			cache_man: Mutex::new(cache_man),
@@ -735,24 +907,12 @@ impl BlockChain {
 			pending_transaction_addresses: RwLock::new(HashMap::new()),
 			pending_transaction_nullifiers: RwLock::new(HashMap::new()),
 			pending_rewards: RwLock::new(None),
+			sync_source: Arc::new(Mutex::new(SyncSourceWatch::default())),
 		};
 
-		{
-			thread::spawn(move || {
-				if receiver.is_some() {
-					let rx = receiver.unwrap();
-					while true {
-						let r = rx.lock();
-						for item in r.iter() {
-							// println!("received SyncTransaction");
-							// let w = bc.pt_wallet.write();
-							// let mut ww = w.unwrap();
-							//TODO(Kui): The receiver should be removed.
-							// ww.sync_transaction(&item);
-						}
-					}
-				}
-			});
+		if let Some(rx) = receiver {
+			let sync_source = bc.sync_source.clone();
+			thread::spawn(move || drain_sync_transactions(&rx, &sync_source));
 		}
 
 		// load best block
@@ -791,7 +951,7 @@ impl BlockChain {
 
 					// the commitment tree root for genesis block
 					let commitment_tree = CommitmentTree::<Node>::new();
-					let root = commitment_tree.root();
+					let root = genesis_shielded_anchor();
 					// the commitment tree serialization for genesis block
 					let commitment_tree_ser =
 						CommitmentTreeSerialization::new(&commitment_tree);
@@ -816,6 +976,7 @@ impl BlockChain {
 					);
 
 					batch.put(db::COL_EXTRA, b"best", &hash);
+					batch.put(db::COL_EXTRA, b"nullifier_set_best", &hash);
 					bc.db.key_value().write(batch).expect(
 						"Low level database error when fetching 'best' block. Some issue with disk?",
 					);
@@ -940,12 +1101,61 @@ impl BlockChain {
 				let mut rewards = bc.rewards.write();
 				*rewards = db_rewards;
 			}
+
+			// load commitment root pruning progress.
+			let oldest_root = bc.db.key_value().get(db::COL_EXTRA, b"oldest_root")
+				.expect("Low level database error when fetching 'oldest root'. Some issue with disk?")
+				.map(|v| rlp::decode::<BlockNumber>(&v).expect("oldest root decoding value from db failed"));
+			if let Some(number) = oldest_root {
+				*bc.oldest_commitment_root.write() = number;
+			}
+
+			// load pinned blocks.
+			let pinned = bc.db.key_value().get(db::COL_EXTRA, b"pinned_blocks")
+				.expect("Low level database error when fetching 'pinned blocks'. Some issue with disk?")
+				.map(|v| rlp::decode_list::<H256>(&v));
+			if let Some(pinned) = pinned {
+				bc.pinned_blocks.write().extend(pinned);
+			}
+
+			// load the shielded checkpoint.
+			let checkpoint = bc.db.key_value().get(db::COL_EXTRA, b"shielded_checkpoint")
+				.expect("Low level database error when fetching 'shielded checkpoint'. Some issue with disk?")
+				.map(|v| rlp::decode::<ShieldedCheckpoint>(&v).expect("shielded checkpoint decoding value from db failed"));
+			*bc.shielded_checkpoint.write() = checkpoint;
 		}
 
+		bc.recover_best_commitment_tree();
 		bc.reload_wallet_info();
 		bc
 	}
 
+	// rebuild the commitment tree of the best block if it was lost, as in a crash, so that
+	// the next block can be imported on top of it.
+	fn recover_best_commitment_tree(&self) {
+		let (number, hash) = {
+			let best = self.best_block.read();
+			(best.header.number(), best.header.hash())
+		};
+		if self.block_commitment_ser(&hash).is_some() {
+			return;
+		}
+
+		warn!(target: "blockchain", "Commitment tree of the best block #{} is missing, recovering it", number);
+		let tree = match self.recover_commitment_tree(number) {
+			Some(tree) => tree,
+			None => {
+				warn!(target: "blockchain", "Cannot recover the commitment tree of block #{}: blocks are missing", number);
+				return;
+			}
+		};
+		let mut batch = self.db.key_value().transaction();
+		batch.write(db::COL_EXTRA, &hash, &CommitmentTreeSerialization::new(&tree));
+		batch.write(db::COL_EXTRA, &tree.root(), &hash);
+		self.db.key_value().write(batch)
+			.expect("Low level database error when writing the commitment tree. Some issue with disk?");
+	}
+
 	pub fn pt_wallet(&self) -> Arc<STD_RwLock<Wallet>> {
 		return self.pt_wallet.clone();
 	}
@@ -1108,7 +1318,7 @@ impl BlockChain {
 		let maybe_parent = self.block_details(&block_parent_hash);
 		/// TODO(Kui): we need to make sure the parent commitment tree exist when
 		/// insert_unordered_block shown in https://wiki.parity.io/Warp-Sync-Snapshot-Format
-		let maybe_parent_commitment_tree_ser = self.block_commitment_ser(&block_parent_hash);
+		let maybe_parent_commitment_tree_ser = self.parent_commitment_ser(&block_parent_hash, block_number);
 		let private_txs = block.view().localized_private_txs();
 
 		if let Some(parent_details) = maybe_parent {
@@ -1121,6 +1331,8 @@ impl BlockChain {
 			};
 
 			self.prepare_wallet_update(batch, &maybe_parent_commitment_tree_ser, &block, &info);
+			#[cfg(feature = "commitment-index")]
+			self.prepare_commitment_positions_update(batch, &maybe_parent_commitment_tree_ser, &private_txs, &info);
 			self.prepare_update(
 				batch,
 				ExtrasUpdate {
@@ -1186,6 +1398,8 @@ impl BlockChain {
 			update.insert(hash, block_details);
 
 			self.prepare_wallet_update(batch, &maybe_parent_commitment_tree_ser, &block, &info);
+			#[cfg(feature = "commitment-index")]
+			self.prepare_commitment_positions_update(batch, &maybe_parent_commitment_tree_ser, &private_txs, &info);
 			self.prepare_update(
 				batch,
 				ExtrasUpdate {
@@ -1340,6 +1554,562 @@ impl BlockChain {
 		}
 	}
 
+	/// Get the position of the note commitment `cm` in the commitment tree of the
+	/// canonical chain, or `None` if no canonical block has appended it.
+	///
+	/// The index is only maintained with the `commitment-index` feature, as it takes
+	/// an entry per shielded output ever mined.
+	#[cfg(feature = "commitment-index")]
+	pub fn commitment_position(&self, cm: &Node) -> Option<u64> {
+		self.db.key_value().read(db::COL_EXTRA, &CommitmentPosition(*cm))
+	}
+
+	/// Get the nullifiers the block with the given hash adds to the nullifier set when it
+	/// is enacted, in canonical order, or `None` if the block is unknown.
+	pub fn block_nullifier_delta(&self, hash: &H256) -> Option<Vec<[u8; 32]>> {
+		self.block(hash).map(|block| block.view().nullifiers())
+	}
+
+	/// Get the net change of the nullifier set on following `route`, for indexers keeping
+	/// a copy of the set. Undoing the removals and then replaying the additions gives
+	/// the set after the import; nullifiers revealed on both branches of a reorg, by
+	/// a transaction mined again, are in neither list.
+	pub fn nullifier_delta(&self, route: &ImportRoute) -> NullifierDelta {
+		// retracted blocks are listed from the old best block down.
+		let removed: Vec<_> = route.retracted.iter()
+			.filter_map(|hash| self.block_nullifier_delta(hash))
+			.flat_map(|nullifiers| nullifiers.into_iter().rev())
+			.collect();
+		let added: Vec<_> = route.enacted.iter()
+			.filter_map(|hash| self.block_nullifier_delta(hash))
+			.flatten()
+			.collect();
+
+		let removed_set: HashSet<_> = removed.iter().cloned().collect();
+		let added_set: HashSet<_> = added.iter().cloned().collect();
+		NullifierDelta {
+			added: added.into_iter().filter(|n| !removed_set.contains(n)).collect(),
+			removed: removed.into_iter().filter(|n| !added_set.contains(n)).collect(),
+		}
+	}
+
+	/// Get a read-only view of the set of spent nullifiers.
+	pub fn nullifier_set(&self) -> NullifierSet {
+		NullifierSet::new(self.db.key_value().clone())
+	}
+
+	/// Whether the nullifier set holds the nullifiers of every canonical block up to the
+	/// best block. It does not after blocks are inserted out of order, or on a database
+	/// written before this was tracked, so it must be rebuilt with `restore_nullifier_set`.
+	/// Snapshot restoration marks the restored set current. It then stays current as
+	/// blocks are imported in order.
+	pub fn nullifier_set_is_current(&self) -> bool {
+		let block = self.db.key_value().get(db::COL_EXTRA, b"nullifier_set_best")
+			.expect("Low level database error when fetching 'nullifier set best'. Some issue with disk?");
+		block.map_or(false, |hash| H256::from_slice(&hash) == self.best_block_hash())
+	}
+
+	/// Save the nullifier set to `path`, as of the current best block. See
+	/// `NullifierSet::save_snapshot`.
+	pub fn save_nullifier_snapshot(&self, path: &Path) -> io::Result<()> {
+		let (number, hash) = {
+			let best_block = self.best_block.read();
+			(best_block.header.number(), best_block.header.hash())
+		};
+		self.db.key_value().flush()?;
+		self.nullifier_set().save_snapshot(path, number, hash)
+	}
+
+	/// Rebuild the nullifier set from the snapshot at `path`, only replaying the canonical
+	/// blocks after the one it was taken at.
+	///
+	/// A snapshot which is missing, corrupt or taken at a block which is no longer
+	/// canonical is rejected, and the set is rebuilt by replaying every block instead.
+	/// Returns the number of blocks replayed.
+	pub fn restore_nullifier_set(&self, path: &Path) -> BlockNumber {
+		let snapshot = NullifierSet::load_snapshot(path).and_then(|snapshot| {
+			let best = self.best_block_number();
+			match self.block_hash(snapshot.block_number) {
+				Some(ref hash) if *hash == snapshot.block_hash && snapshot.block_number <= best => Ok(snapshot),
+				_ => Err(SnapshotError::Stale(snapshot.block_number, snapshot.block_hash)),
+			}
+		});
+		match snapshot {
+			Ok(snapshot) => {
+				info!(target: "blockchain", "Loaded {} nullifiers as of block #{}", snapshot.entries.len(), snapshot.block_number);
+				self.rebuild_nullifier_set(Some(&snapshot))
+			},
+			Err(SnapshotError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+				info!(target: "blockchain", "No nullifier set snapshot, rebuilding the nullifier set");
+				self.rebuild_nullifier_set(None)
+			},
+			Err(e) => {
+				warn!(target: "blockchain", "{}, rebuilding the nullifier set", e);
+				self.rebuild_nullifier_set(None)
+			},
+		}
+	}
+
+	// Replace the nullifier set with the snapshot, or an empty set, and add the nullifiers
+	// revealed by the canonical blocks after it.
+	//
+	// If the body of any of those blocks is missing, e.g. the ancient blocks of a warp-synced
+	// database, the set is left untouched and not marked current, and 0 is returned.
+	fn rebuild_nullifier_set(&self, snapshot: Option<&NullifierSnapshot>) -> BlockNumber {
+		let from = snapshot.map_or(0, |snapshot| snapshot.block_number + 1);
+		let best = self.best_block_number();
+		let missing = (from..=best).find(|&number| {
+			let body = self.block_hash(number).and_then(|hash| {
+				self.db.key_value().get(db::COL_BODIES, &hash)
+					.expect("Low level database error when fetching block body data. Some issue with disk?")
+			});
+			body.is_none()
+		});
+		if let Some(number) = missing {
+			warn!(target: "blockchain", "Cannot rebuild the nullifier set: body of block #{} is missing", number);
+			return 0;
+		}
+
+		let mut batch = DBTransaction::new();
+		let mut pending = 0;
+
+		batch.delete(db::COL_EXTRA, b"nullifier_set_best");
+		for nullifier in self.nullifier_set().iter() {
+			batch.delete(db::COL_EXTRA, &Key::<H256>::key(&U256::from(&nullifier[..])));
+			pending += 1;
+			self.write_nullifier_batch(&mut batch, &mut pending, false);
+		}
+
+		if let Some(snapshot) = snapshot {
+			for &(ref nullifier, ref tx_hash) in &snapshot.entries {
+				batch.write(db::COL_EXTRA, &U256::from(&nullifier[..]), tx_hash);
+				pending += 1;
+				self.write_nullifier_batch(&mut batch, &mut pending, false);
+			}
+		}
+
+		for number in from..=best {
+			let block = match self.block_hash(number).and_then(|hash| self.block(&hash)) {
+				Some(block) => block,
+				None => {
+					self.write_nullifier_batch(&mut batch, &mut pending, true);
+					self.transaction_nullifiers.write().clear();
+					warn!(target: "blockchain", "Block #{} disappeared while rebuilding the nullifier set", number);
+					return 0;
+				},
+			};
+			for tx in block.view().localized_private_txs() {
+				for nullifier in tx.get_nullifier_set() {
+					batch.write(db::COL_EXTRA, &nullifier, &tx.hash());
+					pending += 1;
+				}
+			}
+			self.write_nullifier_batch(&mut batch, &mut pending, false);
+		}
+
+		if let Some(hash) = self.block_hash(best) {
+			batch.put(db::COL_EXTRA, b"nullifier_set_best", &hash);
+		}
+		self.write_nullifier_batch(&mut batch, &mut pending, true);
+		self.transaction_nullifiers.write().clear();
+
+		let replayed = (best + 1).saturating_sub(from);
+		info!(target: "blockchain", "Nullifier set rebuilt up to block #{}, {} blocks replayed", best, replayed);
+		replayed
+	}
+
+	// Write `batch` once it holds `NULLIFIER_REBUILD_BATCH` nullifier updates, or now if `force`.
+	fn write_nullifier_batch(&self, batch: &mut DBTransaction, pending: &mut usize, force: bool) {
+		if force || *pending >= NULLIFIER_REBUILD_BATCH {
+			let full = mem::replace(batch, DBTransaction::new());
+			self.db.key_value().write(full).expect("Low level database error when rebuilding the nullifier set. Some issue with disk?");
+			*pending = 0;
+		}
+	}
+
+	/// Replay the shielded outputs of the canonical chain from genesis up to block `up_to`
+	/// and check that the commitment tree stored for each block matches the recomputed one.
+	///
+	/// This walks every block, so it is slow on long chains. It logs its progress and stops
+	/// with `TreeInconsistency::Aborted` as soon as `abort` is set.
+	pub fn verify_commitment_tree(&self, up_to: BlockNumber, abort: &AtomicBool) -> Result<(), TreeInconsistency> {
+		let mut tree = CommitmentTree::<Node>::new();
+		for number in 0..=up_to {
+			if abort.load(AtomicOrdering::SeqCst) {
+				return Err(TreeInconsistency::Aborted(number));
+			}
+			if number % TREE_CHECK_LOG_INTERVAL == 0 {
+				info!(target: "blockchain", "Checking commitment tree at block #{} of {}", number, up_to);
+			}
+
+			let hash = self.block_hash(number).ok_or(TreeInconsistency::MissingBlock(number))?;
+			let block = self.block(&hash).ok_or(TreeInconsistency::MissingBlock(number))?;
+			update_commitment_tree_with_txs(&mut tree, &block.view().localized_private_txs());
+
+			let stored = self
+				.block_commitment_ser(&hash)
+				.ok_or(TreeInconsistency::MissingTree(number))?
+				.get_commitment_tree()
+				.root();
+			let expected = tree.root();
+			if stored != expected {
+				return Err(TreeInconsistency::RootMismatch { number, expected, stored });
+			}
+			// blocks without shielded outputs share the root of their parent, so the
+			// index may point to another block with the same root.
+			if self.commitment_root_block(&stored).is_none() {
+				if number < *self.oldest_commitment_root.read() {
+					return Err(TreeInconsistency::Pruned(number));
+				}
+				return Err(TreeInconsistency::UnknownAnchor(number));
+			}
+		}
+		info!(target: "blockchain", "Commitment tree is consistent up to block #{}", up_to);
+		Ok(())
+	}
+
+	/// Get the height of the highest canonical block, at most `max_depth` blocks below the best
+	/// block, whose commitment tree is stored and holds the commitments of its outputs. This is
+	/// the best block unless the tree has fallen behind the chain. Returns `None` if none of
+	/// these blocks has such a tree.
+	pub fn commitment_tree_height(&self, max_depth: BlockNumber) -> Option<BlockNumber> {
+		let best = self.best_block_number();
+		(best.saturating_sub(max_depth)..=best).rev().find(|&number| self.commitment_tree_counts_block(number))
+	}
+
+	// whether the stored tree of the canonical block `number` has as many leaves as the tree
+	// of its parent and the outputs of the block. A tree whose parent's is not kept, as for
+	// the first block restored from a snapshot, is only checked to be stored.
+	fn commitment_tree_counts_block(&self, number: BlockNumber) -> bool {
+		let hash = match self.block_hash(number) {
+			Some(hash) => hash,
+			None => return false,
+		};
+		let (tree, block) = match (self.block_commitment_ser(&hash), self.block(&hash)) {
+			(Some(ser), Some(block)) => (ser.get_commitment_tree(), block),
+			_ => return false,
+		};
+		let outputs = block.view().shielded_outputs().len();
+		if number == 0 {
+			return tree.size() == outputs;
+		}
+		match self.block_commitment_ser(&block.parent_hash()) {
+			Some(parent) => tree.size() == parent.get_commitment_tree().size() + outputs,
+			None => true,
+		}
+	}
+
+	/// Get the latest commitment tree checkpoint of the canonical chain, the height of its
+	/// block and the commitment tree after that block.
+	///
+	/// A checkpoint is taken for every block at a multiple of `Config::tree_checkpoint_interval`,
+	/// keyed by the block hash, so a reorg needs no rewrite: the checkpoints of retracted
+	/// blocks are skipped and those of the enacted blocks found.
+	pub fn last_tree_checkpoint(&self) -> Option<(BlockNumber, CommitmentTree<Node>)> {
+		if self.tree_checkpoint_interval == 0 {
+			return None;
+		}
+		let best = self.best_block_number();
+		(1..=best / self.tree_checkpoint_interval).rev()
+			.map(|i| i * self.tree_checkpoint_interval)
+			.filter_map(|number| {
+				let hash = self.block_hash(number)?;
+				let ser: CommitmentTreeSerialization = self.db.key_value().read(db::COL_EXTRA, &TreeCheckpoint(hash))?;
+				Some((number, ser.get_commitment_tree()))
+			})
+			.next()
+	}
+
+	/// Rebuild the commitment tree after the canonical block `up_to` from the blocks, as
+	/// after a crash which lost the stored trees.
+	///
+	/// The replay starts from the latest tree checkpoint at or below `up_to`, so only the
+	/// blocks since are read, or from genesis without one. Returns `None` if a canonical
+	/// block up to `up_to` is not known.
+	pub fn recover_commitment_tree(&self, up_to: BlockNumber) -> Option<CommitmentTree<Node>> {
+		let (from, mut tree) = match self.last_tree_checkpoint() {
+			Some((number, tree)) if number <= up_to => (number + 1, tree),
+			_ => (0, CommitmentTree::new()),
+		};
+		for number in from..=up_to {
+			let block = self.block_hash(number).and_then(|hash| self.block(&hash))?;
+			update_commitment_tree_with_txs(&mut tree, &block.view().localized_private_txs());
+		}
+		info!(target: "blockchain", "Commitment tree recovered up to block #{}, {} blocks replayed", up_to, (up_to + 1).saturating_sub(from));
+		Some(tree)
+	}
+
+	/// Iterate over the anchors of the canonical blocks `from..=to`, in order.
+	///
+	/// Only the commitment tree of block `from` is read from the database. The trees of the
+	/// following blocks are derived from it by appending their shielded outputs, and the
+	/// root is only recomputed for blocks which have any. The anchors are produced as the
+	/// iterator advances, so a long range is never held in memory. The iteration stops early
+	/// at the first block which is not known, or if block `from` has no stored tree.
+	pub fn anchors(&self, from: BlockNumber, to: BlockNumber) -> AnchorIter {
+		let state = self
+			.block_hash(from)
+			.and_then(|hash| self.block_commitment_ser(&hash))
+			.map(|ser| {
+				let tree = ser.get_commitment_tree();
+				let root = tree.root();
+				(tree, root)
+			});
+		AnchorIter {
+			chain: self,
+			state,
+			next: from,
+			to,
+			started: false,
+		}
+	}
+
+	/// Drop the commitment roots of the canonical blocks below `keep_from` from the index,
+	/// so that they are no longer accepted as anchors.
+	///
+	/// At most `COMMITMENT_ROOT_PRUNE_BATCH` blocks are pruned per call and the progress is
+	/// persisted, so this is cheap enough to be called on every tick. A root which is shared
+	/// with a retained or pinned block (blocks without shielded outputs keep the root of their
+	/// parent) stays indexed. Returns the number of roots removed.
+	pub fn prune_commitment_roots(&self, keep_from: BlockNumber) -> usize {
+		let oldest = *self.oldest_commitment_root.read();
+		let until = cmp::min(keep_from, oldest.saturating_add(COMMITMENT_ROOT_PRUNE_BATCH));
+		if until <= oldest {
+			return 0;
+		}
+
+		let pinned_roots = self.pinned_roots();
+		let roots: Vec<Node> = (oldest..until)
+			.filter_map(|number| {
+				let hash = self.block_hash(number)?;
+				let root = self.block_commitment_ser(&hash)?.get_commitment_tree().root();
+				match self.commitment_root_block(&root) {
+					Some(ref indexed) if *indexed == hash && !pinned_roots.contains(&root) => Some(root),
+					_ => None,
+				}
+			})
+			.collect();
+
+		let mut batch = self.db.key_value().transaction();
+		{
+			let mut commitment_root_blocks = self.commitment_root_blocks.write();
+			for root in &roots {
+				batch.delete(db::COL_EXTRA, &Key::<H256>::key(root));
+				commitment_root_blocks.remove(root);
+			}
+		}
+		batch.put(db::COL_EXTRA, b"oldest_root", &rlp::encode(&until));
+		self.db.key_value().write(batch).expect("Low level database error when pruning commitment roots. Some issue with disk?");
+		*self.oldest_commitment_root.write() = until;
+
+		trace!(target: "blockchain", "Pruned {} commitment roots below block #{}", roots.len(), until);
+		roots.len()
+	}
+
+	/// Pin a block, so that its commitment root is never pruned. The root is indexed again
+	/// if it was pruned already. The pinned set is persisted.
+	///
+	/// Pinning only keeps the root indexed: whether it is a valid anchor still depends on
+	/// the anchor window, which is the same on every node.
+	pub fn pin_block(&self, hash: H256) {
+		let mut batch = self.db.key_value().transaction();
+		{
+			let mut pinned = self.pinned_blocks.write();
+			if !pinned.insert(hash) {
+				return;
+			}
+			batch.put(db::COL_EXTRA, b"pinned_blocks", &rlp::encode_list(&pinned.iter().cloned().collect::<Vec<H256>>()));
+		}
+
+		if let Some(ser) = self.block_commitment_ser(&hash) {
+			let root = ser.get_commitment_tree().root();
+			if self.commitment_root_block(&root).is_none() {
+				batch.write(db::COL_EXTRA, &root, &hash);
+			}
+		}
+		self.db.key_value().write(batch).expect("Low level database error when pinning a block. Some issue with disk?");
+	}
+
+	/// Unpin a block, dropping its commitment root from the index if pruning has already
+	/// passed it. Returns `false` if the block was not pinned.
+	pub fn unpin_block(&self, hash: &H256) -> bool {
+		let mut batch = self.db.key_value().transaction();
+		{
+			let mut pinned = self.pinned_blocks.write();
+			if !pinned.remove(hash) {
+				return false;
+			}
+			batch.put(db::COL_EXTRA, b"pinned_blocks", &rlp::encode_list(&pinned.iter().cloned().collect::<Vec<H256>>()));
+		}
+
+		if let Some(ser) = self.block_commitment_ser(hash) {
+			let root = ser.get_commitment_tree().root();
+			let oldest = *self.oldest_commitment_root.read();
+			let pruned = self.commitment_root_block(&root)
+				.and_then(|indexed| self.block_number(&indexed))
+				.map_or(false, |number| number < oldest);
+			if pruned && !self.pinned_roots().contains(&root) {
+				batch.delete(db::COL_EXTRA, &Key::<H256>::key(&root));
+				self.commitment_root_blocks.write().remove(&root);
+			}
+		}
+		self.db.key_value().write(batch).expect("Low level database error when unpinning a block. Some issue with disk?");
+		true
+	}
+
+	/// Install a trusted checkpoint of the shielded state with the nullifier set it was
+	/// taken with. Once installed, the tree of the checkpoint is the parent tree of the
+	/// block following it, instead of the tree the node computes for the block at the
+	/// checkpoint height, which lacks the commitments of the blocks it does not have. The
+	/// nullifiers are added to the nullifier set and the checkpoint is persisted.
+	///
+	/// The checkpoint must be consistent, the nullifier snapshot must be taken at its height
+	/// and hash to its `nullifier_set_hash`, and the best block must be below its height, so
+	/// that the node never has computed the state it replaces. See `ShieldedCheckpoint` for
+	/// what loading one trusts.
+	pub fn install_shielded_checkpoint(&self, checkpoint: ShieldedCheckpoint, nullifiers: &NullifierSnapshot) -> Result<(), CheckpointError> {
+		checkpoint.validate()?;
+		if nullifiers.block_number != checkpoint.height {
+			return Err(CheckpointError::NullifierSetHeight { expected: checkpoint.height, found: nullifiers.block_number });
+		}
+		let computed = nullifiers.hash();
+		if computed != checkpoint.nullifier_set_hash {
+			return Err(CheckpointError::NullifierSetMismatch { expected: checkpoint.nullifier_set_hash, computed });
+		}
+		let best = self.best_block_number();
+		if best >= checkpoint.height {
+			return Err(CheckpointError::NotBelowCheckpoint { best, height: checkpoint.height });
+		}
+
+		let mut batch = self.db.key_value().transaction();
+		for &(ref nullifier, ref tx_hash) in &nullifiers.entries {
+			batch.write(db::COL_EXTRA, &U256::from(&nullifier[..]), tx_hash);
+		}
+		batch.put(db::COL_EXTRA, b"shielded_checkpoint", &rlp::encode(&checkpoint));
+		self.db.key_value().write(batch).expect("Low level database error when installing a shielded checkpoint. Some issue with disk?");
+		info!(target: "blockchain", "Installed shielded checkpoint at block #{}", checkpoint.height);
+		*self.shielded_checkpoint.write() = Some(checkpoint);
+		Ok(())
+	}
+
+	/// Get the installed shielded checkpoint.
+	pub fn shielded_checkpoint(&self) -> Option<ShieldedCheckpoint> {
+		self.shielded_checkpoint.read().clone()
+	}
+
+	/// Get a fingerprint of the shielded state after the canonical block `height`, for
+	/// comparing nodes: the keccak of the RLP list of the anchor, the hash of the nullifier
+	/// set and the size of the commitment tree, in this order. Honest nodes agree on it at
+	/// every height. Returns `None` if `height` is above the best block or a block since
+	/// `height` is not known.
+	///
+	/// The nullifier set hash is the `nullifier_set_hash` of the set stored in the
+	/// database, leaving out the nullifiers revealed after `height`, which are read from
+	/// the blocks since. The set must not change meanwhile, so the caller
+	/// must prevent block imports, as `Client::shielded_state_fingerprint` does.
+	pub fn shielded_state_fingerprint(&self, height: BlockNumber) -> Option<H256> {
+		let best = self.best_block_number();
+		if height > best {
+			return None;
+		}
+		let hash = self.block_hash(height)?;
+		let tree = self.block_commitment_ser(&hash)?.get_commitment_tree();
+		let mut revealed_since = HashSet::new();
+		for number in height + 1..=best {
+			let block_hash = self.block_hash(number)?;
+			revealed_since.extend(self.block_nullifier_delta(&block_hash)?);
+		}
+
+		let nullifiers_hash = nullifier_set_hash(
+			self.nullifier_set().iter().filter(|nullifier| !revealed_since.contains(nullifier))
+		);
+
+		let mut anchor = Vec::new();
+		tree.root().write(&mut anchor).expect("writing to a vector should not fail");
+		let mut s = RlpStream::new_list(3);
+		s.append(&anchor);
+		s.append(&nullifiers_hash);
+		s.append(&(tree.size() as u64));
+		Some(keccak(s.out()))
+	}
+
+	/// Get the commitment tree of the parent of block `number`. The tree of the shielded
+	/// checkpoint takes precedence if the parent is at its height.
+	fn parent_commitment_ser(&self, parent_hash: &H256, number: BlockNumber) -> Option<CommitmentTreeSerialization> {
+		self.shielded_checkpoint.read().as_ref()
+			.filter(|checkpoint| checkpoint.height + 1 == number)
+			.map(|checkpoint| CommitmentTreeSerialization::new(&checkpoint.tree_frontier))
+			.or_else(|| self.block_commitment_ser(parent_hash))
+	}
+
+	/// Get the pinned blocks.
+	pub fn pinned_blocks(&self) -> Vec<H256> {
+		self.pinned_blocks.read().iter().cloned().collect()
+	}
+
+	fn pinned_roots(&self) -> HashSet<Node> {
+		self.pinned_blocks.read().iter()
+			.filter_map(|hash| self.block_commitment_ser(hash))
+			.map(|ser| ser.get_commitment_tree().root())
+			.collect()
+	}
+
+	/// Register a callback run once every sender of the sync transactions given to `new`
+	/// is dropped, so that the embedder can re-establish the source or raise an alert.
+	/// It replaces any previous callback and runs at once if the source is already lost.
+	pub fn on_sync_source_lost<F>(&self, callback: F) where F: FnMut() + Send + 'static {
+		let mut callback: Box<FnMut() + Send> = Box::new(callback);
+		{
+			let mut watch = self.sync_source.lock();
+			if !watch.lost {
+				watch.callback = Some(callback);
+				return;
+			}
+		}
+		callback();
+	}
+
+	/// Get the range of blocks whose commitment roots are still accepted as anchors.
+	pub fn anchor_history_info(&self) -> AnchorHistoryInfo {
+		let oldest_retained = *self.oldest_commitment_root.read();
+		AnchorHistoryInfo {
+			retained: (self.best_block_number() + 1).saturating_sub(oldest_retained),
+			oldest_retained,
+		}
+	}
+
+	/// Get the commitment roots which spends on top of the best block may use as anchor,
+	/// those of the canonical blocks at most `window` blocks below it, newest first.
+	///
+	/// Blocks without shielded outputs share the root of their parent, so each root is
+	/// given once, with the highest block it is the root of. The walk stops at the oldest
+	/// block whose root is retained, so roots already pruned from the index are left out
+	/// and a window wider than the retained history costs no more than the history.
+	pub fn valid_anchors(&self, window: u64) -> Vec<(BlockNumber, Node)> {
+		let best = self.best_block_number();
+		let from = cmp::max(best.saturating_sub(window), *self.oldest_commitment_root.read());
+		let mut anchors: Vec<_> = self.anchors(from, best).collect();
+		anchors.reverse();
+
+		let mut seen = HashSet::new();
+		anchors.retain(|(_, root)| seen.insert(*root) && self.commitment_root_block(root).is_some());
+		anchors
+	}
+
+	/// Check that `witness` authenticates `leaf` and that its root is the commitment root
+	/// of a canonical block at most `window` blocks below the best block, so that a spend
+	/// of the note would be accepted by the pool.
+	pub fn validate_witness(&self, leaf: &Node, witness: &IncrementalWitness<Node>, window: u64) -> bool {
+		let root = witness.root();
+		if witness.path().and_then(|path| path.root(*leaf)) != Some(root) {
+			return false;
+		}
+		let best = self.best_block_number();
+		self.commitment_root_block(&root)
+			.and_then(|hash| self.block_number(&hash))
+			.map_or(false, |number| number <= best && best - number <= window)
+	}
+
 	/// Iterate over all epoch transitions.
 	/// This will only return transitions within the canonical chain.
 	pub fn epoch_transitions(&self) -> EpochTransitionIter {
@@ -1496,8 +2266,7 @@ impl BlockChain {
 		batch.put(db::COL_BODIES, &hash, &compressed_body);
 
 		let info = self.block_info(&block.header_view(), route, &extras);
-		let maybe_parent_commitment_tree_ser = self.block_commitment_ser(&parent_hash);
-		let private_txs = block.view().localized_private_txs();
+		let maybe_parent_commitment_tree_ser = self.parent_commitment_ser(&parent_hash, info.number);
 
 		if let BlockLocation::BranchBecomingCanonChain(ref d) = info.location {
 			info!(target: "reorg", "Reorg to {} ({} {} {})",
@@ -1508,7 +2277,37 @@ impl BlockChain {
 			);
 		}
 
-		self.prepare_wallet_update(batch, &maybe_parent_commitment_tree_ser, &block, &info);
+		// a block without shielded components keeps the commitment tree and nullifier set of
+		// its parent, so there is nothing for the wallet to scan and the tree need not be
+		// rebuilt. The wallet data is still written, it may hold newly imported addresses.
+		// Reorgs take the full path, the retracted blocks may be shielded.
+		let unshielded = match info.location {
+			BlockLocation::BranchBecomingCanonChain(_) => false,
+			_ => !block.has_shielded_components(),
+		};
+		let (transactions_nullifiers, commitment_root_blocks, blocks_commitment_sers) = if unshielded {
+			self.prepare_wallet_data_update(batch, &self.pt_wallet.read().unwrap());
+			let (roots, sers) = self.prepare_unshielded_commitment_update(&maybe_parent_commitment_tree_ser, &info);
+			(HashMap::new(), roots, sers)
+		} else {
+			let private_txs = block.view().localized_private_txs();
+			self.prepare_wallet_update(batch, &maybe_parent_commitment_tree_ser, &block, &info);
+			#[cfg(feature = "commitment-index")]
+			self.prepare_commitment_positions_update(batch, &maybe_parent_commitment_tree_ser, &private_txs, &info);
+			(
+				self.prepare_transaction_nullifiers_update(&private_txs, &info),
+				self.prepare_commitment_root_blocks_update(&maybe_parent_commitment_tree_ser, &private_txs, &info),
+				self.prepare_block_commitment_sers_update(&maybe_parent_commitment_tree_ser, &private_txs, &info),
+			)
+		};
+
+		if let Some(ser) = blocks_commitment_sers.get(&info.hash) {
+			self.prepare_tree_checkpoint_update(batch, ser, &info);
+		}
+		if info.location != BlockLocation::Branch && self.nullifier_set_is_current() {
+			batch.put(db::COL_EXTRA, b"nullifier_set_best", &info.hash);
+		}
+
 		self.prepare_update(
 			batch,
 			ExtrasUpdate {
@@ -1523,18 +2322,9 @@ impl BlockChain {
 					.prepare_block_blooms_update(block.header_view().log_bloom(), &info),
 				transactions_addresses: self
 					.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info),
-				transactions_nullifiers: self
-					.prepare_transaction_nullifiers_update(&private_txs, &info),
-				commitment_root_blocks: self.prepare_commitment_root_blocks_update(
-					&maybe_parent_commitment_tree_ser,
-					&private_txs,
-					&info,
-				),
-				blocks_commitment_sers: self.prepare_block_commitment_sers_update(
-					&maybe_parent_commitment_tree_ser,
-					&private_txs,
-					&info,
-				),
+				transactions_nullifiers,
+				commitment_root_blocks,
+				blocks_commitment_sers,
 				rewards: self.prepare_rewards_update(&info, &block),
 				info: info.clone(),
 				block,
@@ -2196,25 +2986,99 @@ impl BlockChain {
 		}
 	}
 
-	/// This function returns modified commitment tree serialization.
-	/// If the parent_commitment_tree_ser_option is None,
-	/// Return empty HashMap
-	fn prepare_block_commitment_sers_update(
+	/// Persist the commitment tree of the block as a tree checkpoint if the block is at a
+	/// checkpoint height. Blocks of a branch get one as well, as a reorg may enact them.
+	fn prepare_tree_checkpoint_update(&self, batch: &mut DBTransaction, ser: &CommitmentTreeSerialization, info: &BlockInfo) {
+		if self.tree_checkpoint_interval == 0 || info.number % self.tree_checkpoint_interval != 0 {
+			return;
+		}
+		batch.write(db::COL_EXTRA, &TreeCheckpoint(info.hash), ser);
+	}
+
+	/// Index the position of the note commitments which join the canonical commitment tree
+	/// and drop those of the retracted blocks. Entries are written to the batch directly,
+	/// positions are only read from the database.
+	#[cfg(feature = "commitment-index")]
+	fn prepare_commitment_positions_update(
 		&self,
+		batch: &mut DBTransaction,
 		parent_commitment_tree_ser_option: &Option<CommitmentTreeSerialization>,
 		private_txs: &Vec<LocalizedTransaction>,
 		info: &BlockInfo,
-	) -> HashMap<H256, CommitmentTreeSerialization> {
-		let mut result = HashMap::new();
-		if let Some(parent_commitment_tree_ser) = parent_commitment_tree_ser_option {
-			let mut parent_commitment_tree = parent_commitment_tree_ser.get_commitment_tree();
-			update_commitment_tree_with_txs(&mut parent_commitment_tree, &private_txs);
-			let mut commitment_tree_ser = CommitmentTreeSerialization::new(&parent_commitment_tree);
-			result.insert(info.hash.clone(), commitment_tree_ser);
+	) {
+		fn index(batch: &mut DBTransaction, first: u64, commitments: Vec<Node>) {
+			for (position, cm) in (first..).zip(commitments) {
+				batch.write(db::COL_EXTRA, &CommitmentPosition(cm), &position);
+			}
+		}
+
+		match info.location {
+			BlockLocation::CanonChain => {}
+			BlockLocation::BranchBecomingCanonChain(ref data) => {
+				// removals go first, so commitments mined again by the enacted blocks stay.
+				for hash in &data.retracted {
+					let block = self.block(hash).expect("retracted blocks are in the database; qed");
+					for cm in commitments_of(&block.view().localized_private_txs()) {
+						batch.delete(db::COL_EXTRA, &CommitmentPosition(cm));
+					}
+				}
+				for hash in &data.enacted {
+					let block = self.block(hash).expect("enacted blocks are in the database; qed");
+					let first = self.parent_commitment_ser(&block.header_view().parent_hash(), block.header_view().number())
+						.map(|ser| ser.get_commitment_tree().size() as u64);
+					if let Some(first) = first {
+						index(batch, first, commitments_of(&block.view().localized_private_txs()));
+					}
+				}
+			}
+			BlockLocation::Branch => return,
+		}
+
+		if let Some(parent_commitment_tree_ser) = parent_commitment_tree_ser_option {
+			let first = parent_commitment_tree_ser.get_commitment_tree().size() as u64;
+			index(batch, first, commitments_of(private_txs));
+		}
+	}
+
+	/// This function returns modified commitment tree serialization.
+	/// If the parent_commitment_tree_ser_option is None,
+	/// Return empty HashMap
+	fn prepare_block_commitment_sers_update(
+		&self,
+		parent_commitment_tree_ser_option: &Option<CommitmentTreeSerialization>,
+		private_txs: &Vec<LocalizedTransaction>,
+		info: &BlockInfo,
+	) -> HashMap<H256, CommitmentTreeSerialization> {
+		let mut result = HashMap::new();
+		if let Some(parent_commitment_tree_ser) = parent_commitment_tree_ser_option {
+			let mut parent_commitment_tree = parent_commitment_tree_ser.get_commitment_tree();
+			update_commitment_tree_with_txs(&mut parent_commitment_tree, &private_txs);
+			let mut commitment_tree_ser = CommitmentTreeSerialization::new(&parent_commitment_tree);
+			result.insert(info.hash.clone(), commitment_tree_ser);
 		}
 		result
 	}
 
+	/// This function returns the commitment updates of a block without shielded components.
+	/// The block shares the commitment tree of its parent, so the parent serialization is
+	/// copied instead of deserializing the tree, appending nothing and serializing it again,
+	/// and the root only moves its index to this block on the canon chain.
+	fn prepare_unshielded_commitment_update(
+		&self,
+		parent_commitment_tree_ser_option: &Option<CommitmentTreeSerialization>,
+		info: &BlockInfo,
+	) -> (HashMap<Node, Option<H256>>, HashMap<H256, CommitmentTreeSerialization>) {
+		let mut roots = HashMap::new();
+		let mut sers = HashMap::new();
+		if let Some(parent_commitment_tree_ser) = parent_commitment_tree_ser_option {
+			if let BlockLocation::CanonChain = info.location {
+				roots.insert(parent_commitment_tree_ser.get_commitment_tree().root(), Some(info.hash));
+			}
+			sers.insert(info.hash, parent_commitment_tree_ser.clone());
+		}
+		(roots, sers)
+	}
+
 	/// This function update the block info into the wallet.
 	fn prepare_wallet_update(&self, batch: &mut DBTransaction,
 							 parent_commitment_tree_ser_option: &Option<CommitmentTreeSerialization>,
@@ -2254,9 +3118,23 @@ impl BlockChain {
 			}
 			BlockLocation::Branch => {},
 		}
-		pt_wallet.write_wallet_data(batch);
+		self.prepare_wallet_data_update(batch, &pt_wallet);
+	}
+
+	/// Write the wallet transactions and addresses, and remember the addresses as handled
+	/// once the block is committed.
+	///
+	/// Addresses whose rescan was deferred are not handled yet, so the handled addresses are
+	/// left as they are and the rescan is retried with the next block.
+	fn prepare_wallet_data_update(&self, batch: &mut DBTransaction, pt_wallet: &Wallet) {
+		let addresses = if self.wallet_rescan_deferred.load(AtomicOrdering::SeqCst) {
+			self.pt_wallet_addresses.read().clone()
+		} else {
+			pt_wallet.get_wallet_addresses()
+		};
+		pt_wallet.write_wallet_data(batch, &addresses);
 		let mut pending_wallet_addresses = self.pending_pt_wallet_addresses.write();
-		*pending_wallet_addresses = Some(pt_wallet.get_wallet_addresses());
+		*pending_wallet_addresses = Some(addresses);
 	}
 
 	/// Reload the wallet info when blockchain restarts.
@@ -2330,24 +3208,44 @@ impl BlockChain {
 			}
 		}
 
-		if exist_new_imported_user {
-			self.scan_for_wallet_transactions(&Some(handled_addresses));
-		}
+		// block import must not wait behind a user's rescan, it tries again with the next block.
+		let deferred = exist_new_imported_user && match self.scan_for_wallet_transactions(&Some(handled_addresses), false) {
+			Ok(()) => false,
+			Err(e) => {
+				debug!(target: "blockchain", "Deferring the rescan for newly imported wallet addresses: {}", e);
+				true
+			},
+		};
+		self.wallet_rescan_deferred.store(deferred, AtomicOrdering::SeqCst);
 	}
 
-	pub fn scan_for_wallet_transactions(&self, filter_exist_addresses: &Option<HashSet<String>>) {
+	/// Rescan the canonical chain for the transactions of the wallet addresses, skipping the
+	/// addresses in `filter_exist_addresses`.
+	///
+	/// At most `Config::max_concurrent_rescans` rescans run at the same time. A rescan over
+	/// the limit waits for a slot if `wait` is set and fails with `TooManyRescans` otherwise.
+	pub fn scan_for_wallet_transactions(&self, filter_exist_addresses: &Option<HashSet<String>>, wait: bool) -> Result<(), TooManyRescans> {
+		let _slot = if wait {
+			self.rescans.acquire()
+		} else {
+			self.rescans.try_acquire()?
+		};
+
 		let tail = self.best_block_hash();
 		let hash_vec = self.ancestry_iter(tail).unwrap().collect::<Vec<_>>();
-		let mut wallet = self.pt_wallet.write().unwrap();
 
 		for hash in hash_vec.iter().rev() {
 			if let Some(block) = self.block(hash) {
 				if let Some(parent_commitment_tree_ser) = self.block_commitment_ser(&block.parent_hash()) {
 					let mut parent_commitment_tree = parent_commitment_tree_ser.get_commitment_tree();
+					// the wallet is only locked while scanning one block, so that
+					// imports and wallet queries are not held up for the whole chain.
+					let mut wallet = self.pt_wallet.write().unwrap();
 					wallet.scan_new_block(&mut parent_commitment_tree, &block, filter_exist_addresses);
 				}
 			}
 		}
+		Ok(())
 	}
 
 	/// This functions returns modified blocks blooms.
@@ -2675,6 +3573,15 @@ impl BlockChain {
 	}
 }
 
+/// The note commitments of the transactions, in the order they are appended to the tree.
+#[cfg(feature = "commitment-index")]
+fn commitments_of(private_txs: &Vec<LocalizedTransaction>) -> Vec<Node> {
+	private_txs.iter()
+		.flat_map(|tx| tx.get_commitment_notes())
+		.map(|fr| Node::new(fr.into_repr()))
+		.collect()
+}
+
 /// Update the commitment tree with the notes in the transactions.
 fn update_commitment_tree_with_txs(
 	commitment_tree: &mut CommitmentTree<Node>,
@@ -2695,6 +3602,8 @@ mod tests {
 
 	use std::iter;
 	use std::fs;
+	use std::sync::atomic::AtomicUsize;
+	use std::time::Duration;
 
 	use rand::{OsRng, Rand};
 	use crate::wallet::wallet_types::{
@@ -2714,8 +3623,9 @@ mod tests {
 
 	use crate::generator::{BlockBuilder, BlockGenerator, BlockOptions};
 	use common_types::receipt::{Receipt, TransactionOutcome};
-	use common_types::transaction::{Action, Transaction, CONVERSION_FACTOR};
+	use common_types::transaction::{Action, PendingTransaction, Transaction, CONVERSION_FACTOR};
 	use ethkey::Secret;
+	use ethereum_types::H264;
 	use keccak_hash::keccak;
 	use rustc_hex::FromHex;
 	use tempdir::TempDir;
@@ -3016,7 +3926,7 @@ mod tests {
 		}
 		assert_eq!(bc.pt_wallet.read().unwrap().tx_nullifiers.len(), 0);
 		let handled_addresses: HashSet<String> = HashSet::new();
-		bc.scan_for_wallet_transactions(&Some(handled_addresses));
+		bc.scan_for_wallet_transactions(&Some(handled_addresses), false).unwrap();
 		assert_eq!(bc.pt_wallet.read().unwrap().tx_nullifiers.len(), 1);
 		assert_eq!(bc.pt_wallet.read().unwrap().nullifier_notes.len(), 2);
 		assert_eq!(bc.pt_wallet.read().unwrap().address_nullifiers.len(), 1);
@@ -3083,6 +3993,62 @@ mod tests {
 		remove_old_key_files(wallet_file_prefix, "./");
 	}
 
+	#[test]
+	fn test_unshielded_blocks_persist_imported_addresses() {
+		let wallet_file_prefix = "wallet_for_unshielded";
+		let genesis = BlockBuilder::genesis();
+		let rest = genesis.add_blocks(3);
+		let db = new_db();
+		let config = Config::new(wallet_file_prefix.to_string());
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		let address = bc.pt_wallet.write().unwrap().create_new_private_address(None);
+
+		// the first block rescans for the imported address, the next ones know it is handled.
+		let mut batch = db.key_value().transaction();
+		for block in BlockGenerator::new(vec![rest]) {
+			insert_block_batch(&mut batch, &bc, block.encoded(), vec![]);
+			bc.commit();
+		}
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.rescans.started(), 1);
+		assert_eq!(bc.pt_wallet_addresses.read().addresses, vec![address.clone()]);
+
+		let persisted: WalletTxAddresses = rlp::decode(&db.key_value().get(db::COL_EXTRA, b"wallet_addresses").unwrap().unwrap()).unwrap();
+		assert_eq!(persisted.addresses, vec![address]);
+
+		remove_old_key_files(wallet_file_prefix, "./");
+	}
+
+	#[test]
+	fn test_import_defers_rescan_while_slots_are_busy() {
+		let wallet_file_prefix = "wallet_for_deferred_rescan";
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block();
+		let b2 = b1.add_block();
+		let db = new_db();
+		let config = Config::new(wallet_file_prefix.to_string());
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		let address = bc.pt_wallet.write().unwrap().create_new_private_address(None);
+
+		// a user's rescan holds the only slot: the import goes on without rescanning.
+		let slot = bc.rescans.try_acquire().unwrap();
+		let mut batch = db.key_value().transaction();
+		insert_block_batch(&mut batch, &bc, b1.last().encoded(), vec![]);
+		bc.commit();
+		assert_eq!(bc.rescans.started(), 1);
+		assert!(bc.pt_wallet_addresses.read().addresses.is_empty());
+
+		// the next block retries it.
+		drop(slot);
+		insert_block_batch(&mut batch, &bc, b2.last().encoded(), vec![]);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.rescans.started(), 2);
+		assert_eq!(bc.pt_wallet_addresses.read().addresses, vec![address]);
+
+		remove_old_key_files(wallet_file_prefix, "./");
+	}
+
 	#[test]
 	fn test_db_transaction_batch_insert_and_get() {
 		let db = new_db();
@@ -3299,6 +4265,359 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_nullifier_delta_on_reorg() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let t2 =
+			Transaction::create_private(vec![1, 2], 43.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1a = genesis.add_block_with_transactions(vec![t1]);
+		let b1b = genesis.add_block_with_difficulty(9);
+		let b2 = b1b.add_block_with_transactions(vec![t2]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+
+		let mut batch = db.key_value().transaction();
+		let route = insert_block_batch(&mut batch, &bc, b1a.last().encoded(), vec![]);
+		bc.commit();
+		let _ = insert_block_batch(&mut batch, &bc, b1b.last().encoded(), vec![]);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.nullifier_delta(&route), NullifierDelta { added: vec![[0; 32], [1; 32]], removed: vec![] });
+		assert_eq!(bc.block_nullifier_delta(&b1a.last().hash()), Some(vec![[0; 32], [1; 32]]));
+
+		// the fork becomes canon: the nullifier spent on both branches stays.
+		let mut batch = db.key_value().transaction();
+		let route = insert_block_batch(&mut batch, &bc, b2.last().encoded(), vec![]);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(route.retracted, vec![b1a.last().hash()]);
+		assert_eq!(bc.nullifier_delta(&route), NullifierDelta { added: vec![[2; 32]], removed: vec![[0; 32]] });
+	}
+
+	#[cfg(feature = "commitment-index")]
+	#[test]
+	fn test_commitment_position_on_reorg() {
+		use pairing::bls12_381::{Fr, FrRepr};
+
+		let cm = |n: u64| Node::new(Fr::from_repr(FrRepr::from(n)).unwrap().into_repr());
+		let shielded_tx = |nonce: u64, cms: [u64; 2]| {
+			let mut tx = Transaction::create_private(vec![nonce as u8], nonce.into(), Action::PrivateToPrivate);
+			for (output, n) in tx.private.as_mut().unwrap().outputs.iter_mut().zip(cms.iter()) {
+				output.cmu = Fr::from_repr(FrRepr::from(*n)).unwrap();
+			}
+			tx.sign_for_private(TEST_CHAIN_ID)
+		};
+
+		let genesis = BlockBuilder::genesis();
+		let b1a = genesis.add_block_with_transactions(vec![shielded_tx(1, [1, 2])]);
+		let b1b = genesis.add_block_with_difficulty(9);
+		let b2 = b1b.add_block_with_transactions(vec![shielded_tx(2, [2, 3])]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+
+		let mut batch = db.key_value().transaction();
+		insert_block_batch(&mut batch, &bc, b1a.last().encoded(), vec![]);
+		bc.commit();
+		insert_block_batch(&mut batch, &bc, b1b.last().encoded(), vec![]);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.commitment_position(&cm(1)), Some(0));
+		assert_eq!(bc.commitment_position(&cm(2)), Some(1));
+		assert_eq!(bc.commitment_position(&cm(3)), None);
+
+		// the fork becomes canon: the commitment mined on both branches moves.
+		let mut batch = db.key_value().transaction();
+		insert_block_batch(&mut batch, &bc, b2.last().encoded(), vec![]);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.commitment_position(&cm(1)), None);
+		assert_eq!(bc.commitment_position(&cm(2)), Some(0));
+		assert_eq!(bc.commitment_position(&cm(3)), Some(1));
+	}
+
+	#[cfg(feature = "commitment-index")]
+	#[test]
+	fn test_commitment_position_of_unordered_blocks() {
+		use pairing::bls12_381::{Fr, FrRepr};
+
+		let cm = |n: u64| Node::new(Fr::from_repr(FrRepr::from(n)).unwrap().into_repr());
+		let mut tx = Transaction::create_private(vec![1], 1.into(), Action::PrivateToPrivate);
+		for (output, n) in tx.private.as_mut().unwrap().outputs.iter_mut().zip(&[1u64, 2]) {
+			output.cmu = Fr::from_repr(FrRepr::from(*n)).unwrap();
+		}
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![tx.sign_for_private(TEST_CHAIN_ID)]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+
+		// as the blocks of a snapshot or the ancient blocks are imported.
+		let mut batch = db.key_value().transaction();
+		bc.insert_unordered_block(&mut batch, b1.last().encoded(), vec![], None, true, false);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert_eq!(bc.commitment_position(&cm(1)), Some(0));
+		assert_eq!(bc.commitment_position(&cm(2)), Some(1));
+	}
+
+	#[test]
+	fn test_commitment_tree_height_skips_trees_missing_commitments() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let blocks = genesis.add_blocks_with(3, || BlockOptions {
+			transactions: vec![t1.clone()],
+			..Default::default()
+		});
+
+		let db = new_db();
+		let bc = BlockChain::new(Config::default(), genesis.last().encoded().raw(), db.clone(), None);
+		for block in BlockGenerator::new(vec![blocks]) {
+			insert_block(&db, &bc, block.encoded(), vec![]);
+		}
+		assert_eq!(bc.commitment_tree_height(0), Some(3));
+
+		// the best block stores the tree of its parent, as if its outputs were never appended.
+		let parent_tree = bc.block_commitment_ser(&bc.block_hash(2).unwrap()).unwrap();
+		let mut batch = db.key_value().transaction();
+		batch.write(db::COL_EXTRA, &bc.block_hash(3).unwrap(), &parent_tree);
+		db.key_value().write(batch).unwrap();
+		drop(bc);
+
+		let bc = BlockChain::new(Config::default(), genesis.last().encoded().raw(), db.clone(), None);
+		assert_eq!(bc.commitment_tree_height(0), None);
+		assert_eq!(bc.commitment_tree_height(1), Some(2));
+	}
+
+	#[test]
+	fn test_recover_commitment_tree_from_checkpoint() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let blocks = genesis.add_blocks_with(10, || BlockOptions {
+			transactions: vec![t1.clone()],
+			..Default::default()
+		});
+
+		let db = new_db();
+		let config = Config { tree_checkpoint_interval: 4, ..Default::default() };
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		for block in BlockGenerator::new(vec![blocks]) {
+			insert_block(&db, &bc, block.encoded(), vec![]);
+		}
+
+		let (number, checkpoint) = bc.last_tree_checkpoint().unwrap();
+		assert_eq!(number, 8);
+		assert_eq!(Some(checkpoint.root()), bc.block_hash(8).and_then(|h| bc.block_commitment_ser(&h)).map(|ser| ser.get_commitment_tree().root()));
+
+		let mut replayed = CommitmentTree::<Node>::new();
+		for number in 0..=10 {
+			let block = bc.block(&bc.block_hash(number).unwrap()).unwrap();
+			update_commitment_tree_with_txs(&mut replayed, &block.view().localized_private_txs());
+		}
+		assert_eq!(bc.recover_commitment_tree(10).map(|tree| tree.root()), Some(replayed.root()));
+		// below the checkpoint, the replay starts from genesis.
+		let stored = bc.block_commitment_ser(&bc.block_hash(5).unwrap()).unwrap().get_commitment_tree();
+		assert_eq!(bc.recover_commitment_tree(5).map(|tree| tree.root()), Some(stored.root()));
+	}
+
+	#[test]
+	fn test_tree_checkpoint_follows_reorg() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let t2 =
+			Transaction::create_private(vec![2], 43.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let a = genesis.add_blocks_with(4, || BlockOptions {
+			transactions: vec![t1.clone()],
+			..Default::default()
+		});
+		let b = genesis.add_blocks_with(5, || BlockOptions {
+			transactions: vec![t2.clone()],
+			..Default::default()
+		});
+
+		let db = new_db();
+		let config = Config { tree_checkpoint_interval: 4, ..Default::default() };
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		for block in BlockGenerator::new(vec![a]) {
+			insert_block(&db, &bc, block.encoded(), vec![]);
+		}
+		let (_, checkpoint_a) = bc.last_tree_checkpoint().unwrap();
+
+		for block in BlockGenerator::new(vec![b]) {
+			insert_block(&db, &bc, block.encoded(), vec![]);
+		}
+		assert_eq!(bc.best_block_number(), 5);
+
+		let (number, checkpoint) = bc.last_tree_checkpoint().unwrap();
+		assert_eq!(number, 4);
+		assert!(checkpoint.root() != checkpoint_a.root());
+		assert_eq!(Some(checkpoint.root()), bc.block_hash(4).and_then(|h| bc.block_commitment_ser(&h)).map(|ser| ser.get_commitment_tree().root()));
+	}
+
+	#[test]
+	fn test_missing_best_commitment_tree_is_recovered_on_open() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let blocks = genesis.add_blocks_with(6, || BlockOptions {
+			transactions: vec![t1.clone()],
+			..Default::default()
+		});
+
+		let db = new_db();
+		let config = Config { tree_checkpoint_interval: 4, ..Default::default() };
+		let (best_hash, root) = {
+			let bc = BlockChain::new(config.clone(), genesis.last().encoded().raw(), db.clone(), None);
+			for block in BlockGenerator::new(vec![blocks]) {
+				insert_block(&db, &bc, block.encoded(), vec![]);
+			}
+			let best_hash = bc.best_block_hash();
+			(best_hash, bc.block_commitment_ser(&best_hash).unwrap().get_commitment_tree().root())
+		};
+
+		let mut batch = db.key_value().transaction();
+		batch.delete::<CommitmentTreeSerialization, H264>(db::COL_EXTRA, &best_hash);
+		db.key_value().write(batch).unwrap();
+
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		assert_eq!(bc.block_commitment_ser(&best_hash).map(|ser| ser.get_commitment_tree().root()), Some(root));
+	}
+
+	#[test]
+	fn test_shielded_state_fingerprint_agrees_across_nodes() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let t2 =
+			Transaction::create_private(vec![2], 43.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let t3 =
+			Transaction::create_private(vec![3], 44.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let first = genesis.add_block_with_transactions(vec![t1]);
+		let second = first.add_block_with_transactions(vec![t2]);
+		let other = first.add_block_with_transactions(vec![t3]);
+
+		let node = |blocks: Vec<&BlockBuilder>| {
+			let db = new_db();
+			let bc = new_chain(genesis.last().encoded(), db.clone());
+			for block in blocks {
+				insert_block(&db, &bc, block.last().encoded(), vec![]);
+			}
+			bc
+		};
+		let a = node(vec![&first, &second]);
+		let b = node(vec![&first, &second]);
+		let c = node(vec![&first, &other]);
+
+		for height in 0..=2 {
+			assert!(a.shielded_state_fingerprint(height).is_some());
+			assert_eq!(a.shielded_state_fingerprint(height), b.shielded_state_fingerprint(height));
+		}
+		assert_eq!(a.shielded_state_fingerprint(1), c.shielded_state_fingerprint(1));
+		assert_ne!(a.shielded_state_fingerprint(2), c.shielded_state_fingerprint(2));
+		assert_eq!(a.shielded_state_fingerprint(3), None);
+	}
+
+	#[test]
+	fn test_shielded_checkpoint_brings_its_nullifier_set() {
+		let genesis = BlockBuilder::genesis();
+		let mut frontier = CommitmentTree::<Node>::new();
+		frontier.append(Node::empty_root(3)).unwrap();
+		let nullifiers = NullifierSnapshot {
+			block_number: 5,
+			block_hash: H256::from(5),
+			entries: vec![([1u8; 32], H256::from(1)), ([2u8; 32], H256::from(2))],
+		};
+		let mut checkpoint = ShieldedCheckpoint {
+			height: 5,
+			anchor: frontier.root(),
+			nullifier_set_hash: H256::from(1),
+			tree_frontier: frontier.clone(),
+		};
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(
+			bc.install_shielded_checkpoint(checkpoint.clone(), &nullifiers),
+			Err(CheckpointError::NullifierSetMismatch { expected: H256::from(1), computed: nullifiers.hash() })
+		);
+		checkpoint.nullifier_set_hash = nullifiers.hash();
+		let mut stale = nullifiers.clone();
+		stale.block_number = 4;
+		assert_eq!(
+			bc.install_shielded_checkpoint(checkpoint.clone(), &stale),
+			Err(CheckpointError::NullifierSetHeight { expected: 5, found: 4 })
+		);
+		assert!(bc.nullifier_set().iter().next().is_none());
+
+		assert_eq!(bc.install_shielded_checkpoint(checkpoint, &nullifiers), Ok(()));
+		assert_eq!(bc.nullifier_set().iter().collect::<Vec<_>>(), vec![[1u8; 32], [2u8; 32]]);
+	}
+
+	#[test]
+	fn test_shielded_checkpoint_replaces_the_computed_tree() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block_with_transactions(vec![t1.clone()]);
+
+		let mut frontier = CommitmentTree::<Node>::new();
+		frontier.append(Node::empty_root(3)).unwrap();
+		let nullifiers = NullifierSnapshot { block_number: 1, block_hash: H256::zero(), entries: vec![] };
+		let checkpoint = ShieldedCheckpoint {
+			height: 1,
+			anchor: frontier.root(),
+			nullifier_set_hash: nullifiers.hash(),
+			tree_frontier: frontier.clone(),
+		};
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.install_shielded_checkpoint(checkpoint, &nullifiers), Ok(()));
+		insert_block(&db, &bc, b1.last().encoded(), vec![]);
+		insert_block(&db, &bc, b2.last().encoded(), vec![]);
+
+		let mut expected = frontier;
+		for cmu in t1.get_commitment_notes() {
+			expected.append(Node::new(cmu.into_repr())).unwrap();
+		}
+		let tree = bc.block_commitment_ser(&b2.last().hash()).unwrap().get_commitment_tree();
+		assert_eq!(tree.root(), expected.root());
+	}
+
+	#[test]
+	fn test_shielded_state_fingerprint_hashes_the_stored_nullifier_set() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let first = genesis.add_block_with_transactions(vec![t1]);
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		insert_block(&db, &bc, first.last().encoded(), vec![]);
+		let fingerprint = bc.shielded_state_fingerprint(1);
+
+		let mut batch = db.key_value().transaction();
+		batch.write(db::COL_EXTRA, &U256::from(7), &H256::from(7));
+		db.key_value().write(batch).unwrap();
+
+		assert!(bc.shielded_state_fingerprint(1).is_some());
+		assert_ne!(bc.shielded_state_fingerprint(1), fingerprint);
+	}
+
 	#[test]
 	fn test_commitment_serialization_block() {
 		let t1 =
@@ -3381,6 +4700,436 @@ mod tests {
 		assert!(bc.block_commitment_ser(&b1a_hash).is_some());
 	}
 
+	#[test]
+	fn test_verify_commitment_tree() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block();
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&b1, &b2, &b3] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+
+		let abort = AtomicBool::new(false);
+		assert_eq!(bc.verify_commitment_tree(3, &abort), Ok(()));
+		assert_eq!(bc.verify_commitment_tree(4, &abort), Err(TreeInconsistency::MissingBlock(4)));
+
+		// replace the tree of block 2 with the empty one.
+		let mut batch = db.key_value().transaction();
+		let empty = CommitmentTree::<Node>::new();
+		batch.write(db::COL_EXTRA, &b2.last().hash(), &CommitmentTreeSerialization::new(&empty));
+		db.key_value().write(batch).unwrap();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+
+		match bc.verify_commitment_tree(3, &abort) {
+			Err(TreeInconsistency::RootMismatch { number, stored, .. }) => {
+				assert_eq!(number, 2);
+				assert_eq!(stored, empty.root());
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		abort.store(true, AtomicOrdering::SeqCst);
+		assert_eq!(bc.verify_commitment_tree(3, &abort), Err(TreeInconsistency::Aborted(0)));
+	}
+
+	#[test]
+	fn test_verify_commitment_tree_reports_pruned_roots() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&b1, &b2] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+		assert!(bc.prune_commitment_roots(2) > 0);
+
+		let abort = AtomicBool::new(false);
+		assert_eq!(bc.verify_commitment_tree(2, &abort), Err(TreeInconsistency::Pruned(0)));
+	}
+
+	#[test]
+	fn test_validate_witness() {
+		let wallet_file_prefix = "wallet_for_witness";
+		let genesis = BlockBuilder::genesis();
+		let db = new_db();
+		let config = Config::new(wallet_file_prefix.to_string());
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		let t1 = {
+			let mut wallet = bc.pt_wallet.write().unwrap();
+			create_private_transaction(&mut wallet)
+		}.sign_for_private(TEST_CHAIN_ID);
+
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block();
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+		let b4 = b3.add_block();
+		for block in &[&b1, &b2, &b3, &b4] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+
+		// witness the last note of block 1.
+		let notes: Vec<_> = t1.get_commitment_notes().into_iter().map(|fr| Node::new(fr.into_repr())).collect();
+		let leaf = *notes.last().unwrap();
+		let tree = bc.block_commitment_ser(&b1.last().hash()).unwrap().get_commitment_tree();
+		let mut witness = IncrementalWitness::from_tree(&tree);
+
+		// the root of block 1, shared by block 2, is at least two blocks below the best block.
+		assert!(bc.validate_witness(&leaf, &witness, 3));
+		assert!(!bc.validate_witness(&leaf, &witness, 1));
+
+		// updated with the notes of block 3, the witness is anchored within the window.
+		for note in &notes {
+			witness.append(*note).unwrap();
+		}
+		assert!(bc.validate_witness(&leaf, &witness, 1));
+		assert!(!bc.validate_witness(&Node::blank(), &witness, 1));
+
+		remove_old_key_files(wallet_file_prefix, "./");
+	}
+
+	#[test]
+	fn test_anchors_for_range() {
+		let wallet_file_prefix = "wallet_for_anchors";
+		let genesis = BlockBuilder::genesis();
+		let db = new_db();
+		let config = Config::new(wallet_file_prefix.to_string());
+		let bc = BlockChain::new(config, genesis.last().encoded().raw(), db.clone(), None);
+		let t1 = {
+			let mut wallet = bc.pt_wallet.write().unwrap();
+			create_private_transaction(&mut wallet)
+		}.sign_for_private(TEST_CHAIN_ID);
+
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block();
+		let b3 = b2.add_block_with_transactions(vec![t1]);
+		for block in &[&b1, &b2, &b3] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+
+		let stored = |number: BlockNumber| {
+			let hash = bc.block_hash(number).unwrap();
+			bc.block_commitment_ser(&hash).unwrap().get_commitment_tree().root()
+		};
+		let anchors: Vec<_> = bc.anchors(0, 3).collect();
+		assert_eq!(anchors, (0..4).map(|number| (number, stored(number))).collect::<Vec<_>>());
+		assert_ne!(anchors[0].1, anchors[1].1);
+		assert_eq!(anchors[1].1, anchors[2].1);
+		assert_ne!(anchors[2].1, anchors[3].1);
+
+		// starting in the middle of the chain gives the same anchors.
+		assert_eq!(bc.anchors(2, 3).collect::<Vec<_>>(), anchors[2..].to_vec());
+		// the iteration ends at the best block.
+		assert_eq!(bc.anchors(3, 10).collect::<Vec<_>>(), anchors[3..].to_vec());
+		assert_eq!(bc.anchors(4, 10).count(), 0);
+		assert_eq!(bc.anchors(3, 2).count(), 0);
+
+		remove_old_key_files(wallet_file_prefix, "./");
+	}
+
+	#[test]
+	fn test_prune_commitment_roots() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block();
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&b1, &b2, &b3] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+		let root_of = |hash: &H256| bc.block_commitment_ser(hash).unwrap().get_commitment_tree().root();
+		let genesis_root = root_of(&genesis.last().hash());
+		let b1_root = root_of(&b1.last().hash());
+		assert_eq!(bc.anchor_history_info(), AnchorHistoryInfo { retained: 4, oldest_retained: 0 });
+
+		// block 1 shares its root with block 2, which is retained.
+		assert_eq!(bc.prune_commitment_roots(2), 1);
+		assert!(bc.commitment_root_block(&genesis_root).is_none());
+		assert_eq!(bc.commitment_root_block(&b1_root), Some(b2.last().hash()));
+		assert_eq!(bc.anchor_history_info(), AnchorHistoryInfo { retained: 2, oldest_retained: 2 });
+		assert_eq!(bc.prune_commitment_roots(2), 0);
+
+		// the progress survives a restart.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.anchor_history_info().oldest_retained, 2);
+		assert_eq!(bc.prune_commitment_roots(3), 1);
+		assert!(bc.commitment_root_block(&b1_root).is_none());
+		assert!(bc.commitment_root_block(&root_of(&b3.last().hash())).is_some());
+	}
+
+	#[test]
+	fn test_valid_anchors() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block();
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&b1, &b2, &b3] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+		let root_of = |hash: &H256| bc.block_commitment_ser(hash).unwrap().get_commitment_tree().root();
+		let genesis_root = root_of(&genesis.last().hash());
+		let b1_root = root_of(&b1.last().hash());
+		let b3_root = root_of(&b3.last().hash());
+
+		assert_eq!(bc.valid_anchors(1), vec![(3, b3_root), (2, b1_root)]);
+		// block 1 shares its root with block 2.
+		assert_eq!(bc.valid_anchors(10), vec![(3, b3_root), (2, b1_root), (0, genesis_root)]);
+		assert_eq!(bc.valid_anchors(0), vec![(3, b3_root)]);
+
+		// pruned roots are no longer valid.
+		bc.prune_commitment_roots(2);
+		assert_eq!(bc.valid_anchors(10), vec![(3, b3_root), (2, b1_root)]);
+	}
+
+	#[test]
+	fn test_valid_anchors_stop_at_retained_history() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_blocks(3);
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in BlockGenerator::new(vec![b1.clone(), b2.clone(), b3.clone()]) {
+			insert_block(&db, &bc, block.encoded(), vec![]);
+		}
+		let root_of = |hash: &H256| bc.block_commitment_ser(hash).unwrap().get_commitment_tree().root();
+		let b1_root = root_of(&b1.last().hash());
+		let b3_root = root_of(&b3.last().hash());
+
+		bc.prune_commitment_roots(3);
+		assert_eq!(bc.anchor_history_info().oldest_retained, 3);
+		assert_eq!(bc.valid_anchors(u64::max_value()), vec![(5, b3_root), (4, b1_root)]);
+	}
+
+	#[test]
+	fn sync_source_lost_callback_runs_once() {
+		let (tx, rx) = mpsc::channel();
+		let rx = Mutex::new(rx);
+		let sync_source = Arc::new(Mutex::new(SyncSourceWatch::default()));
+		let calls = Arc::new(AtomicUsize::new(0));
+		{
+			let calls = calls.clone();
+			sync_source.lock().callback = Some(Box::new(move || { calls.fetch_add(1, AtomicOrdering::SeqCst); }));
+		}
+
+		let drain = {
+			let sync_source = sync_source.clone();
+			thread::spawn(move || drain_sync_transactions(&rx, &sync_source))
+		};
+		let transaction = Transaction::default().fake_sign(Default::default());
+		tx.send(SyncTransaction::InPool(PendingTransaction::new(transaction, None))).unwrap();
+		drop(tx);
+
+		// the drain loop returns once the sender is gone.
+		drain.join().unwrap();
+		assert_eq!(calls.load(AtomicOrdering::SeqCst), 1);
+		assert!(sync_source.lock().lost);
+		assert!(sync_source.lock().callback.is_none());
+	}
+
+	#[test]
+	fn sync_source_lost_callback_of_chain() {
+		let (tx, rx) = mpsc::channel::<SyncTransaction>();
+		drop(tx);
+		let bc = BlockChain::new(Config::default(), BlockBuilder::genesis().last().encoded().raw(), new_db(), Some(Arc::new(Mutex::new(rx))));
+
+		// whether the source is lost before or after registering, the callback runs once.
+		let (lost_tx, lost_rx) = mpsc::channel();
+		bc.on_sync_source_lost(move || lost_tx.send(()).unwrap());
+		assert!(lost_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+		assert!(lost_rx.recv_timeout(Duration::from_millis(100)).is_err());
+	}
+
+	#[test]
+	fn test_unshielded_block_keeps_shielded_state() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1]);
+		let b2 = b1.add_block();
+		assert!(b1.last().encoded().has_shielded_components());
+		assert!(!b2.last().encoded().has_shielded_components());
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		insert_block(&db, &bc, b1.last().encoded(), vec![]);
+		let tree = bc.block_commitment_ser(&b1.last().hash()).unwrap().get_commitment_tree();
+		let nullifiers = bc.nullifier_set().iter().count();
+
+		insert_block(&db, &bc, b2.last().encoded(), vec![]);
+		let b2_tree = bc.block_commitment_ser(&b2.last().hash()).unwrap().get_commitment_tree();
+		assert_eq!(b2_tree.root(), tree.root());
+		assert_eq!(b2_tree.size(), tree.size());
+		assert_eq!(bc.commitment_root_block(&tree.root()), Some(b2.last().hash()));
+		assert_eq!(bc.nullifier_set().iter().count(), nullifiers);
+
+		// the state survives a restart.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		let b2_tree = bc.block_commitment_ser(&b2.last().hash()).unwrap().get_commitment_tree();
+		assert_eq!(b2_tree.root(), tree.root());
+	}
+
+	#[test]
+	fn test_pinned_block_survives_pruning() {
+		let t1 =
+			Transaction::create_private(vec![0, 1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1.clone()]);
+		let b2 = b1.add_block_with_transactions(vec![t1.clone()]);
+		let b3 = b2.add_block_with_transactions(vec![t1.clone()]);
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&b1, &b2, &b3] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+		let b1_hash = b1.last().hash();
+		let b1_root = bc.block_commitment_ser(&b1_hash).unwrap().get_commitment_tree().root();
+
+		bc.pin_block(b1_hash);
+		assert_eq!(bc.prune_commitment_roots(3), 2);
+		assert_eq!(bc.commitment_root_block(&b1_root), Some(b1_hash));
+		assert_eq!(bc.block_body(&b1_hash).unwrap().transaction_hashes(), vec![t1.hash()]);
+
+		// the pinned set survives a restart.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.pinned_blocks(), vec![b1_hash]);
+		assert_eq!(bc.commitment_root_block(&b1_root), Some(b1_hash));
+
+		// unpinning a pruned block drops its root.
+		assert!(bc.unpin_block(&b1_hash));
+		assert!(!bc.unpin_block(&b1_hash));
+		assert!(bc.commitment_root_block(&b1_root).is_none());
+		assert!(bc.block_body(&b1_hash).is_some());
+
+		// pinning it again restores the anchor.
+		bc.pin_block(b1_hash);
+		assert_eq!(bc.commitment_root_block(&b1_root), Some(b1_hash));
+	}
+
+	#[test]
+	fn test_restore_nullifier_set_from_snapshot() {
+		let t1 =
+			Transaction::create_private(vec![1, 2], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let t2 =
+			Transaction::create_private(vec![3], 43.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1]);
+		let b2 = b1.add_block_with_transactions(vec![t2]);
+
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("nullifiers");
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		insert_block(&db, &bc, b1.last().encoded(), vec![]);
+		bc.save_nullifier_snapshot(&path).unwrap();
+		insert_block(&db, &bc, b2.last().encoded(), vec![]);
+		let nullifiers: Vec<_> = bc.nullifier_set().iter().collect();
+		assert_eq!(nullifiers.len(), 3);
+
+		// only the block after the snapshot is replayed.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.restore_nullifier_set(&path), 1);
+		assert_eq!(bc.nullifier_set().iter().collect::<Vec<_>>(), nullifiers);
+		assert_eq!(bc.nullifier_transaction(&U256::from(&[3u8; 32][..])), Some(b2.last().encoded().transactions()[0].hash()));
+
+		// a snapshot taken at a block which is not canonical is rejected.
+		bc.nullifier_set().save_snapshot(&path, 1, H256::from(1)).unwrap();
+		assert_eq!(bc.restore_nullifier_set(&path), 3);
+		assert_eq!(bc.nullifier_set().iter().collect::<Vec<_>>(), nullifiers);
+
+		// so is a corrupt one.
+		bc.save_nullifier_snapshot(&path).unwrap();
+		let mut bytes = fs::read(&path).unwrap();
+		bytes.pop();
+		fs::write(&path, &bytes).unwrap();
+		assert_eq!(bc.restore_nullifier_set(&path), 3);
+		assert_eq!(bc.nullifier_set().iter().collect::<Vec<_>>(), nullifiers);
+	}
+
+	#[test]
+	fn test_nullifier_set_is_current() {
+		let t1 =
+			Transaction::create_private(vec![1, 2], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(vec![t1]);
+		let b2 = b1.add_block();
+		let b1_total_difficulty = genesis.last().difficulty() + b1.last().difficulty();
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert!(bc.nullifier_set_is_current());
+		insert_block(&db, &bc, b1.last().encoded(), vec![]);
+		assert!(bc.nullifier_set_is_current());
+
+		// blocks inserted out of order do not bring the set up to date.
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		let mut batch = db.key_value().transaction();
+		bc.insert_unordered_block(&mut batch, b2.last().encoded(), vec![], Some(b1_total_difficulty), true, false);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+		assert!(!bc.nullifier_set_is_current());
+
+		// nor do blocks imported in order on top of them.
+		insert_block(&db, &bc, b2.add_block().last().encoded(), vec![]);
+		assert!(!bc.nullifier_set_is_current());
+	}
+
+	#[test]
+	fn test_rebuild_nullifier_set_with_missing_bodies() {
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block();
+		let b2 = b1.add_block();
+		let b1_total_difficulty = genesis.last().difficulty() + b1.last().difficulty();
+
+		// as after a warp sync, the best block is known but not the blocks before it.
+		let tempdir = TempDir::new("").unwrap();
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		let mut batch = db.key_value().transaction();
+		bc.insert_unordered_block(&mut batch, b2.last().encoded(), vec![], Some(b1_total_difficulty), true, false);
+		bc.commit();
+		batch.write(db::COL_EXTRA, &U256::from(7), &H256::from(7));
+		db.key_value().write(batch).unwrap();
+		assert!(!bc.nullifier_set_is_current());
+
+		// the set is neither replaced nor marked current.
+		assert_eq!(bc.restore_nullifier_set(&tempdir.path().join("nullifiers")), 0);
+		assert_eq!(bc.nullifier_transaction(&U256::from(7)), Some(H256::from(7)));
+		assert!(!bc.nullifier_set_is_current());
+	}
+
 	/// TODO(Kui): We should also test the tree root and serialization value are computed correctly.
 	/// We could create some transactions using the commitments in merkle_tree tests,
 	/// and then check the root and serialization we get matches those in the tests as well.
@@ -4092,6 +5841,56 @@ mod tests {
 		assert_eq!(blocks_b3, vec![3]);
 	}
 
+	#[test]
+	fn test_shielded_checkpoint() {
+		let t1 =
+			Transaction::create_private(vec![1], 42.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block();
+		let b2 = b1.add_block_with_transactions(vec![t1.clone()]);
+		let b1_total_difficulty = genesis.last().difficulty() + b1.last().difficulty();
+
+		let mut frontier = CommitmentTree::<Node>::new();
+		frontier.append(Node::empty_root(3)).unwrap();
+		let nullifiers = |block_number| NullifierSnapshot { block_number, block_hash: H256::zero(), entries: vec![] };
+		let checkpoint = |height| ShieldedCheckpoint {
+			height,
+			anchor: frontier.root(),
+			nullifier_set_hash: nullifiers(height).hash(),
+			tree_frontier: frontier.clone(),
+		};
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(
+			bc.install_shielded_checkpoint(checkpoint(0), &nullifiers(0)),
+			Err(CheckpointError::NotBelowCheckpoint { best: 0, height: 0 })
+		);
+		let mut inconsistent = checkpoint(1);
+		inconsistent.anchor = Node::empty_root(0);
+		assert!(bc.install_shielded_checkpoint(inconsistent, &nullifiers(1)).is_err());
+		assert!(bc.shielded_checkpoint().is_none());
+		assert_eq!(bc.install_shielded_checkpoint(checkpoint(1), &nullifiers(1)), Ok(()));
+
+		// the checkpoint survives restarts.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.shielded_checkpoint().map(|c| c.anchor), Some(frontier.root()));
+
+		// restore a block on top of the checkpoint, without its parent.
+		let mut batch = db.key_value().transaction();
+		bc.insert_unordered_block(&mut batch, b2.last().encoded(), vec![], Some(b1_total_difficulty), true, false);
+		bc.commit();
+		db.key_value().write(batch).unwrap();
+
+		let mut expected = frontier.clone();
+		for cmu in t1.get_commitment_notes() {
+			expected.append(Node::new(cmu.into_repr())).unwrap();
+		}
+		let tree = bc.block_commitment_ser(&b2.last().hash()).unwrap().get_commitment_tree();
+		assert_eq!(tree.root(), expected.root());
+		assert_eq!(bc.commitment_root_block(&expected.root()), Some(b2.last().hash()));
+	}
+
 	#[test]
 	fn test_best_block_update() {
 		let genesis = BlockBuilder::genesis();
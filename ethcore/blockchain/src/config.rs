@@ -18,6 +18,9 @@
 
 use common_types::reward_config::RewardConfig;
 
+use crate::blockchain::DEFAULT_TREE_CHECKPOINT_INTERVAL;
+use crate::rescan::DEFAULT_MAX_CONCURRENT_RESCANS;
+
 /// Blockchain configuration.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
@@ -29,6 +32,10 @@ pub struct Config {
 	pub reward_config: RewardConfig,
 	/// File path prefix for wallet.
 	pub file_path_prefix: Option<String>,
+	/// Number of wallet rescans which may walk the chain at the same time.
+	pub max_concurrent_rescans: usize,
+	/// Number of blocks between two commitment tree checkpoints, 0 to take none.
+	pub tree_checkpoint_interval: u64,
 }
 
 impl Default for Config {
@@ -38,6 +45,8 @@ impl Default for Config {
 			max_cache_size: 1 << 20,
 			reward_config: RewardConfig::default(),
 			file_path_prefix: None,
+			max_concurrent_rescans: DEFAULT_MAX_CONCURRENT_RESCANS,
+			tree_checkpoint_interval: DEFAULT_TREE_CHECKPOINT_INTERVAL,
 		}
 	}
 }
@@ -49,6 +58,8 @@ impl Config {
 			max_cache_size: 1 << 20,
 			reward_config: RewardConfig::default(),
 			file_path_prefix: Some(file_path),
+			max_concurrent_rescans: DEFAULT_MAX_CONCURRENT_RESCANS,
+			tree_checkpoint_interval: DEFAULT_TREE_CHECKPOINT_INTERVAL,
 		}
 	}
 }
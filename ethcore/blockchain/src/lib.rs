@@ -24,6 +24,9 @@ mod blockchain;
 mod cache;
 mod config;
 mod import_route;
+mod nullifier_set;
+mod rescan;
+mod shielded_checkpoint;
 mod update;
 pub mod wallet;
 
@@ -45,10 +48,15 @@ extern crate sapling_crypto;
 extern crate backtrace;
 
 pub mod generator;
-pub use self::blockchain::{BlockProvider, BlockChain, BlockChainDB, BlockChainDBHandler};
+pub use self::blockchain::{BlockProvider, BlockChain, BlockChainDB, BlockChainDBHandler, AnchorHistoryInfo, AnchorIter, NullifierDelta, TreeInconsistency, DEFAULT_TREE_CHECKPOINT_INTERVAL, genesis_shielded_anchor};
 pub use self::cache::CacheSize;
 pub use self::config::Config;
 pub use self::import_route::ImportRoute;
+pub use self::nullifier_set::{
+	nullifier_set_hash, BloomFilter, NullifierIter, NullifierSet, NullifierSnapshot, SnapshotError, NULLIFIER_SIZE,
+};
+pub use self::rescan::{RescanLimiter, RescanSlot, TooManyRescans, DEFAULT_MAX_CONCURRENT_RESCANS};
+pub use self::shielded_checkpoint::{CheckpointError, ShieldedCheckpoint};
 pub use self::update::ExtrasInsert;
 pub use ethcore_db::keys::{BlockReceipts, BlockDetails, TransactionAddress, BlockNumberKey};
 pub use common_types::tree_route::TreeRoute;
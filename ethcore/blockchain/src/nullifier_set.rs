@@ -0,0 +1,433 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Read-only view of the set of spent nullifiers stored in the extras column.
+
+use std::{cmp, fmt, fs, io};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ethcore_bloom_journal::Bloom;
+use ethcore_db::{self as db, keys::ExtrasIndex};
+use ethereum_types::H256;
+use keccak_hash::{keccak, KECCAK_EMPTY};
+use kvdb::KeyValueDB;
+use rlp::{self, Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// Number of bytes of a nullifier.
+pub const NULLIFIER_SIZE: usize = 32;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"ORIGONUL";
+const SNAPSHOT_VERSION: u8 = 1;
+// magic, version, block number, block hash, entry count and body checksum.
+const SNAPSHOT_HEADER_SIZE: usize = 8 + 1 + 8 + 32 + 8 + 32;
+const SNAPSHOT_ENTRY_SIZE: usize = NULLIFIER_SIZE + 32;
+
+/// The set of nullifiers which were revealed by the canonical chain.
+///
+/// Each nullifier is stored under `ExtrasIndex::TransactionNullifier` followed by its
+/// big-endian encoding, so walking the prefix yields the nullifiers in ascending order.
+#[derive(Clone)]
+pub struct NullifierSet {
+	db: Arc<KeyValueDB>,
+}
+
+impl NullifierSet {
+	/// Create a view of the nullifier set backed by the given database.
+	pub fn new(db: Arc<KeyValueDB>) -> Self {
+		NullifierSet { db }
+	}
+
+	/// Stream all nullifiers in key order.
+	///
+	/// The iterator reads from the database directly, so it is a point-in-time view
+	/// that does not hold any lock of the chain and never loads the whole set in memory.
+	/// Nullifiers which are only in the write buffer are not yielded until it is flushed.
+	pub fn iter(&self) -> NullifierIter {
+		NullifierIter {
+			prefix_iter: self
+				.db
+				.iter_from_prefix(db::COL_EXTRA, &[ExtrasIndex::TransactionNullifier as u8]),
+		}
+	}
+
+	/// Build a bloom filter holding every nullifier of the set.
+	///
+	/// The filter is sized for the current number of nullifiers so that looking up a
+	/// nullifier which is not in the set gives a false positive with probability at
+	/// most `false_positive_rate`, which must be within `]0.0, 1.0[`.
+	pub fn to_bloom(&self, false_positive_rate: f64) -> BloomFilter {
+		let count = cmp::max(self.iter().count(), 1);
+		// `Bloom::from_parts` only restores whole words, so round the bitmap up to one.
+		let bitmap_size = (Bloom::compute_bitmap_size(count, false_positive_rate) + 7) / 8 * 8;
+
+		let mut bloom = Bloom::new(bitmap_size, count);
+		for nullifier in self.iter() {
+			bloom.set(&nullifier[..]);
+		}
+		BloomFilter { bloom }
+	}
+
+	/// Write every nullifier of the set, with the hash of the transaction which revealed
+	/// it, to the file at `path`, as of the block `block_number` with hash `block_hash`.
+	///
+	/// The file is a fixed size header followed by the entries in ascending nullifier
+	/// order. It is written next to `path` first and then moved over it, so an
+	/// interrupted save never leaves a truncated snapshot behind.
+	pub fn save_snapshot(&self, path: &Path, block_number: u64, block_hash: H256) -> io::Result<()> {
+		let mut body = Vec::new();
+		let mut entries = self.iter();
+		while let Some((nullifier, value)) = entries.next_entry() {
+			let tx_hash: H256 = rlp::decode(&value)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+			body.extend_from_slice(&nullifier);
+			body.extend_from_slice(&tx_hash);
+		}
+
+		let mut header = Vec::with_capacity(SNAPSHOT_HEADER_SIZE);
+		header.extend_from_slice(SNAPSHOT_MAGIC);
+		header.push(SNAPSHOT_VERSION);
+		header.write_u64::<BigEndian>(block_number)?;
+		header.extend_from_slice(&block_hash);
+		header.write_u64::<BigEndian>((body.len() / SNAPSHOT_ENTRY_SIZE) as u64)?;
+		header.extend_from_slice(&keccak(&body));
+
+		let tmp_path = path.with_extension("tmp");
+		{
+			let mut file = fs::File::create(&tmp_path)?;
+			file.write_all(&header)?;
+			file.write_all(&body)?;
+			file.sync_all()?;
+		}
+		fs::rename(&tmp_path, path)
+	}
+
+	/// Read a snapshot written by `save_snapshot`, checking its header, its checksum and
+	/// that its entries are strictly ascending.
+	///
+	/// Whether the snapshot is still valid for the chain is left to the caller, see
+	/// `BlockChain::restore_nullifier_set`.
+	pub fn load_snapshot(path: &Path) -> Result<NullifierSnapshot, SnapshotError> {
+		let mut file = fs::File::open(path)?;
+
+		let mut magic = [0u8; 8];
+		file.read_exact(&mut magic)?;
+		if &magic != SNAPSHOT_MAGIC {
+			return Err(SnapshotError::Corrupt("not a nullifier set snapshot"));
+		}
+		let version = file.read_u8()?;
+		if version != SNAPSHOT_VERSION {
+			return Err(SnapshotError::UnsupportedVersion(version));
+		}
+		let block_number = file.read_u64::<BigEndian>()?;
+		let mut block_hash = H256::zero();
+		file.read_exact(&mut block_hash)?;
+		let count = file.read_u64::<BigEndian>()?;
+		let mut checksum = H256::zero();
+		file.read_exact(&mut checksum)?;
+
+		let mut body = Vec::new();
+		file.read_to_end(&mut body)?;
+		if body.len() as u64 != count * SNAPSHOT_ENTRY_SIZE as u64 {
+			return Err(SnapshotError::Corrupt("wrong number of entries"));
+		}
+		if keccak(&body) != checksum {
+			return Err(SnapshotError::Corrupt("checksum mismatch"));
+		}
+
+		let mut entries: Vec<([u8; NULLIFIER_SIZE], H256)> = Vec::with_capacity(count as usize);
+		for chunk in body.chunks(SNAPSHOT_ENTRY_SIZE) {
+			let mut nullifier = [0u8; NULLIFIER_SIZE];
+			nullifier.copy_from_slice(&chunk[..NULLIFIER_SIZE]);
+			if entries.last().map_or(false, |last| last.0 >= nullifier) {
+				return Err(SnapshotError::Corrupt("entries are not sorted"));
+			}
+			entries.push((nullifier, H256::from_slice(&chunk[NULLIFIER_SIZE..])));
+		}
+
+		Ok(NullifierSnapshot { block_number, block_hash, entries })
+	}
+}
+
+/// Hash of a nullifier set given in ascending order: the keccak chained over the
+/// nullifiers, starting from the keccak of the empty string.
+pub fn nullifier_set_hash<I: IntoIterator<Item = [u8; NULLIFIER_SIZE]>>(nullifiers: I) -> H256 {
+	nullifiers.into_iter().fold(KECCAK_EMPTY, |acc, nullifier| {
+		let mut buf = [0u8; 32 + NULLIFIER_SIZE];
+		buf[..32].copy_from_slice(&acc);
+		buf[32..].copy_from_slice(&nullifier);
+		keccak(&buf[..])
+	})
+}
+
+/// Nullifier set loaded from a snapshot file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NullifierSnapshot {
+	/// Number of the block the snapshot was taken at.
+	pub block_number: u64,
+	/// Hash of the block the snapshot was taken at.
+	pub block_hash: H256,
+	/// Nullifiers, in ascending order, with the hash of the transaction which revealed them.
+	pub entries: Vec<([u8; NULLIFIER_SIZE], H256)>,
+}
+
+impl NullifierSnapshot {
+	/// Hash of the nullifiers of the snapshot, see `nullifier_set_hash`.
+	pub fn hash(&self) -> H256 {
+		nullifier_set_hash(self.entries.iter().map(|&(nullifier, _)| nullifier))
+	}
+}
+
+/// Reason why a nullifier set snapshot was rejected.
+#[derive(Debug)]
+pub enum SnapshotError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file was written by another version of the format.
+	UnsupportedVersion(u8),
+	/// The file is not a well-formed snapshot.
+	Corrupt(&'static str),
+	/// The block the snapshot was taken at is not in the canonical chain any more.
+	Stale(u64, H256),
+}
+
+impl fmt::Display for SnapshotError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SnapshotError::Io(ref e) => write!(f, "Cannot read nullifier set snapshot: {}", e),
+			SnapshotError::UnsupportedVersion(v) => write!(f, "Unsupported nullifier set snapshot version {}", v),
+			SnapshotError::Corrupt(reason) => write!(f, "Corrupt nullifier set snapshot: {}", reason),
+			SnapshotError::Stale(n, ref hash) => write!(f, "Nullifier set snapshot block #{} ({:x}) is not canonical", n, hash),
+		}
+	}
+}
+
+impl From<io::Error> for SnapshotError {
+	fn from(e: io::Error) -> Self {
+		match e.kind() {
+			io::ErrorKind::UnexpectedEof => SnapshotError::Corrupt("truncated file"),
+			_ => SnapshotError::Io(e),
+		}
+	}
+}
+
+/// A bloom filter of spent nullifiers, as handed to light clients.
+///
+/// A nullifier which is in the set always tests positive, one which is not may test
+/// positive with the false positive rate the filter was built for.
+pub struct BloomFilter {
+	bloom: Bloom,
+}
+
+impl BloomFilter {
+	/// Check whether the given nullifier might have been spent.
+	pub fn contains(&self, nullifier: &[u8; NULLIFIER_SIZE]) -> bool {
+		self.bloom.check(&nullifier[..])
+	}
+
+	/// Number of bits of the filter.
+	pub fn number_of_bits(&self) -> u64 {
+		self.bloom.number_of_bits()
+	}
+}
+
+impl Encodable for BloomFilter {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(2);
+		s.append(&self.bloom.number_of_hash_functions());
+		s.append_list(self.bloom.parts());
+	}
+}
+
+impl Decodable for BloomFilter {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		if rlp.item_count()? != 2 {
+			return Err(DecoderError::RlpIncorrectListLen);
+		}
+
+		let hash_functions: u32 = rlp.val_at(0)?;
+		let parts: Vec<u64> = rlp.list_at(1)?;
+		if hash_functions == 0 || parts.is_empty() {
+			return Err(DecoderError::Custom("empty bloom filter"));
+		}
+
+		Ok(BloomFilter {
+			bloom: Bloom::from_parts(&parts, hash_functions),
+		})
+	}
+}
+
+/// An iterator which walks all the nullifiers of a `NullifierSet`.
+pub struct NullifierIter<'a> {
+	prefix_iter: Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>,
+}
+
+impl<'a> NullifierIter<'a> {
+	// Next nullifier with the raw value stored for it.
+	fn next_entry(&mut self) -> Option<([u8; NULLIFIER_SIZE], Box<[u8]>)> {
+		loop {
+			let (key, value) = self.prefix_iter.next()?;
+
+			// iterator may continue beyond values beginning with this
+			// prefix.
+			if key.first() != Some(&(ExtrasIndex::TransactionNullifier as u8)) {
+				return None;
+			}
+
+			// other extras never share the prefix with a full sized key,
+			// but skip anything that is not a nullifier key to be safe.
+			if key.len() != NULLIFIER_SIZE + 1 {
+				continue;
+			}
+
+			let mut nullifier = [0u8; NULLIFIER_SIZE];
+			nullifier.copy_from_slice(&key[1..]);
+			return Some((nullifier, value));
+		}
+	}
+}
+
+impl<'a> Iterator for NullifierIter<'a> {
+	type Item = [u8; NULLIFIER_SIZE];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.next_entry().map(|(nullifier, _)| nullifier)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethcore_db::Writable;
+	use ethereum_types::U256;
+	use tempdir::TempDir;
+
+	fn new_db() -> Arc<KeyValueDB> {
+		Arc::new(kvdb_memorydb::create(ethcore_db::NUM_COLUMNS.unwrap()))
+	}
+
+	#[test]
+	fn iterates_nullifiers_in_key_order() {
+		let db = new_db();
+		let mut batch = db.transaction();
+		for n in &[5u64, 1, 300, 2] {
+			batch.write(db::COL_EXTRA, &U256::from(*n), &H256::from(*n));
+		}
+		// unrelated extras must not be yielded.
+		batch.put(db::COL_EXTRA, &[ExtrasIndex::TransactionNullifier as u8, 1, 2], b"x");
+		batch.put(db::COL_EXTRA, &[ExtrasIndex::CommitmentRootBlock as u8; 33], b"x");
+		db.write(batch).unwrap();
+
+		let set = NullifierSet::new(db);
+		let nullifiers: Vec<U256> = set.iter().map(|n| U256::from(&n[..])).collect();
+		assert_eq!(
+			nullifiers,
+			vec![1u64, 2, 5, 300].into_iter().map(U256::from).collect::<Vec<_>>()
+		);
+	}
+
+	#[test]
+	fn bloom_contains_all_nullifiers() {
+		let db = new_db();
+		let mut batch = db.transaction();
+		for n in 0..1000u64 {
+			batch.write(db::COL_EXTRA, &U256::from(n * 2), &H256::from(n));
+		}
+		db.write(batch).unwrap();
+
+		let set = NullifierSet::new(db);
+		let bloom: BloomFilter = rlp::decode(&rlp::encode(&set.to_bloom(0.01))).unwrap();
+		for nullifier in set.iter() {
+			assert!(bloom.contains(&nullifier));
+		}
+
+		let false_positives = (0..10_000u64)
+			.map(|n| H256::from(U256::from(n * 2 + 1)))
+			.filter(|n| bloom.contains(&n.0))
+			.count();
+		assert!(false_positives < 200, "false positives: {}", false_positives);
+	}
+
+	#[test]
+	fn snapshot_round_trip() {
+		let db = new_db();
+		let mut batch = db.transaction();
+		for n in &[7u64, 3, 500] {
+			batch.write(db::COL_EXTRA, &U256::from(*n), &H256::from(*n + 1));
+		}
+		db.write(batch).unwrap();
+
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("nullifiers");
+		NullifierSet::new(db).save_snapshot(&path, 42, H256::from(9)).unwrap();
+
+		let snapshot = NullifierSet::load_snapshot(&path).unwrap();
+		assert_eq!(snapshot.block_number, 42);
+		assert_eq!(snapshot.block_hash, H256::from(9));
+		assert_eq!(
+			snapshot.entries.iter().map(|e| (U256::from(&e.0[..]), e.1)).collect::<Vec<_>>(),
+			vec![
+				(U256::from(3), H256::from(4)),
+				(U256::from(7), H256::from(8)),
+				(U256::from(500), H256::from(501)),
+			]
+		);
+	}
+
+	#[test]
+	fn corrupt_snapshot_is_rejected() {
+		let db = new_db();
+		let mut batch = db.transaction();
+		batch.write(db::COL_EXTRA, &U256::from(1), &H256::from(1));
+		batch.write(db::COL_EXTRA, &U256::from(2), &H256::from(2));
+		db.write(batch).unwrap();
+
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("nullifiers");
+		NullifierSet::new(db).save_snapshot(&path, 1, H256::from(1)).unwrap();
+		let bytes = fs::read(&path).unwrap();
+
+		let mut flipped = bytes.clone();
+		*flipped.last_mut().unwrap() ^= 1;
+		fs::write(&path, &flipped).unwrap();
+		match NullifierSet::load_snapshot(&path) {
+			Err(SnapshotError::Corrupt(_)) => {},
+			other => panic!("unexpected {:?}", other),
+		}
+
+		fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+		match NullifierSet::load_snapshot(&path) {
+			Err(SnapshotError::Corrupt(_)) => {},
+			other => panic!("unexpected {:?}", other),
+		}
+
+		let mut newer = bytes.clone();
+		newer[SNAPSHOT_MAGIC.len()] = SNAPSHOT_VERSION + 1;
+		fs::write(&path, &newer).unwrap();
+		match NullifierSet::load_snapshot(&path) {
+			Err(SnapshotError::UnsupportedVersion(_)) => {},
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn empty_set_yields_nothing() {
+		let set = NullifierSet::new(new_db());
+		assert_eq!(set.iter().count(), 0);
+	}
+}
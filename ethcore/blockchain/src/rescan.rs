@@ -0,0 +1,148 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Limit on the number of wallet rescans walking the chain at the same time.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::{Condvar, Mutex};
+
+/// Default number of wallet rescans which may run at the same time.
+pub const DEFAULT_MAX_CONCURRENT_RESCANS: usize = 1;
+
+/// Error returned when a rescan does not wait for a slot and none is free.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TooManyRescans {
+	/// Number of rescans which may run at the same time.
+	pub limit: usize,
+}
+
+impl fmt::Display for TooManyRescans {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} wallet rescans are already running", self.limit)
+	}
+}
+
+/// Counting semaphore over the rescans of the chain.
+///
+/// Each rescan reads every block from genesis, so a burst of wallet imports running
+/// them side by side makes them compete for the disk and finishes all of them later
+/// than running them in turn.
+pub struct RescanLimiter {
+	limit: usize,
+	running: Mutex<usize>,
+	released: Condvar,
+	started: AtomicUsize,
+}
+
+/// A slot held by a running rescan, freed on drop.
+pub struct RescanSlot<'a> {
+	limiter: &'a RescanLimiter,
+}
+
+impl RescanLimiter {
+	/// Allow `limit` rescans at the same time, at least one.
+	pub fn new(limit: usize) -> Self {
+		RescanLimiter {
+			limit: limit.max(1),
+			running: Mutex::new(0),
+			released: Condvar::new(),
+			started: AtomicUsize::new(0),
+		}
+	}
+
+	/// Number of rescans which may run at the same time.
+	pub fn limit(&self) -> usize {
+		self.limit
+	}
+
+	/// Number of rescans which have taken a slot so far.
+	pub fn started(&self) -> usize {
+		self.started.load(Ordering::SeqCst)
+	}
+
+	/// Take a slot, waiting for one to be freed if they are all held.
+	pub fn acquire(&self) -> RescanSlot {
+		let mut running = self.running.lock();
+		while *running >= self.limit {
+			self.released.wait(&mut running);
+		}
+		*running += 1;
+		self.started.fetch_add(1, Ordering::SeqCst);
+		RescanSlot { limiter: self }
+	}
+
+	/// Take a slot if one is free.
+	pub fn try_acquire(&self) -> Result<RescanSlot, TooManyRescans> {
+		let mut running = self.running.lock();
+		if *running >= self.limit {
+			return Err(TooManyRescans { limit: self.limit });
+		}
+		*running += 1;
+		self.started.fetch_add(1, Ordering::SeqCst);
+		Ok(RescanSlot { limiter: self })
+	}
+}
+
+impl<'a> Drop for RescanSlot<'a> {
+	fn drop(&mut self) {
+		*self.limiter.running.lock() -= 1;
+		self.limiter.released.notify_one();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use std::sync::atomic::AtomicBool;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn refuses_rescans_over_the_limit() {
+		let limiter = RescanLimiter::new(1);
+		let slot = limiter.try_acquire().unwrap();
+		assert_eq!(limiter.try_acquire().err(), Some(TooManyRescans { limit: 1 }));
+		drop(slot);
+		assert!(limiter.try_acquire().is_ok());
+	}
+
+	#[test]
+	fn third_rescan_waits_for_a_slot() {
+		let limiter = Arc::new(RescanLimiter::new(2));
+		let first = limiter.try_acquire().unwrap();
+		let _second = limiter.try_acquire().unwrap();
+
+		let started = Arc::new(AtomicBool::new(false));
+		let third = {
+			let limiter = limiter.clone();
+			let started = started.clone();
+			thread::spawn(move || {
+				let _slot = limiter.acquire();
+				started.store(true, Ordering::SeqCst);
+			})
+		};
+
+		thread::sleep(Duration::from_millis(100));
+		assert!(!started.load(Ordering::SeqCst));
+
+		drop(first);
+		third.join().unwrap();
+		assert!(started.load(Ordering::SeqCst));
+	}
+}
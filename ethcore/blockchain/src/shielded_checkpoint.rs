@@ -0,0 +1,190 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trusted checkpoint of the shielded state, to bootstrap a node without replaying
+//! the shielded outputs of the whole chain.
+
+use std::fmt;
+
+use common_types::BlockNumber;
+use ethereum_types::H256;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+use crate::zcash_primitives::merkle_tree::{CommitmentTree, Hashable};
+use crate::zcash_primitives::sapling::Node;
+
+/// The shielded state after block `height`.
+///
+/// Nothing in a checkpoint can be checked against the chain, which the node does not
+/// have yet: whoever loads one trusts its signer that the frontier is the commitment tree
+/// of the canonical block at `height`. A wrong frontier makes the node compute anchors
+/// the rest of the network does not know, so it rejects valid spends and the spends it
+/// accepts are not mined by others. The nullifier set is not part of a checkpoint, only
+/// its hash: it is loaded from a snapshot, which must match the hash.
+#[derive(Clone)]
+pub struct ShieldedCheckpoint {
+	/// Number of the block the state is taken after.
+	pub height: BlockNumber,
+	/// Root of the commitment tree after that block.
+	pub anchor: Node,
+	/// Hash of the nullifier set after that block.
+	pub nullifier_set_hash: H256,
+	/// The commitment tree after that block.
+	pub tree_frontier: CommitmentTree<Node>,
+}
+
+/// Reason a checkpoint is not installed.
+#[derive(Debug, PartialEq)]
+pub enum CheckpointError {
+	/// The anchor is not the root of the frontier.
+	AnchorMismatch {
+		/// Anchor of the checkpoint.
+		expected: Node,
+		/// Root of the frontier of the checkpoint.
+		computed: Node,
+	},
+	/// The nullifier set snapshot is not taken at the checkpoint height.
+	NullifierSetHeight {
+		/// Height of the checkpoint.
+		expected: BlockNumber,
+		/// Block number of the nullifier set snapshot.
+		found: BlockNumber,
+	},
+	/// The nullifier set snapshot does not hash to the checkpoint nullifier set hash.
+	NullifierSetMismatch {
+		/// Nullifier set hash of the checkpoint.
+		expected: H256,
+		/// Hash of the nullifier set snapshot.
+		computed: H256,
+	},
+	/// The best block of the chain is not below the checkpoint.
+	NotBelowCheckpoint {
+		/// Number of the best block.
+		best: BlockNumber,
+		/// Height of the checkpoint.
+		height: BlockNumber,
+	},
+}
+
+impl fmt::Display for CheckpointError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			CheckpointError::AnchorMismatch { ref expected, ref computed } =>
+				write!(f, "checkpoint anchor {:?} is not the root {:?} of its frontier", expected, computed),
+			CheckpointError::NullifierSetHeight { expected, found } =>
+				write!(f, "nullifier set snapshot is taken at #{}, not at the checkpoint #{}", found, expected),
+			CheckpointError::NullifierSetMismatch { ref expected, ref computed } =>
+				write!(f, "nullifier set snapshot hash {:x} is not the checkpoint hash {:x}", computed, expected),
+			CheckpointError::NotBelowCheckpoint { best, height } =>
+				write!(f, "best block #{} is not below the checkpoint at #{}", best, height),
+		}
+	}
+}
+
+impl ShieldedCheckpoint {
+	/// Check that the anchor is the root of the frontier.
+	pub fn validate(&self) -> Result<(), CheckpointError> {
+		let computed = self.tree_frontier.root();
+		if computed != self.anchor {
+			return Err(CheckpointError::AnchorMismatch { expected: self.anchor, computed });
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for ShieldedCheckpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ShieldedCheckpoint")
+			.field("height", &self.height)
+			.field("anchor", &self.anchor)
+			.field("nullifier_set_hash", &self.nullifier_set_hash)
+			.field("tree_size", &self.tree_frontier.size())
+			.finish()
+	}
+}
+
+impl Encodable for ShieldedCheckpoint {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		let mut anchor = Vec::new();
+		self.anchor.write(&mut anchor).expect("writing to a vector should not fail");
+		let mut frontier = Vec::new();
+		self.tree_frontier.write(&mut frontier).expect("writing to a vector should not fail");
+
+		s.begin_list(4);
+		s.append(&self.height);
+		s.append(&anchor);
+		s.append(&self.nullifier_set_hash);
+		s.append(&frontier);
+	}
+}
+
+impl Decodable for ShieldedCheckpoint {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		if rlp.item_count()? != 4 {
+			return Err(DecoderError::RlpIncorrectListLen);
+		}
+		let anchor: Vec<u8> = rlp.val_at(1)?;
+		let frontier: Vec<u8> = rlp.val_at(3)?;
+		Ok(ShieldedCheckpoint {
+			height: rlp.val_at(0)?,
+			anchor: Node::read(&anchor[..]).map_err(|_| DecoderError::Custom("invalid anchor"))?,
+			nullifier_set_hash: rlp.val_at(2)?,
+			tree_frontier: CommitmentTree::read(&frontier[..])
+				.map_err(|_| DecoderError::Custom("invalid tree frontier"))?,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn checkpoint() -> ShieldedCheckpoint {
+		let mut tree = CommitmentTree::new();
+		tree.append(Node::empty_root(0)).unwrap();
+		tree.append(Node::empty_root(1)).unwrap();
+		ShieldedCheckpoint {
+			height: 10,
+			anchor: tree.root(),
+			nullifier_set_hash: H256::from(7),
+			tree_frontier: tree,
+		}
+	}
+
+	#[test]
+	fn validates_anchor_against_frontier() {
+		let mut checkpoint = checkpoint();
+		assert_eq!(checkpoint.validate(), Ok(()));
+
+		let computed = checkpoint.anchor;
+		checkpoint.anchor = Node::empty_root(5);
+		assert_eq!(
+			checkpoint.validate(),
+			Err(CheckpointError::AnchorMismatch { expected: Node::empty_root(5), computed })
+		);
+	}
+
+	#[test]
+	fn rlp_round_trip() {
+		let checkpoint = checkpoint();
+		let decoded: ShieldedCheckpoint = rlp::decode(&rlp::encode(&checkpoint)).unwrap();
+		assert_eq!(decoded.height, checkpoint.height);
+		assert_eq!(decoded.anchor, checkpoint.anchor);
+		assert_eq!(decoded.nullifier_set_hash, checkpoint.nullifier_set_hash);
+		assert_eq!(decoded.tree_frontier.root(), checkpoint.tree_frontier.root());
+		assert_eq!(decoded.tree_frontier.size(), 2);
+	}
+}
@@ -1,12 +1,16 @@
 use ethereum_types::{Address, U256};
 use pairing::bls12_381::{Bls12, Fr};
 use rand::{OsRng, Rand};
-use sapling_crypto::{jubjub::fs::Fs, primitives::Diversifier, redjubjub::PrivateKey};
+use sapling_crypto::{
+	jubjub::{fs::Fs, JubjubBls12},
+	primitives::Diversifier,
+	redjubjub::PrivateKey,
+};
 use std::fmt;
 use zcash_primitives::{
 	merkle_tree::{CommitmentTreeWitness, IncrementalWitness},
 	note_encryption::{Memo, SaplingNoteEncryption},
-	sapling::{spend_sig, Node},
+	sapling::{note_commitment, spend_sig, value_commitment, Node},
 	transaction::components::GROTH_PROOF_SIZE,
 	JUBJUB,
 };
@@ -323,51 +327,23 @@ impl TransactionBuilder {
 		}
 
 		for output in self.outputs.iter() {
-			// Encrypt note to the recipient diversified transmission key pk_d
-			// with diversified transmission base g_d, and to the outgoing viewing key ovk,
-			// giving the transmitted note ciphertext (epk, Cenc, Cout)
-			let encryptor = SaplingNoteEncryption::new(
-				output.ovk,
-				output.note.clone(),
+			let rcv = Fs::rand(&mut self.rng);
+			let esk = Fs::rand(&mut self.rng);
+			let output_desc = build_output_description(
 				output.to.clone(),
-				output.memo.clone(),
-			);
-			let xxx = output.note.value;
-			// println!("In build 2.1, {0}", xxx);
-			// Generate a proof ZKOutput for the Output statement
-			let (proof, cv) = ctx.output_proof(
-				*encryptor.esk(),
-				output.to,
-				output.note.r,
 				output.note.value,
-				&OUTPUT_PARAM,
+				output.note.r,
+				output.memo.clone(),
+				rcv,
+				esk,
+				output.ovk,
+				&mut ctx,
 				&JUBJUB,
-			);
-			let mut v = vec![];
-			proof.write(&mut v).unwrap();
-			let mut zkproof = [0u8; GROTH_PROOF_SIZE];
-			zkproof.copy_from_slice(v.as_slice());
-
-			let cmu = output.note.cm(&JUBJUB);
-
-			// Use cv and cmu to derive the outgoing cipher key .
-			let enc_ciphertext = encryptor.encrypt_note_plaintext();
-			let out_ciphertext = encryptor.encrypt_outgoing_plaintext(&cv, &cmu);
-
-			let ephemeral_key = encryptor.epk().clone().into();
-			let output_desc = SaplingOutputDescription {
-				cv,
-				cmu,
-				ephemeral_key,
-				enc_ciphertext,
-				out_ciphertext,
-				zkproof,
-			};
+			)?;
 			self.tx.private.as_mut().unwrap().outputs.push(output_desc);
 		}
 		// Signature
-		// TODO(xin): Compute real sig_hash.
-		let sighash = self.tx.hash(Some(self.chain_id)).into();
+		let sighash = self.tx.shielded_sighash(Some(self.chain_id));
 		for (i, spend) in self.spends.iter().enumerate() {
 			self.tx.private.as_mut().unwrap().spends[i].spend_auth_sig = Some(spend_sig(
 				PrivateKey(spend.expsk.ask),
@@ -388,6 +364,66 @@ impl TransactionBuilder {
 	}
 }
 
+/// Builds a complete Sapling output description sending `value` to `payment_address`
+/// with note commitment trapdoor `rcm`: the value commitment, the note commitment,
+/// both ciphertexts and the output proof.
+/// The value commitment randomness is accumulated into `proving_ctx`.
+pub fn build_output_description(
+	payment_address: SaplingPaymentAddress,
+	value: u64,
+	rcm: Fs,
+	memo: Memo,
+	rcv: Fs,
+	esk: Fs,
+	ovk: SaplingOutgoingViewingKey,
+	proving_ctx: &mut SaplingProvingContext,
+	params: &JubjubBls12,
+) -> Result<SaplingOutputDescription, Error> {
+	let g_d = match payment_address.g_d(params) {
+		Some(g_d) => g_d,
+		None => return Err(Error(ErrorKind::InvalidAddress)),
+	};
+
+	let cv = value_commitment(value, rcv, params);
+	let cmu: Fr = note_commitment(value, &g_d, &payment_address.pk_d, rcm, params).into();
+
+	let note = SaplingNote {
+		value,
+		g_d,
+		pk_d: payment_address.pk_d.clone(),
+		r: rcm,
+	};
+	let encryptor = SaplingNoteEncryption::new_with_esk(esk, ovk, note, payment_address, memo);
+
+	let (proof, proof_cv) = proving_ctx.output_proof_with_rcv(
+		esk,
+		payment_address,
+		rcm,
+		rcv,
+		value,
+		&OUTPUT_PARAM,
+		params,
+	);
+	debug_assert!(proof_cv == cv);
+
+	let mut v = vec![];
+	proof.write(&mut v).unwrap();
+	let mut zkproof = [0u8; GROTH_PROOF_SIZE];
+	zkproof.copy_from_slice(v.as_slice());
+
+	let enc_ciphertext = encryptor.encrypt_note_plaintext();
+	let out_ciphertext = encryptor.encrypt_outgoing_plaintext(&cv, &cmu);
+
+	Ok(SaplingOutputDescription {
+		cv,
+		cmu,
+		ephemeral_key: encryptor.epk().clone().into(),
+		enc_ciphertext,
+		out_ciphertext,
+		zkproof,
+	})
+}
+
 mod tests {
 	use super::*;
 	use crate::wallet::wallet_types::{
@@ -450,7 +486,7 @@ mod tests {
 		let result = builder.build();
 		assert!(result.is_ok());
 		let tx = result.unwrap();
-		let sighash = tx.hash(Some(TEST_CHAIN_ID)).into();
+		let sighash = tx.shielded_sighash(Some(TEST_CHAIN_ID));
 		// Verify input.
 		let mut ctx = SaplingVerificationContext::new();
 		// tx.get_nullifier_set()
@@ -463,7 +499,6 @@ mod tests {
 			spend_desc.anchor,
 			&spend_desc.nullifier,
 			spend_desc.rk,
-			// TODO(xin): Change to real sighash.
 			&sighash,
 			spend_desc.spend_auth_sig.unwrap(),
 			spend_proof,
@@ -615,4 +650,85 @@ mod tests {
 		// Build failed because change is positive but there's no private input.
 		assert!(builder.build().is_err());
 	}
+
+	#[test]
+	fn build_output_description_round_trip() {
+		let mut rng = OsRng::new().expect("should be able to construct RNG");
+
+		let extsk = SaplingExtendedSpendingKey::master(&[]);
+		let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+		let ovk = extfvk.fvk.ovk;
+		let to = extfvk.default_address().unwrap().1;
+
+		let mut proving_ctx = SaplingProvingContext::new();
+		let output_desc = build_output_description(
+			to,
+			200,
+			Fs::rand(&mut rng),
+			Memo::from_str("haha").unwrap(),
+			Fs::rand(&mut rng),
+			Fs::rand(&mut rng),
+			ovk,
+			&mut proving_ctx,
+			&JUBJUB,
+		)
+		.unwrap();
+
+		let mut ctx = SaplingVerificationContext::new();
+		let zkproof = Proof::<Bls12>::read(&output_desc.zkproof[..]).unwrap();
+		assert!(ctx.check_output(
+			output_desc.cv,
+			output_desc.cmu,
+			output_desc.ephemeral_key,
+			zkproof,
+			&OUTPUT_VK,
+			&JUBJUB,
+		));
+
+		let epk = output_desc.ephemeral_key.as_prime_order(&JUBJUB).unwrap();
+		let (note, address, memo) = try_sapling_note_decryption(
+			&extfvk.fvk.vk.ivk(),
+			&epk,
+			&output_desc.cmu,
+			&output_desc.enc_ciphertext,
+		)
+		.unwrap();
+		assert_eq!(address, to);
+		assert_eq!(memo.to_utf8().unwrap().unwrap(), "haha");
+		assert_eq!(note.value, 200);
+	}
+
+	#[test]
+	fn build_output_description_with_invalid_diversifier() {
+		let mut rng = OsRng::new().expect("should be able to construct RNG");
+
+		let extsk = SaplingExtendedSpendingKey::master(&[]);
+		let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+		let ovk = extfvk.fvk.ovk;
+		let to = extfvk.default_address().unwrap().1;
+
+		// Roughly half of all diversifiers have no valid g_d.
+		let diversifier = (0u8..=255)
+			.map(|i| Diversifier([i, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]))
+			.find(|d| d.g_d::<Bls12>(&JUBJUB).is_none())
+			.unwrap();
+		let invalid_to = SaplingPaymentAddress {
+			pk_d: to.pk_d.clone(),
+			diversifier,
+		};
+
+		let mut proving_ctx = SaplingProvingContext::new();
+		let result = build_output_description(
+			invalid_to,
+			200,
+			Fs::rand(&mut rng),
+			Memo::default(),
+			Fs::rand(&mut rng),
+			Fs::rand(&mut rng),
+			ovk,
+			&mut proving_ctx,
+			&JUBJUB,
+		);
+		assert_eq!(result.unwrap_err().kind(), &ErrorKind::InvalidAddress);
+	}
 }
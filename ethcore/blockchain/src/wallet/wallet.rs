@@ -17,10 +17,12 @@
 use dir::Directories;
 use ethereum_types::{H160, H256, U256};
 use ff::PrimeField;
+use heapsize::HeapSizeOf;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use parking_lot::Mutex as parking_Mutex;
 use rand::Rng;
 use std::collections::HashMap;
+use std::mem;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::path::Path;
@@ -216,6 +218,15 @@ impl Wallet {
 			.collect()
 	}
 
+	/// Estimate the heap memory used by the witnesses of the wallet notes, in bytes.
+	pub fn witnesses_heap_size(&self) -> usize {
+		self.nullifier_notes
+			.values()
+			.flat_map(|note_data| note_data.witnesses.iter())
+			.map(|witness| mem::size_of::<SaplingWitness>() + witness.heap_size_of_children())
+			.sum()
+	}
+
 	/// Clear the notes data in wallet.
 	pub fn clear_wallet_data(&mut self) {
 		self.nullifier_notes.clear();
@@ -223,8 +234,8 @@ impl Wallet {
 		self.address_nullifiers.clear();
 	}
 
-	/// Write the related transaction hash data into db.
-	pub fn write_wallet_data(&self, batch: &mut DBTransaction) {
+	/// Write the related transaction hash data and the handled `wallet_addresses` into db.
+	pub fn write_wallet_data(&self, batch: &mut DBTransaction, wallet_addresses: &WalletTxAddresses) {
 		let wallet_hashes = WalletTxHashes {
 			hashes: self
 				.tx_nullifiers
@@ -235,10 +246,7 @@ impl Wallet {
 		let encoded = ::rlp::encode(&wallet_hashes);
 		batch.put(db::COL_EXTRA, b"wallet_txs", &encoded);
 
-		let wallet_addresses = WalletTxAddresses {
-			addresses: self.list_addresses(),
-		};
-		let encoded = ::rlp::encode(&wallet_addresses);
+		let encoded = ::rlp::encode(wallet_addresses);
 		batch.put(db::COL_EXTRA, b"wallet_addresses", &encoded);
 	}
 
@@ -394,7 +402,7 @@ impl Wallet {
 								}
 								let position = front_op.position();
 								let vk = fvk.vk;
-								let nullifier_vec = note.clone().nf(&vk, position as u64, &JUBJUB);
+								let nullifier_vec = note.clone().nf(&vk, position, &JUBJUB);
 								let mut nullifier_arr = [0u8; 32];
 								nullifier_arr.copy_from_slice(&nullifier_vec);
 								let nullifier = U256::from(nullifier_arr);
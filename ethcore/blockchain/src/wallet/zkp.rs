@@ -15,16 +15,22 @@
 // along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
 
 use bellman::groth16::{
-	prepare_verifying_key, Parameters, PreparedVerifyingKey, VerifyingKey as BellmanVerifyingKey,
+	prepare_verifying_key, verify_proof, Parameters, PreparedVerifyingKey, Proof,
+	VerifyingKey as BellmanVerifyingKey,
 };
-use group::EncodedPoint;
-use pairing::bls12_381::{Bls12, G1Uncompressed, G2Uncompressed};
+use ff::Field;
+use group::{CurveAffine, EncodedPoint};
+use pairing::bls12_381::{Bls12, Fr, G1Affine, G1Uncompressed, G2Affine, G2Uncompressed};
 use rustc_hex::FromHex;
 use serde::de::{self, Deserialize, Deserializer, Visitor};
 use serde_derive::Deserialize;
-use std::fmt;
 use std::path::Path;
+use std::time::{Duration, Instant};
+use std::fmt;
 use zcash_proofs::load_parameters_from_bytes;
+use zcash_proofs::sapling::verifying_key_id;
+
+pub use zcash_proofs::sapling::Circuit;
 
 // TODO(xin): Add hash check back.
 const SPEND_PARAM_PATH: &str = "res/sapling-spend.params";
@@ -41,6 +47,12 @@ lazy_static! {
 		{ load_sapling_spend_verifying_key().unwrap() };
 	pub static ref OUTPUT_VK: SaplingPreparedVerifyingKey =
 		{ load_sapling_output_verifying_key().unwrap() };
+	/// Identifies `SPEND_VK`, for caches of verification results.
+	pub static ref SPEND_VK_ID: [u8; 32] =
+		{ verifying_key_id(&spend_verifying_key()) };
+	/// Identifies `OUTPUT_VK`, for caches of verification results.
+	pub static ref OUTPUT_VK_ID: [u8; 32] =
+		{ verifying_key_id(&output_verifying_key()) };
 	pub static ref SPEND_PARAM: SaplingParam =
 		{ load_parameters_from_bytes(&include_bytes!("../../res/sapling-spend.params")[..]).0 };
 	pub static ref OUTPUT_PARAM: SaplingParam =
@@ -133,20 +145,64 @@ impl From<VerifyingKey> for BellmanVerifyingKey<Bls12> {
 	}
 }
 
-pub fn load_sapling_spend_verifying_key() -> Result<SaplingPreparedVerifyingKey, String> {
+fn spend_verifying_key() -> BellmanVerifyingKey<Bls12> {
 	let spend_vk_json = include_bytes!("../../res/sapling-spend-verifying-key.json");
-	let spend_vk = serde_json::from_slice::<VerifyingKey>(&spend_vk_json[..]).unwrap();
-	Ok(prepare_verifying_key(&spend_vk.into()))
+	serde_json::from_slice::<VerifyingKey>(&spend_vk_json[..]).unwrap().into()
 }
 
-pub fn load_sapling_output_verifying_key() -> Result<SaplingPreparedVerifyingKey, String> {
+fn output_verifying_key() -> BellmanVerifyingKey<Bls12> {
 	let output_vk_json = include_bytes!("../../res/sapling-output-verifying-key.json");
-	let output_vk = serde_json::from_slice::<VerifyingKey>(&output_vk_json[..]).unwrap();
-	Ok(prepare_verifying_key(&output_vk.into()))
+	serde_json::from_slice::<VerifyingKey>(&output_vk_json[..]).unwrap().into()
+}
+
+pub fn load_sapling_spend_verifying_key() -> Result<SaplingPreparedVerifyingKey, String> {
+	Ok(prepare_verifying_key(&spend_verifying_key()))
+}
+
+pub fn load_sapling_output_verifying_key() -> Result<SaplingPreparedVerifyingKey, String> {
+	Ok(prepare_verifying_key(&output_verifying_key()))
 }
 
 pub fn load_sapling_spend_param() {}
 
+/// Identifier of the verifying key proofs of `circuit` are verified with, the
+/// `verifying_key_id` of it.
+pub fn circuit_verifying_key_id(circuit: Circuit) -> [u8; 32] {
+	match circuit {
+		Circuit::Spend => *SPEND_VK_ID,
+		Circuit::Output => *OUTPUT_VK_ID,
+	}
+}
+
+/// Number of proofs of `circuit` verified per second, timed over about `budget`.
+///
+/// The proof timed is well-formed but invalid, which costs as much to verify as a valid
+/// one: the pairing is computed in full before it is compared.
+pub fn proof_verifications_per_sec(circuit: Circuit, budget: Duration) -> f64 {
+	let (pvk, vk) = match circuit {
+		Circuit::Spend => (&*SPEND_VK, spend_verifying_key()),
+		Circuit::Output => (&*OUTPUT_VK, output_verifying_key()),
+	};
+	let proof = Proof::<Bls12> {
+		a: G1Affine::one(),
+		b: G2Affine::one(),
+		c: G1Affine::one(),
+	};
+	let inputs = vec![Fr::one(); vk.ic.len() - 1];
+
+	let start = Instant::now();
+	let mut verified = 0u64;
+	while verified == 0 || start.elapsed() < budget {
+		let _ = verify_proof(pvk, &proof, &inputs);
+		verified += 1;
+	}
+	verified as f64 / duration_secs(start.elapsed())
+}
+
+fn duration_secs(d: Duration) -> f64 {
+	d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -154,6 +210,7 @@ mod tests {
 	fn load_vk_key() {
 		assert_eq!(load_sapling_spend_verifying_key().is_ok(), true);
 		assert_eq!(load_sapling_output_verifying_key().is_ok(), true);
+		assert_ne!(*SPEND_VK_ID, *OUTPUT_VK_ID);
 	}
 
 	#[test]
@@ -54,6 +54,10 @@ pub enum ExtrasIndex {
 	CommitmentRootBlock = 8,
 	/// Block commitment tree serialization.
 	BlockCommitmentSer = 9,
+	/// Position of a note commitment in the commitment tree.
+	CommitmentPosition = 10,
+	/// Commitment tree checkpoint.
+	TreeCheckpoint = 11,
 }
 
 fn with_index(hash: &H256, i: ExtrasIndex) -> H264 {
@@ -63,6 +67,33 @@ fn with_index(hash: &H256, i: ExtrasIndex) -> H264 {
 	result
 }
 
+/// A note commitment, used as a DB key for its position in the commitment tree.
+#[derive(Copy, Debug, Eq, PartialEq, Clone)]
+pub struct CommitmentPosition(pub Node);
+
+impl Key<u64> for CommitmentPosition {
+	type Target = H264;
+
+	fn key(&self) -> H264 {
+		let mut result = H264::default();
+		result[0] = ExtrasIndex::CommitmentPosition as u8;
+		self.0.write(&mut result[1..33]).expect("length is 32 bytes");
+		result
+	}
+}
+
+/// The hash of a block, used as a DB key for the commitment tree checkpoint taken at it.
+#[derive(Copy, Debug, Eq, PartialEq, Clone)]
+pub struct TreeCheckpoint(pub H256);
+
+impl Key<CommitmentTreeSerialization> for TreeCheckpoint {
+	type Target = H264;
+
+	fn key(&self) -> H264 {
+		with_index(&self.0, ExtrasIndex::TreeCheckpoint)
+	}
+}
+
 /// Wrapper for block number used as a DB key.
 pub struct BlockNumberKey([u8; 5]);
 
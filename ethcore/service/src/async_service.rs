@@ -0,0 +1,134 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Non-blocking access to the client service for embedders running on a futures
+//! executor such as tokio.
+
+use std::sync::Arc;
+
+use ethcore::client::{Client, ClientIoMessage, ServiceHealth};
+use futures::future::{self, FutureResult};
+use futures::sync::oneshot::{self, Canceled};
+use futures::Future;
+use parking_lot::Mutex;
+
+use service::ClientService;
+
+/// Wrapper of a `ClientService` whose calls return futures instead of blocking.
+///
+/// The closures given to `execute_on_io` run on the IO thread of the service, holding
+/// up block import and timers while they run, just as closures sent with
+/// `ClientIoMessage::execute`. Long CPU-bound work should still be moved to a blocking
+/// thread of the embedder, e.g. with `spawn_blocking` (`tokio_threadpool::blocking` on
+/// tokio 0.1), against the client returned by `ClientService::client`.
+pub struct AsyncClientService {
+	service: Arc<ClientService>,
+}
+
+impl AsyncClientService {
+	/// Wrap a started service.
+	pub fn new(service: Arc<ClientService>) -> Self {
+		AsyncClientService { service }
+	}
+
+	/// The wrapped service, for the calls which have no non-blocking version.
+	pub fn service(&self) -> &Arc<ClientService> {
+		&self.service
+	}
+
+	/// Run a closure against the client on the IO thread and resolve to its result.
+	///
+	/// The future fails with `Canceled` if the closure is never run, because the IO
+	/// service is shutting down, or if it panics.
+	pub fn execute_on_io<T, F>(&self, f: F) -> impl Future<Item = T, Error = Canceled> where
+		T: Send + 'static,
+		F: FnOnce(&Client) -> T + Send + 'static,
+	{
+		let (sender, result) = oneshot::channel();
+		// callbacks may be called more than once, the closure is only run on the first call.
+		let job = Mutex::new(Some((f, sender)));
+		let message = ClientIoMessage::execute(move |client| {
+			if let Some((f, sender)) = job.lock().take() {
+				// the receiver may have been dropped by a caller no longer interested.
+				let _ = sender.send(f(client));
+			}
+		});
+
+		// a message which is not sent drops its sender, failing the future.
+		if let Err(e) = self.service.io().send_message(message) {
+			warn!(target: "client", "Failed to execute a closure on the IO thread: {:?}", e);
+		}
+		result
+	}
+
+	/// Resolve to the current health of the client. The conditions are read without
+	/// waiting for the IO thread, so the future is ready at once.
+	pub fn health(&self) -> FutureResult<ServiceHealth, Canceled> {
+		future::ok(self.service.client().health().clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use ethcore::client::{ChainInfo, ClientConfig};
+	use ethcore::miner::Miner;
+	use ethcore::spec::Spec;
+	use ethcore::test_helpers;
+	use tempdir::TempDir;
+	use tokio::runtime::Runtime;
+
+	fn start_service(tempdir: &TempDir) -> Arc<ClientService> {
+		let client_db_handler = test_helpers::memory_db_handler();
+		let client_db = client_db_handler.open(&tempdir.path().join("client")).unwrap();
+		let spec = Spec::new_test();
+		let service = ClientService::start(
+			ClientConfig::default(),
+			&spec,
+			client_db,
+			&tempdir.path().join("snapshot"),
+			test_helpers::memory_db_handler(),
+			tempdir.path(),
+			Arc::new(Miner::new_for_tests(&spec, None)),
+			None,
+		).unwrap();
+		Arc::new(service)
+	}
+
+	#[test]
+	fn resolves_on_a_tokio_runtime() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = AsyncClientService::new(start_service(&tempdir));
+		let mut runtime = Runtime::new().unwrap();
+
+		let calls = Arc::new(AtomicUsize::new(0));
+		let counted = calls.clone();
+		let best = runtime.block_on(service.execute_on_io(move |client| {
+			counted.fetch_add(1, Ordering::SeqCst);
+			client.chain_info().best_block_number
+		})).unwrap();
+		assert_eq!(best, 0);
+		assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+		let health = runtime.block_on(service.health()).unwrap();
+		assert!(health.is_healthy());
+
+		runtime.shutdown_now().wait().unwrap();
+	}
+}
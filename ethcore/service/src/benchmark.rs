@@ -0,0 +1,80 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Self-benchmark of the shielded verification work, to tell whether the hardware can
+//! keep up with the tip of the chain.
+
+use std::time::{Duration, Instant};
+
+use blockchain::wallet::zkp::{self, Circuit};
+use zcash_primitives::merkle_tree::Hashable;
+use zcash_primitives::sapling::Node;
+
+/// Time spent on each primitive by `ClientService::benchmark_shielded`.
+pub const SHIELDED_BENCHMARK_BUDGET: Duration = Duration::from_millis(500);
+
+/// Throughput of the primitives of shielded verification on this node.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldedBenchmark {
+	/// Pedersen hashes of two commitment tree nodes per second.
+	pub pedersen_hashes_per_sec: f64,
+	/// Spend proofs verified per second.
+	pub spend_verify_per_sec: f64,
+	/// Output proofs verified per second.
+	pub output_verify_per_sec: f64,
+}
+
+impl ShieldedBenchmark {
+	/// Time each primitive for about `budget` on the calling thread.
+	///
+	/// The verifying keys are built in, so proof verification is always timed.
+	pub fn run(budget: Duration) -> Self {
+		ShieldedBenchmark {
+			pedersen_hashes_per_sec: pedersen_hashes_per_sec(budget),
+			spend_verify_per_sec: zkp::proof_verifications_per_sec(Circuit::Spend, budget),
+			output_verify_per_sec: zkp::proof_verifications_per_sec(Circuit::Output, budget),
+		}
+	}
+}
+
+fn pedersen_hashes_per_sec(budget: Duration) -> f64 {
+	let mut node = Node::empty_root(0);
+	let start = Instant::now();
+	let mut hashes = 0u64;
+	while hashes == 0 || start.elapsed() < budget {
+		// hash in batches, reading the clock costs about as much as a hash.
+		for _ in 0..16 {
+			node = Node::combine(0, &node, &node);
+		}
+		hashes += 16;
+	}
+	let elapsed = start.elapsed();
+	hashes as f64 / (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn measures_every_primitive() {
+		let benchmark = ShieldedBenchmark::run(Duration::from_millis(10));
+		assert!(benchmark.pedersen_hashes_per_sec > 0.0);
+		assert!(benchmark.spend_verify_per_sec > 0.0);
+		assert!(benchmark.output_verify_per_sec > 0.0);
+	}
+}
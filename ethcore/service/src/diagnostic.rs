@@ -0,0 +1,188 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshot of the service state to attach to bug reports.
+//!
+//! Everything here is read from in-memory state or a single database lookup, and
+//! nothing identifies the node operator: no keys, addresses or wallet contents.
+
+use common_types::ids::BlockId;
+use ethcore::client::{BlockChainClient, Client, ClientConfig};
+use ethcore::snapshot::RestorationStatus;
+use ethereum_types::H256;
+
+/// State of the service at the time of the dump.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticReport {
+	/// Number of the best block.
+	pub best_block_number: u64,
+	/// Hash of the best block.
+	pub best_block_hash: H256,
+	/// Chain id used to sign transactions, if any.
+	pub chain_id: Option<u64>,
+	/// Name of the consensus engine.
+	pub engine: String,
+	/// Snapshot creation and restoration status.
+	pub snapshot: SnapshotReport,
+	/// Sizes of the import queues.
+	pub queues: QueueReport,
+	/// Sizes of the shielded state.
+	pub shielded: ShieldedReport,
+	/// Summary of the client configuration.
+	pub config: ConfigReport,
+}
+
+/// Snapshot status.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotReport {
+	/// Whether a snapshot is being taken.
+	pub taking_snapshot: bool,
+	/// One of `inactive`, `initializing`, `ongoing` or `failed`.
+	pub restoration: String,
+	/// Number of chunks restored so far.
+	pub chunks_done: u32,
+	/// Number of chunks of the snapshot being restored.
+	pub chunks_total: u32,
+}
+
+impl SnapshotReport {
+	/// Create the report from the snapshot service status.
+	pub fn new(taking_snapshot: bool, status: RestorationStatus) -> Self {
+		let (restoration, chunks_done, chunks_total) = match status {
+			RestorationStatus::Inactive => ("inactive", 0, 0),
+			RestorationStatus::Initializing { chunks_done } => ("initializing", chunks_done, 0),
+			RestorationStatus::Ongoing { state_chunks, block_chunks, state_chunks_done, block_chunks_done } =>
+				("ongoing", state_chunks_done + block_chunks_done, state_chunks + block_chunks),
+			RestorationStatus::Failed => ("failed", 0, 0),
+		};
+		SnapshotReport {
+			taking_snapshot,
+			restoration: restoration.into(),
+			chunks_done,
+			chunks_total,
+		}
+	}
+}
+
+/// Sizes of the block verification queue and of the messages waiting for the IO thread.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueReport {
+	/// Blocks waiting for verification.
+	pub unverified_blocks: usize,
+	/// Blocks being verified.
+	pub verifying_blocks: usize,
+	/// Verified blocks waiting for import.
+	pub verified_blocks: usize,
+	/// Heap memory used by the block queue, in bytes.
+	pub block_queue_mem: usize,
+	/// Transactions waiting for the IO thread.
+	pub transactions: usize,
+	/// Ancient blocks waiting for the IO thread.
+	pub ancient_blocks: usize,
+	/// Consensus messages waiting for the IO thread.
+	pub consensus_messages: usize,
+}
+
+/// Sizes of the shielded state.
+///
+/// The nullifier set is not counted, as that takes a scan of the database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldedReport {
+	/// Number of note commitments in the tree of the best block.
+	pub commitments: usize,
+	/// Number of blocks whose commitment root is still accepted as an anchor.
+	pub anchors_retained: u64,
+	/// Lowest block number whose commitment root is still accepted as an anchor.
+	pub oldest_anchor: u64,
+	/// Number of blocks pinned from anchor pruning.
+	pub pinned_blocks: usize,
+	/// Heap memory used by the witnesses tracked by the wallet, in bytes.
+	pub witnesses_mem: usize,
+}
+
+/// Settings of the client which commonly matter when debugging.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigReport {
+	/// Name of the chain specification.
+	pub spec_name: String,
+	/// Operating mode.
+	pub mode: String,
+	/// State pruning algorithm.
+	pub pruning: String,
+	/// Number of eras kept in the state journal.
+	pub history: u64,
+	/// Whether transaction tracing is enabled.
+	pub tracing: bool,
+	/// Whether the fat database is enabled.
+	pub fat_db: bool,
+	/// Whether block seals are checked on import.
+	pub check_seal: bool,
+	/// Number of blocks kept beyond the anchor validity window.
+	pub anchor_history_margin: u64,
+}
+
+impl<'a> From<&'a ClientConfig> for ConfigReport {
+	fn from(config: &'a ClientConfig) -> Self {
+		ConfigReport {
+			spec_name: config.spec_name.clone(),
+			mode: config.mode.to_string(),
+			pruning: config.pruning.as_str().into(),
+			history: config.history,
+			tracing: config.tracing.enabled,
+			fat_db: config.fat_db,
+			check_seal: config.check_seal,
+			anchor_history_margin: config.anchor_history_margin,
+		}
+	}
+}
+
+impl QueueReport {
+	/// Read the queue sizes of the client.
+	pub fn new(client: &Client) -> Self {
+		let blocks = client.queue_info();
+		let io = client.io_queue_sizes();
+		QueueReport {
+			unverified_blocks: blocks.unverified_queue_size,
+			verifying_blocks: blocks.verifying_queue_size,
+			verified_blocks: blocks.verified_queue_size,
+			block_queue_mem: blocks.mem_used,
+			transactions: io.transactions,
+			ancient_blocks: io.ancient_blocks,
+			consensus_messages: io.consensus_messages,
+		}
+	}
+}
+
+impl ShieldedReport {
+	/// Read the shielded state sizes of the client, as of its best block.
+	pub fn new(client: &Client) -> Self {
+		let anchors = client.anchor_history_info();
+		let commitments = client.commitment_tree(BlockId::Latest)
+			.map_or(0, |tree| tree.size());
+		ShieldedReport {
+			commitments,
+			anchors_retained: anchors.retained,
+			oldest_anchor: anchors.oldest_retained,
+			pinned_blocks: client.pinned_blocks().len(),
+			witnesses_mem: client.wallet_witnesses_mem(),
+		}
+	}
+}
@@ -18,6 +18,9 @@
 // https://github.com/paritytech/parity-ethereum/issues/10302
 #![allow(deprecated)]
 
+use blockchain::CheckpointError;
+use blockchain::wallet::zkp::Circuit;
+use ethereum_types::H256;
 use ethcore;
 use io;
 
@@ -26,4 +29,24 @@ error_chain! {
 		Ethcore(ethcore::error::Error);
 		IoError(io::IoError);
 	}
+
+	errors {
+		#[doc = "Blocks are being imported."]
+		Busy {
+			description("Blocks are being imported")
+			display("Blocks are being imported, try again later")
+		}
+
+		#[doc = "The spec names a verifying key of the shielded pool which is not the one proofs are verified with."]
+		MissingShieldedParams(circuit: Circuit, id: H256) {
+			description("Shielded pool verifying key missing")
+			display("Shielded pool {:?} verifying key {:x} is not the one proofs are verified with", circuit, id)
+		}
+
+		#[doc = "A shielded checkpoint was rejected."]
+		InvalidShieldedCheckpoint(err: CheckpointError) {
+			description("Invalid shielded checkpoint")
+			display("Invalid shielded checkpoint: {}", err)
+		}
+	}
 }
@@ -0,0 +1,176 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded pool of worker threads running closures against the client, off the IO thread.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+use ethcore::client::Client;
+use parking_lot::Mutex;
+
+/// Number of worker threads of the pool.
+pub const EXECUTE_POOL_THREADS: usize = 2;
+/// Number of closures which may wait for a worker before the pool is saturated.
+pub const EXECUTE_POOL_QUEUE_SIZE: usize = 64;
+
+/// A closure queued on the pool.
+pub type Job = Box<FnMut(&Client) + Send>;
+
+/// Handle to the result of a closure run on the pool.
+pub struct ExecuteHandle<T> {
+	result: Receiver<T>,
+}
+
+impl<T> ExecuteHandle<T> {
+	/// Block until the closure has run and return its result, or `None` if it panicked
+	/// or the pool was shut down before running it.
+	pub fn wait(self) -> Option<T> {
+		self.result.recv().ok()
+	}
+
+	/// Return the result of the closure if it has already run.
+	pub fn try_result(&self) -> Option<T> {
+		self.result.try_recv().ok()
+	}
+}
+
+/// A fixed number of worker threads fed through a bounded queue.
+pub struct ExecutePool {
+	sender: Mutex<Option<SyncSender<Job>>>,
+	workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl ExecutePool {
+	/// Spawn `threads` workers running closures against `client`, with room for
+	/// `queue_size` pending closures.
+	pub fn new(client: Arc<Client>, threads: usize, queue_size: usize) -> Self {
+		let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+		let receiver = Arc::new(Mutex::new(receiver));
+
+		let workers = (0..threads).filter_map(|i| {
+			let client = client.clone();
+			let receiver = receiver.clone();
+			let res = thread::Builder::new().name(format!("Client Execute #{}", i)).spawn(move || {
+				loop {
+					// the lock is released before running the job.
+					let job = receiver.lock().recv();
+					let mut job = match job {
+						Ok(job) => job,
+						Err(_) => break,
+					};
+					if panic::catch_unwind(AssertUnwindSafe(|| job(&*client))).is_err() {
+						warn!("A closure executed on the client pool panicked");
+					}
+				}
+			});
+
+			match res {
+				Ok(handle) => Some(handle),
+				Err(e) => {
+					warn!("Failed to spawn client execute thread: {:?}", e);
+					None
+				}
+			}
+		}).collect();
+
+		ExecutePool {
+			sender: Mutex::new(Some(sender)),
+			workers: Mutex::new(workers),
+		}
+	}
+
+	/// Queue a closure, giving it back if the pool is saturated or shut down.
+	pub fn try_execute(&self, job: Job) -> Result<(), Job> {
+		match *self.sender.lock() {
+			Some(ref sender) => sender.try_send(job).map_err(|e| match e {
+				TrySendError::Full(job) | TrySendError::Disconnected(job) => job,
+			}),
+			None => Err(job),
+		}
+	}
+
+	/// Queue a closure, waiting for room if the pool is saturated, and return a handle
+	/// to its result.
+	pub fn execute<T, F>(&self, f: F) -> ExecuteHandle<T> where
+		T: Send + 'static,
+		F: FnOnce(&Client) -> T + Send + 'static,
+	{
+		let (result_sender, result) = mpsc::channel();
+		let mut f = Some(f);
+		let job: Job = Box::new(move |client| {
+			if let Some(f) = f.take() {
+				let _ = result_sender.send(f(client));
+			}
+		});
+
+		// a dropped job drops its result sender, so `wait` returns `None`.
+		let sender = self.sender.lock().clone();
+		if let Some(sender) = sender {
+			let _ = sender.send(job);
+		}
+		ExecuteHandle { result }
+	}
+
+	/// Stop accepting closures, let the workers drain the queue and wait for them.
+	pub fn shutdown(&self) {
+		self.sender.lock().take();
+		for worker in self.workers.lock().drain(..) {
+			if worker.join().is_err() {
+				warn!("Client execute thread panicked");
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use ethcore::client::BlockChainClient;
+	use ethcore::test_helpers::generate_dummy_client;
+	use super::*;
+
+	#[test]
+	fn runs_closures_until_shutdown() {
+		let client = generate_dummy_client(3);
+		let pool = ExecutePool::new(client, 2, 4);
+
+		let handles: Vec<_> = (0..8u64)
+			.map(|i| pool.execute(move |client| client.chain_info().best_block_number + i))
+			.collect();
+		let results: Vec<_> = handles.into_iter().map(|h| h.wait()).collect();
+		assert_eq!(results, (0..8u64).map(|i| Some(3 + i)).collect::<Vec<_>>());
+
+		let panicking = pool.execute(|_| -> u64 { panic!("test panic") });
+		assert_eq!(panicking.wait(), None);
+
+		pool.shutdown();
+		assert!(pool.try_execute(Box::new(|_| {})).is_err());
+		assert_eq!(pool.execute(|_| 1).wait(), None);
+	}
+
+	#[test]
+	fn shutdown_releases_client() {
+		let client = generate_dummy_client(0);
+		let weak = Arc::downgrade(&client);
+		let pool = ExecutePool::new(client, 2, 4);
+		pool.shutdown();
+		assert!(weak.upgrade().is_none());
+	}
+}
@@ -23,26 +23,53 @@ extern crate ethereum_types;
 extern crate kvdb;
 extern crate common_types;
 extern crate parking_lot;
+extern crate serde;
+extern crate zcash_primitives;
+
+#[cfg(feature = "async")]
+extern crate futures;
 
 #[macro_use]
 extern crate error_chain;
 #[macro_use]
 extern crate log;
 #[macro_use]
+extern crate serde_derive;
+#[macro_use]
 extern crate trace_time;
 
 #[cfg(test)]
 extern crate ethcore_db;
 #[cfg(test)]
+extern crate ethkey;
+#[cfg(test)]
+extern crate keccak_hash as hash;
+#[cfg(test)]
+extern crate serde_json;
+#[cfg(test)]
 extern crate tempdir;
+#[cfg(all(test, feature = "async"))]
+extern crate tokio;
 
+#[cfg(feature = "async")]
+mod async_service;
+mod benchmark;
+mod diagnostic;
 mod error;
+mod execute_pool;
 mod service;
+mod shielded_feed;
 mod stop_guard;
 
 #[cfg(test)]
 extern crate kvdb_rocksdb;
 
+#[cfg(feature = "async")]
+pub use async_service::AsyncClientService;
+pub use benchmark::{ShieldedBenchmark, SHIELDED_BENCHMARK_BUDGET};
+pub use diagnostic::{ConfigReport, DiagnosticReport, QueueReport, ShieldedReport, SnapshotReport};
 pub use error::{Error, ErrorKind};
-pub use service::ClientService;
+pub use execute_pool::ExecuteHandle;
+pub use service::{ClientService, IoOnlyService};
+pub use shielded_feed::{ShieldedOutputEvent, SHIELDED_FEED_BUFFER};
 
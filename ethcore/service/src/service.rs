@@ -17,26 +17,40 @@
 //! Creates and registers client and network services.
 
 use std::sync::Arc;
-use std::path::Path;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::sync::mpsc;
-
+use std::thread;
 
 use ansi_term::Colour;
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use io::{IoContext, TimerToken, IoHandler, IoService, IoError};
 use stop_guard::StopGuard;
 
-use blockchain::{BlockChainDB, BlockChainDBHandler};
-use ethcore::client::{Client, ClientConfig, ChainNotify, ClientIoMessage};
-use ethcore::miner::Miner;
+use blockchain::{BlockChainDB, BlockChainDBHandler, NullifierSnapshot, ShieldedCheckpoint, TreeInconsistency};
+use blockchain::wallet::zkp::{self, Circuit};
+use ethcore::client::{BlockChainClient, BlockInfo, ChainInfo, Client, ClientConfig, ChainNotify, ClientIoMessage};
+use ethcore::miner::{Miner, TransactionSource};
 use ethcore::snapshot::service::{Service as SnapshotService, ServiceParams as SnapServiceParams};
 use ethcore::snapshot::{SnapshotService as _SnapshotService, RestorationStatus};
-use ethcore::spec::Spec;
+use ethcore::spec::{ShieldedParams, Spec};
+use common_types::compact_block::CompactBlock;
+use common_types::ids::BlockId;
 use common_types::transaction::SyncTransaction;
 use parking_lot::{Mutex};
+use zcash_primitives::merkle_tree::{CommitmentTree, IncrementalWitness};
+use zcash_primitives::sapling::Node;
+use zcash_primitives::transaction::components::OutputDescription;
+
+use {Error, ErrorKind};
+use benchmark::{ShieldedBenchmark, SHIELDED_BENCHMARK_BUDGET};
+use diagnostic::{DiagnosticReport, QueueReport, ShieldedReport, SnapshotReport};
+use execute_pool::{ExecuteHandle, ExecutePool, EXECUTE_POOL_QUEUE_SIZE, EXECUTE_POOL_THREADS};
+use shielded_feed::{ShieldedOutputEvent, ShieldedOutputFeed, SHIELDED_FEED_BUFFER};
 
-use Error;
+/// Name of the nullifier set snapshot file, in the snapshot directory.
+const NULLIFIER_SNAPSHOT_FILE: &str = "nullifiers";
 
 /// Client service setup. Creates and registers client and network services with the IO subsystem.
 pub struct ClientService {
@@ -44,9 +58,49 @@ pub struct ClientService {
 	client: Arc<Client>,
 	snapshot: Arc<SnapshotService>,
 	database: Arc<BlockChainDB>,
+	client_io: Arc<ClientIoHandler>,
+	abort_tree_check: AtomicBool,
+	cache_warming: Mutex<Option<thread::JoinHandle<()>>>,
+	abort_cache_warming: Arc<AtomicBool>,
+	nullifier_snapshot: PathBuf,
+	shielded_feed: Arc<ShieldedOutputFeed>,
+	shielded_benchmark: Mutex<Option<ShieldedBenchmark>>,
 	_stop_guard: StopGuard,
 }
 
+/// A started IO service without a client, to attach one to.
+///
+/// Keeping this handle keeps the IO service running whatever happens to the clients
+/// attached to it: when attaching a client fails, for instance on a corrupt state, the
+/// embedder can fix the cause and attach again without restarting the IO subsystem.
+pub struct IoOnlyService {
+	io_service: Arc<IoService<ClientIoMessage>>,
+}
+
+impl IoOnlyService {
+	/// Get general IO interface
+	pub fn io(&self) -> Arc<IoService<ClientIoMessage>> {
+		self.io_service.clone()
+	}
+
+	/// Create the client and start the services around it on this IO service, as
+	/// `ClientService::start` does. On failure nothing is registered with the IO service,
+	/// so this can be called again.
+	pub fn attach_client(
+		&self,
+		config: ClientConfig,
+		spec: &Spec,
+		blockchain_db: Arc<BlockChainDB>,
+		snapshot_path: &Path,
+		restoration_db_handler: Box<BlockChainDBHandler>,
+		ipc_path: &Path,
+		miner: Arc<Miner>,
+		receiver: Option<Arc<Mutex<mpsc::Receiver<SyncTransaction>>>>
+	) -> Result<ClientService, Error> {
+		ClientService::attach(self.io_service.clone(), config, spec, blockchain_db, snapshot_path, restoration_db_handler, ipc_path, miner, receiver)
+	}
+}
+
 impl ClientService {
 	/// Start the `ClientService`.
 	pub fn start(
@@ -55,17 +109,44 @@ impl ClientService {
 		blockchain_db: Arc<BlockChainDB>,
 		snapshot_path: &Path,
 		restoration_db_handler: Box<BlockChainDBHandler>,
-		_ipc_path: &Path,
+		ipc_path: &Path,
 		miner: Arc<Miner>,
 		//Put pt_wallet in the same level with miner
 		//pt_wallet: Arc<Wallet>,
 		recevier: Option<Arc<Mutex<mpsc::Receiver<SyncTransaction>>>>
 		) -> Result<ClientService, Error>
 	{
-		let io_service = IoService::<ClientIoMessage>::start()?;
+		let io_service = Arc::new(IoService::<ClientIoMessage>::start()?);
+		Self::attach(io_service, config, spec, blockchain_db, snapshot_path, restoration_db_handler, ipc_path, miner, recevier)
+	}
+
+	/// Start only the IO service, so that a failure creating the client does not stop it.
+	/// Attach the client with `IoOnlyService::attach_client`.
+	pub fn start_io_only() -> Result<IoOnlyService, Error> {
+		Ok(IoOnlyService {
+			io_service: Arc::new(IoService::<ClientIoMessage>::start()?),
+		})
+	}
+
+	fn attach(
+		io_service: Arc<IoService<ClientIoMessage>>,
+		config: ClientConfig,
+		spec: &Spec,
+		blockchain_db: Arc<BlockChainDB>,
+		snapshot_path: &Path,
+		restoration_db_handler: Box<BlockChainDBHandler>,
+		_ipc_path: &Path,
+		miner: Arc<Miner>,
+		recevier: Option<Arc<Mutex<mpsc::Receiver<SyncTransaction>>>>
+	) -> Result<ClientService, Error> {
+		if let Some(params) = spec.shielded_params() {
+			check_shielded_params(&params)?;
+		}
 
 
 		let pruning = config.pruning;
+		let snapshot_startup_grace = config.snapshot.startup_grace;
+		let shielded_benchmark_at_startup = config.shielded_benchmark_at_startup;
 		let client = Client::new(
 			config,
 			&spec,
@@ -77,6 +158,11 @@ impl ClientService {
 		miner.set_io_channel(io_service.channel());
 		miner.set_in_chain_checker(&client.clone());
 
+		let nullifier_snapshot = snapshot_path.join(NULLIFIER_SNAPSHOT_FILE);
+		if !client.nullifier_set_is_current() {
+			client.restore_nullifier_set(&nullifier_snapshot);
+		}
+
 		let snapshot_params = SnapServiceParams {
 			engine: spec.engine.clone(),
 			genesis_block: spec.genesis_block(),
@@ -91,20 +177,38 @@ impl ClientService {
 		let client_io = Arc::new(ClientIoHandler {
 			client: client.clone(),
 			snapshot: snapshot.clone(),
+			snapshot_thread: Mutex::new(None),
+			execute_pool: ExecutePool::new(client.clone(), EXECUTE_POOL_THREADS, EXECUTE_POOL_QUEUE_SIZE),
+			snapshot_startup_grace,
+			initialized_at: Mutex::new(None),
 		});
-		io_service.register_handler(client_io)?;
+		io_service.register_handler(client_io.clone())?;
 
 		spec.engine.register_client(Arc::downgrade(&client) as _);
 
+		let shielded_feed = Arc::new(ShieldedOutputFeed::new(Arc::downgrade(&client), SHIELDED_FEED_BUFFER));
+		client.add_notify(shielded_feed.clone());
+
 		let stop_guard = StopGuard::new();
 
-		Ok(ClientService {
-			io_service: Arc::new(io_service),
+		let service = ClientService {
+			io_service,
 			client: client,
 			snapshot: snapshot,
 			database: blockchain_db,
+			client_io: client_io,
+			abort_tree_check: AtomicBool::new(false),
+			cache_warming: Mutex::new(None),
+			abort_cache_warming: Arc::new(AtomicBool::new(false)),
+			nullifier_snapshot,
+			shielded_feed,
+			shielded_benchmark: Mutex::new(None),
 			_stop_guard: stop_guard,
-		})
+		};
+		if shielded_benchmark_at_startup {
+			service.benchmark_shielded();
+		}
+		Ok(service)
 	}
 
 	/// Get general IO interface
@@ -135,16 +239,352 @@ impl ClientService {
 	/// Get a handle to the database.
 	pub fn db(&self) -> Arc<BlockChainDB> { self.database.clone() }
 
+	/// Get the compact representation of the block with the given hash, for light wallets.
+	pub fn compact_block(&self, hash: &H256) -> Option<CompactBlock> {
+		self.client.block(BlockId::Hash(*hash)).map(|block| CompactBlock::from_block(&block))
+	}
+
+	/// Get the shielded outputs of the transaction with the given hash, for explorers.
+	/// Returns `None` for unknown transactions and no outputs for public transactions.
+	pub fn shielded_outputs_of(&self, txid: &H256) -> Option<Vec<OutputDescription>> {
+		self.client.shielded_outputs_of(txid)
+	}
+
+	/// Iterate over the shielded outputs of the canonical blocks `from..=to`, with the
+	/// height of their block, the hash of their transaction and their index in it.
+	///
+	/// Blocks are read one at a time as the iterator advances and the outputs are in the
+	/// order their note commitments enter the commitment tree, so counting them gives the
+	/// position of each note. No lock is held between blocks, so a reorg during the walk
+	/// may mix blocks of both branches. The iteration ends at the first unknown block.
+	pub fn iter_shielded_outputs(&self, from: u64, to: u64) -> impl Iterator<Item = (u64, H256, usize, OutputDescription)> {
+		let client = self.client.clone();
+		(from..=to)
+			.map(move |number| client.block(BlockId::Number(number)).map(|block| (number, block)))
+			.take_while(Option::is_some)
+			.flatten()
+			.flat_map(|(number, block)| {
+				block.view()
+					.shielded_outputs()
+					.into_iter()
+					.map(move |(hash, i, output)| (number, hash, i, output))
+			})
+	}
+
+	/// Subscribe to the shielded outputs of the blocks imported from now on, in the order
+	/// of the commitment tree, and to a `Rollback` marker when a reorg retracts blocks.
+	///
+	/// Delivery is at least once: after a rollback the outputs of the new branch are sent,
+	/// including those of transactions which were already sent for the retracted blocks.
+	/// Events a subscriber leaves unread beyond `SHIELDED_FEED_BUFFER` are dropped, and
+	/// reported to it by a `Lost` event once it has room again; it must then resync, for
+	/// instance with `iter_shielded_outputs`.
+	pub fn subscribe_shielded_outputs(&self) -> mpsc::Receiver<ShieldedOutputEvent> {
+		self.shielded_feed.subscribe()
+	}
+
+	/// Number of shielded output events dropped because a subscriber did not keep up,
+	/// over all subscribers.
+	pub fn dropped_shielded_outputs(&self) -> usize {
+		self.shielded_feed.dropped()
+	}
+
+	/// Pin a block, so that its commitment root stays indexed, and `commitment_root_block`
+	/// finds it, however far the anchor history is pruned. The pinned set survives restarts.
+	///
+	/// Pins are local to the node, so they cannot change which anchors are valid: a pinned
+	/// root outside the anchor window is refused by the pool and by block verification
+	/// like any other.
+	pub fn pin_block(&self, hash: H256) {
+		self.client.pin_block(hash)
+	}
+
+	/// Unpin a block pinned with `pin_block`. Returns `false` if it was not pinned.
+	pub fn unpin_block(&self, hash: H256) -> bool {
+		self.client.unpin_block(&hash)
+	}
+
+	/// Get the fee, as a gas price, of the lowest priority transaction in the queue when
+	/// the queue is full, or `None` when there is room. Wallets should pay more than this
+	/// for a transaction not to be dropped straight away.
+	pub fn sync_transaction_min_fee(&self) -> Option<U256> {
+		self.client.miner().full_queue_min_gas_price()
+	}
+
+	/// Gather the state of the service to attach to a bug report. This holds no keys or
+	/// wallet contents and does not scan the database, so it is cheap to call.
+	pub fn diagnostic_dump(&self) -> DiagnosticReport {
+		let chain_info = self.client.chain_info();
+		DiagnosticReport {
+			best_block_number: chain_info.best_block_number,
+			best_block_hash: chain_info.best_block_hash,
+			chain_id: self.client.signing_chain_id(),
+			engine: self.client.engine().name().into(),
+			snapshot: SnapshotReport::new(self.snapshot.is_taking_snapshot(), self.snapshot.status()),
+			queues: QueueReport::new(&self.client),
+			shielded: ShieldedReport::new(&self.client),
+			config: self.client.config().into(),
+		}
+	}
+
+	/// Check that the persisted commitment tree matches the canonical chain up to block
+	/// `up_to`, reporting the first inconsistent block.
+	///
+	/// This replays every block from genesis, so it can take a long time. It can be
+	/// cancelled from another thread with `abort_commitment_tree_check`.
+	pub fn verify_commitment_tree(&self, up_to: u64) -> Result<(), TreeInconsistency> {
+		self.abort_tree_check.store(false, Ordering::SeqCst);
+		self.client.verify_commitment_tree(up_to, &self.abort_tree_check)
+	}
+
+	/// Get the latest commitment tree checkpoint: the height of its block and the tree
+	/// frontier after that block. Rebuilding the tree starts from it instead of genesis,
+	/// as done on start when the tree of the best block is missing.
+	pub fn last_tree_checkpoint(&self) -> Option<(u64, CommitmentTree<Node>)> {
+		self.client.last_tree_checkpoint()
+	}
+
+	/// Get a fingerprint of the shielded state after the canonical block at `height`, to
+	/// check that nodes agree on it. See `BlockChain::shielded_state_fingerprint`.
+	pub fn shielded_state_fingerprint(&self, height: u64) -> Option<H256> {
+		self.client.shielded_state_fingerprint(height)
+	}
+
+	/// Get the chunks of the snapshot being restored which have not arrived yet, as
+	/// (state chunks, block chunks) in manifest order. Both are empty if no snapshot is
+	/// being restored.
+	pub fn outstanding_restore_chunks(&self) -> (Vec<H256>, Vec<H256>) {
+		self.snapshot.outstanding_chunks()
+	}
+
+	/// Get the anchor of each canonical block from height `from` to `to`, in order.
+	///
+	/// The range is walked once, appending the shielded outputs of each block to the tree
+	/// of the first one, so this is far cheaper than reading every tree. The result holds
+	/// about 40 bytes per height and ends early at the first unknown block, so callers
+	/// walking a long range should split it.
+	pub fn anchors_for_range(&self, from: u64, to: u64) -> Vec<(u64, Node)> {
+		self.client.anchors(from, to)
+	}
+
+	/// Get the position of the note commitment `cm` in the canonical commitment tree, so
+	/// that a wallet can check a note it expects to hold was mined. `None` if no canonical
+	/// block has appended it.
+	///
+	/// Only available with the `commitment-index` feature: the index behind it is kept
+	/// during import and costs storage proportional to the number of shielded outputs.
+	#[cfg(feature = "commitment-index")]
+	pub fn commitment_position(&self, cm: &Node) -> Option<u64> {
+		self.client.commitment_position(cm)
+	}
+
+	/// Check a witness a wallet restored, e.g. from a backup, before spending the note
+	/// `leaf` with it: the witness must authenticate the note and its root must be the
+	/// commitment root of a block within the anchor window, or the pool would refuse the
+	/// spend as anchored to an expired root.
+	pub fn validate_witness(&self, leaf: &Node, witness: &IncrementalWitness<Node>) -> bool {
+		self.client.validate_witness(leaf, witness)
+	}
+
+	/// Time the primitives of shielded verification on this node, log the result and keep
+	/// it for `shielded_benchmark`.
+	///
+	/// This blocks the calling thread for `3 * SHIELDED_BENCHMARK_BUDGET` and competes with
+	/// the import for the CPU while it runs. A node whose spend verifications per second
+	/// are close to the spends per second of the chain cannot keep up with the tip.
+	pub fn benchmark_shielded(&self) -> ShieldedBenchmark {
+		let benchmark = ShieldedBenchmark::run(SHIELDED_BENCHMARK_BUDGET);
+		info!(
+			"Shielded verification: {:.0} pedersen hashes/s, {:.1} spend proofs/s, {:.1} output proofs/s",
+			benchmark.pedersen_hashes_per_sec,
+			benchmark.spend_verify_per_sec,
+			benchmark.output_verify_per_sec
+		);
+		*self.shielded_benchmark.lock() = Some(benchmark);
+		benchmark
+	}
+
+	/// Get the result of the last `benchmark_shielded`, if any.
+	pub fn shielded_benchmark(&self) -> Option<ShieldedBenchmark> {
+		*self.shielded_benchmark.lock()
+	}
+
+	/// Bootstrap the shielded state from a trusted checkpoint, instead of computing it
+	/// from the shielded outputs of every block up to the checkpoint.
+	///
+	/// The nullifier set at the checkpoint height, as read by `NullifierSet::load_snapshot`,
+	/// must come with it. Only the consistency of the checkpoint is checked, that its anchor
+	/// is the root of its tree frontier and the nullifier set matches its hash: the caller
+	/// must have checked its signature and trust its signer, see `ShieldedCheckpoint`. The
+	/// node must be below the checkpoint height.
+	pub fn load_shielded_checkpoint(&self, checkpoint: ShieldedCheckpoint, nullifiers: &NullifierSnapshot) -> Result<(), Error> {
+		self.client.load_shielded_checkpoint(checkpoint, nullifiers)
+			.map_err(|e| ErrorKind::InvalidShieldedCheckpoint(e).into())
+	}
+
+	/// Run a closure against the client on the execution pool instead of the IO thread,
+	/// waiting for room if the pool is saturated, and return a handle to its result.
+	///
+	/// The closure may run concurrently with the IO thread and with other pooled
+	/// closures, so it must not assume exclusive access to the client.
+	pub fn execute_on_pool<T, F>(&self, f: F) -> ExecuteHandle<T> where
+		T: Send + 'static,
+		F: FnOnce(&Client) -> T + Send + 'static,
+	{
+		self.client_io.execute_pool.execute(f)
+	}
+
+	/// Register the client with the engine of `spec` and hand it the chain rewards, as
+	/// on start, so that this engine can query the chain, as to prepare a testnet fork.
+	///
+	/// This does not swap the engine of the client: blocks are still verified, imported
+	/// and sealed by the engine the client was started with. Fails with `ErrorKind::Busy`
+	/// while blocks are being imported, so that the engine never reads the chain in the
+	/// middle of a block.
+	pub fn register_engine(&self, spec: &Spec) -> Result<(), Error> {
+		let client = self.client.clone();
+		self.client.with_import_paused(|| {
+			spec.engine.register_client(Arc::downgrade(&client) as _);
+			spec.engine.update_rewards(client.get_rewards());
+		}).ok_or_else(|| ErrorKind::Busy.into())
+	}
+
+	/// Take the transactions of the blocks the miner prepares from `source` instead of its
+	/// transaction queue, without restarting. See `Miner::set_transaction_source`.
+	pub fn set_miner_tx_source(&self, source: Arc<TransactionSource>) {
+		self.client.miner().set_transaction_source(source);
+	}
+
+	/// Cancel a running `verify_commitment_tree`.
+	pub fn abort_commitment_tree_check(&self) {
+		self.abort_tree_check.store(true, Ordering::SeqCst);
+	}
+
+	/// Verify the private transactions of the last `last_n_blocks` blocks in a background
+	/// thread, recording the results in the proof cache, so that these transactions are
+	/// not verified again if a reorg brings them back. See `Client::warm_verification_cache`.
+	/// Returns without waiting for it.
+	///
+	/// A warming still running is cancelled first. `abort_cache_warming` cancels this one
+	/// after the transaction being verified.
+	pub fn warm_verification_cache(&self, last_n_blocks: usize) {
+		let mut warming = self.cache_warming.lock();
+		if let Some(handle) = warming.take() {
+			self.abort_cache_warming.store(true, Ordering::SeqCst);
+			if handle.join().is_err() {
+				warn!("Proof cache warming thread panicked");
+			}
+		}
+		self.abort_cache_warming.store(false, Ordering::SeqCst);
+
+		let client = self.client.clone();
+		let abort = self.abort_cache_warming.clone();
+		let res = thread::Builder::new().name("Cache Warming".into()).spawn(move || {
+			let verified = client.warm_verification_cache(last_n_blocks, &abort);
+			info!("Warmed the proof cache with {} transactions of the last {} blocks", verified, last_n_blocks);
+		});
+		match res {
+			Ok(handle) => *warming = Some(handle),
+			Err(e) => warn!("Failed to start warming the proof cache: {}", e),
+		}
+	}
+
+	/// Cancel a running `warm_verification_cache`.
+	pub fn abort_cache_warming(&self) {
+		self.abort_cache_warming.store(true, Ordering::SeqCst);
+	}
+
+	// cancel the cache warming, if any, and wait for its thread.
+	fn join_cache_warming(&self) {
+		self.abort_cache_warming();
+		if let Some(handle) = self.cache_warming.lock().take() {
+			trace!(target: "shutdown", "[ClientService] Waiting for the cache warming thread");
+			if handle.join().is_err() {
+				warn!("Proof cache warming thread panicked");
+			}
+		}
+	}
+
 	/// Shutdown the Client Service
 	pub fn shutdown(&self) {
+		self.abort_commitment_tree_check();
+		self.join_cache_warming();
 		self.snapshot.shutdown();
+		self.client_io.join_snapshot_thread();
+		self.client_io.execute_pool.shutdown();
+	}
+}
+
+impl Drop for ClientService {
+	/// Tear the service down in a fixed order instead of relying on field drop order:
+	/// block import is cancelled after the block being imported, the proof cache warming is
+	/// cancelled and joined, the snapshot service is shut down, then the IO handlers (which hold
+	/// `Arc`s to the client and snapshot service) are deregistered, the periodic
+	/// snapshot thread and the execution pool are joined, the nullifier set is saved
+	/// for the next start, and finally the IO service is stopped if no one else holds it.
+	fn drop(&mut self) {
+		trace!(target: "shutdown", "[ClientService] Closing...");
+		self.client.cancel_import();
+		self.join_cache_warming();
+		self.snapshot.shutdown();
+		self.io_service.deregister_handlers();
+		self.client_io.join_snapshot_thread();
+		self.client_io.execute_pool.shutdown();
+		if let Err(e) = self.client.save_nullifier_snapshot(&self.nullifier_snapshot) {
+			warn!("Failed to save the nullifier set snapshot: {}", e);
+		}
+		match Arc::get_mut(&mut self.io_service) {
+			Some(io_service) => io_service.stop(),
+			None => debug!(target: "shutdown", "IO service is still shared, leaving it running"),
+		}
+		trace!(target: "shutdown", "[ClientService] Closed.");
 	}
 }
 
+/// Check that the verifying keys named by the spec are the ones shielded proofs are
+/// checked with, so that a misconfigured node fails on start instead of mid-sync.
+fn check_shielded_params(params: &ShieldedParams) -> Result<(), Error> {
+	let keys = [
+		(params.spend_verifying_key, Circuit::Spend),
+		(params.output_verifying_key, Circuit::Output),
+	];
+	for &(id, circuit) in &keys {
+		if id != H256::from(zkp::circuit_verifying_key_id(circuit)) {
+			return Err(ErrorKind::MissingShieldedParams(circuit, id).into());
+		}
+	}
+	Ok(())
+}
+
 /// IO interface for the Client handler
 struct ClientIoHandler {
 	client: Arc<Client>,
 	snapshot: Arc<SnapshotService>,
+	snapshot_thread: Mutex<Option<thread::JoinHandle<()>>>,
+	execute_pool: ExecutePool,
+	snapshot_startup_grace: Duration,
+	initialized_at: Mutex<Option<Instant>>,
+}
+
+impl ClientIoHandler {
+	/// Wait for the periodic snapshot thread, if any, to finish.
+	fn join_snapshot_thread(&self) {
+		if let Some(handle) = self.snapshot_thread.lock().take() {
+			trace!(target: "shutdown", "[ClientService] Waiting for the periodic snapshot thread");
+			if handle.join().is_err() {
+				warn!("Periodic snapshot thread panicked");
+			}
+		}
+	}
+
+	/// Whether the handler was initialized less than `snapshot_startup_grace` ago, or not
+	/// yet, in which case snapshot ticks are ignored.
+	fn in_snapshot_startup_grace(&self) -> bool {
+		match *self.initialized_at.lock() {
+			Some(at) => at.elapsed() < self.snapshot_startup_grace,
+			None => true,
+		}
+	}
 }
 
 const CLIENT_TICK_TIMER: TimerToken = 0;
@@ -157,6 +597,7 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 	fn initialize(&self, io: &IoContext<ClientIoMessage>) {
 		io.register_timer(CLIENT_TICK_TIMER, CLIENT_TICK).expect("Error registering client timer");
 		io.register_timer(SNAPSHOT_TICK_TIMER, SNAPSHOT_TICK).expect("Error registering snapshot timer");
+		*self.initialized_at.lock() = Some(Instant::now());
 	}
 
 	fn timeout(&self, _io: &IoContext<ClientIoMessage>, timer: TimerToken) {
@@ -167,6 +608,7 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 				let snapshot_restoration = if let RestorationStatus::Ongoing{..} = self.snapshot.status() { true } else { false };
 				self.client.tick(snapshot_restoration)
 			},
+			SNAPSHOT_TICK_TIMER if self.in_snapshot_startup_grace() => {},
 			SNAPSHOT_TICK_TIMER => self.snapshot.tick(),
 			_ => warn!("IO service triggered unregistered timer '{}'", timer),
 		}
@@ -174,7 +616,6 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 
 	fn message(&self, _io: &IoContext<ClientIoMessage>, net_message: &ClientIoMessage) {
 		trace_time!("service::message");
-		use std::thread;
 
 		match *net_message {
 			ClientIoMessage::BlockVerified => {
@@ -192,6 +633,19 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 				self.snapshot.feed_block_chunk(*hash, chunk)
 			}
 			ClientIoMessage::TakeSnapshot(num) => {
+				if self.in_snapshot_startup_grace() {
+					info!("Skipping snapshot at #{} as the node was started less than {:?} ago.", num, self.snapshot_startup_grace);
+					return;
+				}
+				if self.snapshot.is_taking_snapshot() {
+					info!("Skipping snapshot at #{} as another one is currently in-progress.", num);
+					return;
+				}
+
+				// the previous snapshot thread is done or about to be, reap it
+				// before keeping track of the new one.
+				self.join_snapshot_thread();
+
 				let client = self.client.clone();
 				let snapshot = self.snapshot.clone();
 
@@ -201,13 +655,21 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 					}
 				});
 
-				if let Err(e) = res {
-					debug!(target: "snapshot", "Failed to initialize periodic snapshot thread: {:?}", e);
+				match res {
+					Ok(handle) => *self.snapshot_thread.lock() = Some(handle),
+					Err(e) => debug!(target: "snapshot", "Failed to initialize periodic snapshot thread: {:?}", e),
 				}
 			},
 			ClientIoMessage::Execute(ref exec) => {
 				(*exec.0)(&self.client);
 			}
+			ClientIoMessage::ExecuteOnPool(ref exec) => {
+				let callback = exec.clone();
+				if self.execute_pool.try_execute(Box::new(move |client| (*callback.0)(client))).is_err() {
+					trace!(target: "client", "Execution pool saturated, running the closure on the IO thread");
+					(*exec.0)(&self.client);
+				}
+			}
 			_ => {} // ignore other messages
 		}
 	}
@@ -220,43 +682,248 @@ mod tests {
 
 	use tempdir::TempDir;
 
-	use ethcore_db::NUM_COLUMNS;
 	use ethcore::client::ClientConfig;
 	use ethcore::miner::Miner;
 	use ethcore::spec::Spec;
 	use ethcore::test_helpers;
-	use kvdb_rocksdb::{DatabaseConfig, CompactionProfile};
+	use blockchain::NullifierSet;
+	use zcash_primitives::merkle_tree::{CommitmentTree, Hashable};
 	use super::*;
 
+	fn start_service(tempdir: &TempDir, config: ClientConfig) -> ClientService {
+		let client_db_handler = test_helpers::memory_db_handler();
+		let client_db = client_db_handler.open(&tempdir.path().join("client")).unwrap();
+		let spec = Spec::new_test();
+		ClientService::start(
+			config,
+			&spec,
+			client_db,
+			&tempdir.path().join("snapshot"),
+			test_helpers::memory_db_handler(),
+			tempdir.path(),
+			Arc::new(Miner::new_for_tests(&spec, None)),
+			None,
+		).unwrap()
+	}
+
 	#[test]
 	fn it_can_be_started() {
 		let tempdir = TempDir::new("").unwrap();
-		let client_path = tempdir.path().join("client");
+		let service = start_service(&tempdir, ClientConfig::default());
+		drop(service);
+		thread::park_timeout(time::Duration::from_millis(100));
+	}
+
+	#[test]
+	fn saves_nullifier_snapshot_on_drop() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+		let genesis_hash = service.client().chain_info().best_block_hash;
+		drop(service);
+
+		let snapshot_path = tempdir.path().join("snapshot");
+		let snapshot = NullifierSet::load_snapshot(&snapshot_path.join(NULLIFIER_SNAPSHOT_FILE)).unwrap();
+		assert_eq!(snapshot.block_number, 0);
+		assert_eq!(snapshot.block_hash, genesis_hash);
+		assert!(snapshot.entries.is_empty());
+	}
+
+	#[test]
+	fn attaches_clients_to_a_running_io_service() {
+		let tempdir = TempDir::new("").unwrap();
 		let snapshot_path = tempdir.path().join("snapshot");
+		let spec = Spec::new_test();
+		let io = ClientService::start_io_only().unwrap();
+
+		for i in 0..2 {
+			let client_db = test_helpers::memory_db_handler().open(&tempdir.path().join(format!("client{}", i))).unwrap();
+			let service = io.attach_client(
+				ClientConfig::default(),
+				&spec,
+				client_db,
+				&snapshot_path,
+				test_helpers::memory_db_handler(),
+				tempdir.path(),
+				Arc::new(Miner::new_for_tests(&spec, None)),
+				None,
+			).unwrap();
+			assert!(Arc::ptr_eq(&service.io(), &io.io()));
+
+			let client = Arc::downgrade(&service.client());
+			drop(service);
+			assert!(client.upgrade().is_none());
+		}
+	}
+
+	#[test]
+	fn dropping_service_releases_client() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
 
-		let client_config = ClientConfig::default();
-		let mut client_db_config = DatabaseConfig::with_columns(NUM_COLUMNS);
+		let client = Arc::downgrade(&service.client());
+		drop(service);
+		assert!(client.upgrade().is_none());
+	}
 
-		client_db_config.memory_budget = client_config.db_cache_size;
-		client_db_config.compaction = CompactionProfile::auto(&client_path);
+	#[test]
+	fn shutdown_joins_snapshot_thread() {
+		let tempdir = TempDir::new("").unwrap();
+		let mut config = ClientConfig::default();
+		config.snapshot.startup_grace = time::Duration::from_secs(0);
+		let service = start_service(&tempdir, config);
+
+		service.io().send_message(ClientIoMessage::TakeSnapshot(0)).unwrap();
+		let mut waited = 0;
+		while service.client_io.snapshot_thread.lock().is_none() {
+			assert!(waited < 100, "snapshot thread was not spawned");
+			thread::sleep(time::Duration::from_millis(10));
+			waited += 1;
+		}
 
-		let client_db_handler = test_helpers::restoration_db_handler(client_db_config.clone());
-		let client_db = client_db_handler.open(&client_path).unwrap();
-		let restoration_db_handler = test_helpers::restoration_db_handler(client_db_config);
+		let client = Arc::downgrade(&service.client());
+		let snapshot = Arc::downgrade(&service.snapshot_service());
+		service.shutdown();
+		assert!(service.client_io.snapshot_thread.lock().is_none());
+		drop(service);
 
-		let spec = Spec::new_test();
-		let service = ClientService::start(
-			ClientConfig::default(),
-			&spec,
-			client_db,
-			&snapshot_path,
-			restoration_db_handler,
-			tempdir.path(),
-			Arc::new(Miner::new_for_tests(&spec, None)),
-			None,
-		);
-		assert!(service.is_ok());
-		drop(service.unwrap());
-		thread::park_timeout(time::Duration::from_millis(100));
+		// the snapshot thread holds both, so it must be gone.
+		assert!(client.upgrade().is_none());
+		assert!(snapshot.upgrade().is_none());
+	}
+
+	#[test]
+	fn shutdown_joins_cache_warming_thread() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+
+		service.warm_verification_cache(100);
+		// starting again replaces the running warming.
+		service.warm_verification_cache(100);
+		assert!(service.cache_warming.lock().is_some());
+
+		let client = Arc::downgrade(&service.client());
+		service.shutdown();
+		assert!(service.cache_warming.lock().is_none());
+		drop(service);
+		assert!(client.upgrade().is_none());
+	}
+
+	#[test]
+	fn skips_snapshots_during_startup_grace() {
+		let tempdir = TempDir::new("").unwrap();
+		let mut config = ClientConfig::default();
+		config.snapshot.startup_grace = time::Duration::from_secs(3600);
+		let service = start_service(&tempdir, config);
+
+		// as the periodic snapshot watcher would on every new block.
+		for num in 0..5 {
+			service.io().send_message(ClientIoMessage::TakeSnapshot(num)).unwrap();
+		}
+		// the messages are handled in order, so this runs after all of them.
+		let (tx, rx) = mpsc::channel();
+		let tx = Mutex::new(tx);
+		service.io().send_message(ClientIoMessage::execute(move |_| { let _ = tx.lock().send(()); })).unwrap();
+		rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+
+		assert!(service.client_io.in_snapshot_startup_grace());
+		assert!(service.client_io.snapshot_thread.lock().is_none());
+		assert!(!service.snapshot_service().is_taking_snapshot());
+	}
+
+	#[test]
+	fn checks_shielded_params() {
+		let spec = Spec::new_test_confidential();
+		let mut params = spec.shielded_params().unwrap();
+		assert!(check_shielded_params(&params).is_ok());
+
+		let swapped = params.spend_verifying_key;
+		params.output_verifying_key = swapped;
+		match check_shielded_params(&params) {
+			Err(Error(ErrorKind::MissingShieldedParams(circuit, id), _)) => {
+				assert_eq!(circuit, Circuit::Output);
+				assert_eq!(id, swapped);
+			},
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn loads_shielded_checkpoint_above_best_block() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+
+		let mut tree = CommitmentTree::<Node>::new();
+		tree.append(Node::empty_root(0)).unwrap();
+		let nullifiers = |block_number| NullifierSnapshot { block_number, block_hash: H256::zero(), entries: vec![] };
+		let checkpoint = |height| ShieldedCheckpoint {
+			height,
+			anchor: tree.root(),
+			nullifier_set_hash: nullifiers(height).hash(),
+			tree_frontier: tree.clone(),
+		};
+
+		match service.load_shielded_checkpoint(checkpoint(0), &nullifiers(0)) {
+			Err(Error(ErrorKind::InvalidShieldedCheckpoint(_), _)) => {},
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(service.load_shielded_checkpoint(checkpoint(5), &nullifiers(5)).is_ok());
+		assert_eq!(service.client().shielded_checkpoint().map(|c| c.height), Some(5));
+	}
+
+	#[test]
+	fn executes_closures_on_pool() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+
+		let handle = service.execute_on_pool(|client| client.chain_info().best_block_number);
+		assert_eq!(handle.wait(), Some(0));
+
+		let (tx, rx) = mpsc::channel();
+		let tx = Mutex::new(tx);
+		service.io().send_message(ClientIoMessage::execute_on_pool(move |_| {
+			tx.lock().send(thread::current().name().map(str::to_owned)).unwrap();
+		})).unwrap();
+		let name = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+		assert!(name.unwrap().starts_with("Client Execute"));
+
+		let client = Arc::downgrade(&service.client());
+		drop(service);
+		assert!(client.upgrade().is_none());
+	}
+
+	#[test]
+	fn registers_engine_unless_importing() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+
+		let fork = Spec::new_test_round();
+		assert!(service.register_engine(&fork).is_ok());
+
+		// a concurrent registration holds the import lock.
+		let client = service.client();
+		let nested = client.with_import_paused(|| service.register_engine(&fork)).unwrap();
+		match nested {
+			Err(Error(ErrorKind::Busy, _)) => {},
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn dumps_diagnostic_report() {
+		let tempdir = TempDir::new("").unwrap();
+		let service = start_service(&tempdir, ClientConfig::default());
+
+		let report = service.diagnostic_dump();
+		assert_eq!(report.best_block_number, 0);
+		assert_eq!(report.best_block_hash, Spec::new_test().genesis_header().hash());
+		assert_eq!(report.engine, "NullEngine");
+		assert_eq!(report.snapshot.restoration, "inactive");
+		assert_eq!(report.queues.unverified_blocks, 0);
+		assert_eq!(report.shielded.pinned_blocks, 0);
+		assert_eq!(report.config.pruning, ClientConfig::default().pruning.as_str());
+
+		let json = ::serde_json::to_value(&report).unwrap();
+		assert_eq!(json["bestBlockNumber"], 0);
+		assert_eq!(json["shielded"]["anchorsRetained"], report.shielded.anchors_retained);
 	}
 }
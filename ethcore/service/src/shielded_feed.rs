@@ -0,0 +1,303 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Push stream of the shielded outputs of imported blocks, for light wallet backends.
+
+use std::sync::Weak;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+use ethcore::client::{BlockChainClient, ChainNotify, Client, NewBlocks};
+use common_types::ids::BlockId;
+use parking_lot::Mutex;
+use zcash_primitives::transaction::components::OutputDescription;
+
+/// Number of events a subscriber may leave unread before new ones are dropped.
+pub const SHIELDED_FEED_BUFFER: usize = 4096;
+
+/// An event of the shielded output stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShieldedOutputEvent {
+	/// A shielded output of the canonical block at the given height, in the order of the
+	/// commitment tree.
+	Output(u64, OutputDescription),
+	/// The canonical blocks from the given height up were retracted by a reorg: the
+	/// outputs received for them are orphaned. The outputs of the new branch follow.
+	Rollback(u64),
+	/// The given number of events did not fit the subscriber's buffer and were dropped
+	/// before this one: the subscriber must resync by polling the chain.
+	Lost(usize),
+}
+
+/// A subscriber and the number of its events dropped since the last one it was sent.
+struct Subscriber {
+	sender: SyncSender<ShieldedOutputEvent>,
+	lost: usize,
+}
+
+/// Sends the shielded outputs of each imported canonical block to its subscribers.
+///
+/// Delivery is at least once: an output is sent again if its transaction is mined again
+/// in a new branch after a `Rollback`, so subscribers should key outputs by height and
+/// discard those at or above a rollback height. A subscriber which does not keep up
+/// loses the events which do not fit its buffer, including rollbacks, and is told so
+/// by a `Lost` event ahead of the next events which fit.
+pub struct ShieldedOutputFeed {
+	client: Weak<Client>,
+	subscribers: Mutex<Vec<Subscriber>>,
+	buffer: usize,
+	dropped: AtomicUsize,
+}
+
+impl ShieldedOutputFeed {
+	/// Create a feed reading the imported blocks from `client`, with room for `buffer`
+	/// unread events per subscriber.
+	pub fn new(client: Weak<Client>, buffer: usize) -> Self {
+		ShieldedOutputFeed {
+			client,
+			subscribers: Mutex::new(Vec::new()),
+			buffer,
+			dropped: AtomicUsize::new(0),
+		}
+	}
+
+	/// Add a subscriber. It is removed once the receiver is dropped.
+	pub fn subscribe(&self) -> Receiver<ShieldedOutputEvent> {
+		let (sender, receiver) = mpsc::sync_channel(self.buffer);
+		self.subscribers.lock().push(Subscriber { sender, lost: 0 });
+		receiver
+	}
+
+	/// Number of events dropped because a subscriber's buffer was full, over all subscribers.
+	pub fn dropped(&self) -> usize {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Send `events` to every subscriber, dropping what does not fit.
+	fn publish(&self, events: Vec<ShieldedOutputEvent>) {
+		let mut subscribers = self.subscribers.lock();
+		let mut connected = Vec::with_capacity(subscribers.len());
+		for mut subscriber in subscribers.drain(..) {
+			if let Some(dropped) = subscriber.send(&events) {
+				self.dropped.fetch_add(dropped, Ordering::Relaxed);
+				connected.push(subscriber);
+			}
+		}
+		*subscribers = connected;
+	}
+}
+
+impl Subscriber {
+	/// Send `events`, preceded by a `Lost` event if some were dropped before. Once an event
+	/// does not fit, it and the following ones are dropped, so that no gap goes unreported.
+	///
+	/// Returns the number of events dropped, or `None` if the receiver is gone.
+	fn send(&mut self, events: &[ShieldedOutputEvent]) -> Option<usize> {
+		for (i, event) in events.iter().enumerate() {
+			if self.lost > 0 {
+				match self.sender.try_send(ShieldedOutputEvent::Lost(self.lost)) {
+					Ok(()) => self.lost = 0,
+					Err(TrySendError::Full(_)) => {
+						self.lost += events.len() - i;
+						return Some(events.len() - i);
+					},
+					Err(TrySendError::Disconnected(_)) => return None,
+				}
+			}
+			match self.sender.try_send(event.clone()) {
+				Ok(()) => {},
+				Err(TrySendError::Full(_)) => {
+					self.lost = events.len() - i;
+					return Some(events.len() - i);
+				},
+				Err(TrySendError::Disconnected(_)) => return None,
+			}
+		}
+		Some(0)
+	}
+}
+
+impl ChainNotify for ShieldedOutputFeed {
+	fn new_blocks(&self, new_blocks: NewBlocks) {
+		if self.subscribers.lock().is_empty() {
+			return;
+		}
+		let client = match self.client.upgrade() {
+			Some(client) => client,
+			None => return,
+		};
+
+		let mut events = Vec::new();
+		let rollback = new_blocks.route.retracted().iter()
+			.filter_map(|hash| client.block_header(BlockId::Hash(*hash)))
+			.map(|header| header.number())
+			.min();
+		if let Some(height) = rollback {
+			events.push(ShieldedOutputEvent::Rollback(height));
+		}
+
+		for hash in new_blocks.route.enacted() {
+			let block = match client.block(BlockId::Hash(*hash)) {
+				Some(block) => block,
+				None => continue,
+			};
+			let height = block.number();
+			events.extend(block.view()
+				.shielded_outputs()
+				.into_iter()
+				.map(|(_, _, output)| ShieldedOutputEvent::Output(height, output)));
+		}
+
+		if !events.is_empty() {
+			self.publish(events);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use blockchain::wallet::transaction_builder::TransactionBuilder;
+	use blockchain::wallet::wallet_types::{SaplingExtendedFullViewingKey, SaplingExtendedSpendingKey};
+	use common_types::transaction::{SignedTransaction, CONVERSION_FACTOR};
+	use ethcore::client::{ChainInfo, ChainRoute, ChainRouteType};
+	use ethcore::spec::Spec;
+	use ethcore::test_helpers::{generate_dummy_client, generate_dummy_client_with_spec, push_block_with_transactions};
+	use ethereum_types::{H256, U256};
+	use ethkey::KeyPair;
+	use hash::keccak;
+
+	fn feed(buffer: usize) -> ShieldedOutputFeed {
+		ShieldedOutputFeed::new(Weak::new(), buffer)
+	}
+
+	fn new_blocks(route: Vec<(H256, ChainRouteType)>) -> NewBlocks {
+		NewBlocks::new(vec![], vec![], ChainRoute::new(route), vec![], vec![], Duration::from_millis(0), false)
+	}
+
+	// a transaction shielding public funds of the premined account.
+	fn shielding_transaction(client: &Client) -> SignedTransaction {
+		let chain_id = client.signing_chain_id();
+		let key = KeyPair::from_secret(keccak("").into()).unwrap();
+		let extfvk = SaplingExtendedFullViewingKey::from(&SaplingExtendedSpendingKey::master(&[]));
+		let to = extfvk.default_address().unwrap().1;
+		let value = U256::from(100) * CONVERSION_FACTOR;
+
+		let mut builder = TransactionBuilder::new(0.into(), 0.into(), 100_000.into(), vec![], chain_id.unwrap());
+		builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "fed").unwrap();
+		builder.set_public_input(value);
+		builder.build().unwrap().sign(key.secret(), chain_id)
+	}
+
+	#[test]
+	fn sends_the_outputs_of_enacted_blocks() {
+		let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+		let tx = shielding_transaction(&client);
+		push_block_with_transactions(&client, &[tx.clone()]);
+		let hash = client.chain_info().best_block_hash;
+		let feed = ShieldedOutputFeed::new(Arc::downgrade(&client), 16);
+		let receiver = feed.subscribe();
+
+		feed.new_blocks(new_blocks(vec![(hash, ChainRouteType::Enacted)]));
+
+		assert_eq!(
+			receiver.try_iter().collect::<Vec<_>>(),
+			vec![ShieldedOutputEvent::Output(1, tx.v_shielded_output()[0].clone())]
+		);
+	}
+
+	#[test]
+	fn sends_a_rollback_before_the_outputs_of_the_new_branch() {
+		let client = generate_dummy_client(3);
+		let retracted: Vec<_> = (2..4).map(|n| client.block_hash(BlockId::Number(n)).unwrap()).collect();
+		let feed = ShieldedOutputFeed::new(Arc::downgrade(&client), 16);
+		let receiver = feed.subscribe();
+
+		// the lowest retracted height is reported once, whatever the order of the route.
+		feed.new_blocks(new_blocks(vec![
+			(retracted[1], ChainRouteType::Retracted),
+			(retracted[0], ChainRouteType::Retracted),
+		]));
+		assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![ShieldedOutputEvent::Rollback(2)]);
+
+		// a route enacting blocks without shielded outputs sends nothing.
+		let enacted = client.block_hash(BlockId::Number(1)).unwrap();
+		feed.new_blocks(new_blocks(vec![(enacted, ChainRouteType::Enacted)]));
+		assert_eq!(receiver.try_iter().count(), 0);
+	}
+
+	#[test]
+	fn reports_lost_events_once_there_is_room() {
+		let feed = feed(2);
+		let slow = feed.subscribe();
+
+		feed.publish(vec![
+			ShieldedOutputEvent::Rollback(1),
+			ShieldedOutputEvent::Rollback(2),
+			ShieldedOutputEvent::Rollback(3),
+		]);
+		assert_eq!(
+			slow.try_iter().collect::<Vec<_>>(),
+			vec![ShieldedOutputEvent::Rollback(1), ShieldedOutputEvent::Rollback(2)]
+		);
+
+		// the marker takes room in the buffer, so the last event is lost in turn.
+		feed.publish(vec![ShieldedOutputEvent::Rollback(4), ShieldedOutputEvent::Rollback(5)]);
+		assert_eq!(
+			slow.try_iter().collect::<Vec<_>>(),
+			vec![ShieldedOutputEvent::Lost(1), ShieldedOutputEvent::Rollback(4)]
+		);
+		feed.publish(vec![ShieldedOutputEvent::Rollback(6)]);
+		assert_eq!(
+			slow.try_iter().collect::<Vec<_>>(),
+			vec![ShieldedOutputEvent::Lost(1), ShieldedOutputEvent::Rollback(6)]
+		);
+		assert_eq!(feed.dropped(), 2);
+	}
+
+	#[test]
+	fn drops_and_counts_events_of_slow_subscribers() {
+		let feed = feed(2);
+		let slow = feed.subscribe();
+		let fast = feed.subscribe();
+
+		feed.publish(vec![ShieldedOutputEvent::Rollback(1), ShieldedOutputEvent::Rollback(2)]);
+		assert_eq!(fast.try_iter().count(), 2);
+		feed.publish(vec![ShieldedOutputEvent::Rollback(3)]);
+
+		assert_eq!(fast.try_iter().count(), 1);
+		assert_eq!(feed.dropped(), 1);
+		assert_eq!(
+			slow.try_iter().collect::<Vec<_>>(),
+			vec![ShieldedOutputEvent::Rollback(1), ShieldedOutputEvent::Rollback(2)]
+		);
+	}
+
+	#[test]
+	fn removes_dropped_subscribers() {
+		let feed = feed(2);
+		let receiver = feed.subscribe();
+		drop(feed.subscribe());
+
+		feed.publish(vec![ShieldedOutputEvent::Rollback(1)]);
+		assert_eq!(feed.subscribers.lock().len(), 1);
+		assert_eq!(receiver.try_iter().count(), 1);
+		assert_eq!(feed.dropped(), 0);
+	}
+}
@@ -16,6 +16,7 @@
 
 use std::cmp;
 use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::mpsc;
@@ -25,8 +26,8 @@ use std::time::{Duration, Instant};
 
 use blockchain::wallet::wallet::Wallet;
 use blockchain::{
-	BlockChain, BlockChainDB, BlockNumberKey, BlockProvider, BlockReceipts, ExtrasInsert,
-	ImportRoute, TransactionAddress, TreeRoute,
+	AnchorHistoryInfo, BlockChain, BlockChainDB, BlockNumberKey, BlockProvider, BlockReceipts, CheckpointError,
+	ExtrasInsert, ImportRoute, NullifierSnapshot, ShieldedCheckpoint, TransactionAddress, TreeInconsistency, TreeRoute,
 };
 use bytes::Bytes;
 use call_contract::{CallContract, RegistryInfo};
@@ -63,7 +64,7 @@ use client::bad_blocks;
 use client::{
 	AccountData, Balance, BlockChain as BlockChainTrait, BlockChainReset, BlockInfo, BlockProducer,
 	BroadcastProposalBlock, Call, ChainInfo, ClientIoMessage, ImportBlock, ImportSealedBlock,
-	Nonce, PrepareOpenBlock, ReopenBlock, ScheduleInfo, SealedBlockImporter, StateClient,
+	Nonce, PrepareOpenBlock, ReopenBlock, ScheduleInfo, SealedBlockImporter, ServiceHealth, StateClient,
 	StateInfo, StateOrBlock, TransactionInfo,
 };
 use client::{
@@ -100,7 +101,9 @@ use db::Writable;
 pub use types::block_status::BlockStatus;
 pub use types::blockchain_info::BlockChainInfo;
 pub use verification::QueueInfo as BlockQueueInfo;
+use zcash_primitives::merkle_tree::{CommitmentTree, IncrementalWitness};
 use zcash_primitives::sapling::Node;
+use zcash_primitives::transaction::components::OutputDescription;
 
 use_contract!(registry, "res/contracts/registrar.json");
 
@@ -109,6 +112,9 @@ const MAX_ANCIENT_BLOCKS_QUEUE_SIZE: usize = 4096;
 const MAX_ANCIENT_BLOCKS_TO_IMPORT: usize = 4;
 const MAX_QUEUE_SIZE_TO_SLEEP_ON: usize = 2;
 const MIN_HISTORY_SIZE: u64 = 8;
+// how far below the best block, in multiples of `max_commitment_tree_lag`, a stale
+// commitment tree is looked for before it is only reported as that far behind.
+const COMMITMENT_TREE_SEARCH_FACTOR: u64 = 16;
 
 /// Report on the status of a client.
 #[derive(Default, Clone, Debug, Eq, PartialEq)]
@@ -172,6 +178,9 @@ struct Importer {
 	/// Lock used during block import
 	pub import_lock: Mutex<()>, // FIXME Maybe wrap the whole `Importer` instead?
 
+	/// Set to stop importing verified blocks after the block being imported
+	pub import_cancelled: AtomicBool,
+
 	/// Used to verify blocks
 	pub verifier: Box<Verifier<Client>>,
 
@@ -223,6 +232,9 @@ pub struct Client {
 	/// Report on the status of client
 	report: RwLock<ClientReport>,
 
+	/// Conditions found unhealthy on the last tick
+	health: ServiceHealth,
+
 	sleep_state: Mutex<SleepState>,
 
 	/// Flag changed by `sleep` and `wake_up` methods. Not to be confused with `enabled`.
@@ -275,6 +287,7 @@ impl Importer {
 
 		Ok(Importer {
 			import_lock: Mutex::new(()),
+			import_cancelled: AtomicBool::new(false),
 			verifier: verification::new(config.verifier_type.clone()),
 			block_queue,
 			miner,
@@ -287,7 +300,7 @@ impl Importer {
 	/// This is triggered by a message coming from a block queue when the block is ready for insertion
 	pub fn import_verified_blocks(&self, client: &Client) -> usize {
 		// Shortcut out if we know we're incapable of syncing the chain.
-		if !client.enabled.load(AtomicOrdering::Relaxed) {
+		if !client.enabled.load(AtomicOrdering::Relaxed) || self.import_cancelled.load(AtomicOrdering::SeqCst) {
 			return 0;
 		}
 
@@ -314,7 +327,18 @@ impl Importer {
 			trace_time!("import_verified_blocks");
 			let start = Instant::now();
 
-			for block in blocks {
+			let mut blocks = blocks.into_iter();
+			while let Some(block) = blocks.next() {
+				// Each block is committed as a whole, so stopping between two of them leaves
+				// the chain at a block boundary; the rest are imported once resumed.
+				if self.import_cancelled.load(AtomicOrdering::SeqCst) {
+					let mut remaining = vec![block];
+					remaining.extend(blocks);
+					debug!(target: "client", "Block import cancelled with {} verified blocks left", remaining.len());
+					self.block_queue.requeue(remaining);
+					break;
+				}
+
 				let header = block.header.clone();
 				let bytes = block.bytes.clone();
 				let hash = header.hash();
@@ -436,6 +460,9 @@ impl Importer {
 				block: &block,
 				block_provider: &**chain,
 				client,
+				// the commitment tree and nullifier set of a trusted block are still
+				// updated when it is inserted, only its proofs are not verified again.
+				shielded_verifier: if block.trusted { None } else { Some(&*client.miner) },
 			}),
 		);
 
@@ -934,6 +961,7 @@ impl Client {
 			db: RwLock::new(db.clone()),
 			state_db: RwLock::new(state_db),
 			report: RwLock::new(Default::default()),
+			health: Default::default(),
 			io_channel: RwLock::new(message_channel),
 			notify: RwLock::new(Vec::new()),
 			queue_transactions: IoChannelQueue::new(config.transaction_verification_queue_size),
@@ -1036,6 +1064,28 @@ impl Client {
 		&*self.engine
 	}
 
+	/// Stop importing verified blocks once the block being imported is committed.
+	/// The blocks left are kept in the queue until `resume_import` is called.
+	pub fn cancel_import(&self) {
+		self.importer.import_cancelled.store(true, AtomicOrdering::SeqCst);
+	}
+
+	/// Resume importing verified blocks after `cancel_import`.
+	pub fn resume_import(&self) {
+		self.importer.import_cancelled.store(false, AtomicOrdering::SeqCst);
+		self.importer.block_queue.signal_verified();
+	}
+
+	/// Run `f` while holding the import lock, so that no block is imported meanwhile, or
+	/// return `None` without running it if blocks are being imported or queued for import.
+	pub fn with_import_paused<T, F: FnOnce() -> T>(&self, f: F) -> Option<T> {
+		let _import_lock = self.importer.import_lock.try_lock()?;
+		if !self.importer.block_queue.queue_info().is_empty() {
+			return None;
+		}
+		Some(f())
+	}
+
 	fn notify<F>(&self, f: F)
 	where
 		F: Fn(&ChainNotify),
@@ -1055,10 +1105,13 @@ impl Client {
 		*self.on_user_defaults_change.lock() = Some(Box::new(f));
 	}
 
-	/// Flush the block import queue.
+	/// Flush the block import queue, or only verify it if import is cancelled.
 	pub fn flush_queue(&self) {
 		self.importer.block_queue.flush();
 		while !self.importer.block_queue.is_empty() {
+			if self.importer.import_cancelled.load(AtomicOrdering::SeqCst) {
+				break;
+			}
 			self.import_verified_blocks();
 		}
 	}
@@ -1225,6 +1278,140 @@ impl Client {
 		self.chain.read().pt_wallet()
 	}
 
+	/// Get the shielded outputs of the transaction with the given hash, from the block
+	/// which includes it. Returns `None` if the transaction is not in the chain.
+	pub fn shielded_outputs_of(&self, txid: &H256) -> Option<Vec<OutputDescription>> {
+		self.transaction(TransactionId::Hash(*txid)).map(|tx| tx.v_shielded_output())
+	}
+
+	/// Check the persisted commitment tree against the canonical chain up to block `up_to`.
+	/// See `BlockChain::verify_commitment_tree`.
+	pub fn verify_commitment_tree(&self, up_to: BlockNumber, abort: &AtomicBool) -> Result<(), TreeInconsistency> {
+		self.chain.read().verify_commitment_tree(up_to, abort)
+	}
+
+	/// Get the number of blocks whose commitment roots are still accepted as anchors and the
+	/// oldest of them, to monitor the pruning of the anchor history.
+	pub fn anchor_history_info(&self) -> AnchorHistoryInfo {
+		self.chain.read().anchor_history_info()
+	}
+
+	/// Get the commitment roots a spend on top of the best block may use as anchor, newest
+	/// first, so that wallets can pick one they hold a witness for. See
+	/// `BlockChain::valid_anchors`.
+	pub fn valid_anchors(&self) -> Vec<(BlockNumber, Node)> {
+		self.chain.read().valid_anchors(self.engine.params().anchor_window as u64)
+	}
+
+	/// Get the anchor of each canonical block from height `from` to `to`, in order. See
+	/// `BlockChain::anchors`.
+	pub fn anchors(&self, from: BlockNumber, to: BlockNumber) -> Vec<(BlockNumber, Node)> {
+		self.chain.read().anchors(from, to).collect()
+	}
+
+	/// Get the position of the note commitment `cm` in the canonical commitment tree. See
+	/// `BlockChain::commitment_position`.
+	#[cfg(feature = "commitment-index")]
+	pub fn commitment_position(&self, cm: &Node) -> Option<u64> {
+		self.chain.read().commitment_position(cm)
+	}
+
+	/// Get the latest commitment tree checkpoint of the canonical chain. See
+	/// `BlockChain::last_tree_checkpoint`.
+	pub fn last_tree_checkpoint(&self) -> Option<(BlockNumber, CommitmentTree<Node>)> {
+		self.chain.read().last_tree_checkpoint()
+	}
+
+	/// Get a fingerprint of the shielded state after the canonical block `height`. Block
+	/// imports wait until it is computed. See `BlockChain::shielded_state_fingerprint`.
+	pub fn shielded_state_fingerprint(&self, height: BlockNumber) -> Option<H256> {
+		let _import_lock = self.importer.import_lock.lock();
+		self.chain.read().shielded_state_fingerprint(height)
+	}
+
+	/// Check that a witness restored by a wallet authenticates `leaf` and is anchored within
+	/// the current anchor window. See `BlockChain::validate_witness`.
+	pub fn validate_witness(&self, leaf: &Node, witness: &IncrementalWitness<Node>) -> bool {
+		self.chain.read().validate_witness(leaf, witness, self.engine.params().anchor_window as u64)
+	}
+
+	/// Keep the commitment root of the given block from being pruned. See `BlockChain::pin_block`.
+	pub fn pin_block(&self, hash: H256) {
+		self.chain.read().pin_block(hash)
+	}
+
+	/// Get the pinned blocks. See `BlockChain::pinned_blocks`.
+	pub fn pinned_blocks(&self) -> Vec<H256> {
+		self.chain.read().pinned_blocks()
+	}
+
+	/// Let the commitment root of the given block be pruned again. Returns `false` if the
+	/// block was not pinned.
+	pub fn unpin_block(&self, hash: &H256) -> bool {
+		self.chain.read().unpin_block(hash)
+	}
+
+	/// Import a block from a trusted local source, such as an archive exported by this node.
+	/// Its shielded proofs are not verified again, but it otherwise goes through the same
+	/// verification as `import_block`, and updates the commitment tree and nullifier set.
+	///
+	/// This is not part of `ImportBlock`, so that blocks from the network, which are
+	/// imported through it, can never be trusted.
+	pub fn import_trusted_block(&self, mut unverified: Unverified) -> EthcoreResult<H256> {
+		unverified.trusted = true;
+		self.import_block(unverified)
+	}
+
+	/// Save the nullifier set as of the best block. See `BlockChain::save_nullifier_snapshot`.
+	pub fn save_nullifier_snapshot(&self, path: &Path) -> ::std::io::Result<()> {
+		self.chain.read().save_nullifier_snapshot(path)
+	}
+
+	/// Whether the nullifier set is up to date with the best block. See
+	/// `BlockChain::nullifier_set_is_current`.
+	pub fn nullifier_set_is_current(&self) -> bool {
+		self.chain.read().nullifier_set_is_current()
+	}
+
+	/// Rebuild the nullifier set from a snapshot, or from genesis if it is rejected. Returns
+	/// the number of blocks replayed. See `BlockChain::restore_nullifier_set`.
+	pub fn restore_nullifier_set(&self, path: &Path) -> BlockNumber {
+		self.chain.read().restore_nullifier_set(path)
+	}
+
+	/// Install a trusted checkpoint of the shielded state. Blocks are not imported meanwhile,
+	/// so the chain cannot pass the checkpoint while it is checked. See
+	/// `BlockChain::install_shielded_checkpoint`.
+	pub fn load_shielded_checkpoint(&self, checkpoint: ShieldedCheckpoint, nullifiers: &NullifierSnapshot) -> Result<(), CheckpointError> {
+		let _import_lock = self.importer.import_lock.lock();
+		self.chain.read().install_shielded_checkpoint(checkpoint, nullifiers)
+	}
+
+	/// Get the installed shielded checkpoint.
+	pub fn shielded_checkpoint(&self) -> Option<ShieldedCheckpoint> {
+		self.chain.read().shielded_checkpoint()
+	}
+
+	/// Register a callback run once the senders of the sync transactions given to `new`
+	/// are all dropped. See `BlockChain::on_sync_source_lost`.
+	pub fn on_sync_source_lost<F>(&self, callback: F) where F: FnMut() + Send + 'static {
+		self.chain.read().on_sync_source_lost(callback)
+	}
+
+	/// Get the client configuration.
+	pub fn config(&self) -> &ClientConfig {
+		&self.config
+	}
+
+	/// Get the number of messages of each kind waiting for the IO thread.
+	pub fn io_queue_sizes(&self) -> IoQueueSizes {
+		IoQueueSizes {
+			transactions: self.queue_transactions.len(),
+			ancient_blocks: self.queue_ancient_blocks.len(),
+			consensus_messages: self.queue_consensus_message.len(),
+		}
+	}
+
 	/// Replace io channel. Useful for testing.
 	pub fn set_io_channel(&self, io_channel: IoChannel<ClientIoMessage>) {
 		*self.io_channel.write() = io_channel;
@@ -1300,6 +1487,34 @@ impl Client {
 		self.chain.read().cache_size()
 	}
 
+	/// Verify the private transactions of the last `last_n_blocks` canonical blocks and record
+	/// the results in the proof cache of the miner, stopping early once `abort` is set.
+	/// Returns the number of transactions verified.
+	///
+	/// Only the transactions of these blocks gain from it, when a reorg retracts their block
+	/// and they come back to the pool or into another block. New transactions after
+	/// a restart are verified as before. This verifies every proof of these blocks, so it
+	/// should run off the IO threads.
+	pub fn warm_verification_cache(&self, last_n_blocks: usize, abort: &AtomicBool) -> usize {
+		let chain = self.chain.read().clone();
+		let best = chain.best_block_number();
+		let from = (best + 1).saturating_sub(last_n_blocks as u64);
+		let transactions = (from..=best)
+			.filter_map(|number| chain.block_hash(number).and_then(|hash| chain.block(&hash)))
+			.flat_map(|block| block.transactions())
+			.filter(|tx| tx.is_private())
+			.filter_map(|tx| SignedTransaction::new(tx).ok());
+		self.importer.miner.warm_proof_cache(transactions, abort)
+	}
+
+	/// Get the memory used by the note witnesses tracked by the wallet.
+	pub fn wallet_witnesses_mem(&self) -> usize {
+		match self.wallet().read() {
+			Ok(wallet) => wallet.witnesses_heap_size(),
+			Err(_) => 0,
+		}
+	}
+
 	/// Get the report.
 	pub fn report(&self) -> ClientReport {
 		let mut report = self.report.read().clone();
@@ -1307,10 +1522,16 @@ impl Client {
 		report
 	}
 
+	/// Get the health of the client, as of the last tick.
+	pub fn health(&self) -> &ServiceHealth {
+		&self.health
+	}
+
 	/// Tick the client.
 	// TODO: manage by real events.
 	pub fn tick(&self, prevent_sleep: bool) {
 		self.check_garbage();
+		self.check_commitment_tree();
 		if !prevent_sleep {
 			self.check_snooze();
 		}
@@ -1320,6 +1541,40 @@ impl Client {
 		self.chain.read().collect_garbage();
 		self.importer.block_queue.collect_garbage();
 		self.tracedb.read().collect_garbage();
+		self.prune_anchor_history();
+	}
+
+	fn prune_anchor_history(&self) {
+		let retention = (self.engine.params().anchor_window as u64).saturating_add(self.config.anchor_history_margin);
+		let chain = self.chain.read();
+		let keep_from = chain.best_block_number().saturating_sub(retention);
+		let pruned = chain.prune_commitment_roots(keep_from);
+		if pruned > 0 {
+			let info = chain.anchor_history_info();
+			debug!(target: "client", "Pruned {} commitment roots, {} blocks retained from #{}", pruned, info.retained, info.oldest_retained);
+		}
+	}
+
+	// a tree left behind the best block accepts stale anchors and misses the latest notes.
+	fn check_commitment_tree(&self) {
+		let max_lag = self.config.max_commitment_tree_lag;
+		let chain = self.chain.read();
+		let stale = chain.commitment_tree_height(max_lag).is_none();
+		if self.health.set_commitment_tree_stale(stale) == stale {
+			return;
+		}
+
+		let best = chain.best_block_number();
+		if stale {
+			let search_depth = max_lag.saturating_mul(COMMITMENT_TREE_SEARCH_FACTOR);
+			match chain.commitment_tree_height(search_depth) {
+				Some(height) => error!(target: "client", "Commitment tree is at block #{} but the best block is #{}", height, best),
+				None if search_depth < best => error!(target: "client", "Commitment tree is more than {} blocks behind the best block #{}", search_depth, best),
+				None => error!(target: "client", "No commitment tree is stored up to the best block #{}", best),
+			}
+		} else {
+			info!(target: "client", "Commitment tree caught up with the best block #{}", best);
+		}
 	}
 
 	fn check_snooze(&self) {
@@ -1403,6 +1658,7 @@ impl Client {
 			writer,
 			p,
 			processing_threads,
+			self.config.snapshot.compression,
 		)?;
 
 		Ok(())
@@ -1726,6 +1982,14 @@ impl BlockInfo for Client {
 	fn commitment_root_block(&self, root: &Node) -> Option<H256> {
 		self.chain.read().commitment_root_block(root)
 	}
+
+	fn commitment_tree(&self, id: BlockId) -> Option<CommitmentTree<Node>> {
+		let chain = self.chain.read();
+
+		Self::block_hash(&chain, id)
+			.and_then(|hash| chain.block_commitment_ser(&hash))
+			.map(|ser| ser.get_commitment_tree())
+	}
 }
 
 impl TransactionInfo for Client {
@@ -3270,6 +3534,17 @@ mod tests {
 	}
 }
 
+/// Number of messages waiting for the IO thread, by kind.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IoQueueSizes {
+	/// Transactions from the network.
+	pub transactions: usize,
+	/// Ancient blocks.
+	pub ancient_blocks: usize,
+	/// Consensus messages.
+	pub consensus_messages: usize,
+}
+
 /// Queue some items to be processed by IO client.
 struct IoChannelQueue {
 	currently_queued: Arc<AtomicUsize>,
@@ -3284,6 +3559,10 @@ impl IoChannelQueue {
 		}
 	}
 
+	pub fn len(&self) -> usize {
+		self.currently_queued.load(AtomicOrdering::Relaxed)
+	}
+
 	pub fn queue<F>(
 		&self,
 		channel: &IoChannel<ClientIoMessage>,
@@ -26,6 +26,12 @@ pub use blockchain::Config as BlockChainConfig;
 pub use trace::Config as TraceConfig;
 pub use evm::VMType;
 
+/// Default number of blocks beyond the anchor window whose commitment roots are kept.
+pub const DEFAULT_ANCHOR_HISTORY_MARGIN: u64 = 1024;
+
+/// Default number of blocks the commitment tree may lag behind the best block.
+pub const DEFAULT_MAX_COMMITMENT_TREE_LAG: u64 = 8;
+
 /// Client state db compaction profile
 #[derive(Debug, PartialEq, Clone)]
 pub enum DatabaseCompactionProfile {
@@ -125,6 +131,13 @@ pub struct ClientConfig {
 	pub max_round_blocks_to_import: usize,
 	/// Snapshot configuration
 	pub snapshot: SnapshotConfiguration,
+	/// Number of blocks beyond the anchor window whose commitment roots are kept for reorg safety.
+	pub anchor_history_margin: u64,
+	/// Number of blocks the commitment tree may lag behind the best block before the client
+	/// is reported unhealthy.
+	pub max_commitment_tree_lag: u64,
+	/// Time the shielded verification primitives when the client service starts.
+	pub shielded_benchmark_at_startup: bool,
 }
 
 impl Default for ClientConfig {
@@ -151,9 +164,13 @@ impl Default for ClientConfig {
 			transaction_verification_queue_size: 8192,
 			max_round_blocks_to_import: 12,
 			snapshot: Default::default(),
+			anchor_history_margin: DEFAULT_ANCHOR_HISTORY_MARGIN,
+			max_commitment_tree_lag: DEFAULT_MAX_COMMITMENT_TREE_LAG,
+			shielded_benchmark_at_startup: false,
 		}
 	}
 }
+
 #[cfg(test)]
 mod test {
 	use super::DatabaseCompactionProfile;
@@ -0,0 +1,51 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Health of the client, checked on each tick.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Conditions which make the client unhealthy.
+#[derive(Debug, Default)]
+pub struct ServiceHealth {
+	commitment_tree_stale: AtomicBool,
+}
+
+impl ServiceHealth {
+	/// Whether none of the conditions is set.
+	pub fn is_healthy(&self) -> bool {
+		!self.commitment_tree_stale()
+	}
+
+	/// Whether the commitment tree lags too far behind the best block.
+	pub fn commitment_tree_stale(&self) -> bool {
+		self.commitment_tree_stale.load(Ordering::SeqCst)
+	}
+
+	/// Set whether the commitment tree is stale, returning the previous value.
+	pub fn set_commitment_tree_stale(&self, stale: bool) -> bool {
+		self.commitment_tree_stale.swap(stale, Ordering::SeqCst)
+	}
+}
+
+impl Clone for ServiceHealth {
+	/// A snapshot of the conditions, which the client does not update.
+	fn clone(&self) -> Self {
+		ServiceHealth {
+			commitment_tree_stale: AtomicBool::new(self.commitment_tree_stale()),
+		}
+	}
+}
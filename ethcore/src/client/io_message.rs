@@ -15,6 +15,7 @@
 // along with Parity Ethereum.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::fmt;
+use std::sync::Arc;
 use bytes::Bytes;
 use client::Client;
 use ethereum_types::H256;
@@ -37,13 +38,27 @@ pub enum ClientIoMessage {
 	TakeSnapshot(u64),
 	/// Execute wrapped closure
 	Execute(Callback),
+	/// Execute wrapped closure on the execution pool of the client service
+	ExecuteOnPool(Arc<Callback>),
 }
 
 impl ClientIoMessage {
 	/// Create new `ClientIoMessage` that executes given procedure.
+	///
+	/// The procedure runs on the IO thread, blocking timers and block import until it
+	/// returns, so it should be fast.
 	pub fn execute<F: Fn(&Client) + Send + Sync + 'static>(fun: F) -> Self {
 		ClientIoMessage::Execute(Callback(Box::new(fun)))
 	}
+
+	/// Create new `ClientIoMessage` that executes given procedure on a worker thread.
+	///
+	/// Unlike `execute`, the procedure may run concurrently with the IO thread and with
+	/// other pooled procedures, so it must not assume exclusive access to the client.
+	/// It falls back to running on the IO thread when the pool is saturated.
+	pub fn execute_on_pool<F: Fn(&Client) + Send + Sync + 'static>(fun: F) -> Self {
+		ClientIoMessage::ExecuteOnPool(Arc::new(Callback(Box::new(fun))))
+	}
 }
 
 /// A function to invoke in the client thread.
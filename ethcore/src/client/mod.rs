@@ -22,6 +22,7 @@ mod client;
 mod config;
 #[cfg(any(test, feature = "test-helpers"))]
 mod evm_test_client;
+mod health;
 mod io_message;
 #[cfg(any(test, feature = "test-helpers"))]
 mod test_client;
@@ -31,6 +32,7 @@ pub use self::client::*;
 pub use self::config::{Mode, ClientConfig, DatabaseCompactionProfile, BlockChainConfig, VMType};
 #[cfg(any(test, feature = "test-helpers"))]
 pub use self::evm_test_client::{EvmTestClient, EvmTestError, TransactErr, TransactSuccess};
+pub use self::health::ServiceHealth;
 pub use self::io_message::ClientIoMessage;
 #[cfg(any(test, feature = "test-helpers"))]
 pub use self::test_client::{TestBlockChainClient, EachBlockWith};
@@ -72,6 +72,7 @@ use state_db::StateDB;
 use trace::LocalizedTrace;
 use verification::queue::kind::blocks::Unverified;
 use verification::queue::QueueInfo as BlockQueueInfo;
+use zcash_primitives::merkle_tree::CommitmentTree;
 use zcash_primitives::sapling::Node;
 
 /// Test client.
@@ -568,6 +569,11 @@ impl BlockInfo for TestBlockChainClient {
 	fn commitment_root_block(&self, root: &Node) -> Option<H256> {
 		Some(H256::from(0))
 	}
+
+	/// Mocked blocks have no shielded outputs, so every tree is the empty one.
+	fn commitment_tree(&self, id: BlockId) -> Option<CommitmentTree<Node>> {
+		self.block_hash(id).map(|_| CommitmentTree::new())
+	}
 }
 
 impl CallContract for TestBlockChainClient {
@@ -730,12 +736,7 @@ impl EngineInfo for TestBlockChainClient {
 impl BadBlocks for TestBlockChainClient {
 	fn bad_blocks(&self) -> Vec<(Unverified, String)> {
 		vec![(
-			Unverified {
-				header: Default::default(),
-				transactions: vec![],
-				uncles: vec![],
-				bytes: vec![1, 2, 3],
-			},
+			Unverified::new(Default::default(), vec![], vec![], vec![1, 2, 3]),
 			"Invalid block".into(),
 		)]
 	}
@@ -52,6 +52,7 @@ use state::StateInfo;
 use trace::LocalizedTrace;
 use verification::queue::kind::blocks::Unverified;
 use verification::queue::QueueInfo as BlockQueueInfo;
+use zcash_primitives::merkle_tree::CommitmentTree;
 use zcash_primitives::sapling::Node;
 
 /// State information to be used during client query
@@ -138,6 +139,9 @@ pub trait BlockInfo {
 
 	/// Get the block hash for the given commitment tree root.
 	fn commitment_root_block(&self, root: &Node) -> Option<H256>;
+
+	/// Get the commitment tree after the given block.
+	fn commitment_tree(&self, id: BlockId) -> Option<CommitmentTree<Node>>;
 }
 
 /// Provides various information on a transaction by it's ID
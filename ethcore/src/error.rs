@@ -32,6 +32,7 @@ use snapshot::Error as SnapshotError;
 use types::BlockNumber;
 use types::transaction::Error as TransactionError;
 use unexpected::{Mismatch, OutOfBounds};
+use zcash_proofs::sapling::ShieldedError;
 
 use engines::EngineError;
 
@@ -105,6 +106,12 @@ pub enum BlockError {
 	UnknownEpochTransition(u64),
 	/// solution is incorrect.
 	InvalidSolution,
+	/// Block has too many shielded spends and outputs to verify.
+	TooManyShieldedDescriptions(OutOfBounds<u64>),
+	/// A shielded transaction of the block fails verification.
+	InvalidShielded(ShieldedError),
+	/// Verification of the block panicked, with the panic message.
+	VerificationPanicked(String),
 }
 
 impl fmt::Display for BlockError {
@@ -149,6 +156,9 @@ impl fmt::Display for BlockError {
 			TimestampOverflow => format!("Timestamp overflow"),
 			TooManyTransactions(ref address) => format!("Too many transactions from: {}", address),
 			InvalidSolution => "Solution is invalid .".into(),
+			TooManyShieldedDescriptions(ref oob) => format!("Block has too many shielded descriptions. {}", oob),
+			InvalidShielded(ref err) => format!("Invalid shielded transaction: {}", err),
+			VerificationPanicked(ref msg) => format!("Block verification panicked: {}", msg),
 		};
 
 		f.write_fmt(format_args!("Block error ({})", msg))
@@ -107,6 +107,8 @@ extern crate vm;
 extern crate wasm;
 extern crate backtrace;
 extern crate zcash_primitives;
+extern crate zcash_proofs;
+extern crate zstd;
 extern crate sapling_crypto;
 extern crate ff;
 extern crate crypto as rust_crypto;
@@ -18,6 +18,7 @@ use std::cmp;
 use std::time::{Instant, Duration};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::Sender;
 
 use ansi_term::Colour;
@@ -25,11 +26,12 @@ use bytes::Bytes;
 use call_contract::CallContract;
 use ethcore_miner::gas_pricer::GasPricer;
 use ethcore_miner::local_accounts::LocalAccounts;
-use ethcore_miner::pool::{self, TransactionQueue, VerifiedTransaction, QueueStatus, PrioritizationStrategy};
+use ethcore_miner::pool::{self, TransactionQueue, TransactionSource, VerifiedTransaction, QueueStatus, PrioritizationStrategy};
 use ethcore_miner::service_transaction_checker::ServiceTransactionChecker;
 #[cfg(feature = "work-notify")]
 use ethcore_miner::work_notify::NotifyWork;
 use ethereum_types::{H256, H512, U256, Address};
+use ff::PrimeField;
 use io::IoChannel;
 use miner::pool_client::{PoolClient, CachedNonceClient, NonceCache};
 use miner;
@@ -48,6 +50,8 @@ use types::block::Block;
 use types::header::Header;
 use types::receipt::RichReceipt;
 use using_queue::{UsingQueue, GetAction};
+use zcash_primitives::sapling::Node;
+use zcash_proofs::sapling::ShieldedError;
 
 use block::{ClosedBlock, SealedBlock};
 use client::{
@@ -60,6 +64,7 @@ use executed::ExecutionError;
 use executive::contract_address;
 use spec::Spec;
 use state::State;
+use verification::{shielded_tx_weight, ShieldedBlockVerifier};
 
 /// Different possible definitions for pending transaction set.
 #[derive(Debug, PartialEq)]
@@ -154,6 +159,11 @@ pub struct MinerOptions {
 	pub pool_limits: pool::Options,
 	/// Initial transaction verification options.
 	pub pool_verification_options: pool::verifier::Options,
+	/// Number of threads verifying the proofs of shielded transactions, 0 to verify them
+	/// on the importing thread.
+	pub shielded_verifier_threads: usize,
+	/// Maximal total RLP length of the transactions of an authored block.
+	pub max_block_bytes: usize,
 }
 
 impl Default for MinerOptions {
@@ -184,6 +194,8 @@ impl Default for MinerOptions {
 				tx_gas_limit: U256::max_value(),
 				no_early_reject: false,
 			},
+			shielded_verifier_threads: pool::shielded_verifier::default_threads(),
+			max_block_bytes: usize::max_value(),
 		}
 	}
 }
@@ -246,6 +258,8 @@ pub struct Miner {
 	options: MinerOptions,
 	// TODO [ToDr] Arc is only required because of price updater
 	transaction_queue: Arc<TransactionQueue>,
+	// source of the transactions of the prepared blocks, the transaction queue unless replaced
+	transaction_source: RwLock<Arc<TransactionSource>>,
 	engine: Arc<EthEngine>,
 	accounts: Arc<LocalAccounts>,
 	io_channel: RwLock<Option<IoChannel<ClientIoMessage>>>,
@@ -278,6 +292,8 @@ impl Miner {
 		let tx_queue_strategy = options.tx_queue_strategy;
 		let nonce_cache_size = cmp::max(4096, limits.max_count / 4);
 		let refuse_service_transactions = options.refuse_service_transactions;
+		let transaction_queue = Arc::new(TransactionQueue::new(limits, verifier_options, tx_queue_strategy, sender)
+			.with_shielded_verifier_threads(options.shielded_verifier_threads));
 
 		Miner {
 			sealing: Mutex::new(SealingWork {
@@ -294,7 +310,8 @@ impl Miner {
 			gas_pricer: Mutex::new(gas_pricer),
 			nonce_cache: NonceCache::new(nonce_cache_size),
 			options,
-			transaction_queue: Arc::new(TransactionQueue::new(limits, verifier_options, tx_queue_strategy, sender)),
+			transaction_source: RwLock::new(transaction_queue.clone()),
+			transaction_queue,
 			accounts: Arc::new(accounts),
 			engine: spec.engine.clone(),
 			io_channel: RwLock::new(None),
@@ -367,6 +384,50 @@ impl Miner {
 		self.service_transaction_checker.clone()
 	}
 
+	/// Take the transactions of the blocks prepared from now on from `source` instead of
+	/// the transaction queue. Each block is prepared from a single source: one being
+	/// prepared while the source is replaced keeps the previous one.
+	///
+	/// Transactions are still imported into the queue, and the pending set reported to
+	/// RPC is still the one of the queue.
+	pub fn set_transaction_source(&self, source: Arc<TransactionSource>) {
+		*self.transaction_source.write() = source;
+	}
+
+	/// Take the transactions of the blocks prepared from now on from the transaction queue again.
+	pub fn reset_transaction_source(&self) {
+		self.set_transaction_source(self.transaction_queue.clone());
+	}
+
+	/// Returns the time spent verifying shielded transactions entering the queue.
+	pub fn shielded_timings(&self) -> pool::verifier::ShieldedTimings {
+		self.transaction_queue.shielded_timings()
+	}
+
+	/// Record the shielded verification results of `transactions` in the proof cache of the queue.
+	/// See `TransactionQueue::warm_proof_cache`.
+	pub fn warm_proof_cache<I>(&self, transactions: I, abort: &AtomicBool) -> usize where
+		I: IntoIterator<Item = SignedTransaction>,
+	{
+		self.transaction_queue.warm_proof_cache(transactions, abort)
+	}
+
+	/// Returns the gas price a transaction has to beat to enter the full queue.
+	/// See `TransactionQueue::full_queue_min_gas_price`.
+	pub fn full_queue_min_gas_price(&self) -> Option<U256> {
+		self.transaction_queue.full_queue_min_gas_price()
+	}
+
+	/// Returns the shielded anchor of a block built on the best block of `chain` whose
+	/// transactions create the note commitments `outputs`, in block order.
+	///
+	/// Block import appends the same commitments to the tree of the parent, so the anchor
+	/// recorded for the sealed block is this one. Returns `None` if the tree of the best
+	/// block is unknown or has no room for `outputs`.
+	pub fn final_anchor_for_template<C: BlockChain>(&self, chain: &C, outputs: &[Node]) -> Option<Node> {
+		chain.commitment_tree(BlockId::Latest)?.root_after(outputs).ok()
+	}
+
 	/// Retrieves an existing pending block iff it's not older than given block number.
 	///
 	/// NOTE: This will not prepare a new pending block if it's not existing.
@@ -459,6 +520,12 @@ impl Miner {
 
 		let client = self.pool_client(chain);
 		let engine_params = self.engine.params();
+		// a block under the gas limit can still be too costly to verify.
+		let max_verify_ops = engine_params.max_shielded_verify_ops_per_block;
+		let max_block_bytes = self.options.max_block_bytes;
+		let (mut block_bytes, mut verify_ops) = open_block.transactions.iter()
+			.map(|t| shielded_tx_weight(t))
+			.fold((0usize, 0u64), |(bytes, ops), w| (bytes + w.bytes, ops + w.verify_ops));
 		let min_tx_gas: U256 = self.engine.schedule(chain_info.best_block_number).tx_gas.into();
 		let nonce_cap: Option<U256> = if chain_info.best_block_number + 1 >= engine_params.dust_protection_transition {
 			Some((engine_params.nonce_cap_increment * (chain_info.best_block_number + 1)).into())
@@ -472,8 +539,9 @@ impl Miner {
 			MAX_SKIPPED_TRANSACTIONS.saturating_add(cmp::min(*open_block.header.gas_limit() / min_tx_gas, u64::max_value().into()).as_u64() as usize)
 		};
 
-		let pending: Vec<Arc<_>> = self.transaction_queue.pending(
-			client.clone(),
+		let source = self.transaction_source.read().clone();
+		let pending: Vec<Arc<_>> = source.pending(
+			&client,
 			pool::PendingSettings {
 				block_number: chain_info.best_block_number,
 				current_timestamp: chain_info.best_block_timestamp,
@@ -501,6 +569,34 @@ impl Miner {
 			let hash = transaction.hash();
 			let sender = transaction.sender();
 
+			let weight = shielded_tx_weight(&transaction);
+			if block_bytes.saturating_add(weight.bytes) > max_block_bytes {
+				debug!(target: "miner", "Skipping adding transaction to block because of size limit: {:?} (limit: {}, used: {}, bytes: {})", hash, max_block_bytes, block_bytes, weight.bytes);
+
+				skipped_transactions += 1;
+				if skipped_transactions > MAX_SKIPPED_TRANSACTIONS {
+					debug!(target: "miner", "Reached skipped transactions threshold. Assuming block is full.");
+					break;
+				}
+				continue;
+			}
+			if verify_ops.saturating_add(weight.verify_ops) > max_verify_ops {
+				debug!(target: "miner", "Skipping adding transaction to block because of shielded verification limit: {:?} (limit: {}, used: {}, ops: {})", hash, max_verify_ops, verify_ops, weight.verify_ops);
+
+				// No block can include it.
+				if weight.verify_ops > max_verify_ops {
+					debug!(target: "txqueue", "[{:?}] Transaction above block shielded verification limit.", hash);
+					invalid_transactions.insert(hash);
+				}
+
+				skipped_transactions += 1;
+				if skipped_transactions > MAX_SKIPPED_TRANSACTIONS {
+					debug!(target: "miner", "Reached skipped transactions threshold. Assuming block is full.");
+					break;
+				}
+				continue;
+			}
+
 			// Re-verify transaction again vs current state.
 			let result = client.verify_signed(&transaction)
 				.map_err(|e| e.into())
@@ -563,7 +659,11 @@ impl Miner {
 					invalid_transactions.insert(hash);
 				},
 				// imported ok
-				_ => tx_count += 1,
+				_ => {
+					tx_count += 1;
+					block_bytes += weight.bytes;
+					verify_ops += weight.verify_ops;
+				},
 			}
 		}
 		let elapsed = block_start.elapsed();
@@ -711,11 +811,29 @@ impl Miner {
 					sealing.next_mandatory_reseal = Instant::now() + self.options.reseal_max_period;
 				}
 
+				// the anchor import should record for this block, if it still extends the best block.
+				let expected_anchor = if chain.chain_info().best_block_hash == *block.header.parent_hash() {
+					let outputs: Vec<_> = block.transactions.iter()
+						.flat_map(|tx| tx.get_commitment_notes())
+						.map(|cmu| Node::new(cmu.into_repr()))
+						.collect();
+					self.final_anchor_for_template(chain, &outputs)
+				} else {
+					None
+				};
+
 				block
 					.lock()
 					.seal(&*self.engine, seal)
-					.map(|sealed| {
-						chain.import_sealed_block(sealed).is_ok()
+					.map(|sealed| match chain.import_sealed_block(sealed) {
+						Ok(hash) => {
+							let anchor = chain.commitment_tree(BlockId::Hash(hash)).map(|tree| tree.root());
+							if expected_anchor.is_some() && anchor.is_some() && anchor != expected_anchor {
+								warn!(target: "miner", "Sealed block {} has shielded anchor {:?}, expected {:?}.", hash, anchor, expected_anchor);
+							}
+							true
+						},
+						Err(_) => false,
 					})
 					.unwrap_or_else(|e| {
 						warn!("ERROR: seal failed when given internally generated seal: {}", e);
@@ -862,6 +980,17 @@ impl Miner {
 
 const SEALING_TIMEOUT_IN_BLOCKS : u64 = 5;
 
+impl ShieldedBlockVerifier for Miner {
+	/// See `TransactionQueue::verify_block_shielded`.
+	fn verify_block_shielded(
+		&self,
+		transactions: &[SignedTransaction],
+		anchor_valid: &Fn(&Node) -> bool,
+	) -> Result<(), ShieldedError> {
+		self.transaction_queue.verify_block_shielded(transactions, anchor_valid)
+	}
+}
+
 impl miner::MinerService for Miner {
 	type State = State<::state_db::StateDB>;
 
@@ -1436,6 +1565,10 @@ mod tests {
 	}
 
 	fn miner() -> Miner {
+		miner_with_spec(&Spec::new_test())
+	}
+
+	fn miner_with_spec(spec: &Spec) -> Miner {
 		Miner::new(
 			MinerOptions {
 				force_sealing: false,
@@ -1459,9 +1592,11 @@ mod tests {
 					tx_gas_limit: U256::max_value(),
 					no_early_reject: false,
 				},
+				shielded_verifier_threads: 1,
+				max_block_bytes: usize::max_value(),
 			},
 			GasPricer::new_fixed(0u64.into()),
-			&Spec::new_test(),
+			spec,
 			::std::collections::HashSet::new(), // local accounts
 			None,
 		)
@@ -1581,6 +1716,100 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn should_bound_pending_block_by_shielded_verify_ops() {
+		// given
+		let transactions: Vec<_> = (0..3).map(|_| privacy_transaction()).collect();
+		let ops = shielded_tx_weight(&transactions[0]).verify_ops;
+		assert!(ops <= 5 && 3 * ops > 5);
+		let fitting = (5 / ops) as usize;
+
+		// when
+		let limited = miner_with_spec(&Spec::new_test_shielded_ops_limit());
+		let unlimited = miner();
+		let client = TestBlockChainClient::default();
+		for miner in &[&limited, &unlimited] {
+			let unverified = transactions.iter().cloned().map(Into::into).collect();
+			assert!(miner.import_external_transactions(&client, unverified).iter().all(Result::is_ok));
+			assert_eq!(miner.prepare_pending_block(&client), BlockPreparationStatus::Succeeded);
+		}
+
+		// then
+		// all three fit the gas limit, only some fit the verification limit.
+		assert_eq!(unlimited.pending_transactions(0).unwrap().len(), 3);
+		assert_eq!(limited.pending_transactions(0).unwrap().len(), fitting);
+		// the others wait in the queue for the next block.
+		assert_eq!(limited.queue_status().status.transaction_count, 3);
+	}
+
+	#[test]
+	fn should_bound_pending_block_by_bytes_and_shielded_verify_ops() {
+		// given
+		let transactions: Vec<_> = (0..3).map(|_| privacy_transaction()).collect();
+		let weights: Vec<_> = transactions.iter().map(|t| shielded_tx_weight(t)).collect();
+		let ops = weights[0].verify_ops;
+		let bytes = weights.iter().map(|w| w.bytes).max().unwrap();
+		assert!(ops <= 5 && 3 * ops > 5);
+		let fitting = (5 / ops) as usize;
+
+		// when
+		// all three fit in the bytes of the first miner, only one in those of the second.
+		let mut by_bytes_and_ops = miner_with_spec(&Spec::new_test_shielded_ops_limit());
+		by_bytes_and_ops.options.max_block_bytes = 3 * bytes;
+		let mut by_bytes = miner();
+		by_bytes.options.max_block_bytes = bytes;
+		let client = TestBlockChainClient::default();
+		for miner in &[&by_bytes_and_ops, &by_bytes] {
+			let unverified = transactions.iter().cloned().map(Into::into).collect();
+			assert!(miner.import_external_transactions(&client, unverified).iter().all(Result::is_ok));
+			assert_eq!(miner.prepare_pending_block(&client), BlockPreparationStatus::Succeeded);
+		}
+
+		// then
+		assert_eq!(by_bytes.pending_transactions(0).unwrap().len(), 1);
+		// under the size limit, the block is still bounded by its verification work.
+		assert_eq!(by_bytes_and_ops.pending_transactions(0).unwrap().len(), fitting);
+		assert_eq!(by_bytes_and_ops.queue_status().status.transaction_count, 3);
+	}
+
+	/// Stands in for another pool, always offering the same transactions.
+	struct FixedSource(Vec<Arc<VerifiedTransaction>>);
+
+	impl TransactionSource for FixedSource {
+		fn pending(&self, _client: &pool::SourceClient, settings: pool::PendingSettings) -> Vec<Arc<VerifiedTransaction>> {
+			self.0.iter().take(settings.max_len).cloned().collect()
+		}
+	}
+
+	#[test]
+	fn should_take_block_transactions_from_the_current_source() {
+		// given
+		let client = TestBlockChainClient::default();
+		let miner = miner();
+		let queued = transaction();
+		let other = transaction();
+		miner.import_own_transaction(&client, PendingTransaction::new(queued.clone(), None)).unwrap();
+		let hashes = |block: &ClosedBlock| block.transactions.iter().map(|tx| tx.hash()).collect::<Vec<_>>();
+
+		// when
+		let (first, _) = miner.prepare_block(&client).unwrap();
+		client.add_blocks(1, EachBlockWith::Nothing);
+		miner.set_transaction_source(Arc::new(FixedSource(vec![
+			Arc::new(VerifiedTransaction::from_pending_block_transaction(other.clone())),
+		])));
+		let (second, _) = miner.prepare_block(&client).unwrap();
+		client.add_blocks(1, EachBlockWith::Nothing);
+		miner.reset_transaction_source();
+		let (third, _) = miner.prepare_block(&client).unwrap();
+
+		// then
+		assert_eq!(hashes(&first), vec![queued.hash()]);
+		// the queued transaction is still queued, but not offered by the new source.
+		assert_eq!(hashes(&second), vec![other.hash()]);
+		assert_eq!(miner.queue_status().status.transaction_count, 1);
+		assert_eq!(hashes(&third), vec![queued.hash()]);
+	}
+
 	#[test]
 	fn should_not_return_stale_work_packages() {
 		// given
@@ -27,7 +27,7 @@ pub mod stratum;
 
 pub use self::miner::{Miner, MinerOptions, Penalization, PendingSet, AuthoringParams, Author};
 pub use ethcore_miner::local_accounts::LocalAccounts;
-pub use ethcore_miner::pool::PendingOrdering;
+pub use ethcore_miner::pool::{PendingOrdering, SourceClient, TransactionSource};
 
 use std::sync::Arc;
 use std::collections::{BTreeSet, BTreeMap};
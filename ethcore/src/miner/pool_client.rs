@@ -25,6 +25,7 @@ use ethcore_miner::service_transaction_checker::ServiceTransactionChecker;
 use ethereum_types::{Address, H256, U256};
 use parking_lot::RwLock;
 use types::header::Header;
+use types::ids::BlockId;
 use types::transaction::{self, SignedTransaction, UnverifiedTransaction};
 
 use call_contract::CallContract;
@@ -149,13 +150,9 @@ where
 			}
 		}
 
-		// Second, check whether the anchors are valid.
+		// Second, check whether the anchors are valid and recent enough.
 		for ref anchor in t.get_commitment_anchors() {
-			if self
-				.chain
-				.commitment_root_block(&Node::new(anchor.into_repr()))
-				.is_none()
-			{
+			if !self.is_commitment_anchor_valid(&Node::new(anchor.into_repr())) {
 				return Err(transaction::Error::InvalidCommitmentAnchor);
 			}
 		}
@@ -163,6 +160,19 @@ where
 		Ok(())
 	}
 
+	fn is_commitment_anchor_valid(&self, anchor: &Node) -> bool {
+		let anchor_number = self
+			.chain
+			.commitment_root_block(anchor)
+			.and_then(|hash| self.chain.block_header(BlockId::Hash(hash)))
+			.map(|header| header.number());
+
+		match anchor_number {
+			Some(number) => self.engine.params().is_anchor_in_window(number, self.best_block_header.number()),
+			None => false,
+		}
+	}
+
 	fn verify_transaction(
 		&self,
 		tx: UnverifiedTransaction,
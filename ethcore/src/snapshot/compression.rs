@@ -0,0 +1,99 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compression of snapshot chunks.
+
+use snappy;
+use zstd;
+
+use error::Error as EthcoreError;
+
+use super::{Error, SnapshotCompression, MAX_CHUNK_SIZE};
+
+/// Compress the raw chunk `raw` into `buffer`, returning the compressed length.
+pub fn compress_chunk(compression: SnapshotCompression, raw: &[u8], buffer: &mut Vec<u8>) -> Result<usize, Error> {
+	match compression {
+		SnapshotCompression::None => {
+			buffer.clear();
+			buffer.extend_from_slice(raw);
+			Ok(raw.len())
+		},
+		SnapshotCompression::Snappy => Ok(snappy::compress_into(raw, buffer)),
+		SnapshotCompression::Zstd { level } => {
+			let compressed = zstd::block::compress(raw, level as i32)?;
+			buffer.clear();
+			buffer.extend_from_slice(&compressed);
+			Ok(compressed.len())
+		},
+	}
+}
+
+/// Decompress `chunk` into `buffer`, returning the decompressed length. Chunks which
+/// decompress to more than `MAX_CHUNK_SIZE` are refused.
+pub fn decompress_chunk(compression: SnapshotCompression, chunk: &[u8], buffer: &mut Vec<u8>) -> Result<usize, EthcoreError> {
+	match compression {
+		SnapshotCompression::None => {
+			if chunk.len() > MAX_CHUNK_SIZE {
+				return Err(chunk_too_large(chunk.len()));
+			}
+			buffer.clear();
+			buffer.extend_from_slice(chunk);
+			Ok(chunk.len())
+		},
+		SnapshotCompression::Snappy => {
+			let expected_len = snappy::decompressed_len(chunk)?;
+			if expected_len > MAX_CHUNK_SIZE {
+				return Err(chunk_too_large(expected_len));
+			}
+			Ok(snappy::decompress_into(chunk, buffer)?)
+		},
+		SnapshotCompression::Zstd { .. } => {
+			// decompression also fails on chunks larger than the capacity.
+			let decompressed = zstd::block::decompress(chunk, MAX_CHUNK_SIZE).map_err(Error::Io)?;
+			buffer.clear();
+			buffer.extend_from_slice(&decompressed);
+			Ok(decompressed.len())
+		},
+	}
+}
+
+fn chunk_too_large(len: usize) -> EthcoreError {
+	trace!(target: "snapshot", "Discarding large chunk: {} vs {}", len, MAX_CHUNK_SIZE);
+	Error::ChunkTooLarge.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trips_with_every_compression() {
+		let raw: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+		let compressions = vec![
+			SnapshotCompression::None,
+			SnapshotCompression::Snappy,
+			SnapshotCompression::Zstd { level: 3 },
+		];
+
+		for compression in compressions {
+			let mut compressed = Vec::new();
+			let len = compress_chunk(compression, &raw, &mut compressed).unwrap();
+			let mut decompressed = Vec::new();
+			let decompressed_len = decompress_chunk(compression, &compressed[..len], &mut decompressed).unwrap();
+			assert_eq!(&decompressed[..decompressed_len], &raw[..], "{:?}", compression);
+		}
+	}
+}
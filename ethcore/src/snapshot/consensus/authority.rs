@@ -142,7 +142,7 @@ impl SnapshotComponents for PoaSnapshot {
 	}
 
 	fn min_supported_version(&self) -> u64 { 3 }
-	fn current_version(&self) -> u64 { 3 }
+	fn current_version(&self) -> u64 { 5 }
 }
 
 // writes a chunk composed of the inner RLPs here.
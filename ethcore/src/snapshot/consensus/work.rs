@@ -113,6 +113,7 @@ impl<'a> PowWorker<'a> {
 
 		for _ in 0..snapshot_blocks {
 			if self.current_hash == genesis_hash { break }
+			if self.progress.aborted() { return Err(Error::SnapshotAborted) }
 
 			let (block, receipts) = self.chain.block(&self.current_hash)
 				.and_then(|b| self.chain.block_receipts(&self.current_hash).map(|r| (b, r)))
@@ -47,6 +47,8 @@ pub enum Error {
 	UnrecognizedCodeState(u8),
 	/// Restoration aborted.
 	RestorationAborted,
+	/// Snapshot creation aborted.
+	SnapshotAborted,
 	/// Trie error.
 	Trie(TrieError),
 	/// Decoder error.
@@ -67,6 +69,8 @@ pub enum Error {
 	WrongChunkFormat(String),
 	/// Unlinked ancient block chain
 	UnlinkedAncientBlockChain,
+	/// Chunk content does not match its hash.
+	ChunkHashMismatch(H256),
 }
 
 impl fmt::Display for Error {
@@ -84,6 +88,7 @@ impl fmt::Display for Error {
 			Error::MissingCode(ref missing) => write!(f, "Incomplete snapshot: {} contract codes not found.", missing.len()),
 			Error::UnrecognizedCodeState(state) => write!(f, "Unrecognized code encoding ({})", state),
 			Error::RestorationAborted => write!(f, "Snapshot restoration aborted."),
+			Error::SnapshotAborted => write!(f, "Snapshot creation aborted."),
 			Error::Io(ref err) => err.fmt(f),
 			Error::Decoder(ref err) => err.fmt(f),
 			Error::Trie(ref err) => err.fmt(f),
@@ -94,6 +99,7 @@ impl fmt::Display for Error {
 			Error::BadEpochProof(i) => write!(f, "Bad epoch proof for transition to epoch {}", i),
 			Error::WrongChunkFormat(ref msg) => write!(f, "Wrong chunk format: {}", msg),
 			Error::UnlinkedAncientBlockChain => write!(f, "Unlinked ancient blocks chain"),
+			Error::ChunkHashMismatch(ref hash) => write!(f, "Chunk {:x} does not match its hash", hash),
 		}
 	}
 }
@@ -21,7 +21,7 @@
 //! written to multiple files in one directory.
 
 use std::collections::HashMap;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 
@@ -44,10 +44,131 @@ pub trait SnapshotWriter {
 	fn write_block_chunk(&mut self, hash: H256, chunk: &[u8]) -> io::Result<()>;
 
 	/// Complete writing. The manifest's chunk lists must be consistent
-	/// with the chunks written.
+	/// with the chunks written, or both be left empty for the writer to list
+	/// the chunks written to it, in the order they were written.
 	fn finish(self, manifest: ManifestData) -> io::Result<()> where Self: Sized;
 }
 
+/// Length of the RLP of a `H256`.
+const HASH_RLP_LEN: u64 = 33;
+
+// RLP prefix of a list whose items take `payload_len` bytes.
+fn rlp_list_prefix(payload_len: u64) -> Vec<u8> {
+	if payload_len < 56 {
+		return vec![0xc0 + payload_len as u8];
+	}
+	let len_bytes: Vec<u8> = payload_len.to_be_bytes().iter().cloned().skip_while(|b| *b == 0).collect();
+	let mut prefix = vec![0xf7 + len_bytes.len() as u8];
+	prefix.extend(len_bytes);
+	prefix
+}
+
+// Chunk hashes appended to a file as they are produced.
+struct HashSpill {
+	path: PathBuf,
+	file: BufWriter<File>,
+	count: u64,
+}
+
+impl HashSpill {
+	fn create(path: PathBuf) -> io::Result<Self> {
+		Ok(HashSpill {
+			file: BufWriter::new(File::create(&path)?),
+			path,
+			count: 0,
+		})
+	}
+
+	fn push(&mut self, hash: &H256) -> io::Result<()> {
+		self.file.write_all(&hash[..])?;
+		self.count += 1;
+		Ok(())
+	}
+
+	fn rlp_len(&self) -> u64 {
+		let payload_len = self.count * HASH_RLP_LEN;
+		rlp_list_prefix(payload_len).len() as u64 + payload_len
+	}
+
+	// write the hashes as an RLP list and remove the file.
+	fn write_rlp<W: Write>(self, out: &mut W) -> io::Result<()> {
+		let HashSpill { path, mut file, count } = self;
+		file.flush()?;
+		drop(file);
+
+		out.write_all(&rlp_list_prefix(count * HASH_RLP_LEN))?;
+		let mut reader = BufReader::new(File::open(&path)?);
+		let mut hash = [0u8; 32];
+		for _ in 0..count {
+			reader.read_exact(&mut hash)?;
+			out.write_all(&[0x80 + 32])?;
+			out.write_all(&hash)?;
+		}
+		drop(reader);
+		fs::remove_file(&path)
+	}
+}
+
+/// Builds a snapshot manifest from chunk hashes kept on disk rather than in memory,
+/// so that the memory used while taking a snapshot does not grow with the number of
+/// chunks of the state.
+///
+/// The hashes are appended to spill files as the chunks are written and the manifest
+/// is written in a final pass over them. It is encoded as `ManifestData::into_rlp`
+/// would encode the same lists.
+pub struct ManifestBuilder {
+	state: HashSpill,
+	block: HashSpill,
+}
+
+impl ManifestBuilder {
+	/// Create a builder keeping its spill files in `dir`.
+	pub fn new(dir: &Path) -> io::Result<Self> {
+		Ok(ManifestBuilder {
+			state: HashSpill::create(dir.join(".state_hashes"))?,
+			block: HashSpill::create(dir.join(".block_hashes"))?,
+		})
+	}
+
+	/// Record the hash of a state chunk.
+	pub fn push_state_hash(&mut self, hash: &H256) -> io::Result<()> {
+		self.state.push(hash)
+	}
+
+	/// Record the hash of a block chunk.
+	pub fn push_block_hash(&mut self, hash: &H256) -> io::Result<()> {
+		self.block.push(hash)
+	}
+
+	/// Number of state and block chunks recorded.
+	pub fn len(&self) -> (u64, u64) {
+		(self.state.count, self.block.count)
+	}
+
+	/// Write the RLP of the manifest with the recorded chunks and the other fields of
+	/// `manifest`, whose chunk lists are ignored, and remove the spill files.
+	pub fn finish<W: Write>(self, manifest: &ManifestData, mut out: W) -> io::Result<()> {
+		let version = ::rlp::encode(&manifest.version);
+		let state_root = ::rlp::encode(&manifest.state_root);
+		let block_number = ::rlp::encode(&manifest.block_number);
+		let block_hash = ::rlp::encode(&manifest.block_hash);
+		let (_, trailing) = manifest.trailing_fields();
+
+		let payload_len = self.state.rlp_len() + self.block.rlp_len()
+			+ (version.len() + state_root.len() + block_number.len() + block_hash.len() + trailing.len()) as u64;
+
+		out.write_all(&rlp_list_prefix(payload_len))?;
+		out.write_all(&version)?;
+		self.state.write_rlp(&mut out)?;
+		self.block.write_rlp(&mut out)?;
+		out.write_all(&state_root)?;
+		out.write_all(&block_number)?;
+		out.write_all(&block_hash)?;
+		out.write_all(&trailing)?;
+		out.flush()
+	}
+}
+
 // (hash, len, offset)
 #[derive(RlpEncodable, RlpDecodable)]
 struct ChunkInfo(H256, u64, u64);
@@ -105,14 +226,16 @@ impl SnapshotWriter for PackedWriter {
 	fn finish(mut self, manifest: ManifestData) -> io::Result<()> {
 		// we ignore the hashes fields of the manifest under the assumption that
 		// they are consistent with ours.
-		let mut stream = RlpStream::new_list(6);
+		let (trailing_count, trailing) = manifest.trailing_fields();
+		let mut stream = RlpStream::new_list(6 + trailing_count);
 		stream
 			.append(&SNAPSHOT_VERSION)
 			.append_list(&self.state_hashes)
 			.append_list(&self.block_hashes)
 			.append(&manifest.state_root)
 			.append(&manifest.block_number)
-			.append(&manifest.block_hash);
+			.append(&manifest.block_hash)
+			.append_raw(&trailing, trailing_count);
 
 		let manifest_rlp = stream.out();
 
@@ -139,8 +262,12 @@ impl SnapshotWriter for PackedWriter {
 }
 
 /// A "loose" writer writes chunk files into a directory.
+///
+/// The hashes of the chunks are recorded in the directory as they are written, so the
+/// manifest passed to `finish` may leave its chunk lists empty.
 pub struct LooseWriter {
 	dir: PathBuf,
+	manifest: ManifestBuilder,
 }
 
 impl LooseWriter {
@@ -150,6 +277,7 @@ impl LooseWriter {
 		fs::create_dir_all(&path)?;
 
 		Ok(LooseWriter {
+			manifest: ManifestBuilder::new(&path)?,
 			dir: path,
 		})
 	}
@@ -165,20 +293,30 @@ impl LooseWriter {
 
 impl SnapshotWriter for LooseWriter {
 	fn write_state_chunk(&mut self, hash: H256, chunk: &[u8]) -> io::Result<()> {
-		self.write_chunk(hash, chunk)
+		self.write_chunk(hash, chunk)?;
+		self.manifest.push_state_hash(&hash)
 	}
 
 	fn write_block_chunk(&mut self, hash: H256, chunk: &[u8]) -> io::Result<()> {
-		self.write_chunk(hash, chunk)
+		self.write_chunk(hash, chunk)?;
+		self.manifest.push_block_hash(&hash)
 	}
 
 	fn finish(self, manifest: ManifestData) -> io::Result<()> {
-		let rlp = manifest.into_rlp();
 		let mut path = self.dir.clone();
 		path.push("MANIFEST");
 
-		let mut file = File::create(path)?;
-		file.write_all(&rlp[..])?;
+		let mut file = BufWriter::new(File::create(path)?);
+		if manifest.state_hashes.is_empty() && manifest.block_hashes.is_empty() {
+			self.manifest.finish(&manifest, &mut file)?;
+		} else {
+			// keep the order of the given manifest, as when recovering a restored one.
+			let ManifestBuilder { state, block } = self.manifest;
+			fs::remove_file(&state.path)?;
+			fs::remove_file(&block.path)?;
+			file.write_all(&manifest.into_rlp()[..])?;
+		}
+		file.flush()?;
 
 		Ok(())
 	}
@@ -252,6 +390,8 @@ impl PackedReader {
 		let state: Vec<ChunkInfo> = rlp.list_at(0 + start)?;
 		let blocks: Vec<ChunkInfo> = rlp.list_at(1 + start)?;
 
+		let (shielded_hashes, compression) = ManifestData::decode_trailing_fields(&rlp, 5 + start)?;
+
 		let manifest = ManifestData {
 			version: version,
 			state_hashes: state.iter().map(|c| c.0).collect(),
@@ -259,6 +399,8 @@ impl PackedReader {
 			state_root: rlp.val_at(2 + start)?,
 			block_number: rlp.val_at(3 + start)?,
 			block_hash: rlp.val_at(4 + start)?,
+			shielded_hashes: shielded_hashes,
+			compression: compression,
 		};
 
 		Ok(Some(PackedReader {
@@ -336,8 +478,10 @@ mod tests {
 	use tempdir::TempDir;
 	use hash::keccak;
 
+	use std::fs;
+
 	use snapshot::ManifestData;
-	use super::{SnapshotWriter, SnapshotReader, PackedWriter, PackedReader, LooseWriter, LooseReader, SNAPSHOT_VERSION};
+	use super::{SnapshotWriter, SnapshotReader, PackedWriter, PackedReader, LooseWriter, LooseReader, ManifestBuilder, SNAPSHOT_VERSION};
 
 	const STATE_CHUNKS: &'static [&'static [u8]] = &[b"dog", b"cat", b"hello world", b"hi", b"notarealchunk"];
 	const BLOCK_CHUNKS: &'static [&'static [u8]] = &[b"hello!", b"goodbye!", b"abcdefg", b"hijklmnop", b"qrstuvwxy", b"and", b"z"];
@@ -370,6 +514,8 @@ mod tests {
 			state_root: keccak(b"notarealroot"),
 			block_number: 12345678987654321,
 			block_hash: keccak(b"notarealblock"),
+			shielded_hashes: vec![keccak(&STATE_CHUNKS[0])],
+			compression: Default::default(),
 		};
 
 		writer.finish(manifest.clone()).unwrap();
@@ -409,6 +555,8 @@ mod tests {
 			state_root: keccak(b"notarealroot"),
 			block_number: 12345678987654321,
 			block_hash: keccak(b"notarealblock)"),
+			shielded_hashes: Vec::new(),
+			compression: Default::default(),
 		};
 
 		writer.finish(manifest.clone()).unwrap();
@@ -420,4 +568,76 @@ mod tests {
 			reader.chunk(hash.clone()).unwrap();
 		}
 	}
+
+	#[test]
+	fn streamed_manifest_matches_manifest_rlp() {
+		// enough chunks for the lists to need long RLP prefixes.
+		let state_hashes: Vec<_> = (0..300u32).map(|i| keccak(&i.to_le_bytes())).collect();
+		let block_hashes: Vec<_> = (300..310u32).map(|i| keccak(&i.to_le_bytes())).collect();
+
+		for shielded_hashes in vec![Vec::new(), state_hashes[298..].to_vec()] {
+			let manifest = ManifestData {
+				version: SNAPSHOT_VERSION,
+				state_hashes: state_hashes.clone(),
+				block_hashes: block_hashes.clone(),
+				state_root: keccak(b"notarealroot"),
+				block_number: 12345678987654321,
+				block_hash: keccak(b"notarealblock"),
+				shielded_hashes,
+				compression: Default::default(),
+			};
+
+			let tempdir = TempDir::new("").unwrap();
+			let mut builder = ManifestBuilder::new(tempdir.path()).unwrap();
+			for hash in &manifest.state_hashes {
+				builder.push_state_hash(hash).unwrap();
+			}
+			for hash in &manifest.block_hashes {
+				builder.push_block_hash(hash).unwrap();
+			}
+			assert_eq!(builder.len(), (300, 10));
+
+			let mut streamed = Vec::new();
+			builder.finish(&manifest, &mut streamed).unwrap();
+			assert_eq!(streamed, manifest.clone().into_rlp());
+			assert_eq!(fs::read_dir(tempdir.path()).unwrap().count(), 0);
+		}
+	}
+
+	#[test]
+	fn loose_writer_lists_written_chunks() {
+		let tempdir = TempDir::new("").unwrap();
+		let mut writer = LooseWriter::new(tempdir.path().into()).unwrap();
+
+		let mut manifest = ManifestData {
+			version: SNAPSHOT_VERSION,
+			state_hashes: Vec::new(),
+			block_hashes: Vec::new(),
+			state_root: keccak(b"notarealroot"),
+			block_number: 12345678987654321,
+			block_hash: keccak(b"notarealblock"),
+			shielded_hashes: vec![keccak(&STATE_CHUNKS[4])],
+			compression: Default::default(),
+		};
+
+		for chunk in STATE_CHUNKS {
+			writer.write_state_chunk(keccak(&chunk), chunk).unwrap();
+			manifest.state_hashes.push(keccak(&chunk));
+		}
+		for chunk in BLOCK_CHUNKS {
+			writer.write_block_chunk(keccak(&chunk), chunk).unwrap();
+			manifest.block_hashes.push(keccak(&chunk));
+		}
+
+		let mut without_lists = manifest.clone();
+		without_lists.state_hashes.clear();
+		without_lists.block_hashes.clear();
+		writer.finish(without_lists).unwrap();
+
+		let written = fs::read(tempdir.path().join("MANIFEST")).unwrap();
+		assert_eq!(written, manifest.clone().into_rlp());
+		let reader = LooseReader::new(tempdir.path().into()).unwrap();
+		assert_eq!(reader.manifest(), &manifest);
+		assert_eq!(fs::read_dir(tempdir.path()).unwrap().count(), STATE_CHUNKS.len() + BLOCK_CHUNKS.len() + 1);
+	}
 }
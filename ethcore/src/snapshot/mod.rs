@@ -23,6 +23,7 @@ use std::collections::{HashMap, HashSet};
 use std::cmp;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use hash::{keccak, KECCAK_NULL_RLP, KECCAK_EMPTY};
 
 use account_db::{AccountDB, AccountDBMut};
@@ -45,6 +46,7 @@ use rlp::{RlpStream, Rlp};
 use bloom_journal::Bloom;
 use num_cpus;
 
+use self::compression::compress_chunk;
 use self::io::SnapshotWriter;
 
 use super::state_db::StateDB;
@@ -56,10 +58,11 @@ use rand::{Rng, OsRng};
 pub use self::error::Error;
 
 pub use self::consensus::*;
-pub use self::service::{SnapshotClient, Service, DatabaseRestore};
+pub use self::shielded::{chunk_shielded, ShieldedRebuilder};
+pub use self::service::{SnapshotClient, SnapshotChunkProvider, Service, DatabaseRestore};
 pub use self::traits::SnapshotService;
 pub use self::watcher::Watcher;
-pub use types::snapshot_manifest::ManifestData;
+pub use types::snapshot_manifest::{ManifestData, SnapshotCompression};
 pub use types::restoration_status::RestorationStatus;
 pub use types::basic_account::BasicAccount;
 
@@ -68,8 +71,10 @@ pub mod service;
 
 mod account;
 mod block;
+mod compression;
 mod consensus;
 mod error;
+mod shielded;
 mod watcher;
 
 #[cfg(test)]
@@ -87,13 +92,18 @@ const MAX_CHUNK_SIZE: usize = PREFERRED_CHUNK_SIZE / 4 * 5;
 
 // Minimum supported state chunk version.
 const MIN_SUPPORTED_STATE_CHUNK_VERSION: u64 = 1;
-// current state chunk version.
-const STATE_CHUNK_VERSION: u64 = 2;
+// current state chunk version. Version 3 only keeps the commitment trees of the
+// retained anchor history in the shielded chunks. Version 4 chunks may be compressed
+// with another algorithm than snappy, as recorded in the manifest.
+const STATE_CHUNK_VERSION: u64 = 4;
 /// number of snapshot subparts, must be a power of 2 in [1; 256]
 const SNAPSHOT_SUBPARTS: usize = 16;
 /// Maximum number of snapshot subparts (must be a multiple of `SNAPSHOT_SUBPARTS`)
 const MAX_SNAPSHOT_SUBPARTS: usize = 256;
 
+/// Default time after startup during which no snapshot is taken.
+pub const DEFAULT_SNAPSHOT_STARTUP_GRACE: Duration = Duration::from_secs(5 * 60);
+
 /// Configuration for the Snapshot service
 #[derive(Debug, Clone, PartialEq)]
 pub struct SnapshotConfiguration {
@@ -101,6 +111,12 @@ pub struct SnapshotConfiguration {
 	pub no_periodic: bool,
 	/// Number of threads for creating snapshots
 	pub processing_threads: usize,
+	/// Time after startup during which snapshot ticks are ignored, so that snapshots
+	/// do not compete for IO with the initial sync
+	pub startup_grace: Duration,
+	/// Compression of the chunks of the snapshots taken. Restorations decompress the
+	/// chunks with the compression recorded in the manifest, whatever this is.
+	pub compression: SnapshotCompression,
 }
 
 impl Default for SnapshotConfiguration {
@@ -108,6 +124,8 @@ impl Default for SnapshotConfiguration {
 		SnapshotConfiguration {
 			no_periodic: false,
 			processing_threads: ::std::cmp::max(1, num_cpus::get() / 2),
+			startup_grace: DEFAULT_SNAPSHOT_STARTUP_GRACE,
+			compression: SnapshotCompression::default(),
 		}
 	}
 }
@@ -119,6 +137,7 @@ pub struct Progress {
 	blocks: AtomicUsize,
 	size: AtomicUsize, // Todo [rob] use Atomicu64 when it stabilizes.
 	done: AtomicBool,
+	abort: AtomicBool,
 }
 
 impl Progress {
@@ -145,8 +164,16 @@ impl Progress {
 	/// Whether the snapshot is complete.
 	pub fn done(&self) -> bool  { self.done.load(Ordering::Acquire) }
 
+	/// Ask the snapshot using this progress to stop at the next chunk.
+	/// This is not cleared by `reset`.
+	pub fn abort(&self) { self.abort.store(true, Ordering::Release) }
+
+	/// Whether the snapshot was asked to stop.
+	pub fn aborted(&self) -> bool { self.abort.load(Ordering::Acquire) }
+
 }
 /// Take a snapshot using the given blockchain, starting block hash, and database, writing into the given writer.
+/// The chunks are compressed with `compression`.
 pub fn take_snapshot<W: SnapshotWriter + Send>(
 	engine: &EthEngine,
 	chain: &BlockChain,
@@ -155,6 +182,7 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 	writer: W,
 	p: &Progress,
 	processing_threads: usize,
+	compression: SnapshotCompression,
 ) -> Result<(), Error> {
 	let start_header = chain.block_header_data(&block_at)
 		.ok_or_else(|| Error::InvalidStartingBlock(BlockId::Hash(block_at)))?;
@@ -166,9 +194,11 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 	let writer = Mutex::new(writer);
 	let chunker = engine.snapshot_components().ok_or(Error::SnapshotsUnsupported)?;
 	let snapshot_version = chunker.current_version();
-	let (state_hashes, block_hashes) = scope(|scope| -> Result<(Vec<H256>, Vec<H256>), Error> {
+	// only the number of chunks is kept here: the writer lists the chunks written to it,
+	// so memory does not grow with the size of the state.
+	let (state_chunks, block_chunks) = scope(|scope| -> Result<(usize, usize), Error> {
 		let writer = &writer;
-		let block_guard = scope.spawn(move || chunk_secondary(chunker, chain, block_at, writer, p));
+		let block_guard = scope.spawn(move || chunk_secondary(chunker, chain, block_at, writer, p, compression));
 
 		// The number of threads must be between 1 and SNAPSHOT_SUBPARTS
 		assert!(processing_threads >= 1, "Cannot use less than 1 threads for creating snapshots");
@@ -178,41 +208,43 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 		let mut state_guards = Vec::with_capacity(num_threads as usize);
 
 		for thread_idx in 0..num_threads {
-			let state_guard = scope.spawn(move || -> Result<Vec<H256>, Error> {
-				let mut chunk_hashes = Vec::new();
+			let state_guard = scope.spawn(move || -> Result<usize, Error> {
+				let mut chunks = 0;
 
 				for part in (thread_idx..SNAPSHOT_SUBPARTS).step_by(num_threads) {
 					debug!(target: "snapshot", "Chunking part {} in thread {}", part, thread_idx);
-					let mut hashes = chunk_state(state_db, &state_root, writer, p, Some(part))?;
-					chunk_hashes.append(&mut hashes);
+					chunks += chunk_state(state_db, &state_root, writer, p, Some(part), compression)?.len();
 				}
 
-				Ok(chunk_hashes)
+				Ok(chunks)
 			});
 			state_guards.push(state_guard);
 		}
 
-		let block_hashes = block_guard.join().expect("Sub-thread never panics; qed")?;
-		let mut state_hashes = Vec::new();
+		let block_chunks = block_guard.join().expect("Sub-thread never panics; qed")?.len();
+		let mut state_chunks = 0;
 
 		for guard in state_guards {
-			let part_state_hashes = guard.join().expect("Sub-thread never panics; qed")?;
-			state_hashes.extend(part_state_hashes);
+			state_chunks += guard.join().expect("Sub-thread never panics; qed")?;
 		}
 
 		debug!(target: "snapshot", "Took a snapshot of {} accounts", p.accounts.load(Ordering::SeqCst));
-		Ok((state_hashes, block_hashes))
+		Ok((state_chunks, block_chunks))
 	})?;
 
-	info!(target: "snapshot", "produced {} state chunks and {} block chunks.", state_hashes.len(), block_hashes.len());
+	let shielded_hashes = chunk_shielded(chain, block_at, engine.params().anchor_window, &writer, p, compression)?;
+
+	info!(target: "snapshot", "produced {} state chunks ({} shielded) and {} block chunks.", state_chunks + shielded_hashes.len(), shielded_hashes.len(), block_chunks);
 
 	let manifest_data = ManifestData {
 		version: snapshot_version,
-		state_hashes: state_hashes,
-		block_hashes: block_hashes,
+		state_hashes: Vec::new(),
+		block_hashes: Vec::new(),
 		state_root: state_root,
 		block_number: number,
 		block_hash: block_at,
+		shielded_hashes: shielded_hashes,
+		compression: compression,
 	};
 
 	writer.into_inner().finish(manifest_data)?;
@@ -228,14 +260,15 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 /// Secondary chunks are engine-specific, but they intend to corroborate the state data
 /// in the state chunks.
 /// Returns a list of chunk hashes, with the first having the blocks furthest from the genesis.
-pub fn chunk_secondary<'a>(mut chunker: Box<SnapshotComponents>, chain: &'a BlockChain, start_hash: H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<Vec<H256>, Error> {
+pub fn chunk_secondary<'a>(mut chunker: Box<SnapshotComponents>, chain: &'a BlockChain, start_hash: H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress, compression: SnapshotCompression) -> Result<Vec<H256>, Error> {
 	let mut chunk_hashes = Vec::new();
-	let mut snappy_buffer = vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)];
+	let mut compression_buffer = vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)];
 
 	{
 		let mut chunk_sink = |raw_data: &[u8]| {
-			let compressed_size = snappy::compress_into(raw_data, &mut snappy_buffer);
-			let compressed = &snappy_buffer[..compressed_size];
+			let compressed_size = compress_chunk(compression, raw_data, &mut compression_buffer)
+				.map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::Other, e.to_string()))?;
+			let compressed = &compression_buffer[..compressed_size];
 			let hash = keccak(&compressed);
 			let size = compressed.len();
 
@@ -265,7 +298,8 @@ struct StateChunker<'a> {
 	hashes: Vec<H256>,
 	rlps: Vec<Bytes>,
 	cur_size: usize,
-	snappy_buffer: Vec<u8>,
+	compression: SnapshotCompression,
+	compression_buffer: Vec<u8>,
 	writer: &'a Mutex<SnapshotWriter + 'a>,
 	progress: &'a Progress,
 }
@@ -292,8 +326,8 @@ impl<'a> StateChunker<'a> {
 
 		let raw_data = stream.out();
 
-		let compressed_size = snappy::compress_into(&raw_data, &mut self.snappy_buffer);
-		let compressed = &self.snappy_buffer[..compressed_size];
+		let compressed_size = compress_chunk(self.compression, &raw_data, &mut self.compression_buffer)?;
+		let compressed = &self.compression_buffer[..compressed_size];
 		let hash = keccak(&compressed);
 
 		self.writer.lock().write_state_chunk(hash, compressed)?;
@@ -321,14 +355,15 @@ impl<'a> StateChunker<'a> {
 ///
 /// Returns a list of hashes of chunks created, or any error it may
 /// have encountered.
-pub fn chunk_state<'a>(db: &HashDB<KeccakHasher, DBValue>, root: &H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress, part: Option<usize>) -> Result<Vec<H256>, Error> {
+pub fn chunk_state<'a>(db: &HashDB<KeccakHasher, DBValue>, root: &H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress, part: Option<usize>, compression: SnapshotCompression) -> Result<Vec<H256>, Error> {
 	let account_trie = TrieDB::new(&db, &root)?;
 
 	let mut chunker = StateChunker {
 		hashes: Vec::new(),
 		rlps: Vec::new(),
 		cur_size: 0,
-		snappy_buffer: vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
+		compression: compression,
+		compression_buffer: vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
 		writer: writer,
 		progress: progress,
 	};
@@ -355,6 +390,10 @@ pub fn chunk_state<'a>(db: &HashDB<KeccakHasher, DBValue>, root: &H256, writer:
 	}
 
 	for item in account_iter {
+		if progress.aborted() {
+			return Err(Error::SnapshotAborted);
+		}
+
 		let (account_key, account_data) = item?;
 		let account_key_hash = H256::from_slice(&account_key);
 
@@ -24,7 +24,8 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::cmp;
 
-use super::{ManifestData, StateRebuilder, Rebuilder, RestorationStatus, SnapshotService, MAX_CHUNK_SIZE};
+use super::{ManifestData, StateRebuilder, ShieldedRebuilder, Rebuilder, RestorationStatus, SnapshotService};
+use super::compression::decompress_chunk;
 use super::io::{SnapshotReader, LooseReader, SnapshotWriter, LooseWriter};
 
 use blockchain::{BlockChain, BlockChainDB, BlockChainDBHandler};
@@ -42,7 +43,6 @@ use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use bytes::Bytes;
 use journaldb::Algorithm;
 use kvdb::DBTransaction;
-use snappy;
 
 /// Helper for removing directories in case of error.
 struct Guard(bool, PathBuf);
@@ -70,12 +70,29 @@ pub trait DatabaseRestore: Send + Sync {
 	fn restore_db(&self, new_db: &str) -> Result<(), Error>;
 }
 
+/// Number of times a chunk which failed hash verification is re-requested
+/// from the chunk provider before the restoration is given up.
+const MAX_CHUNK_FETCH_ATTEMPTS: usize = 3;
+
+/// A source the restoration can re-request snapshot chunks from.
+///
+/// This is typically backed by the network sync layer, but any implementation
+/// returning the raw chunk for a given hash will do.
+pub trait SnapshotChunkProvider: Send + Sync {
+	/// Fetch the state chunk with the given hash.
+	fn fetch_state_chunk(&self, hash: H256) -> Result<Bytes, Error>;
+	/// Fetch the block chunk with the given hash.
+	fn fetch_block_chunk(&self, hash: H256) -> Result<Bytes, Error>;
+}
+
 /// State restoration manager.
 struct Restoration {
 	manifest: ManifestData,
 	state_chunks_left: HashSet<H256>,
 	block_chunks_left: HashSet<H256>,
+	shielded_chunks: HashSet<H256>,
 	state: StateRebuilder,
+	shielded: ShieldedRebuilder,
 	secondary: Box<Rebuilder>,
 	writer: Option<LooseWriter>,
 	snappy_buffer: Bytes,
@@ -101,6 +118,7 @@ impl Restoration {
 
 		let state_chunks = manifest.state_hashes.iter().cloned().collect();
 		let block_chunks = manifest.block_hashes.iter().cloned().collect();
+		let shielded_chunks = manifest.shielded_hashes.iter().cloned().collect();
 
 		let raw_db = params.db;
 
@@ -116,7 +134,9 @@ impl Restoration {
 			manifest: manifest,
 			state_chunks_left: state_chunks,
 			block_chunks_left: block_chunks,
+			shielded_chunks: shielded_chunks,
 			state: StateRebuilder::new(raw_db.key_value().clone(), params.pruning),
+			shielded: ShieldedRebuilder::new(raw_db.key_value().clone()),
 			secondary: secondary,
 			writer: params.writer,
 			snappy_buffer: Vec::new(),
@@ -127,16 +147,16 @@ impl Restoration {
 	}
 
 	// feeds a state chunk, aborts early if `flag` becomes false.
+	// shielded chunks are listed with the state chunks but rebuild the shielded state.
 	fn feed_state(&mut self, hash: H256, chunk: &[u8], flag: &AtomicBool) -> Result<(), Error> {
 		if self.state_chunks_left.contains(&hash) {
-			let expected_len = snappy::decompressed_len(chunk)?;
-			if expected_len > MAX_CHUNK_SIZE {
-				trace!(target: "snapshot", "Discarding large chunk: {} vs {}", expected_len, MAX_CHUNK_SIZE);
-				return Err(::snapshot::Error::ChunkTooLarge.into());
-			}
-			let len = snappy::decompress_into(chunk, &mut self.snappy_buffer)?;
+			let len = decompress_chunk(self.manifest.compression, chunk, &mut self.snappy_buffer)?;
 
-			self.state.feed(&self.snappy_buffer[..len], flag)?;
+			if self.shielded_chunks.contains(&hash) {
+				self.shielded.feed(&self.snappy_buffer[..len], flag)?;
+			} else {
+				self.state.feed(&self.snappy_buffer[..len], flag)?;
+			}
 
 			if let Some(ref mut writer) = self.writer.as_mut() {
 				writer.write_state_chunk(hash, chunk)?;
@@ -151,12 +171,7 @@ impl Restoration {
 	// feeds a block chunk
 	fn feed_blocks(&mut self, hash: H256, chunk: &[u8], engine: &EthEngine, flag: &AtomicBool) -> Result<(), Error> {
 		if self.block_chunks_left.contains(&hash) {
-			let expected_len = snappy::decompressed_len(chunk)?;
-			if expected_len > MAX_CHUNK_SIZE {
-				trace!(target: "snapshot", "Discarding large chunk: {} vs {}", expected_len, MAX_CHUNK_SIZE);
-				return Err(::snapshot::Error::ChunkTooLarge.into());
-			}
-			let len = snappy::decompress_into(chunk, &mut self.snappy_buffer)?;
+			let len = decompress_chunk(self.manifest.compression, chunk, &mut self.snappy_buffer)?;
 
 			self.secondary.feed(&self.snappy_buffer[..len], engine, flag)?;
 			if let Some(ref mut writer) = self.writer.as_mut() {
@@ -185,6 +200,11 @@ impl Restoration {
 		// check for missing code.
 		self.state.finalize(self.manifest.block_number, self.manifest.block_hash)?;
 
+		// snapshots of older versions have no shielded state.
+		if !self.shielded_chunks.is_empty() {
+			self.shielded.finalize(self.manifest.block_hash)?;
+		}
+
 		// connect out-of-order chunks and verify chain integrity.
 		self.secondary.finalize(engine)?;
 
@@ -196,6 +216,19 @@ impl Restoration {
 		Ok(())
 	}
 
+	// chunks of the manifest not fed yet: (state chunks, block chunks), in manifest order.
+	fn outstanding_chunks(&self) -> (Vec<H256>, Vec<H256>) {
+		let state = self.manifest.state_hashes.iter()
+			.filter(|h| self.state_chunks_left.contains(h))
+			.cloned()
+			.collect();
+		let blocks = self.manifest.block_hashes.iter()
+			.filter(|h| self.block_chunks_left.contains(h))
+			.cloned()
+			.collect();
+		(state, blocks)
+	}
+
 	// is everything done?
 	fn is_done(&self) -> bool {
 		self.block_chunks_left.is_empty() && self.state_chunks_left.is_empty()
@@ -245,6 +278,7 @@ pub struct Service {
 	progress: super::Progress,
 	taking_snapshot: AtomicBool,
 	restoring_snapshot: AtomicBool,
+	chunk_provider: RwLock<Option<Arc<SnapshotChunkProvider>>>,
 }
 
 impl Service {
@@ -266,6 +300,7 @@ impl Service {
 			progress: Default::default(),
 			taking_snapshot: AtomicBool::new(false),
 			restoring_snapshot: AtomicBool::new(false),
+			chunk_provider: RwLock::new(None),
 		};
 
 		// create the root snapshot dir if it doesn't exist.
@@ -462,6 +497,11 @@ impl Service {
 	/// will lead to a race condition where the first one to finish will
 	/// have their produced snapshot overwritten.
 	pub fn take_snapshot(&self, client: &Client, num: u64) -> Result<(), Error> {
+		if self.progress.aborted() {
+			info!("Skipping snapshot at #{} as the snapshot service is shutting down.", num);
+			return Ok(());
+		}
+
 		if self.taking_snapshot.compare_and_swap(false, true, Ordering::SeqCst) {
 			info!("Skipping snapshot at #{} as another one is currently in-progress.", num);
 			return Ok(());
@@ -482,6 +522,10 @@ impl Service {
 
 		self.taking_snapshot.store(false, Ordering::SeqCst);
 		if let Err(e) = res {
+			if self.progress.aborted() {
+				info!("Snapshot at #{} aborted.", num);
+				return Ok(())
+			}
 			if client.chain_info().best_block_number >= num + client.pruning_history() {
 				// "Cancelled" is mincing words a bit -- what really happened
 				// is that the state we were snapshotting got pruned out
@@ -513,9 +557,42 @@ impl Service {
 		Ok(())
 	}
 
+	/// Whether a snapshot is currently being taken.
+	pub fn is_taking_snapshot(&self) -> bool {
+		self.taking_snapshot.load(Ordering::SeqCst)
+	}
+
+	/// Chunks of the restoration in progress which were not fed yet, as
+	/// (state chunks, block chunks). Both are empty if nothing is being restored.
+	pub fn outstanding_chunks(&self) -> (Vec<H256>, Vec<H256>) {
+		self.restoration.lock().as_ref()
+			.map_or_else(|| (Vec::new(), Vec::new()), Restoration::outstanding_chunks)
+	}
+
+	/// Stop the snapshot being taken, if any, at its next chunk and refuse to
+	/// take any new one. Only meant to be used when shutting down.
+	pub fn abort_snapshot(&self) {
+		if self.taking_snapshot.load(Ordering::SeqCst) {
+			trace!(target: "snapshot", "Aborting snapshot");
+		}
+		self.progress.abort();
+	}
+
 	/// Initialize the restoration synchronously.
 	/// The recover flag indicates whether to recover the restored snapshot.
 	pub fn init_restore(&self, manifest: ManifestData, recover: bool) -> Result<(), Error> {
+		self.init_restore_with_provider(manifest, recover, None)
+	}
+
+	/// Initialize the restoration synchronously, re-requesting chunks which fail
+	/// hash verification from `provider` instead of failing the whole restoration.
+	/// The recover flag indicates whether to recover the restored snapshot.
+	pub fn init_restore_with_provider(
+		&self,
+		manifest: ManifestData,
+		recover: bool,
+		provider: Option<Arc<SnapshotChunkProvider>>,
+	) -> Result<(), Error> {
 		let mut res = self.restoration.lock();
 
 		let rest_dir = self.restoration_dir();
@@ -542,6 +619,7 @@ impl Service {
 
 		// tear down existing restoration.
 		*res = None;
+		*self.chunk_provider.write() = provider;
 
 		// delete and restore the restoration dir.
 		if let Err(e) = fs::remove_dir_all(&rest_dir) {
@@ -695,18 +773,59 @@ impl Service {
 	fn feed_chunk(&self, hash: H256, chunk: &[u8], is_state: bool) {
 		// TODO: be able to process block chunks and state chunks at same time?
 		let mut restoration = self.restoration.lock();
-		match self.feed_chunk_with_restoration(&mut restoration, hash, chunk, is_state) {
+		let result = self.verified_chunk(&restoration, hash, chunk, is_state).and_then(|refetched| {
+			let chunk = refetched.as_ref().map_or(chunk, |c| &c[..]);
+			self.feed_chunk_with_restoration(&mut restoration, hash, chunk, is_state)
+		});
+
+		match result {
 			Ok(()) |
 			Err(Error(SnapshotErrorKind::Snapshot(SnapshotError::RestorationAborted), _)) => (),
 			Err(e) => {
 				warn!("Encountered error during snapshot restoration: {}", e);
-				*self.restoration.lock() = None;
+				*restoration = None;
 				*self.status.lock() = RestorationStatus::Failed;
 				let _ = fs::remove_dir_all(self.restoration_dir());
 			}
 		}
 	}
 
+	/// Check that a chunk still expected by the restoration matches its hash.
+	/// If it does not, the chunk is re-requested from the chunk provider and the
+	/// fetched one is returned. Chunks which are not expected are passed through.
+	fn verified_chunk(&self, restoration: &Option<Restoration>, hash: H256, chunk: &[u8], is_state: bool) -> Result<Option<Bytes>, Error> {
+		let expected = restoration.as_ref().map_or(false, |r| match is_state {
+			true => r.state_chunks_left.contains(&hash),
+			false => r.block_chunks_left.contains(&hash),
+		});
+
+		if !expected || keccak(chunk) == hash {
+			return Ok(None);
+		}
+
+		let provider = match *self.chunk_provider.read() {
+			Some(ref provider) => provider.clone(),
+			None => return Err(SnapshotError::ChunkHashMismatch(hash).into()),
+		};
+
+		for attempt in 1..=MAX_CHUNK_FETCH_ATTEMPTS {
+			trace!(target: "snapshot", "Chunk {:x} failed hash verification, re-requesting (attempt {})", hash, attempt);
+			let fetched = match is_state {
+				true => provider.fetch_state_chunk(hash),
+				false => provider.fetch_block_chunk(hash),
+			};
+
+			match fetched {
+				Ok(fetched) => if keccak(&fetched) == hash {
+					return Ok(Some(fetched));
+				},
+				Err(e) => trace!(target: "snapshot", "Failed to re-request chunk {:x}: {}", hash, e),
+			}
+		}
+
+		Err(SnapshotError::ChunkHashMismatch(hash).into())
+	}
+
 	/// Feed a chunk with the Restoration
 	fn feed_chunk_with_restoration(&self, restoration: &mut Option<Restoration>, hash: H256, chunk: &[u8], is_state: bool) -> Result<(), Error> {
 		let (result, db) = {
@@ -847,6 +966,7 @@ impl SnapshotService for Service {
 	}
 
 	fn shutdown(&self) {
+		self.abort_snapshot();
 		self.abort_restore();
 	}
 }
@@ -901,6 +1021,8 @@ mod tests {
 			state_root: Default::default(),
 			block_number: 0,
 			block_hash: Default::default(),
+			shielded_hashes: Vec::new(),
+			compression: Default::default(),
 		};
 
 		service.begin_restore(manifest);
@@ -931,6 +1053,8 @@ mod tests {
 				state_root: H256::default(),
 				block_number: 100000,
 				block_hash: H256::default(),
+				shielded_hashes: Vec::new(),
+				compression: Default::default(),
 			},
 			pruning: Algorithm::Archive,
 			db: restoration_db_handler(db_config).open(&tempdir.path().to_owned()).unwrap(),
@@ -953,4 +1077,47 @@ mod tests {
 			assert!(!restoration.is_done());
 		}
 	}
+
+	#[test]
+	fn lists_the_outstanding_chunks_in_manifest_order() {
+		use ethereum_types::H256;
+		use kvdb_rocksdb::DatabaseConfig;
+
+		let spec = Spec::new_test();
+		let tempdir = TempDir::new("").unwrap();
+
+		let state_hashes: Vec<_> = (0..5).map(|_| H256::random()).collect();
+		let block_hashes: Vec<_> = (0..5).map(|_| H256::random()).collect();
+		let db_config = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+		let gb = spec.genesis_block();
+		let flag = ::std::sync::atomic::AtomicBool::new(true);
+
+		let params = RestorationParams {
+			manifest: ManifestData {
+				version: 2,
+				state_hashes: state_hashes.clone(),
+				block_hashes: block_hashes.clone(),
+				state_root: H256::default(),
+				block_number: 100000,
+				block_hash: H256::default(),
+				shielded_hashes: Vec::new(),
+				compression: Default::default(),
+			},
+			pruning: Algorithm::Archive,
+			db: restoration_db_handler(db_config).open(&tempdir.path().to_owned()).unwrap(),
+			writer: None,
+			genesis: &gb,
+			guard: Guard::benign(),
+			engine: &*spec.engine.clone(),
+		};
+
+		let mut restoration = Restoration::new(params).unwrap();
+		assert_eq!(restoration.outstanding_chunks(), (state_hashes.clone(), block_hashes.clone()));
+
+		// chunks which fail to restore are still outstanding.
+		let definitely_bad_chunk = [1, 2, 3, 4, 5];
+		assert!(restoration.feed_state(state_hashes[3], &definitely_bad_chunk, &flag).is_err());
+		assert!(restoration.feed_blocks(block_hashes[0], &definitely_bad_chunk, &*spec.engine, &flag).is_err());
+		assert_eq!(restoration.outstanding_chunks(), (state_hashes, block_hashes));
+	}
 }
@@ -0,0 +1,256 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshot chunks of the shielded state.
+//!
+//! A shielded chunk is an RLP list of two lists:
+//! 	[[block hash, commitment tree]...] for the starting block and the blocks
+//! 	whose commitment root is still accepted as an anchor, newest first.
+//! 	[[nullifier, transaction hash]...] for the nullifiers revealed up to the
+//! 	starting block.
+//! The trees are chunked before the nullifiers, so the first chunks hold trees only,
+//! the last ones nullifiers only, and one chunk in between may hold both.
+
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blockchain::{BlockChain, BlockProvider};
+use db::{self, Writable};
+use db::keys::CommitmentTreeSerialization;
+use ethereum_types::{H256, U256};
+use hash::keccak;
+use kvdb::{DBTransaction, KeyValueDB};
+use parking_lot::Mutex;
+use rlp::{Rlp, RlpStream};
+use snappy;
+use types::ids::BlockId;
+use zcash_primitives::merkle_tree::CommitmentTree;
+use zcash_primitives::sapling::Node;
+
+use super::{Error, Progress, SnapshotCompression, PREFERRED_CHUNK_SIZE};
+use super::compression::compress_chunk;
+use super::io::SnapshotWriter;
+
+/// Shielded state chunker.
+struct ShieldedChunker<'a> {
+	hashes: Vec<H256>,
+	trees: Vec<Vec<u8>>,
+	nullifiers: Vec<Vec<u8>>,
+	cur_size: usize,
+	compression: SnapshotCompression,
+	compression_buffer: Vec<u8>,
+	writer: &'a Mutex<SnapshotWriter + 'a>,
+	progress: &'a Progress,
+}
+
+impl<'a> ShieldedChunker<'a> {
+	fn push_tree(&mut self, hash: H256, tree: &CommitmentTreeSerialization) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&hash).append(tree);
+		self.push(true, stream.out())
+	}
+
+	fn push_nullifier(&mut self, nullifier: U256, tx_hash: H256) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&nullifier).append(&tx_hash);
+		self.push(false, stream.out())
+	}
+
+	// Push an encoded entry, writing out the buffer first if it is full.
+	fn push(&mut self, is_tree: bool, data: Vec<u8>) -> Result<(), Error> {
+		if self.cur_size + data.len() > PREFERRED_CHUNK_SIZE && self.cur_size != 0 {
+			self.write_chunk()?;
+		}
+		self.cur_size += data.len();
+		if is_tree {
+			self.trees.push(data);
+		} else {
+			self.nullifiers.push(data);
+		}
+		Ok(())
+	}
+
+	// Write out the buffer to disk, pushing the created chunk's hash to the list.
+	fn write_chunk(&mut self) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(2);
+		stream.begin_list(self.trees.len());
+		for tree in self.trees.drain(..) {
+			stream.append_raw(&tree, 1);
+		}
+		stream.begin_list(self.nullifiers.len());
+		for nullifier in self.nullifiers.drain(..) {
+			stream.append_raw(&nullifier, 1);
+		}
+
+		let raw_data = stream.out();
+
+		let compressed_size = compress_chunk(self.compression, &raw_data, &mut self.compression_buffer)?;
+		let compressed = &self.compression_buffer[..compressed_size];
+		let hash = keccak(&compressed);
+
+		self.writer.lock().write_state_chunk(hash, compressed)?;
+		trace!(target: "snapshot", "wrote shielded chunk. size: {}, uncompressed size: {}", compressed_size, raw_data.len());
+
+		self.progress.size.fetch_add(compressed_size, Ordering::SeqCst);
+
+		self.hashes.push(hash);
+		self.cur_size = 0;
+
+		Ok(())
+	}
+}
+
+/// Create and write out the shielded state as of the given block, returning
+/// the hashes of the chunks created.
+///
+/// The commitment trees of the starting block and of the `anchor_window` blocks
+/// before it are included, so that the restored chain accepts the same anchors. Blocks
+/// below the retained anchor history are left out, their roots are no longer accepted.
+pub fn chunk_shielded<'a>(
+	chain: &BlockChain,
+	block_at: H256,
+	anchor_window: usize,
+	writer: &Mutex<SnapshotWriter + 'a>,
+	progress: &'a Progress,
+	compression: SnapshotCompression,
+) -> Result<Vec<H256>, Error> {
+	let number = chain.block_number(&block_at)
+		.ok_or_else(|| Error::InvalidStartingBlock(BlockId::Hash(block_at)))?;
+
+	let mut chunker = ShieldedChunker {
+		hashes: Vec::new(),
+		trees: Vec::new(),
+		nullifiers: Vec::new(),
+		cur_size: 0,
+		compression: compression,
+		compression_buffer: vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
+		writer: writer,
+		progress: progress,
+	};
+
+	let oldest_retained = chain.anchor_history_info().oldest_retained;
+	let depth = cmp::min(anchor_window as u64, number.saturating_sub(oldest_retained));
+
+	let mut hash = block_at;
+	for _ in 0..=depth {
+		let tree = match chain.block_commitment_ser(&hash) {
+			Some(tree) => tree,
+			None => break,
+		};
+		chunker.push_tree(hash, &tree)?;
+
+		match chain.block_details(&hash) {
+			Some(details) => hash = details.parent,
+			None => break,
+		}
+	}
+
+	if chunker.trees.is_empty() {
+		return Err(Error::IncompleteChain);
+	}
+
+	// the set follows the best block, leave out what was revealed after the starting block.
+	for nullifier in chain.nullifier_set().iter() {
+		if progress.aborted() {
+			return Err(Error::SnapshotAborted);
+		}
+
+		let nullifier = U256::from(&nullifier[..]);
+		let tx_hash = match chain.nullifier_transaction(&nullifier) {
+			Some(tx_hash) => tx_hash,
+			None => continue,
+		};
+		let revealed_at = chain.transaction_address(&tx_hash)
+			.and_then(|address| chain.block_number(&address.block_hash));
+		match revealed_at {
+			Some(n) if n <= number => chunker.push_nullifier(nullifier, tx_hash)?,
+			_ => continue,
+		}
+	}
+
+	chunker.write_chunk()?;
+
+	Ok(chunker.hashes)
+}
+
+/// Used to rebuild the commitment trees, anchors and nullifier set from shielded chunks.
+pub struct ShieldedRebuilder {
+	db: Arc<KeyValueDB>,
+	trees: usize,
+	nullifiers: usize,
+}
+
+impl ShieldedRebuilder {
+	/// Create a new shielded state rebuilder writing into the given database.
+	pub fn new(db: Arc<KeyValueDB>) -> Self {
+		ShieldedRebuilder {
+			db: db,
+			trees: 0,
+			nullifiers: 0,
+		}
+	}
+
+	/// Feed an uncompressed shielded chunk into the rebuilder.
+	pub fn feed(&mut self, chunk: &[u8], flag: &AtomicBool) -> Result<(), ::error::Error> {
+		let rlp = Rlp::new(chunk);
+		let mut batch = DBTransaction::new();
+
+		for item in rlp.at(0)?.iter() {
+			if !flag.load(Ordering::SeqCst) { return Err(Error::RestorationAborted.into()) }
+
+			let hash: H256 = item.val_at(0)?;
+			let raw_tree: Vec<u8> = item.val_at(1)?;
+			let tree = CommitmentTree::<Node>::read(&raw_tree[..])
+				.map_err(|_| Error::WrongChunkFormat(format!("invalid commitment tree for block {:x}", hash)))?;
+
+			batch.write(db::COL_EXTRA, &tree.root(), &hash);
+			batch.write(db::COL_EXTRA, &hash, &CommitmentTreeSerialization::new(&tree));
+			self.trees += 1;
+		}
+
+		for item in rlp.at(1)?.iter() {
+			if !flag.load(Ordering::SeqCst) { return Err(Error::RestorationAborted.into()) }
+
+			let nullifier: U256 = item.val_at(0)?;
+			let tx_hash: H256 = item.val_at(1)?;
+			batch.write(db::COL_EXTRA, &nullifier, &tx_hash);
+			self.nullifiers += 1;
+		}
+
+		self.db.write_buffered(batch);
+		trace!(target: "snapshot", "restored {} commitment trees and {} nullifiers", self.trees, self.nullifiers);
+		Ok(())
+	}
+
+	/// Finalize the restoration, checking that the commitment tree of the
+	/// starting block was restored.
+	///
+	/// The restored nullifier set holds every nullifier revealed up to the
+	/// starting block, so it is marked current as of that block and need not
+	/// be rebuilt from the ancient blocks, which a warp-synced node lacks.
+	pub fn finalize(self, block_hash: H256) -> Result<(), ::error::Error> {
+		let key = db::Key::<CommitmentTreeSerialization>::key(&block_hash);
+		if self.db.get(db::COL_EXTRA, &key)?.is_none() {
+			return Err(Error::WrongChunkFormat(format!("missing commitment tree of block {:x}", block_hash)).into());
+		}
+
+		let mut batch = DBTransaction::new();
+		batch.put(db::COL_EXTRA, b"nullifier_set_best", &block_hash);
+		self.db.write_buffered(batch);
+		Ok(())
+	}
+}
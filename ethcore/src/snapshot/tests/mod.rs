@@ -20,10 +20,13 @@ mod proof_of_work;
 mod proof_of_authority;
 mod state;
 mod service;
+mod shielded;
 
 pub mod helpers;
 
-use super::ManifestData;
+use super::{ManifestData, SnapshotCompression};
+use ethereum_types::H256;
+use rlp::Rlp;
 
 #[test]
 fn manifest_rlp() {
@@ -34,7 +37,26 @@ fn manifest_rlp() {
 		block_number: 1234567,
 		state_root: Default::default(),
 		block_hash: Default::default(),
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	};
 	let raw = manifest.clone().into_rlp();
 	assert_eq!(ManifestData::from_rlp(&raw).unwrap(), manifest);
+
+	let shielded = ManifestData {
+		shielded_hashes: vec![H256::from(1)],
+		state_hashes: vec![H256::from(1), H256::from(2)],
+		..manifest.clone()
+	};
+	let raw_shielded = shielded.clone().into_rlp();
+	assert_eq!(ManifestData::from_rlp(&raw_shielded).unwrap(), shielded);
+	// manifests without shielded state keep their encoding.
+	assert_eq!(Rlp::new(&raw).item_count().unwrap(), 6);
+
+	for compression in vec![SnapshotCompression::None, SnapshotCompression::Zstd { level: 19 }] {
+		let compressed = ManifestData { compression, ..manifest.clone() };
+		let raw_compressed = compressed.clone().into_rlp();
+		assert_eq!(ManifestData::from_rlp(&raw_compressed).unwrap(), compressed);
+		assert_eq!(Rlp::new(&raw_compressed).item_count().unwrap(), 8);
+	}
 }
@@ -22,7 +22,7 @@ use error::{Error, ErrorKind};
 
 use blockchain::generator::{BlockGenerator, BlockBuilder};
 use blockchain::{BlockChain, ExtrasInsert};
-use snapshot::{chunk_secondary, Error as SnapshotError, Progress, SnapshotComponents};
+use snapshot::{chunk_secondary, Error as SnapshotError, Progress, SnapshotComponents, SnapshotCompression};
 use snapshot::io::{PackedReader, PackedWriter, SnapshotReader, SnapshotWriter};
 
 use parking_lot::Mutex;
@@ -66,7 +66,8 @@ fn chunk_and_restore(amount: u64) {
 		&bc,
 		best_hash,
 		&writer,
-		&Progress::default()
+		&Progress::default(),
+		SnapshotCompression::Snappy,
 	).unwrap();
 
 	let manifest = ::snapshot::ManifestData {
@@ -76,6 +77,8 @@ fn chunk_and_restore(amount: u64) {
 		state_root: ::hash::KECCAK_NULL_RLP,
 		block_number: amount,
 		block_hash: best_hash,
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	};
 
 	writer.into_inner().finish(manifest.clone()).unwrap();
@@ -138,6 +141,8 @@ fn checks_flag() {
 		state_root: ::hash::KECCAK_NULL_RLP,
 		block_number: 102,
 		block_hash: H256::default(),
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	};
 
 	let mut rebuilder = SNAPSHOT_MODE.rebuilder(chain, db.clone(), &manifest).unwrap();
@@ -16,6 +16,7 @@
 
 //! Tests for the snapshot service.
 
+use std::collections::HashMap;
 use std::fs;
 use std::sync::Arc;
 
@@ -25,10 +26,13 @@ use client::{Client, ClientConfig, ImportBlock, BlockInfo};
 use types::ids::BlockId;
 use snapshot::io::{PackedReader, PackedWriter, SnapshotReader, SnapshotWriter};
 use snapshot::service::{Service, ServiceParams};
-use snapshot::{chunk_state, chunk_secondary, ManifestData, Progress, SnapshotService, RestorationStatus};
+use snapshot::{SnapshotChunkProvider, chunk_state, chunk_secondary, ManifestData, Progress, SnapshotCompression, SnapshotService, RestorationStatus};
 use spec::Spec;
 use test_helpers::{new_db, new_temp_db, generate_dummy_client_with_spec_and_data, restoration_db_handler};
 
+use bytes::Bytes;
+use error::Error;
+use ethereum_types::H256;
 use parking_lot::Mutex;
 use io::IoChannel;
 use kvdb_rocksdb::DatabaseConfig;
@@ -100,6 +104,81 @@ fn restored_is_equivalent() {
 	}
 }
 
+struct MemoryChunkProvider(HashMap<H256, Bytes>);
+
+impl SnapshotChunkProvider for MemoryChunkProvider {
+	fn fetch_state_chunk(&self, hash: H256) -> Result<Bytes, Error> {
+		self.0.get(&hash).cloned().ok_or_else(|| ::snapshot::Error::BlockNotFound(hash).into())
+	}
+
+	fn fetch_block_chunk(&self, hash: H256) -> Result<Bytes, Error> {
+		self.fetch_state_chunk(hash)
+	}
+}
+
+#[test]
+fn refetches_corrupted_chunks() {
+	let gas_prices = vec![1.into(), 2.into(), 3.into(), 999.into()];
+	let client = generate_dummy_client_with_spec_and_data(Spec::new_null, 100, 5, &gas_prices);
+
+	let tempdir = TempDir::new("").unwrap();
+	let client_db = tempdir.path().join("client_db");
+	let path = tempdir.path().join("snapshot");
+
+	let db_config = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+	let restoration = restoration_db_handler(db_config);
+	let blockchain_db = restoration.open(&client_db).unwrap();
+
+	let spec = Spec::new_null();
+	let client2 = Client::new(
+		Default::default(),
+		&spec,
+		blockchain_db,
+		Arc::new(::miner::Miner::new_for_tests(&spec, None)),
+		IoChannel::disconnected(),
+		None,
+	).unwrap();
+
+	let service_params = ServiceParams {
+		engine: spec.engine.clone(),
+		genesis_block: spec.genesis_block(),
+		restoration_db_handler: restoration,
+		pruning: ::journaldb::Algorithm::Archive,
+		channel: IoChannel::disconnected(),
+		snapshot_root: path,
+		client: client2.clone(),
+	};
+
+	let service = Service::new(service_params).unwrap();
+	service.take_snapshot(&client, 100).unwrap();
+
+	let manifest = service.manifest().unwrap();
+	let chunks: HashMap<_, _> = manifest.state_hashes.iter()
+		.chain(manifest.block_hashes.iter())
+		.map(|hash| (*hash, service.chunk(*hash).unwrap()))
+		.collect();
+	let corrupted = vec![1, 2, 3, 4, 5];
+
+	// without a provider a corrupted chunk fails the restoration.
+	service.init_restore(manifest.clone(), false).unwrap();
+	service.feed_state_chunk(manifest.state_hashes[0], &corrupted);
+	assert_eq!(service.status(), RestorationStatus::Failed);
+
+	let provider = Arc::new(MemoryChunkProvider(chunks.clone()));
+	service.init_restore_with_provider(manifest.clone(), false, Some(provider)).unwrap();
+
+	for hash in &manifest.state_hashes {
+		service.feed_state_chunk(*hash, &corrupted);
+	}
+
+	for hash in &manifest.block_hashes {
+		service.feed_block_chunk(*hash, &corrupted);
+	}
+
+	assert_eq!(service.status(), RestorationStatus::Inactive);
+	assert_eq!(client2.block(BlockId::Latest).unwrap().number(), 100);
+}
+
 // on windows the guards deletion (remove_dir_all)
 // is not happening (error directory is not empty).
 // So the test is disabled until windows api behave.
@@ -130,6 +209,8 @@ fn guards_delete_folders() {
 		block_hashes: vec![],
 		block_number: 0,
 		block_hash: Default::default(),
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 		state_root: Default::default(),
 	};
 
@@ -179,7 +260,8 @@ fn keep_ancient_blocks() {
 		&bc,
 		best_hash,
 		&writer,
-		&Progress::default()
+		&Progress::default(),
+		SnapshotCompression::Snappy,
 	).unwrap();
 	let state_db = client.state_db().journal_db().boxed_clone();
 	let start_header = bc.block_header_data(&best_hash).unwrap();
@@ -189,7 +271,8 @@ fn keep_ancient_blocks() {
 		&state_root,
 		&writer,
 		&Progress::default(),
-		None
+		None,
+		SnapshotCompression::Snappy,
 	).unwrap();
 
 	let manifest = ::snapshot::ManifestData {
@@ -199,6 +282,8 @@ fn keep_ancient_blocks() {
 		block_hashes: block_hashes,
 		block_number: NUM_BLOCKS,
 		block_hash: best_hash,
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	};
 
 	writer.into_inner().finish(manifest.clone()).unwrap();
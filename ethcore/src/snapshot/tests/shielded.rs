@@ -0,0 +1,209 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Shielded state snapshotting tests.
+
+use std::sync::atomic::AtomicBool;
+use tempdir::TempDir;
+
+use blockchain::generator::{BlockGenerator, BlockBuilder};
+use blockchain::{BlockChain, BlockProvider, ExtrasInsert};
+use db::{self, Writable};
+use db::keys::{CommitmentTreeSerialization, TransactionAddress};
+use snapshot::{chunk_shielded, Progress, ShieldedRebuilder, SnapshotCompression};
+use snapshot::io::{PackedReader, PackedWriter, SnapshotReader, SnapshotWriter};
+use zcash_primitives::merkle_tree::{CommitmentTree, Hashable};
+use zcash_primitives::sapling::Node;
+
+use ethereum_types::{H256, U256};
+use kvdb::DBTransaction;
+use parking_lot::Mutex;
+use rlp::RlpStream;
+use snappy;
+use test_helpers;
+
+#[test]
+fn snap_and_restore_shielded_state() {
+	let genesis = BlockBuilder::genesis();
+	let rest = genesis.add_blocks(8);
+	let generator = BlockGenerator::new(vec![rest]);
+	let genesis = genesis.last();
+
+	let old_db = test_helpers::new_db();
+	let bc = BlockChain::new(Default::default(), genesis.encoded().raw(), old_db.clone(), None);
+
+	let mut batch = DBTransaction::new();
+	for block in generator {
+		bc.insert_block(&mut batch, block.encoded(), vec![], ExtrasInsert {
+			fork_choice: ::engines::ForkChoice::New,
+			is_finalized: false,
+		});
+		bc.commit();
+	}
+	old_db.key_value().write(batch).unwrap();
+
+	// give the starting block a non-empty commitment tree, and reveal one
+	// nullifier before and one after it.
+	let block_at = bc.block_hash(5).unwrap();
+	let mut tree = CommitmentTree::<Node>::new();
+	for i in 1..4 {
+		tree.append(Node::empty_root(i)).unwrap();
+	}
+	let (early, late) = (U256::from(1), U256::from(2));
+	let (early_tx, late_tx) = (H256::from(11), H256::from(12));
+
+	let mut batch = DBTransaction::new();
+	batch.write(db::COL_EXTRA, &block_at, &CommitmentTreeSerialization::new(&tree));
+	batch.write(db::COL_EXTRA, &tree.root(), &block_at);
+	batch.write(db::COL_EXTRA, &early, &early_tx);
+	batch.write(db::COL_EXTRA, &early_tx, &TransactionAddress { block_hash: bc.block_hash(3).unwrap(), index: 0 });
+	batch.write(db::COL_EXTRA, &late, &late_tx);
+	batch.write(db::COL_EXTRA, &late_tx, &TransactionAddress { block_hash: bc.block_hash(7).unwrap(), index: 0 });
+	old_db.key_value().write(batch).unwrap();
+
+	// reopen the chain so that nothing is served from the caches.
+	let bc = BlockChain::new(Default::default(), genesis.encoded().raw(), old_db.clone(), None);
+
+	// snapshot it.
+	let tempdir = TempDir::new("").unwrap();
+	let snapshot_path = tempdir.path().join("SNAP");
+	let writer = Mutex::new(PackedWriter::new(&snapshot_path).unwrap());
+	let shielded_hashes = chunk_shielded(&bc, block_at, 2, &writer, &Progress::default(), SnapshotCompression::Snappy).unwrap();
+
+	writer.into_inner().finish(::snapshot::ManifestData {
+		version: 2,
+		state_hashes: shielded_hashes.clone(),
+		block_hashes: Vec::new(),
+		state_root: ::hash::KECCAK_NULL_RLP,
+		block_number: 5,
+		block_hash: block_at,
+		shielded_hashes: shielded_hashes,
+		compression: Default::default(),
+	}).unwrap();
+
+	// restore it.
+	let new_db = test_helpers::new_db();
+	let reader = PackedReader::new(&snapshot_path).unwrap().unwrap();
+	assert_eq!(reader.manifest().shielded_hashes, reader.manifest().state_hashes);
+
+	let flag = AtomicBool::new(true);
+	let mut rebuilder = ShieldedRebuilder::new(new_db.key_value().clone());
+	for chunk_hash in &reader.manifest().shielded_hashes {
+		let compressed = reader.chunk(*chunk_hash).unwrap();
+		let chunk = snappy::decompress(&compressed).unwrap();
+		rebuilder.feed(&chunk, &flag).unwrap();
+	}
+	rebuilder.finalize(block_at).unwrap();
+
+	let restored = BlockChain::new(Default::default(), genesis.encoded().raw(), new_db.clone(), None);
+
+	// the anchor of the starting block matches the source chain.
+	let restored_tree = restored.block_commitment_ser(&block_at).unwrap().get_commitment_tree();
+	assert_eq!(restored_tree.root(), tree.root());
+	assert_eq!(restored_tree.size(), 3);
+	assert_eq!(restored.commitment_root_block(&tree.root()), Some(block_at));
+
+	// the anchor window is restored, older trees are not.
+	for n in 3..5 {
+		let hash = bc.block_hash(n).unwrap();
+		assert_eq!(
+			restored.block_commitment_ser(&hash).map(|s| s.get_commitment_tree().root()),
+			bc.block_commitment_ser(&hash).map(|s| s.get_commitment_tree().root())
+		);
+	}
+	assert!(restored.block_commitment_ser(&bc.block_hash(2).unwrap()).is_none());
+
+	// only nullifiers revealed up to the starting block are kept.
+	assert_eq!(restored.nullifier_transaction(&early), Some(early_tx));
+	assert_eq!(restored.nullifier_transaction(&late), None);
+}
+
+#[test]
+fn pruned_trees_are_not_snapshotted() {
+	let genesis = BlockBuilder::genesis();
+	let rest = genesis.add_blocks(8);
+	let generator = BlockGenerator::new(vec![rest]);
+	let genesis = genesis.last();
+
+	let db = test_helpers::new_db();
+	let bc = BlockChain::new(Default::default(), genesis.encoded().raw(), db.clone(), None);
+
+	let mut batch = DBTransaction::new();
+	for block in generator {
+		bc.insert_block(&mut batch, block.encoded(), vec![], ExtrasInsert {
+			fork_choice: ::engines::ForkChoice::New,
+			is_finalized: false,
+		});
+		bc.commit();
+	}
+	db.key_value().write(batch).unwrap();
+	bc.prune_commitment_roots(4);
+
+	let tempdir = TempDir::new("").unwrap();
+	let writer = Mutex::new(PackedWriter::new(&tempdir.path().join("SNAP")).unwrap());
+	let shielded_hashes = chunk_shielded(&bc, bc.block_hash(6).unwrap(), usize::max_value(), &writer, &Progress::default(), SnapshotCompression::Snappy).unwrap();
+	writer.into_inner().finish(::snapshot::ManifestData {
+		version: 2,
+		state_hashes: shielded_hashes.clone(),
+		block_hashes: Vec::new(),
+		state_root: ::hash::KECCAK_NULL_RLP,
+		block_number: 6,
+		block_hash: bc.block_hash(6).unwrap(),
+		shielded_hashes: shielded_hashes,
+		compression: Default::default(),
+	}).unwrap();
+
+	let new_db = test_helpers::new_db();
+	let reader = PackedReader::new(&tempdir.path().join("SNAP")).unwrap().unwrap();
+	let flag = AtomicBool::new(true);
+	let mut rebuilder = ShieldedRebuilder::new(new_db.key_value().clone());
+	for chunk_hash in &reader.manifest().shielded_hashes {
+		let chunk = snappy::decompress(&reader.chunk(*chunk_hash).unwrap()).unwrap();
+		rebuilder.feed(&chunk, &flag).unwrap();
+	}
+
+	// blocks 4 to 6 are retained.
+	let restored = BlockChain::new(Default::default(), genesis.encoded().raw(), new_db.clone(), None);
+	for n in 4..7 {
+		assert!(restored.block_commitment_ser(&bc.block_hash(n).unwrap()).is_some());
+	}
+	assert!(restored.block_commitment_ser(&bc.block_hash(3).unwrap()).is_none());
+}
+
+#[test]
+fn missing_starting_tree_fails_restoration() {
+	let new_db = test_helpers::new_db();
+	let rebuilder = ShieldedRebuilder::new(new_db.key_value().clone());
+	assert!(rebuilder.finalize(H256::from(5)).is_err());
+}
+
+#[test]
+fn restoration_marks_nullifier_set_current() {
+	let block_hash = H256::from(5);
+	let tree = CommitmentTree::<Node>::new();
+	let mut stream = RlpStream::new_list(2);
+	stream.begin_list(1).begin_list(2).append(&block_hash).append(&CommitmentTreeSerialization::new(&tree));
+	stream.begin_list(0);
+
+	let new_db = test_helpers::new_db();
+	let flag = AtomicBool::new(true);
+	let mut rebuilder = ShieldedRebuilder::new(new_db.key_value().clone());
+	rebuilder.feed(&stream.out(), &flag).unwrap();
+	rebuilder.finalize(block_hash).unwrap();
+
+	let marker = new_db.key_value().get(db::COL_EXTRA, b"nullifier_set_best").unwrap();
+	assert_eq!(marker.map(|hash| H256::from_slice(&hash)), Some(block_hash));
+}
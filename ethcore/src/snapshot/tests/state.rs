@@ -22,7 +22,7 @@ use hash::{KECCAK_NULL_RLP, keccak};
 
 use types::basic_account::BasicAccount;
 use snapshot::account;
-use snapshot::{chunk_state, Error as SnapshotError, Progress, StateRebuilder, SNAPSHOT_SUBPARTS};
+use snapshot::{chunk_state, Error as SnapshotError, Progress, SnapshotCompression, StateRebuilder, SNAPSHOT_SUBPARTS};
 use snapshot::io::{PackedReader, PackedWriter, SnapshotReader, SnapshotWriter};
 use super::helpers::StateProducer;
 
@@ -55,7 +55,7 @@ fn snap_and_restore() {
 
 	let mut state_hashes = Vec::new();
 	for part in 0..SNAPSHOT_SUBPARTS {
-		let mut hashes = chunk_state(&old_db, &state_root, &writer, &Progress::default(), Some(part)).unwrap();
+		let mut hashes = chunk_state(&old_db, &state_root, &writer, &Progress::default(), Some(part), SnapshotCompression::Snappy).unwrap();
 		state_hashes.append(&mut hashes);
 	}
 
@@ -66,6 +66,8 @@ fn snap_and_restore() {
 		state_root: state_root,
 		block_number: 1000,
 		block_hash: H256::default(),
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	}).unwrap();
 
 	let db_path = tempdir.path().join("db");
@@ -171,7 +173,7 @@ fn checks_flag() {
 	let state_root = producer.state_root();
 	let writer = Mutex::new(PackedWriter::new(&snap_file).unwrap());
 
-	let state_hashes = chunk_state(&old_db, &state_root, &writer, &Progress::default(), None).unwrap();
+	let state_hashes = chunk_state(&old_db, &state_root, &writer, &Progress::default(), None, SnapshotCompression::Snappy).unwrap();
 
 	writer.into_inner().finish(::snapshot::ManifestData {
 		version: 2,
@@ -180,6 +182,8 @@ fn checks_flag() {
 		state_root: state_root,
 		block_number: 0,
 		block_hash: H256::default(),
+		shielded_hashes: Vec::new(),
+		compression: Default::default(),
 	}).unwrap();
 
 	let tempdir = TempDir::new("").unwrap();
@@ -52,6 +52,11 @@ use state::{Backend, State, Substate};
 use trace::{NoopTracer, NoopVMTracer};
 
 const MAX_TRANSACTION_SIZE: usize = 300 * 1024;
+/// Default maximum number of shielded spends and outputs verified per block.
+const MAX_SHIELDED_VERIFY_OPS_PER_BLOCK: u64 = 2000;
+/// Default anchor window, about a day and a half of blocks: long enough for a wallet to
+/// build its proofs, short enough to bound the roots the nodes have to keep.
+const ANCHOR_WINDOW: usize = 10_000;
 
 // helper for formatting errors.
 fn fmt_err<F: ::std::fmt::Display>(f: F) -> String {
@@ -151,11 +156,59 @@ pub struct CommonParams {
 	pub transaction_permission_contract_transition: BlockNumber,
 	/// Maximum size of transaction's RLP payload
 	pub max_transaction_size: usize,
+	/// Maximum number of shielded descriptions (spends plus outputs) a block may
+	/// require to verify.
+	pub max_shielded_verify_ops_per_block: u64,
+	/// Number of first block whose shielded descriptions are bounded by
+	/// `max_shielded_verify_ops_per_block`.
+	pub max_shielded_verify_ops_transition: BlockNumber,
+	/// Number of most recent blocks whose commitment tree roots spends may use as anchor.
+	pub anchor_window: usize,
+	/// Block at which the shielded pool is activated, if any.
+	pub sapling_activation: Option<BlockNumber>,
+	/// Number of first block where the shielded proofs and anchors of the block are verified.
+	pub shielded_block_verification_transition: BlockNumber,
+	/// Number of first block whose transactions must encode their shielded points canonically.
+	pub shielded_canonical_encoding_transition: BlockNumber,
+	/// Identifier (`verifying_key_id`) of the Sapling spend circuit verifying key.
+	pub sapling_spend_verifying_key: Option<H256>,
+	/// Identifier (`verifying_key_id`) of the Sapling output circuit verifying key.
+	pub sapling_output_verifying_key: Option<H256>,
 	/// Reward configure
 	pub reward_config: RewardConfig,
 }
 
+/// Parameters of the shielded pool of a chain, gathered from the spec params.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ShieldedParams {
+	/// Block at which the shielded pool is activated.
+	pub activation: BlockNumber,
+	/// Number of most recent blocks whose commitment tree roots spends may use as anchor.
+	pub anchor_window: usize,
+	/// Identifier (`verifying_key_id`) of the Sapling spend circuit verifying key.
+	pub spend_verifying_key: H256,
+	/// Identifier (`verifying_key_id`) of the Sapling output circuit verifying key.
+	pub output_verifying_key: H256,
+}
+
 impl CommonParams {
+	/// Parameters of the shielded pool, or `None` if the chain has no shielded pool,
+	/// that is unless its activation block and both verifying keys are set.
+	pub fn shielded_params(&self) -> Option<ShieldedParams> {
+		Some(ShieldedParams {
+			activation: self.sapling_activation?,
+			anchor_window: self.anchor_window,
+			spend_verifying_key: self.sapling_spend_verifying_key?,
+			output_verifying_key: self.sapling_output_verifying_key?,
+		})
+	}
+
+	/// Whether the commitment tree root of block `anchor_number` may be used as anchor
+	/// by a spend included on top of block `best_number`.
+	pub fn is_anchor_in_window(&self, anchor_number: u64, best_number: u64) -> bool {
+		anchor_number <= best_number && best_number - anchor_number <= self.anchor_window as u64
+	}
+
 	/// Schedule for an EVM in the post-EIP-150-era of the Ethereum main net.
 	pub fn schedule(&self, block_number: u64) -> ::vm::Schedule {
 		if block_number < self.eip150_transition {
@@ -329,6 +382,24 @@ impl From<ethjson::spec::Params> for CommonParams {
 			node_permission_contract: p.node_permission_contract.map(Into::into),
 			max_code_size: p.max_code_size.map_or(u64::max_value(), Into::into),
 			max_transaction_size: p.max_transaction_size.map_or(MAX_TRANSACTION_SIZE, Into::into),
+			max_shielded_verify_ops_per_block: p.max_shielded_verify_ops_per_block
+				.map_or(MAX_SHIELDED_VERIFY_OPS_PER_BLOCK, Into::into),
+			max_shielded_verify_ops_transition: p.max_shielded_verify_ops_transition.map_or_else(
+				BlockNumber::max_value,
+				Into::into
+			),
+			anchor_window: p.anchor_window.map_or(ANCHOR_WINDOW, Into::into),
+			sapling_activation: p.sapling_activation.map(Into::into),
+			shielded_block_verification_transition: p.shielded_block_verification_transition.map_or_else(
+				BlockNumber::max_value,
+				Into::into
+			),
+			shielded_canonical_encoding_transition: p.shielded_canonical_encoding_transition.map_or_else(
+				BlockNumber::max_value,
+				Into::into
+			),
+			sapling_spend_verifying_key: p.sapling_spend_verifying_key.map(Into::into),
+			sapling_output_verifying_key: p.sapling_output_verifying_key.map(Into::into),
 			max_code_size_transition: p.max_code_size_transition.map_or(0, Into::into),
 			transaction_permission_contract: p.transaction_permission_contract.map(Into::into),
 			transaction_permission_contract_transition:
@@ -718,6 +789,11 @@ impl Spec {
 		&self.engine.params()
 	}
 
+	/// Get the parameters of the shielded pool, or `None` if the chain has none.
+	pub fn shielded_params(&self) -> Option<ShieldedParams> {
+		self.params().shielded_params()
+	}
+
 	/// Get the known knodes of the network in enode format.
 	pub fn nodes(&self) -> &[String] {
 		&self.nodes
@@ -914,6 +990,13 @@ impl Spec {
 		load_bundled!("instant_seal")
 	}
 
+	/// Create a new Spec with InstantSeal consensus and the shielded pool active from genesis,
+	/// with a premine of address whose secret is keccak('').
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub fn new_test_confidential() -> Spec {
+		load_bundled!("instant_seal_confidential")
+	}
+
 	/// Create a new Spec which conforms to the Frontier-era Morden chain except that it's a
 	/// NullEngine consensus.
 	#[cfg(any(test, feature = "test-helpers"))]
@@ -921,6 +1004,12 @@ impl Spec {
 		load_bundled!("null_morden")
 	}
 
+	/// Create a new Spec like `new_test` which allows five shielded spends and outputs in a block.
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub fn new_test_shielded_ops_limit() -> Spec {
+		load_bundled!("null_morden_shielded_ops")
+	}
+
 	/// Create the EthereumMachine corresponding to Spec::new_test.
 	#[cfg(any(test, feature = "test-helpers"))]
 	pub fn new_test_machine() -> EthereumMachine { load_machine_bundled!("null_morden") }
@@ -1012,6 +1101,37 @@ mod tests {
 		assert!(Spec::load(&tempdir.path(), &[] as &[u8]).is_err());
 	}
 
+	#[test]
+	fn anchor_window_boundary() {
+		let mut params = Spec::new_test().params().clone();
+		params.anchor_window = 100;
+
+		assert!(params.is_anchor_in_window(1000, 1000));
+		assert!(params.is_anchor_in_window(900, 1000));
+		assert!(!params.is_anchor_in_window(899, 1000));
+		assert!(!params.is_anchor_in_window(1001, 1000));
+	}
+
+	#[test]
+	fn shielded_params_require_activation_and_keys() {
+		let spec = Spec::new_test();
+		assert_eq!(spec.shielded_params(), None);
+
+		let mut params = spec.params().clone();
+		params.anchor_window = 100;
+		params.sapling_activation = Some(10);
+		params.sapling_spend_verifying_key = Some(H256::from(1));
+		assert_eq!(params.shielded_params(), None);
+
+		params.sapling_output_verifying_key = Some(H256::from(2));
+		assert_eq!(params.shielded_params(), Some(ShieldedParams {
+			activation: 10,
+			anchor_window: 100,
+			spend_verifying_key: H256::from(1),
+			output_verifying_key: H256::from(2),
+		}));
+	}
+
 	#[test]
 	fn test_chain() {
 		let test_spec = Spec::new_test();
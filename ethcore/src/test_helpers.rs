@@ -16,7 +16,8 @@
 
 //! Set of different helpers for client tests
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{fs, io};
 
@@ -30,7 +31,7 @@ use hash::keccak;
 use io::IoChannel;
 use kvdb::KeyValueDB;
 use kvdb_rocksdb::{self, Database, DatabaseConfig};
-use parking_lot::{RwLock};
+use parking_lot::{Mutex, RwLock};
 use rlp::{self, RlpStream};
 use tempdir::TempDir;
 use types::transaction::{Action, Transaction, SignedTransaction};
@@ -370,6 +371,40 @@ pub fn restoration_db_handler(config: kvdb_rocksdb::DatabaseConfig) -> Box<Block
 	Box::new(RestorationDBHandler { config })
 }
 
+/// Creates a new `BlockChainDBHandler` which keeps the key-value databases in memory.
+///
+/// Opening the same path twice returns the same database. Bloom databases have no
+/// in-memory backend, so they are kept in temporary directories owned by the database.
+pub fn memory_db_handler() -> Box<BlockChainDBHandler> {
+	#[derive(Default)]
+	struct MemoryDBHandler {
+		dbs: Mutex<HashMap<PathBuf, Arc<BlockChainDB>>>,
+	}
+
+	impl BlockChainDBHandler for MemoryDBHandler {
+		fn open(&self, db_path: &Path) -> io::Result<Arc<BlockChainDB>> {
+			let mut dbs = self.dbs.lock();
+			if let Some(db) = dbs.get(db_path) {
+				return Ok(db.clone());
+			}
+
+			let blooms_dir = TempDir::new("")?;
+			let trace_blooms_dir = TempDir::new("")?;
+			let db: Arc<BlockChainDB> = Arc::new(TestBlockChainDB {
+				blooms: blooms_db::Database::open(blooms_dir.path())?,
+				trace_blooms: blooms_db::Database::open(trace_blooms_dir.path())?,
+				_blooms_dir: blooms_dir,
+				_trace_blooms_dir: trace_blooms_dir,
+				key_value: Arc::new(::kvdb_memorydb::create(::db::NUM_COLUMNS.unwrap())),
+			});
+			dbs.insert(db_path.to_owned(), db.clone());
+			Ok(db)
+		}
+	}
+
+	Box::new(MemoryDBHandler::default())
+}
+
 /// Generates dummy blockchain with corresponding amount of blocks
 pub fn generate_dummy_blockchain(block_number: u32) -> BlockChain {
 	let db = new_db();
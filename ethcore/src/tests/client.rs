@@ -19,6 +19,8 @@ use std::sync::Arc;
 
 use ethereum_types::{U256, Address};
 use ethkey::KeyPair;
+use db::Writable;
+use db::keys::CommitmentTreeSerialization;
 use hash::keccak;
 use io::IoChannel;
 use tempdir::TempDir;
@@ -193,6 +195,63 @@ fn imports_block_sequence() {
 	assert!(!block.into_inner().is_empty());
 }
 
+#[test]
+fn cancelled_import_stops_at_a_block_boundary() {
+	let client = get_test_client_with_blocks(vec![]);
+	client.cancel_import();
+	for block in get_good_dummy_block_seq(4) {
+		client.import_block(Unverified::from_rlp(block).unwrap()).unwrap();
+	}
+	client.flush_queue();
+
+	assert_eq!(client.import_verified_blocks(), 0);
+	assert_eq!(client.chain_info().best_block_number, 0);
+	assert_eq!(client.queue_info().verified_queue_size, 5);
+
+	client.resume_import();
+	client.flush_queue();
+	assert_eq!(client.chain_info().best_block_number, 5);
+	assert!(client.queue_info().is_empty());
+}
+
+#[test]
+fn flags_a_commitment_tree_behind_the_best_block() {
+	let db = test_helpers::new_db();
+	let spec = Spec::new_test();
+	let config = ClientConfig { max_commitment_tree_lag: 1, ..Default::default() };
+	let new_client = |db| Client::new(
+		config.clone(),
+		&spec,
+		db,
+		Arc::new(Miner::new_for_tests(&spec, None)),
+		IoChannel::disconnected(),
+		None,
+	).unwrap();
+
+	let client = new_client(db.clone());
+	for block in get_good_dummy_block_seq(4) {
+		client.import_block(Unverified::from_rlp(block).unwrap()).unwrap();
+	}
+	client.flush_queue();
+	client.tick(true);
+	assert!(client.health().is_healthy());
+
+	// drop the trees of the two best blocks, as if the tree stopped following the chain.
+	let best = client.chain_info().best_block_number;
+	let mut batch = db.key_value().transaction();
+	for number in best - 1..=best {
+		let hash = client.block_hash(BlockId::Number(number)).unwrap();
+		Writable::delete::<CommitmentTreeSerialization, _>(&mut batch, ::db::COL_EXTRA, &hash);
+	}
+	db.key_value().write(batch).unwrap();
+	drop(client);
+
+	let client = new_client(db);
+	client.tick(true);
+	assert!(client.health().commitment_tree_stale());
+	assert!(!client.health().is_healthy());
+}
+
 #[test]
 fn can_collect_garbage() {
 	let client = generate_dummy_client(100);
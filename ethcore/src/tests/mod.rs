@@ -19,3 +19,4 @@ mod blockchain;
 mod evm;
 mod trace;
 mod reward;
+mod shielded;
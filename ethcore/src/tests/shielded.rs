@@ -0,0 +1,245 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use block::OpenBlock;
+use blockchain::{genesis_shielded_anchor, BlockProvider};
+use blockchain::wallet::transaction_builder::TransactionBuilder;
+use blockchain::wallet::wallet_types::{SaplingExtendedFullViewingKey, SaplingExtendedSpendingKey};
+use ethereum_types::{H256, U256};
+use ethkey::KeyPair;
+use ff::PrimeField;
+use hash::keccak;
+use types::ids::BlockId;
+use types::transaction::{PendingTransaction, SyncTransaction, CONVERSION_FACTOR};
+use client::{BlockChainClient, BlockInfo, ChainInfo, ImportBlock};
+use miner::MinerService;
+use spec::Spec;
+use test_helpers::{generate_dummy_client_with_data, generate_dummy_client_with_spec, get_temp_state_db};
+use verification::queue::kind::blocks::Unverified;
+use zcash_primitives::sapling::Node;
+use zcash_primitives::transaction::components::GROTH_PROOF_SIZE;
+
+#[test]
+fn mines_and_verifies_shielded_transaction() {
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let chain_id = client.signing_chain_id();
+	let genesis_hash = client.chain_info().best_block_hash;
+	let genesis_tree = client.chain().block_commitment_ser(&genesis_hash).unwrap().get_commitment_tree();
+
+	// shield public funds of the premined account to a fresh payment address.
+	let key = KeyPair::from_secret(keccak("").into()).unwrap();
+	let extsk = SaplingExtendedSpendingKey::master(&[]);
+	let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+	let to = extfvk.default_address().unwrap().1;
+	let value = U256::from(200) * CONVERSION_FACTOR;
+
+	let mut builder = TransactionBuilder::new(0.into(), 0.into(), 100_000.into(), vec![], chain_id.unwrap());
+	builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "shielded").unwrap();
+	builder.set_public_input(value);
+	let tx = builder.build().unwrap().sign(key.secret(), chain_id);
+
+	let pending = SyncTransaction::InPool(PendingTransaction::new(tx.clone(), None));
+	assert_eq!(pending.v_shielded_output().len(), 1);
+	let pending = match pending {
+		SyncTransaction::InPool(pending) => pending,
+		SyncTransaction::InBlock(_) => unreachable!(),
+	};
+
+	// the instant seal engine mines the transaction as soon as it is in the queue.
+	client.miner().import_own_transaction(&*client, pending).unwrap();
+	client.miner().update_sealing(&*client);
+	client.flush_queue();
+	client.import_verified_blocks();
+
+	let info = client.chain_info();
+	assert_eq!(info.best_block_number, 1);
+	let block = client.block(BlockId::Number(1)).unwrap();
+	assert_eq!(block.transaction_hashes(), vec![tx.hash()]);
+
+	let chain = client.chain();
+	let tree = chain.block_commitment_ser(&info.best_block_hash).unwrap().get_commitment_tree();
+	assert_eq!(tree.size(), genesis_tree.size() + 1);
+	assert_ne!(tree.root(), genesis_tree.root());
+	assert_eq!(chain.commitment_root_block(&tree.root()), Some(info.best_block_hash));
+
+	let outputs = client.shielded_outputs_of(&tx.hash()).unwrap();
+	assert_eq!(outputs.len(), 1);
+	assert_eq!(outputs[0].cmu, tx.v_shielded_output()[0].cmu);
+}
+
+#[test]
+fn fresh_node_anchor_at_genesis_is_the_empty_root() {
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let genesis_hash = client.chain_info().genesis_hash;
+	let anchor = genesis_shielded_anchor();
+
+	assert_eq!(client.commitment_tree(BlockId::Number(0)).unwrap().root(), anchor);
+	assert_eq!(client.commitment_root_block(&anchor), Some(genesis_hash));
+	assert_eq!(client.valid_anchors(), vec![(0, anchor)]);
+}
+
+#[test]
+fn shielded_outputs_of_public_and_unknown_transactions() {
+	let client = generate_dummy_client_with_data(1, 1, &[1.into()]);
+	let block = client.block(BlockId::Number(1)).unwrap();
+	let public_tx = block.transaction_hashes()[0];
+
+	assert_eq!(client.shielded_outputs_of(&public_tx).map(|outputs| outputs.len()), Some(0));
+	assert!(client.shielded_outputs_of(&H256::from(1)).is_none());
+}
+
+#[test]
+fn mined_block_anchor_matches_template_anchor() {
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let chain_id = client.signing_chain_id();
+
+	let key = KeyPair::from_secret(keccak("").into()).unwrap();
+	let extsk = SaplingExtendedSpendingKey::master(&[]);
+	let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+	let to = extfvk.default_address().unwrap().1;
+	let value = U256::from(100) * CONVERSION_FACTOR;
+
+	let mut builder = TransactionBuilder::new(0.into(), 0.into(), 100_000.into(), vec![], chain_id.unwrap());
+	builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "first").unwrap();
+	builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "second").unwrap();
+	builder.set_public_input(value * 2);
+	let tx = builder.build().unwrap().sign(key.secret(), chain_id);
+
+	let outputs: Vec<_> = tx.get_commitment_notes().iter().map(|cmu| Node::new(cmu.into_repr())).collect();
+	let anchor = client.miner().final_anchor_for_template(&*client, &outputs).unwrap();
+	let genesis_tree = client.commitment_tree(BlockId::Latest).unwrap();
+	assert_ne!(anchor, genesis_tree.root());
+
+	client.miner().import_own_transaction(&*client, PendingTransaction::new(tx, None)).unwrap();
+	client.miner().update_sealing(&*client);
+	client.flush_queue();
+	client.import_verified_blocks();
+
+	// import recomputed the tree from the parent and the outputs of the block.
+	let info = client.chain_info();
+	assert_eq!(info.best_block_number, 1);
+	assert_eq!(client.commitment_tree(BlockId::Latest).unwrap().root(), anchor);
+	assert_eq!(client.chain().commitment_root_block(&anchor), Some(info.best_block_hash));
+}
+
+#[test]
+fn imports_block_with_valid_shielded_transaction() {
+	let miner = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let chain_id = miner.signing_chain_id();
+
+	let key = KeyPair::from_secret(keccak("").into()).unwrap();
+	let extsk = SaplingExtendedSpendingKey::master(&[]);
+	let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+	let to = extfvk.default_address().unwrap().1;
+	let value = U256::from(100) * CONVERSION_FACTOR;
+
+	let mut builder = TransactionBuilder::new(0.into(), 0.into(), 100_000.into(), vec![], chain_id.unwrap());
+	builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "imported").unwrap();
+	builder.set_public_input(value);
+	let tx = builder.build().unwrap().sign(key.secret(), chain_id);
+
+	miner.miner().import_own_transaction(&*miner, PendingTransaction::new(tx, None)).unwrap();
+	miner.miner().update_sealing(&*miner);
+	miner.flush_queue();
+	miner.import_verified_blocks();
+	let block = miner.block(BlockId::Number(1)).unwrap().into_inner();
+
+	// a second node verifies the proofs of the block on import.
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	client.import_block(Unverified::from_rlp(block).unwrap()).unwrap();
+	client.flush_queue();
+	client.import_verified_blocks();
+
+	assert_eq!(client.chain_info().best_block_number, 1);
+	assert!(client.bad_blocks().is_empty());
+}
+
+// A block from the premined account with one shielded output whose proof is garbage.
+fn block_with_forged_output_proof(spec: &Spec, chain_id: Option<u64>) -> Vec<u8> {
+	let engine = &*spec.engine;
+	let key = KeyPair::from_secret(keccak("").into()).unwrap();
+	let extsk = SaplingExtendedSpendingKey::master(&[]);
+	let extfvk = SaplingExtendedFullViewingKey::from(&extsk);
+	let to = extfvk.default_address().unwrap().1;
+	let value = U256::from(100) * CONVERSION_FACTOR;
+
+	let mut builder = TransactionBuilder::new(0.into(), 0.into(), 100_000.into(), vec![], chain_id.unwrap());
+	builder.add_sapling_output(extfvk.fvk.ovk, to, &value, "forged").unwrap();
+	builder.set_public_input(value);
+	let mut unsigned = builder.build().unwrap();
+	unsigned.private.as_mut().unwrap().outputs[0].zkproof = [0u8; GROTH_PROOF_SIZE];
+	let tx = unsigned.sign(key.secret(), chain_id);
+
+	// the proof is not looked at when the block is built, only when it is imported.
+	let genesis_header = spec.genesis_header();
+	let gas_limit = *genesis_header.gas_limit();
+	let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+	let mut open_block = OpenBlock::new(
+		engine,
+		Default::default(),
+		false,
+		db,
+		&genesis_header,
+		Arc::new(vec![genesis_header.hash()]),
+		key.address(),
+		(gas_limit, gas_limit),
+		vec![],
+		false,
+		None,
+	).unwrap();
+	open_block.set_timestamp(genesis_header.timestamp() + 10);
+	open_block.push_transaction(tx, None).unwrap();
+	open_block.close_and_lock().unwrap().seal(engine, vec![]).unwrap().rlp_bytes()
+}
+
+#[test]
+fn rejects_block_with_invalid_shielded_proof() {
+	let spec = Spec::new_test_confidential();
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let block = block_with_forged_output_proof(&spec, client.signing_chain_id());
+
+	client.import_block(Unverified::from_rlp(block).unwrap()).unwrap();
+	client.flush_queue();
+	client.import_verified_blocks();
+
+	assert_eq!(client.chain_info().best_block_number, 0);
+	let bad_blocks = client.bad_blocks();
+	assert_eq!(bad_blocks.len(), 1);
+	assert!(bad_blocks[0].1.contains("InvalidShielded"));
+}
+
+#[test]
+fn trusted_import_skips_proofs_and_advances_shielded_state() {
+	let spec = Spec::new_test_confidential();
+	let client = generate_dummy_client_with_spec(Spec::new_test_confidential);
+	let block = block_with_forged_output_proof(&spec, client.signing_chain_id());
+	let genesis_tree = client.commitment_tree(BlockId::Latest).unwrap();
+
+	// the block would be rejected from the network, see above, but not from a trusted source.
+	client.import_trusted_block(Unverified::from_rlp(block).unwrap()).unwrap();
+	client.flush_queue();
+	client.import_verified_blocks();
+
+	let info = client.chain_info();
+	assert_eq!(info.best_block_number, 1);
+	assert!(client.bad_blocks().is_empty());
+	let tree = client.commitment_tree(BlockId::Latest).unwrap();
+	assert_eq!(tree.size(), genesis_tree.size() + 1);
+	assert_eq!(client.chain().commitment_root_block(&tree.root()), Some(info.best_block_hash));
+	assert!(client.nullifier_set_is_current());
+}
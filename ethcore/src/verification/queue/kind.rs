@@ -125,9 +125,24 @@ pub mod blocks {
 		pub uncles: Vec<Header>,
 		/// Raw block bytes.
 		pub bytes: Bytes,
+		/// Whether the block comes from a trusted local source, see
+		/// `Client::import_trusted_block`. Only this crate can set it, so that a
+		/// block received from the network is never trusted.
+		pub(crate) trusted: bool,
 	}
 
 	impl Unverified {
+		/// Create an untrusted `Unverified` from its decoded parts and raw bytes.
+		pub fn new(header: Header, transactions: Vec<UnverifiedTransaction>, uncles: Vec<Header>, bytes: Bytes) -> Self {
+			Unverified {
+				header,
+				transactions,
+				uncles,
+				bytes,
+				trusted: false,
+			}
+		}
+
 		/// Create an `Unverified` from raw bytes.
 		pub fn from_rlp(bytes: Bytes) -> Result<Self, ::rlp::DecoderError> {
 			use rlp::Rlp;
@@ -139,12 +154,7 @@ pub mod blocks {
 				(header, transactions, uncles)
 			};
 
-			Ok(Unverified {
-				header,
-				transactions,
-				uncles,
-				bytes,
-			})
+			Ok(Unverified::new(header, transactions, uncles, bytes))
 		}
 
 		pub fn rlp(&self)->Bytes{
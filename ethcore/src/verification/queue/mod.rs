@@ -17,6 +17,8 @@
 //! A queue of blocks. Sits between network or other I/O and the `BlockChain`.
 //! Sorts them ready for blockchain insertion.
 
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread::{self, JoinHandle};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
@@ -57,6 +59,17 @@ pub struct Config {
 	pub max_mem_use: usize,
 	/// Settings for the number of verifiers and adaptation strategy.
 	pub verifier_settings: VerifierSettings,
+	/// Reject an item whose verification panics instead of losing its verifier thread,
+	/// which stalls the queue behind the item. Only the second stage, run by the verifier
+	/// threads of the queue, is caught: that is the seal and the transaction signatures.
+	/// The shielded proofs are checked during family verification, on the importing
+	/// thread, which this does not cover.
+	///
+	/// Verification holds no queue lock while it runs, so the queue is left consistent,
+	/// but anything else the panicking code was updating may be left half done. The
+	/// panic hook of the process still runs first: a hook which exits on any panic
+	/// makes this setting moot.
+	pub catch_verification_panics: bool,
 }
 
 impl Default for Config {
@@ -65,6 +78,7 @@ impl Default for Config {
 			max_queue_size: 30000,
 			max_mem_use: 50 * 1024 * 1024,
 			verifier_settings: VerifierSettings::default(),
+			catch_verification_panics: false,
 		}
 	}
 }
@@ -228,6 +242,7 @@ impl<K: Kind> VerificationQueue<K> {
 		});
 		let empty = Arc::new(Condvar::new());
 		let scale_verifiers = config.verifier_settings.scale_verifiers;
+		let catch_panics = config.catch_verification_panics;
 
 		let max_verifiers = ::num_cpus::get();
 		let default_amount = cmp::max(1, cmp::min(max_verifiers, config.verifier_settings.num_verifiers));
@@ -267,6 +282,7 @@ impl<K: Kind> VerificationQueue<K> {
 						empty,
 						state,
 						i,
+						catch_panics,
 					)
 				})
 				.expect("Failed to create verifier thread.");
@@ -299,6 +315,7 @@ impl<K: Kind> VerificationQueue<K> {
 		empty: Arc<Condvar>,
 		state: Arc<(Mutex<State>, Condvar)>,
 		id: usize,
+		catch_panics: bool,
 	) {
 		loop {
 			// check current state.
@@ -359,7 +376,18 @@ impl<K: Kind> VerificationQueue<K> {
 			};
 
 			let hash = item.hash();
-			let is_ready = match K::verify(item, &*engine, verification.check_seal) {
+			let result = if catch_panics {
+				let check_seal = verification.check_seal;
+				panic::catch_unwind(AssertUnwindSafe(|| K::verify(item, &*engine, check_seal)))
+					.unwrap_or_else(|panic| {
+						let msg = panic_message(&*panic);
+						warn!(target: "verification", "Verification of {} panicked, rejecting it: {}", hash, msg);
+						Err(BlockError::VerificationPanicked(msg).into())
+					})
+			} else {
+				K::verify(item, &*engine, verification.check_seal)
+			};
+			let is_ready = match result {
 				Ok(verified) => {
 					let mut verifying = verification.verifying.lock();
 					let mut idx = None;
@@ -584,6 +612,28 @@ impl<K: Kind> VerificationQueue<K> {
 		result
 	}
 
+	/// Puts drained items which were not imported back at the front of the queue, in order.
+	/// The queue does not signal them as ready until `signal_verified` is called.
+	pub fn requeue(&self, items: Vec<K::Verified>) {
+		if items.is_empty() {
+			return;
+		}
+		let mut verified = self.verification.verified.lock();
+		let requeued_size = items.iter().map(HeapSizeOf::heap_size_of_children).fold(0, |a, c| a + c);
+		self.verification.sizes.verified.fetch_add(requeued_size, AtomicOrdering::SeqCst);
+		for item in items.into_iter().rev() {
+			verified.push_front(item);
+		}
+	}
+
+	/// Signal the verified items as ready again, even if they were signalled already.
+	pub fn signal_verified(&self) {
+		self.ready_signal.reset();
+		if self.verification.verified.load_len() != 0 {
+			self.ready_signal.set_async();
+		}
+	}
+
 	/// Returns true if there is nothing currently in the queue.
 	pub fn is_empty(&self) -> bool {
 		let v = &self.verification;
@@ -710,6 +760,14 @@ impl<K: Kind> VerificationQueue<K> {
 	}
 }
 
+// the message a panic was raised with, if it is a string.
+fn panic_message(panic: &(Any + Send)) -> String {
+	match panic.downcast_ref::<&'static str>() {
+		Some(msg) => msg.to_string(),
+		None => panic.downcast_ref::<String>().cloned().unwrap_or_else(|| "Box<Any>".into()),
+	}
+}
+
 impl<K: Kind> Drop for VerificationQueue<K> {
 	fn drop(&mut self) {
 		trace!(target: "shutdown", "[VerificationQueue] Closing...");
@@ -838,6 +896,25 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn requeued_blocks_are_drained_first_in_order() {
+		let queue = get_test_queue(false);
+		let blocks = get_good_dummy_block_seq(3);
+		let hashes: Vec<_> = blocks.iter().map(|b| view!(BlockView, b).header().hash()).collect();
+		for block in blocks {
+			queue.import(new_unverified(block)).expect("error importing block that is valid by definition");
+		}
+		queue.flush();
+
+		let mut drained = queue.drain(3);
+		let rest = drained.split_off(1);
+		queue.requeue(rest);
+		assert_eq!(queue.queue_info().verified_queue_size, 3);
+
+		let drained: Vec<_> = queue.drain(10).into_iter().map(|b| b.header.hash()).collect();
+		assert_eq!(drained, hashes[1..].to_vec());
+	}
+
 	#[test]
 	fn returns_empty_once_finished() {
 		let queue = get_test_queue(false);
@@ -864,6 +941,50 @@ mod tests {
 		assert!(queue.queue_info().is_full());
 	}
 
+	#[test]
+	fn rejects_blocks_whose_verification_panics() {
+		use engines::EthEngine;
+		use verification::PreverifiedBlock;
+		use super::VerificationQueue;
+		use super::kind::{BlockLike, Blocks, Kind};
+
+		// verifies blocks as usual but panics on the second one.
+		struct PanickingBlocks;
+
+		impl Kind for PanickingBlocks {
+			type Input = Unverified;
+			type Unverified = Unverified;
+			type Verified = PreverifiedBlock;
+
+			fn create(input: Unverified, engine: &EthEngine, check_seal: bool) -> Result<Unverified, (Unverified, Error)> {
+				Blocks::create(input, engine, check_seal)
+			}
+
+			fn verify(un: Unverified, engine: &EthEngine, check_seal: bool) -> Result<PreverifiedBlock, Error> {
+				if un.header.number() == 2 {
+					panic!("pairing check failed");
+				}
+				Blocks::verify(un, engine, check_seal)
+			}
+		}
+
+		let spec = Spec::new_test();
+		let mut config = get_test_config(1, false);
+		config.catch_verification_panics = true;
+		let queue = VerificationQueue::<PanickingBlocks>::new(config, spec.engine, IoChannel::disconnected(), true);
+
+		let blocks = get_good_dummy_block_seq(2);
+		let hashes: Vec<_> = blocks.iter().map(|b| view!(BlockView, b).header().hash()).collect();
+		for block in blocks {
+			queue.import(new_unverified(block)).expect("error importing block that is valid by definition");
+		}
+		queue.flush();
+
+		assert!(queue.verification.bad.lock().contains(&hashes[1]));
+		let verified: Vec<_> = queue.drain(10).iter().map(|b| b.hash()).collect();
+		assert_eq!(verified, vec![hashes[0], hashes[2]]);
+	}
+
 	#[test]
 	fn scaling_limits() {
 		let max_verifiers = ::num_cpus::get();
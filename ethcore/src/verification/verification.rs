@@ -37,9 +37,11 @@ use client::BlockInfo;
 use db::keys::CommitmentTreeSerialization;
 use engines::{EthEngine, MAX_UNCLE_AGE};
 use error::{BlockError, Error};
-use types::transaction::SignedTransaction;
+use types::transaction::{SignedTransaction, UnverifiedTransaction};
 use types::{header::Header, BlockNumber};
 use verification::queue::kind::blocks::Unverified;
+use zcash_primitives::sapling::Node;
+use zcash_proofs::sapling::ShieldedError;
 
 #[cfg(not(time_checked_add))]
 use time_utils::CheckedSystemTime;
@@ -54,6 +56,9 @@ pub struct PreverifiedBlock {
 	pub uncles: Vec<Header>,
 	/// Block bytes
 	pub bytes: Bytes,
+	/// Whether the block comes from a trusted local source, whose shielded proofs
+	/// are not verified again.
+	pub(crate) trusted: bool,
 }
 
 impl HeapSizeOf for PreverifiedBlock {
@@ -84,6 +89,13 @@ pub fn verify_block_basic(
 		}
 	}
 
+	if block.header.number() >= engine.params().max_shielded_verify_ops_transition {
+		verify_shielded_ops_limit(
+			&block.transactions,
+			engine.params().max_shielded_verify_ops_per_block,
+		)?;
+	}
+
 	for t in &block.transactions {
 		engine.verify_transaction_basic(t, &block.header)?;
 	}
@@ -91,6 +103,50 @@ pub fn verify_block_basic(
 	Ok(())
 }
 
+/// Cost of including a transaction in a block, besides its gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TxWeight {
+	/// Length of the RLP of the transaction.
+	pub bytes: usize,
+	/// Number of shielded spends and outputs whose proofs are verified on import.
+	pub verify_ops: u64,
+}
+
+/// Weight of a transaction: a small private transaction can cost far more to verify
+/// than a large public one, so block authoring bounds both.
+pub fn shielded_tx_weight(tx: &UnverifiedTransaction) -> TxWeight {
+	TxWeight {
+		bytes: ::rlp::encode(tx).len(),
+		verify_ops: shielded_verify_ops(tx),
+	}
+}
+
+fn shielded_verify_ops(tx: &UnverifiedTransaction) -> u64 {
+	if tx.is_private() {
+		(tx.v_shielded_spend().len() + tx.v_shielded_output().len()) as u64
+	} else {
+		0
+	}
+}
+
+/// Bound the proof verification work of a block: the number of shielded spends and
+/// outputs is counted before any of them is verified, so that an over-limit block is
+/// rejected without doing pairing work.
+fn verify_shielded_ops_limit(transactions: &[UnverifiedTransaction], limit: u64) -> Result<(), Error> {
+	let ops = transactions
+		.iter()
+		.map(shielded_verify_ops)
+		.sum::<u64>();
+	if ops > limit {
+		bail!(BlockError::TooManyShieldedDescriptions(OutOfBounds {
+			min: None,
+			max: Some(limit),
+			found: ops,
+		}));
+	}
+	Ok(())
+}
+
 /// Phase 2 verification. Perform costly checks such as transaction signatures and block nonce for ethash.
 /// Still operates on a individual block
 /// Returns a `PreverifiedBlock` structure populated with transactions
@@ -106,6 +162,9 @@ pub fn verify_block_unordered(
 			engine.verify_block_unordered(uncle)?;
 		}
 	}
+	if header.number() >= engine.params().shielded_canonical_encoding_transition {
+		verify_shielded_encoding(&block.bytes)?;
+	}
 	// Verify transactions.
 	let nonce_cap = if header.number() >= engine.params().dust_protection_transition {
 		Some((engine.params().nonce_cap_increment * header.number()).into())
@@ -132,9 +191,23 @@ pub fn verify_block_unordered(
 		transactions,
 		uncles: block.uncles,
 		bytes: block.bytes,
+		trusted: block.trusted,
 	})
 }
 
+// Check that the transactions of the block encode their shielded points canonically. The
+// descriptions are decoded leniently, so this looks at the raw bytes of the block.
+fn verify_shielded_encoding(block: &[u8]) -> Result<(), Error> {
+	let canonical = Rlp::new(block).at(1).map_or(false, |transactions| {
+		transactions.iter().all(|tx| UnverifiedTransaction::has_canonical_shielded_points(&tx))
+	});
+	if canonical {
+		Ok(())
+	} else {
+		Err(BlockError::InvalidShielded(ShieldedError::CanonicalEncoding).into())
+	}
+}
+
 /// Parameters for full verification of block family
 pub struct FullFamilyParams<'a, C: BlockInfo + CallContract + 'a> {
 	/// Preverified block
@@ -145,6 +218,20 @@ pub struct FullFamilyParams<'a, C: BlockInfo + CallContract + 'a> {
 
 	/// Engine client to use during verification
 	pub client: &'a C,
+
+	/// Verifier of the shielded transactions of the block, if they are to be verified
+	pub shielded_verifier: Option<&'a ShieldedBlockVerifier>,
+}
+
+/// Verifies the shielded proofs and signatures of the transactions of a block.
+pub trait ShieldedBlockVerifier {
+	/// Verify the shielded components of `transactions`, the spends of which must use
+	/// anchors passing `anchor_valid`.
+	fn verify_block_shielded(
+		&self,
+		transactions: &[SignedTransaction],
+		anchor_valid: &Fn(&Node) -> bool,
+	) -> Result<(), ShieldedError>;
 }
 
 /// Phase 3 verification. Check block information against parent and uncles.
@@ -173,6 +260,18 @@ pub fn verify_block_family<C: BlockInfo + CallContract>(
 			.verify_transaction(tx, parent, params.client)?;
 	}
 
+	if let Some(shielded_verifier) = params.shielded_verifier {
+		if header.number() >= engine.params().shielded_block_verification_transition {
+			let block_provider = params.block_provider;
+			// spends may use the roots of the blocks in the anchor window of the parent.
+			let anchor_valid = |root: &Node| block_provider.commitment_root_block(root)
+				.and_then(|hash| block_provider.block_number(&hash))
+				.map_or(false, |number| engine.params().is_anchor_in_window(number, parent.number()));
+			shielded_verifier.verify_block_shielded(&params.block.transactions, &anchor_valid)
+				.map_err(BlockError::InvalidShielded)?;
+		}
+	}
+
 	Ok(())
 }
 
@@ -674,6 +773,29 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn rejects_too_many_shielded_descriptions() {
+		// Two spends and two outputs. Its proofs are garbage, so the limit must be
+		// enforced before any of them is looked at.
+		let private: UnverifiedTransaction =
+			Transaction::create_private(vec![0, 1], U256::zero(), Action::PrivateToPrivate)
+				.sign_for_private(2)
+				.into();
+		let public: UnverifiedTransaction = Transaction::default().null_sign(2).into();
+		let transactions = vec![private, public];
+
+		assert_eq!(shielded_tx_weight(&transactions[0]).verify_ops, 4);
+		assert_eq!(shielded_tx_weight(&transactions[1]).verify_ops, 0);
+		assert_eq!(shielded_tx_weight(&transactions[1]).bytes, ::rlp::encode(&transactions[1]).len());
+		assert!(verify_shielded_ops_limit(&transactions, 4).is_ok());
+		match verify_shielded_ops_limit(&transactions, 3) {
+			Err(Error(ErrorKind::Block(TooManyShieldedDescriptions(oob)), _)) => {
+				assert_eq!(oob, OutOfBounds { min: None, max: Some(3), found: 4 });
+			}
+			other => panic!("unexpected result: {:?}", other.map(|_| ())),
+		}
+	}
+
 	fn basic_test(bytes: &[u8], engine: &EthEngine) -> Result<(), Error> {
 		let unverified = Unverified::from_rlp(bytes.to_vec())?;
 		verify_block_basic(&unverified, engine, true)
@@ -706,12 +828,14 @@ mod tests {
 			transactions,
 			uncles: block.uncles,
 			bytes: bytes.to_vec(),
+			trusted: false,
 		};
 
 		let full_params = FullFamilyParams {
 			block: &block,
 			block_provider: bc as &BlockProvider,
 			client: &client,
+			shielded_verifier: None,
 		};
 		verify_block_family(&block.header, &parent, engine, Some(full_params))
 	}
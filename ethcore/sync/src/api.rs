@@ -116,6 +116,8 @@ pub struct SyncConfig {
 	pub warp_sync: WarpSync,
 	/// Enable light client server.
 	pub serve_light: bool,
+	/// Max transactions relayed to a single peer per second, unlimited if `None`.
+	pub max_peer_transactions_per_sec: Option<usize>,
 }
 
 impl Default for SyncConfig {
@@ -129,6 +131,7 @@ impl Default for SyncConfig {
 			fork_block: None,
 			warp_sync: WarpSync::Disabled,
 			serve_light: false,
+			max_peer_transactions_per_sec: None,
 		}
 	}
 }
@@ -115,12 +115,7 @@ fn unverified_from_sync(header: SyncHeader, body: Option<SyncBody>) -> Unverifie
 	stream.append_raw(&body.transactions_bytes, 1);
 	stream.append_raw(&body.uncles_bytes, 1);
 
-	Unverified {
-		header: header.header,
-		transactions: body.transactions,
-		uncles: body.uncles,
-		bytes: stream.out().to_vec(),
-	}
+	Unverified::new(header.header, body.transactions, body.uncles, stream.out().to_vec())
 }
 
 /// Block with optional receipt
@@ -158,6 +158,8 @@ const MAX_NEW_BLOCK_AGE: BlockNumber = 20;
 // maximal packet size with transactions (cannot be greater than 16MB - protocol limitation).
 // keep it under 8MB as well, cause it seems that it may result oversized after compression.
 const MAX_TRANSACTION_PACKET_SIZE: usize = 5 * 1024 * 1024;
+// Window over which the per-peer transactions relay limit applies.
+const TRANSACTIONS_RELAY_WINDOW: Duration = Duration::from_secs(1);
 // Min number of blocks to be behind for a snapshot sync
 const SNAPSHOT_RESTORE_THRESHOLD: BlockNumber = 30000;
 const SNAPSHOT_MIN_PEERS: usize = 3;
@@ -315,6 +317,8 @@ pub struct PeerInfo {
 	ask_time: Instant,
 	/// Holds a set of transactions recently sent to this peer to avoid spamming.
 	last_sent_transactions: H256FastSet,
+	/// Start of the current relay window and number of transactions sent to this peer in it.
+	relayed_transactions: (Instant, usize),
 	/// Pending request is expired and result should be ignored
 	expired: bool,
 	/// Peer fork confirmation status
@@ -338,6 +342,14 @@ impl PeerInfo {
 		self.confirmation != ForkConfirmation::Unconfirmed && !self.expired
 	}
 
+	/// Number of transactions which may still be relayed to this peer in the current window.
+	fn transactions_allowance(&mut self, limit: usize, now: Instant) -> usize {
+		if now.duration_since(self.relayed_transactions.0) >= TRANSACTIONS_RELAY_WINDOW {
+			self.relayed_transactions = (now, 0);
+		}
+		limit.saturating_sub(self.relayed_transactions.1)
+	}
+
 	fn reset_asking(&mut self) {
 		self.asking_blocks.clear();
 		self.asking_hash = None;
@@ -609,6 +621,8 @@ pub struct ChainSync {
 
 	/// Enable warp sync.
 	warp_sync: WarpSync,
+	/// Max transactions relayed to a single peer per relay window.
+	max_peer_transactions_per_sec: Option<usize>,
 
 	status_sinks: Vec<futures_mpsc::UnboundedSender<SyncState>>
 }
@@ -640,6 +654,7 @@ impl ChainSync {
 			sync_start_time: None,
 			transactions_stats: TransactionsStats::default(),
 			warp_sync: config.warp_sync,
+			max_peer_transactions_per_sec: config.max_peer_transactions_per_sec,
 			status_sinks: Vec::new()
 		};
 		sync.update_targets(chain);
@@ -1436,6 +1451,7 @@ pub mod tests {
 				asking_hash: None,
 				ask_time: Instant::now(),
 				last_sent_transactions: Default::default(),
+				relayed_transactions: (Instant::now(), 0),
 				expired: false,
 				confirmation: super::ForkConfirmation::Confirmed,
 				snapshot_number: None,
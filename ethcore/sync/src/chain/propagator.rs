@@ -16,6 +16,7 @@
 
 use std::cmp;
 use std::collections::HashSet;
+use std::time::Instant;
 
 use bytes::Bytes;
 use ethereum_types::H256;
@@ -165,6 +166,8 @@ impl SyncPropagator {
 		};
 
 		let block_number = io.chain().chain_info().best_block_number;
+		let rate_limit = sync.max_peer_transactions_per_sec;
+		let now = Instant::now();
 		let mut sent_to_peers = HashSet::new();
 		let mut max_sent = 0;
 
@@ -179,14 +182,24 @@ impl SyncPropagator {
 			let peer_info = sync.peers.get_mut(&peer_id)
 				.expect("peer_id is form peers; peers is result of select_peers_for_transactions; select_peers_for_transactions selects peers from self.peers; qed");
 
+			// Transactions over the peer's allowance are left for a later propagation.
+			let allowance = match rate_limit {
+				Some(limit) => peer_info.transactions_allowance(limit, now),
+				None => usize::max_value(),
+			};
+			if allowance == 0 {
+				continue;
+			}
+
 			// Send all transactions, if the peer doesn't know about anything
-			if peer_info.last_sent_transactions.is_empty() {
+			if peer_info.last_sent_transactions.is_empty() && all_transactions_hashes.len() <= allowance {
 				// update stats
 				for hash in &all_transactions_hashes {
 					let id = io.peer_session_info(peer_id).and_then(|info| info.id);
 					stats.propagated(hash, id, block_number);
 				}
 				peer_info.last_sent_transactions = all_transactions_hashes.clone();
+				peer_info.relayed_transactions.1 += all_transactions_hashes.len();
 
 				send_packet(io, peer_id, all_transactions_hashes.len(), all_transactions_rlp.clone());
 				sent_to_peers.insert(peer_id);
@@ -211,6 +224,11 @@ impl SyncPropagator {
 				for tx in &transactions {
 					let hash = tx.hash();
 					if to_send.contains(&hash) {
+						if pushed == allowance {
+							trace!(target: "sync", "{:02} <- Transactions over the relay limit, sending {}/{}.", peer_id, pushed, to_send.len());
+							to_send = transactions.iter().map(|tx| tx.hash()).filter(|h| to_send.contains(h)).take(pushed).collect();
+							break;
+						}
 						let mut transaction = RlpStream::new();
 						tx.rlp_append(&mut transaction);
 						let appended = packet.append_raw_checked(&transaction.drain(), 1, MAX_TRANSACTION_PACKET_SIZE);
@@ -239,6 +257,7 @@ impl SyncPropagator {
 				.chain(&to_send)
 				.cloned()
 				.collect();
+			peer_info.relayed_transactions.1 += to_send.len();
 			send_packet(io, peer_id, to_send.len(), packet.out());
 			sent_to_peers.insert(peer_id);
 			max_sent = cmp::max(max_sent, to_send.len());
@@ -410,6 +429,7 @@ mod tests {
 				asking_hash: None,
 				ask_time: Instant::now(),
 				last_sent_transactions: Default::default(),
+				relayed_transactions: (Instant::now(), 0),
 				expired: false,
 				confirmation: ForkConfirmation::Confirmed,
 				snapshot_number: None,
@@ -534,6 +554,37 @@ mod tests {
 		assert_eq!(0x02, queue.read()[1].packet_id);
 	}
 
+	#[test]
+	fn relays_transactions_within_peer_rate_limit() {
+		let mut client = TestBlockChainClient::new();
+		client.add_blocks(100, EachBlockWith::Uncle);
+		client.insert_transaction_to_queue();
+		client.insert_transaction_to_queue();
+		let mut sync = ChainSync::new(SyncConfig {
+			max_peer_transactions_per_sec: Some(1),
+			..SyncConfig::default()
+		}, &client);
+		insert_dummy_peer(&mut sync, 0, client.block_hash_delta_minus(1));
+		let queue = RwLock::new(VecDeque::new());
+		let ss = TestSnapshotService::new();
+		let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+		// only one transaction fits in the window, resubmitting relays nothing.
+		assert_eq!(1, SyncPropagator::propagate_new_transactions(&mut sync, &mut io, || true));
+		assert_eq!(0, SyncPropagator::propagate_new_transactions(&mut sync, &mut io, || true));
+		assert_eq!(1, io.packets.len());
+		assert_eq!(1, Rlp::new(&io.packets[0].data).item_count().unwrap());
+
+		// the other one is relayed in the next window, and only once.
+		sync.peers.get_mut(&0).unwrap().relayed_transactions.0 -= TRANSACTIONS_RELAY_WINDOW;
+		assert_eq!(1, SyncPropagator::propagate_new_transactions(&mut sync, &mut io, || true));
+		sync.peers.get_mut(&0).unwrap().relayed_transactions.0 -= TRANSACTIONS_RELAY_WINDOW;
+		assert_eq!(0, SyncPropagator::propagate_new_transactions(&mut sync, &mut io, || true));
+		assert_eq!(2, io.packets.len());
+		assert_eq!(1, Rlp::new(&io.packets[1].data).item_count().unwrap());
+		assert_eq!(2, sync.peers[&0].last_sent_transactions.len());
+	}
+
 	#[test]
 	fn should_maintain_transations_propagation_stats() {
 		let mut client = TestBlockChainClient::new();
@@ -0,0 +1,163 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact block format for light wallets.
+//!
+//! A compact block only carries what a wallet needs to trial-decrypt the shielded
+//! outputs of a block and to detect its own spends, following the layout of the
+//! Zcash light wallet protocol: for every private transaction, its nullifiers and,
+//! per output, `(cmu, epk, enc_ciphertext[..COMPACT_NOTE_SIZE])`.
+
+use ethereum_types::H256;
+use ff::{PrimeField, PrimeFieldRepr};
+use zcash_primitives::note_encryption::COMPACT_NOTE_SIZE;
+use zcash_primitives::transaction::components::{OutputDescription, SpendDescription};
+
+use encoded;
+use BlockNumber;
+
+/// A shielded spend, reduced to its nullifier.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct CompactSpend {
+	/// Nullifier revealed by the spend.
+	pub nf: H256,
+}
+
+impl<'a> From<&'a SpendDescription> for CompactSpend {
+	fn from(spend: &'a SpendDescription) -> Self {
+		CompactSpend {
+			nf: H256::from(spend.nullifier),
+		}
+	}
+}
+
+/// A shielded output, reduced to what is needed for trial decryption.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct CompactOutput {
+	/// Note commitment, little-endian.
+	pub cmu: H256,
+	/// Ephemeral public key.
+	pub epk: H256,
+	/// The first `COMPACT_NOTE_SIZE` bytes of the note ciphertext.
+	pub ciphertext: Vec<u8>,
+}
+
+impl<'a> From<&'a OutputDescription> for CompactOutput {
+	fn from(output: &'a OutputDescription) -> Self {
+		let mut cmu = H256::default();
+		output.cmu.into_repr().write_le(&mut cmu[..]).expect("cmu is 32 bytes");
+		let mut epk = H256::default();
+		output.ephemeral_key.write(&mut epk[..]).expect("epk is 32 bytes");
+
+		CompactOutput {
+			cmu,
+			epk,
+			ciphertext: output.enc_ciphertext[..COMPACT_NOTE_SIZE].to_vec(),
+		}
+	}
+}
+
+/// A private transaction within a compact block.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct CompactTx {
+	/// Index of the transaction within the block.
+	pub index: u64,
+	/// Transaction hash.
+	pub hash: H256,
+	/// Shielded spends of the transaction.
+	pub spends: Vec<CompactSpend>,
+	/// Shielded outputs of the transaction.
+	pub outputs: Vec<CompactOutput>,
+}
+
+/// A block reduced to its private transactions.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct CompactBlock {
+	/// Block number.
+	pub height: BlockNumber,
+	/// Block hash.
+	pub hash: H256,
+	/// Parent block hash.
+	pub prev_hash: H256,
+	/// Block timestamp.
+	pub time: u64,
+	/// Private transactions of the block, in block order.
+	pub vtx: Vec<CompactTx>,
+}
+
+impl CompactBlock {
+	/// Build the compact representation of a block. Public transactions are skipped.
+	pub fn from_block(block: &encoded::Block) -> Self {
+		let vtx = block
+			.transactions()
+			.iter()
+			.enumerate()
+			.filter(|&(_, tx)| tx.is_private())
+			.map(|(index, tx)| CompactTx {
+				index: index as u64,
+				hash: tx.hash(),
+				spends: tx.v_shielded_spend().iter().map(CompactSpend::from).collect(),
+				outputs: tx.v_shielded_output().iter().map(CompactOutput::from).collect(),
+			})
+			.collect();
+
+		CompactBlock {
+			height: block.number(),
+			hash: block.hash(),
+			prev_hash: block.parent_hash(),
+			time: block.timestamp(),
+			vtx,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use block::Block;
+	use rlp;
+	use transaction::{Action, Transaction};
+
+	#[test]
+	fn compact_block_keeps_only_private_transactions() {
+		let public = Transaction::default().null_sign(0);
+		let private = Transaction::create_private(vec![1, 2], 42.into(), Action::PrivateToPrivate)
+			.sign_for_private(0);
+
+		let block = Block {
+			transactions: vec![public.into(), private.clone().into()],
+			..Default::default()
+		};
+		let block = encoded::Block::new(block.rlp_bytes());
+
+		let compact = CompactBlock::from_block(&block);
+		assert_eq!(compact.hash, block.hash());
+		assert_eq!(compact.vtx.len(), 1);
+
+		let tx = &compact.vtx[0];
+		assert_eq!(tx.index, 1);
+		assert_eq!(tx.hash, private.hash());
+		assert_eq!(tx.spends, vec![
+			CompactSpend { nf: H256::from([1u8; 32]) },
+			CompactSpend { nf: H256::from([2u8; 32]) },
+		]);
+		assert_eq!(tx.outputs.len(), 2);
+		assert!(tx.outputs.iter().all(|o| o.ciphertext.len() == COMPACT_NOTE_SIZE));
+
+		let decoded: CompactBlock = rlp::decode(&rlp::encode(&compact)).unwrap();
+		assert_eq!(decoded, compact);
+	}
+}
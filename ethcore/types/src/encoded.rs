@@ -292,6 +292,9 @@ impl Block {
 	/// The hash of each transaction in the block.
 	pub fn transaction_hashes(&self) -> Vec<H256> { self.view().transaction_hashes() }
 
+	/// Whether any transaction in the block spends or creates shielded notes.
+	pub fn has_shielded_components(&self) -> bool { self.view().has_shielded_components() }
+
 	/// Decode uncle headers.
 	pub fn uncles(&self) -> Vec<FullHeader> { self.view().uncles() }
 
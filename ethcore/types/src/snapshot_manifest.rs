@@ -17,9 +17,64 @@
 //! Snapshot manifest type definition
 
 use ethereum_types::H256;
-use rlp::{Rlp, RlpStream, DecoderError};
+use rlp::{Decodable, Encodable, Rlp, RlpStream, DecoderError};
 use bytes::Bytes;
 
+/// Compression algorithm of the snapshot chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotCompression {
+	/// Chunks are stored as is.
+	None,
+	/// Chunks are compressed with snappy.
+	Snappy,
+	/// Chunks are compressed with zstd at the given level.
+	Zstd {
+		/// Compression level, from 1 (fastest) to 22 (densest).
+		level: u8,
+	},
+}
+
+/// Densest zstd compression level.
+pub const MAX_ZSTD_LEVEL: u8 = 22;
+
+impl SnapshotCompression {
+	/// Zstd compression at `level`, if it is between 1 and `MAX_ZSTD_LEVEL`.
+	pub fn zstd(level: u8) -> Option<Self> {
+		if level >= 1 && level <= MAX_ZSTD_LEVEL {
+			Some(SnapshotCompression::Zstd { level })
+		} else {
+			None
+		}
+	}
+}
+
+impl Default for SnapshotCompression {
+	fn default() -> Self {
+		SnapshotCompression::Snappy
+	}
+}
+
+impl Encodable for SnapshotCompression {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		match *self {
+			SnapshotCompression::None => { s.begin_list(1).append(&0u8); },
+			SnapshotCompression::Snappy => { s.begin_list(1).append(&1u8); },
+			SnapshotCompression::Zstd { level } => { s.begin_list(2).append(&2u8).append(&level); },
+		}
+	}
+}
+
+impl Decodable for SnapshotCompression {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		match rlp.val_at::<u8>(0)? {
+			0 => Ok(SnapshotCompression::None),
+			1 => Ok(SnapshotCompression::Snappy),
+			2 => Ok(SnapshotCompression::Zstd { level: rlp.val_at(1)? }),
+			_ => Err(DecoderError::Custom("unknown snapshot compression")),
+		}
+	}
+}
+
 /// Manifest data.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ManifestData {
@@ -35,22 +90,58 @@ pub struct ManifestData {
 	pub block_number: u64,
 	/// Block hash this snapshot was taken at.
 	pub block_hash: H256,
+	/// Hashes of the state chunks holding shielded state instead of accounts.
+	/// These are also listed in `state_hashes`.
+	pub shielded_hashes: Vec<H256>,
+	/// Compression of the chunks, which restorations decompress them with.
+	pub compression: SnapshotCompression,
 }
 
 impl ManifestData {
 	/// Encode the manifest data to rlp.
 	pub fn into_rlp(self) -> Bytes {
-		let mut stream = RlpStream::new_list(6);
+		let (trailing_count, trailing) = self.trailing_fields();
+		let mut stream = RlpStream::new_list(6 + trailing_count);
 		stream.append(&self.version);
 		stream.append_list(&self.state_hashes);
 		stream.append_list(&self.block_hashes);
 		stream.append(&self.state_root);
 		stream.append(&self.block_number);
 		stream.append(&self.block_hash);
+		stream.append_raw(&trailing, trailing_count);
 
 		stream.out()
 	}
 
+	/// Get the number and the concatenated RLP of the optional fields following the
+	/// block hash. The shielded chunk hashes are only included when there are any, or
+	/// when the chunks are not compressed with snappy, which is recorded after them, so
+	/// that manifests without shielded state and snappy chunks keep their encoding.
+	pub fn trailing_fields(&self) -> (usize, Bytes) {
+		let with_compression = self.compression != SnapshotCompression::Snappy;
+		let with_shielded = with_compression || !self.shielded_hashes.is_empty();
+		let mut fields = Vec::new();
+		let mut count = 0;
+		if with_shielded {
+			fields.extend_from_slice(&::rlp::encode_list(&self.shielded_hashes));
+			count += 1;
+		}
+		if with_compression {
+			fields.extend_from_slice(&::rlp::encode(&self.compression));
+			count += 1;
+		}
+		(count, fields)
+	}
+
+	/// Decode the optional fields following the block hash, which is at `index - 1`,
+	/// as `(shielded_hashes, compression)`.
+	pub fn decode_trailing_fields(rlp: &Rlp, index: usize) -> Result<(Vec<H256>, SnapshotCompression), DecoderError> {
+		let count = rlp.item_count()?;
+		let shielded_hashes = if count > index { rlp.list_at(index)? } else { Vec::new() };
+		let compression = if count > index + 1 { rlp.val_at(index + 1)? } else { SnapshotCompression::Snappy };
+		Ok((shielded_hashes, compression))
+	}
+
 	/// Try to restore manifest data from raw bytes, interpreted as RLP.
 	pub fn from_rlp(raw: &[u8]) -> Result<Self, DecoderError> {
 		let decoder = Rlp::new(raw);
@@ -65,6 +156,7 @@ impl ManifestData {
 		let state_root: H256 = decoder.val_at(start + 2)?;
 		let block_number: u64 = decoder.val_at(start + 3)?;
 		let block_hash: H256 = decoder.val_at(start + 4)?;
+		let (shielded_hashes, compression) = Self::decode_trailing_fields(&decoder, start + 5)?;
 
 		Ok(ManifestData {
 			version: version,
@@ -73,6 +165,8 @@ impl ManifestData {
 			state_root: state_root,
 			block_number: block_number,
 			block_hash: block_hash,
+			shielded_hashes: shielded_hashes,
+			compression: compression,
 		})
 	}
 }
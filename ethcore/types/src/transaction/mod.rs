@@ -19,5 +19,5 @@
 mod error;
 pub mod transaction;
 
-pub use self::error::Error;
+pub use self::error::{Error, PrivateTxError};
 pub use self::transaction::*;
@@ -238,6 +238,16 @@ impl rlp::Encodable for PrivateTransaction {
 }
 
 impl PrivateTransaction {
+	/// Returns whether the points of the spends and outputs of the RLP encoded private
+	/// transaction `d` are canonically encoded.
+	pub fn has_canonical_points(d: &Rlp) -> bool {
+		let all_canonical = |i: usize, canonical: fn(&Rlp) -> bool| {
+			d.at(i).map_or(false, |list| list.iter().all(|item| canonical(&item)))
+		};
+		all_canonical(0, SpendDescription::has_canonical_points)
+			&& all_canonical(1, OutputDescription::has_canonical_points)
+	}
+
 	pub fn rlp_append_unsigned(&self, s: &mut RlpStream) {
 		s.begin_list(4);
 		s.begin_list(self.spends.len());
@@ -324,6 +334,14 @@ impl Transaction {
 		}
 	}
 
+	/// If this transaction spends or creates shielded notes.
+	pub fn has_shielded_components(&self) -> bool {
+		match self.private {
+			Some(ref tx) => !tx.spends.is_empty() || !tx.outputs.is_empty(),
+			None => false,
+		}
+	}
+
 	/// If the transaction is private to private
 	pub fn is_privacy(&self) -> bool {
 		self.action == Action::PrivateToPrivate
@@ -565,6 +583,16 @@ impl Transaction {
 		keccak(stream.as_raw())
 	}
 
+	/// The hash signed by the spend authorization and binding signatures of a shielded
+	/// transaction, which is its signing hash.
+	///
+	/// Spends are hashed without their spend authorization signature and the binding
+	/// signature is left out, so that every signature of the transaction signs the same
+	/// hash. There are no transparent inputs, so it does not depend on what is signed.
+	pub fn shielded_sighash(&self, chain_id: Option<u64>) -> [u8; 32] {
+		self.hash(chain_id).into()
+	}
+
 	/// Signs the transaction as coming from `sender`.
 	pub fn sign(self, secret: &Secret, chain_id: Option<u64>) -> SignedTransaction {
 		let sig = ::ethkey::sign(secret, &self.hash(chain_id))
@@ -726,6 +754,15 @@ impl rlp::Encodable for UnverifiedTransaction {
 }
 
 impl UnverifiedTransaction {
+	/// Returns whether the shielded points of the RLP encoded transaction `d` are
+	/// canonically encoded. Transactions without private data always are.
+	pub fn has_canonical_shielded_points(d: &Rlp) -> bool {
+		match d.item_count() {
+			Ok(10) => d.at(6).map_or(false, |private| PrivateTransaction::has_canonical_points(&private)),
+			_ => true,
+		}
+	}
+
 	/// Used to compute hash of created transactions
 	fn compute_hash(mut self) -> UnverifiedTransaction {
 		let hash = keccak(&*self.rlp_bytes());
@@ -1331,6 +1368,25 @@ mod tests {
 		assert_eq!(ct, ct2);
 	}
 
+	#[test]
+	fn detects_non_canonical_shielded_points() {
+		let tx = Transaction::create_private(vec![0], U256::zero(), Action::PrivateToPrivate)
+			.sign_for_private(TEST_CHAIN_ID);
+		let encoded = tx.rlp_bytes();
+		assert!(UnverifiedTransaction::has_canonical_shielded_points(&Rlp::new(&encoded)));
+
+		// the value commitment of the spend is the identity, set the sign bit of its encoding.
+		let mut identity = vec![0xa0, 1];
+		identity.extend_from_slice(&[0u8; 31]);
+		let position = encoded.windows(identity.len()).position(|w| w == &identity[..]).unwrap();
+		let mut signed = encoded.to_vec();
+		signed[position + identity.len() - 1] |= 0x80;
+		assert!(!UnverifiedTransaction::has_canonical_shielded_points(&Rlp::new(&signed)));
+
+		let plain = Transaction::default().fake_sign(Address::default());
+		assert!(UnverifiedTransaction::has_canonical_shielded_points(&Rlp::new(&plain.rlp_bytes())));
+	}
+
 	#[test]
 	#[ignore]
 	fn test_create_private() {
@@ -1634,4 +1690,50 @@ mod tests {
 		let hash4 = tx1.hash(Some(TEST_CHAIN_ID));
 		assert_ne!(hash, hash4);
 	}
+
+	#[test]
+	fn shielded_sighash_known_answers() {
+		use rustc_hex::FromHex;
+
+		let expected = |hex: &str| {
+			let mut sighash = [0u8; 32];
+			sighash.copy_from_slice(&hex.from_hex().unwrap());
+			sighash
+		};
+
+		// the EIP-155 signing example, without shielded fields.
+		let public = Transaction {
+			nonce: U256::from(9),
+			gas_price: U256::from(20_000_000_000u64),
+			gas: U256::from(21_000),
+			action: Action::Call("3535353535353535353535353535353535353535".into()),
+			value: U256::from(1_000_000_000_000_000_000u64),
+			data: vec![],
+			private: None,
+		};
+		assert_eq!(
+			public.shielded_sighash(Some(1)),
+			expected("daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53")
+		);
+
+		// rlp([1, 2, 21000, 1, 0, "", [[], [], 5, ""], 2, 0, 0])
+		let private = Transaction {
+			nonce: U256::from(1),
+			gas_price: U256::from(2),
+			gas: U256::from(21_000),
+			action: Action::PrivateToPrivate,
+			value: U256::zero(),
+			data: vec![],
+			private: Some(PrivateTransaction {
+				spends: vec![],
+				outputs: vec![],
+				balancing_value: 5,
+				binding_sig: [1u8; 64],
+			}),
+		};
+		assert_eq!(
+			private.shielded_sighash(Some(2)),
+			expected("34a7d08ca3e52decb8fc62892fecf357cc44f1f5808d5df5bd44417fd0fe81fe")
+		);
+	}
 }
@@ -23,6 +23,7 @@ use hash::keccak;
 use header::Header;
 use transaction::{LocalizedTransaction, UnverifiedTransaction};
 use views::{HeaderView, TransactionView};
+use zcash_primitives::transaction::components::OutputDescription;
 
 /// View onto block rlp.
 pub struct BlockView<'a> {
@@ -116,6 +117,36 @@ impl<'a> BlockView<'a> {
 			.collect()
 	}
 
+	/// Return the shielded outputs of the block with the hash of their transaction and
+	/// their index in it, in the order their note commitments are appended to the
+	/// commitment tree.
+	pub fn shielded_outputs(&self) -> Vec<(H256, usize, OutputDescription)> {
+		self.localized_private_txs()
+			.into_iter()
+			.flat_map(|tx| {
+				let hash = tx.hash();
+				tx.v_shielded_output()
+					.into_iter()
+					.enumerate()
+					.map(move |(i, output)| (hash, i, output))
+			})
+			.collect()
+	}
+
+	/// Return the nullifiers the block reveals, in the order of its transactions and of
+	/// the spends in each transaction.
+	pub fn nullifiers(&self) -> Vec<[u8; 32]> {
+		self.transactions()
+			.iter()
+			.flat_map(|tx| tx.v_shielded_spend().into_iter().map(|spend| spend.nullifier))
+			.collect()
+	}
+
+	/// Return true if any transaction in given block spends or creates shielded notes.
+	pub fn has_shielded_components(&self) -> bool {
+		self.transactions().iter().any(|t| t.has_shielded_components())
+	}
+
 	/// Return the raw rlp for the transactions in the given block.
 	pub fn transactions_rlp(&self) -> ViewRlp<'a> {
 		self.rlp.at(1)
@@ -253,4 +284,52 @@ mod tests {
 		assert_eq!(view.localized_transactions().len(), 2);
 		assert_eq!(view.localized_private_txs().len(), 1);
 	}
+
+	#[test]
+	fn test_shielded_outputs_in_block() {
+		let t1 = Transaction::create_private(vec![0], 42.into(), transaction::Action::PrivateToPrivate)
+			.sign_for_private(0);
+		let t2 = Transaction {
+			nonce: 0.into(),
+			gas_price: 0.into(),
+			gas: 100_000.into(),
+			action: transaction::Action::Create,
+			value: 100.into(),
+			data: vec![],
+			private: None,
+		}
+		.invalid_sign();
+		let t3 = Transaction::create_private(vec![1], 43.into(), transaction::Action::PrivateToPrivate)
+			.sign_for_private(0);
+		let (h1, h3) = (t1.hash(), t3.hash());
+		let transactions: Vec<UnverifiedTransaction> = vec![t1.into(), t2, t3.into()];
+		let block = Block {
+			transactions,
+			..Default::default()
+		};
+		let rlp = block.rlp_bytes();
+		let view = view!(BlockView, &rlp);
+
+		// the public transaction has no outputs and the notes keep the tree order.
+		let outputs: Vec<_> = view.shielded_outputs().into_iter().map(|(hash, i, _)| (hash, i)).collect();
+		assert_eq!(outputs, vec![(h1, 0), (h1, 1), (h3, 0), (h3, 1)]);
+		let notes: Vec<_> = view.localized_private_txs().iter().flat_map(|tx| tx.get_commitment_notes()).collect();
+		assert_eq!(view.shielded_outputs().into_iter().map(|(_, _, output)| output.cmu).collect::<Vec<_>>(), notes);
+	}
+
+	#[test]
+	fn test_nullifiers_in_block() {
+		let t1 = Transaction::create_private(vec![3, 1], 42.into(), transaction::Action::PrivateToPrivate)
+			.sign_for_private(0);
+		let t2 = Transaction::create_private(vec![2], 43.into(), transaction::Action::PrivateToPrivate)
+			.sign_for_private(0);
+		let block = Block {
+			transactions: vec![t1.into(), t2.into()],
+			..Default::default()
+		};
+		let rlp = block.rlp_bytes();
+		let view = view!(BlockView, &rlp);
+
+		assert_eq!(view.nullifiers(), vec![[3u8; 32], [1u8; 32], [2u8; 32]]);
+	}
 }
@@ -112,6 +112,23 @@ pub struct Params {
 	pub max_code_size: Option<Uint>,
 	/// Maximum size of transaction RLP payload.
 	pub max_transaction_size: Option<Uint>,
+	/// Maximum number of shielded spends and outputs to verify in one block.
+	pub max_shielded_verify_ops_per_block: Option<Uint>,
+	/// Block from which imported blocks must respect `max_shielded_verify_ops_per_block`.
+	pub max_shielded_verify_ops_transition: Option<Uint>,
+	/// Number of most recent blocks whose commitment tree roots spends may use as anchor.
+	pub anchor_window: Option<Uint>,
+	/// Block at which the shielded pool is activated.
+	pub sapling_activation: Option<Uint>,
+	/// Block from which the shielded proofs and anchors of imported blocks are verified.
+	pub shielded_block_verification_transition: Option<Uint>,
+	/// Block from which the shielded points of the transactions of imported blocks must be
+	/// canonically encoded.
+	pub shielded_canonical_encoding_transition: Option<Uint>,
+	/// Identifier of the Sapling spend circuit verifying key.
+	pub sapling_spend_verifying_key: Option<H256>,
+	/// Identifier of the Sapling output circuit verifying key.
+	pub sapling_output_verifying_key: Option<H256>,
 	/// See main EthashParams docs.
 	pub max_code_size_transition: Option<Uint>,
 	/// Transaction permission contract address.
@@ -138,7 +155,8 @@ pub struct Params {
 mod tests {
 	use serde_json;
 	use uint::Uint;
-	use ethereum_types::U256;
+	use ethereum_types::{U256, H256 as Eth256};
+	use hash::H256;
 	use spec::params::Params;
 
 	#[test]
@@ -152,7 +170,9 @@ mod tests {
 			"accountStartNonce": "0x01",
 			"gasLimitBoundDivisor": "0x20",
 			"maxCodeSize": "0x1000",
-			"wasmActivationTransition": "0x1010"
+			"wasmActivationTransition": "0x1010",
+			"saplingActivation": "0x10",
+			"saplingSpendVerifyingKey": "0xad6bb8a6e703d372a74f24ed1da318b2c0e2b34774ac5eb3add60995f108eff8"
 		}"#;
 
 		let deserialized: Params = serde_json::from_str(s).unwrap();
@@ -165,6 +185,9 @@ mod tests {
 		assert_eq!(deserialized.gas_limit_bound_divisor, Uint(U256::from(0x20)));
 		assert_eq!(deserialized.max_code_size, Some(Uint(U256::from(0x1000))));
 		assert_eq!(deserialized.wasm_activation_transition, Some(Uint(U256::from(0x1010))));
+		assert_eq!(deserialized.sapling_activation, Some(Uint(U256::from(0x10))));
+		assert_eq!(deserialized.sapling_spend_verifying_key, Some(H256(Eth256::from("0xad6bb8a6e703d372a74f24ed1da318b2c0e2b34774ac5eb3add60995f108eff8"))));
+		assert_eq!(deserialized.sapling_output_verifying_key, None);
 	}
 
 	#[test]
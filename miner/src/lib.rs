@@ -28,6 +28,7 @@ extern crate futures;
 extern crate heapsize;
 extern crate keccak_hash as hash;
 extern crate linked_hash_map;
+extern crate num_cpus;
 extern crate parity_runtime;
 extern crate parking_lot;
 #[cfg(feature = "price-info")]
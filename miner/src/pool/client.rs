@@ -24,6 +24,7 @@ use std::fmt;
 
 use ethereum_types::{U256, H256, H160 as Address};
 use types::transaction;
+use zcash_primitives::sapling::Node;
 
 /// Account Details
 #[derive(Debug, Clone)]
@@ -56,6 +57,9 @@ pub trait Client: fmt::Debug + Sync {
 		t: &transaction::UnverifiedTransaction,
 	) -> Result<(), transaction::Error>;
 
+	/// Is the given commitment tree root known and recent enough to be spent from?
+	fn is_commitment_anchor_valid(&self, anchor: &Node) -> bool;
+
 	/// Structurarily verify given transaction.
 	fn verify_transaction(&self, tx: transaction::UnverifiedTransaction)
 		-> Result<transaction::SignedTransaction, transaction::Error>;
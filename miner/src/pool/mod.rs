@@ -24,17 +24,20 @@ use txpool;
 mod listener;
 mod queue;
 mod ready;
+mod source;
 
 pub mod client;
 pub mod local_transactions;
 pub mod replace;
 pub mod scoring;
+pub mod shielded_verifier;
 pub mod verifier;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::queue::{TransactionQueue, Status as QueueStatus};
+pub use self::source::{SourceClient, TransactionSource};
 pub use self::txpool::{VerifiedTransaction as PoolVerifiedTransaction, Options};
 
 /// How to prioritize transactions in the pool
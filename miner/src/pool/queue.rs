@@ -17,8 +17,8 @@
 //! Ethereum Transaction Queue
 
 use std::{cmp, fmt};
-use std::sync::Arc;
-use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use ethereum_types::{H256, U256, Address};
@@ -27,9 +27,13 @@ use txpool::{self, Verifier};
 use types::transaction;
 use std::sync::mpsc::Sender;
 use types::transaction::SyncTransaction;
+use zcash_primitives::sapling::Node;
+use zcash_proofs::sapling::{ProofCache, SharedProofCache, ShieldedError, DEFAULT_PROOF_CACHE_SIZE};
+use ethcore_blockchain::wallet::zkp::{OUTPUT_VK_ID, SPEND_VK_ID};
 
 use pool::{self, replace, scoring, verifier, client, ready, listener, PrioritizationStrategy, PendingOrdering, PendingSettings, VerifiedTransaction};
 use pool::local_transactions::LocalTransactionsList;
+use pool::shielded_verifier::ShieldedVerifier;
 use std::ptr::null;
 use txpool::scoring::Scoring;
 
@@ -202,6 +206,11 @@ impl NullifierPending {
 		}
 	}
 
+	/// Return the transaction hash which revealed the nullifier, if any.
+	fn transaction_hash(&self, nullifier: &U256) -> Option<H256> {
+		self.nullifier_hash_map.read().get(nullifier).cloned()
+	}
+
 	/// Return the transaction hashes which conflict with the nullifiers
 	fn conflict_transaction_hashes(&self, nullifiers: &HashSet<U256>) -> HashSet<H256> {
 		let mut conflict_hashes = HashSet::new();
@@ -227,6 +236,11 @@ impl NullifierPending {
 		}
 	}
 
+	/// Keep only the nullifiers revealed by the transactions `f` accepts.
+	fn retain<F: Fn(&H256) -> bool>(&self, f: F) {
+		self.nullifier_hash_map.write().retain(|_, hash| f(hash));
+	}
+
 	fn size(&self) -> usize {
 		self.nullifier_hash_map.read().len()
 	}
@@ -255,6 +269,11 @@ pub struct TransactionQueue {
 	// check the nullifier for the private transaction
 	nullifier_pending: NullifierPending,
 	sender: Option<Arc<Mutex<Sender<SyncTransaction>>>>,
+	shielded_timings: Arc<Mutex<verifier::ShieldedTimings>>,
+	// results of the proof verifications and the transactions which passed, shared by
+	// the verifiers and block import
+	proof_cache: SharedProofCache,
+	shielded_verifier: Arc<ShieldedVerifier>,
 }
 
 impl TransactionQueue {
@@ -274,9 +293,24 @@ impl TransactionQueue {
 			recently_rejected: RecentlyRejected::new(cmp::max(MIN_REJECTED_CACHE_SIZE, max_count / 4)),
 			nullifier_pending: NullifierPending::new(),
 			sender,
+			shielded_timings: Default::default(),
+			proof_cache: Arc::new(StdMutex::new(ProofCache::new(DEFAULT_PROOF_CACHE_SIZE, *SPEND_VK_ID, *OUTPUT_VK_ID))),
+			shielded_verifier: Arc::new(ShieldedVerifier::new(0)),
 		}
 	}
 
+	/// Verify the proofs of shielded transactions on `threads` dedicated threads instead of
+	/// the importing thread, which still waits for them before importing a transaction.
+	pub fn with_shielded_verifier_threads(mut self, threads: usize) -> Self {
+		self.shielded_verifier = Arc::new(ShieldedVerifier::new(threads));
+		self
+	}
+
+	/// Number of threads verifying the proofs of shielded transactions.
+	pub fn shielded_verifier_threads(&self) -> usize {
+		self.shielded_verifier.threads()
+	}
+
 	/// Update verification options
 	///
 	/// Some parameters of verification may vary in time (like block gas limit or minimal gas price).
@@ -295,7 +329,7 @@ impl TransactionQueue {
 	///
 	/// Given blockchain and state access (Client)
 	/// verifies and imports transactions to the pool.
-	pub fn import<C: client::Client + client::NonceClient + Clone>(
+	pub fn import<C: client::Client + client::NonceClient + client::NullifierClient + Clone>(
 		&self,
 		client: C,
 		transactions: Vec<verifier::Transaction>,
@@ -322,6 +356,9 @@ impl TransactionQueue {
 			options,
 			self.insertion_id.clone(),
 			transaction_to_replace,
+			self.shielded_timings.clone(),
+			self.proof_cache.clone(),
+			self.shielded_verifier.clone(),
 		);
 
 		let mut replace = replace::ReplaceByScoreAndReadiness::new(self.pool.read().scoring().clone(), client);
@@ -343,8 +380,9 @@ impl TransactionQueue {
 				let mut transaction_nullifiers = HashSet::new();
 				let mut replaced_transaction_hashes = HashSet::new();
 
-				let imported = verifier
-					.verify_transaction(transaction)
+				let imported = self
+					.precheck_private_transaction(&client, transaction.unverified())
+					.and_then(|_| verifier.verify_transaction(transaction))
 					.and_then(|verified| {
 						self.check_transaction_nullifier_conflict(&verified,
 														   &mut transaction_nullifiers,
@@ -381,6 +419,40 @@ impl TransactionQueue {
 		results
 	}
 
+	/// Run the cheap admission checks of a private transaction, so that obviously invalid
+	/// ones are rejected before verifying their proofs.
+	fn precheck_private_transaction<C: client::Client + client::NullifierClient>(
+		&self,
+		client: &C,
+		tx: &transaction::UnverifiedTransaction,
+	) -> Result<(), transaction::Error> {
+		if !tx.is_private() {
+			return Ok(());
+		}
+
+		// conflicting queued transactions which this one would replace are
+		// handled in `check_transaction_nullifier_conflict`.
+		let pending_nullifiers = {
+			let pool = self.pool.read();
+			tx.get_nullifier_set().into_iter().filter(|nullifier| {
+				self.nullifier_pending.transaction_hash(nullifier)
+					.and_then(|hash| pool.find(&hash))
+					.map_or(false, |pending| !pool.scoring().can_replace_conflict(&pending, &tx.gas_price))
+			}).collect()
+		};
+
+		verifier::precheck_shielded_tx(
+			tx,
+			&|anchor: &Node| client.is_commitment_anchor_valid(anchor),
+			client,
+			&pending_nullifiers,
+		).map_err(|err| match err {
+			ShieldedError::UnknownAnchor => transaction::Error::InvalidCommitmentAnchor,
+			// the other admission checks are on the nullifiers.
+			_ => transaction::Error::ConflictNullifier,
+		})
+	}
+
 	/// Check the private transaction nullifier conflict.
 	pub fn check_transaction_nullifier_conflict(&self, verified: &VerifiedTransaction,
 										 transaction_nullifiers: &mut HashSet<U256>,
@@ -543,9 +615,23 @@ impl TransactionQueue {
 			let state_readiness = ready::State::new(client.clone(), stale_id, nonce_cap);
 			removed += self.pool.write().cull(Some(chunk), state_readiness);
 		}
+		// culled transactions do not go through `remove`, release their nullifiers so that
+		// they are not reported as pending any more.
+		{
+			let pool = self.pool.read();
+			self.nullifier_pending.retain(|hash| pool.find(hash).is_some());
+		}
 		debug!(target: "txqueue", "Removed {} stalled transactions. {}", removed, self.status());
 	}
 
+	/// Returns the number of nullifiers revealed by the private transactions in the pool.
+	///
+	/// A private transaction spending one of them is rejected with `ConflictNullifier`,
+	/// unless it may replace the pending one, see `check_transaction_nullifier_conflict`.
+	pub fn pending_nullifiers(&self) -> usize {
+		self.nullifier_pending.size()
+	}
+
 	/// Returns next valid nonce for given sender
 	/// or `None` if there are no pending transactions from that sender.
 	pub fn next_nonce<C: client::NonceClient + client::NullifierClient>(
@@ -625,6 +711,19 @@ impl TransactionQueue {
 		}
 	}
 
+	/// Returns gas price of the worst transaction in the pool if the pool is full, that is
+	/// the price a new transaction has to beat not to be rejected. Returns `None` if there
+	/// is room in the pool.
+	pub fn full_queue_min_gas_price(&self) -> Option<U256> {
+		let pool = self.pool.read();
+		let status = pool.light_status();
+		let limits = pool.options();
+		if status.transaction_count < limits.max_count && status.mem_usage < limits.max_mem_usage {
+			return None;
+		}
+		pool.worst_transaction().map(|tx| tx.signed().gas_price)
+	}
+
 	/// Returns a status of the queue.
 	pub fn status(&self) -> Status {
 		let pool = self.pool.read();
@@ -639,6 +738,57 @@ impl TransactionQueue {
 		}
 	}
 
+	/// Returns the time spent verifying shielded transactions since the queue was created.
+	pub fn shielded_timings(&self) -> verifier::ShieldedTimings {
+		*self.shielded_timings.lock()
+	}
+
+	/// Whether the proofs and signatures of the transaction `hash` passed verification,
+	/// on admission, on block import or while warming the proof cache. Its anchors and
+	/// nullifiers must still be checked against the chain.
+	pub fn is_shielded_verified(&self, hash: &H256) -> bool {
+		verifier::is_transaction_verified(&self.proof_cache, hash)
+	}
+
+	/// Verify the shielded descriptions of `transactions` and record the results in the
+	/// proof cache, and the transactions which pass, so that they are looked up instead of
+	/// verified again when the transactions come back, as after a reorg. Stops early once
+	/// `abort` is set.
+	///
+	/// The time spent is not added to `shielded_timings`. Returns the number of
+	/// transactions verified.
+	pub fn warm_proof_cache<I>(&self, transactions: I, abort: &AtomicBool) -> usize where
+		I: IntoIterator<Item = transaction::SignedTransaction>,
+	{
+		let mut timings = verifier::ShieldedTimings::default();
+		let mut verified = 0;
+		for tx in transactions {
+			if abort.load(atomic::Ordering::SeqCst) {
+				break;
+			}
+			match verifier::verify_private_transaction_on(&tx, &mut timings, Some(&self.proof_cache), &self.shielded_verifier) {
+				Ok(()) => verifier::record_verified_transaction(&self.proof_cache, &tx.hash()),
+				Err(e) => debug!(target: "txqueue", "[{:?}] Shielded verification failed while warming the proof cache: {}", tx.hash(), e),
+			}
+			verified += 1;
+		}
+		verified
+	}
+
+	/// Verify the shielded components of the transactions of a block, with the proof cache
+	/// of the queue, so that the transactions which passed on admission only have their
+	/// anchors and nullifiers checked. See `verifier::verify_block_shielded`.
+	///
+	/// The time spent is not added to `shielded_timings`.
+	pub fn verify_block_shielded(
+		&self,
+		transactions: &[transaction::SignedTransaction],
+		anchor_valid: &Fn(&Node) -> bool,
+	) -> Result<(), ShieldedError> {
+		let mut timings = verifier::ShieldedTimings::default();
+		verifier::verify_block_shielded(transactions, anchor_valid, &mut timings, Some(&self.proof_cache), &self.shielded_verifier)
+	}
+
 	/// Check if there are any local transactions in the pool.
 	///
 	/// Returns `true` if there are any transactions in the pool
@@ -69,12 +69,17 @@ impl NonceAndGasPrice {
 	/// Decide if the new transaction should replace the old transaction.
 	/// When there is conflict between the nullifiers.
 	pub fn should_replace_conflict(&self, old: &VerifiedTransaction, new: &VerifiedTransaction) -> bool {
+		self.can_replace_conflict(old, new.gas_price())
+	}
+
+	/// Decide if a transaction with the given gas price could replace the old transaction
+	/// when their nullifiers conflict.
+	pub fn can_replace_conflict(&self, old: &VerifiedTransaction, new_gp: &U256) -> bool {
 		let old_gp = old.gas_price();
-		let new_gp = new.gas_price();
 
 		let min_required_gp = bump_gas_price(*old_gp);
 
-		match min_required_gp.cmp(&new_gp) {
+		match min_required_gp.cmp(new_gp) {
 			cmp::Ordering::Less => true,
 			_ => false,
 		}
@@ -0,0 +1,187 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pool of worker threads verifying the proofs of shielded transactions.
+//!
+//! The importing thread hands the descriptions of a transaction to the workers and
+//! waits for all of them, so the pairings of a transaction run in parallel instead of
+//! one after the other on the importing thread.
+
+use std::cmp;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
+use std::thread;
+
+use num_cpus;
+use parking_lot::Mutex;
+
+/// Work queued on the pool.
+type Job = Box<FnMut() + Send>;
+
+/// Default number of worker threads: one less than the number of CPUs, leaving one for
+/// the importing thread, and at least one.
+pub fn default_threads() -> usize {
+	cmp::max(num_cpus::get(), 2) - 1
+}
+
+/// A fixed number of worker threads verifying shielded proofs.
+///
+/// A verifier without threads, or one which was shut down, runs the work on the
+/// calling thread instead, so verification never fails for lack of workers.
+pub struct ShieldedVerifier {
+	sender: Mutex<Option<Sender<Job>>>,
+	workers: Mutex<Vec<thread::JoinHandle<()>>>,
+	threads: usize,
+}
+
+impl ShieldedVerifier {
+	/// Spawn `threads` workers.
+	pub fn new(threads: usize) -> Self {
+		if threads == 0 {
+			return ShieldedVerifier {
+				sender: Mutex::new(None),
+				workers: Mutex::new(Vec::new()),
+				threads: 0,
+			};
+		}
+
+		let (sender, receiver) = mpsc::channel::<Job>();
+		let receiver = Arc::new(Mutex::new(receiver));
+
+		let workers: Vec<_> = (0..threads).filter_map(|i| {
+			let receiver = receiver.clone();
+			let res = thread::Builder::new().name(format!("Shielded Verifier #{}", i)).spawn(move || {
+				loop {
+					// the lock is released before running the job.
+					let job = receiver.lock().recv();
+					let mut job = match job {
+						Ok(job) => job,
+						Err(_) => break,
+					};
+					if panic::catch_unwind(AssertUnwindSafe(|| job())).is_err() {
+						warn!(target: "txqueue", "A shielded proof verification panicked");
+					}
+				}
+			});
+
+			match res {
+				Ok(handle) => Some(handle),
+				Err(e) => {
+					warn!(target: "txqueue", "Failed to spawn shielded verifier thread: {:?}", e);
+					None
+				}
+			}
+		}).collect();
+
+		let threads = workers.len();
+		ShieldedVerifier {
+			sender: Mutex::new(if threads == 0 { None } else { Some(sender) }),
+			workers: Mutex::new(workers),
+			threads,
+		}
+	}
+
+	/// Number of worker threads.
+	pub fn threads(&self) -> usize {
+		self.threads
+	}
+
+	/// Queue a closure and return a receiver of its result.
+	///
+	/// The closure is run on the calling thread if there is no worker. If it panics,
+	/// the receiver is disconnected without a result.
+	pub fn execute<T, F>(&self, f: F) -> Receiver<T> where
+		T: Send + 'static,
+		F: FnOnce() -> T + Send + 'static,
+	{
+		let (result_sender, result) = mpsc::channel();
+		let mut f = Some(f);
+		let job: Job = Box::new(move || {
+			if let Some(f) = f.take() {
+				let _ = result_sender.send(f());
+			}
+		});
+
+		let sender = self.sender.lock().clone();
+		let mut job = match sender {
+			Some(sender) => match sender.send(job) {
+				Ok(()) => return result,
+				Err(SendError(job)) => job,
+			},
+			None => job,
+		};
+
+		// no worker to run it.
+		if panic::catch_unwind(AssertUnwindSafe(|| job())).is_err() {
+			warn!(target: "txqueue", "A shielded proof verification panicked");
+		}
+		result
+	}
+
+	/// Stop accepting work, let the workers drain the queue and wait for them.
+	pub fn shutdown(&self) {
+		self.sender.lock().take();
+		for worker in self.workers.lock().drain(..) {
+			if worker.join().is_err() {
+				warn!(target: "txqueue", "Shielded verifier thread panicked");
+			}
+		}
+	}
+}
+
+impl Drop for ShieldedVerifier {
+	fn drop(&mut self) {
+		self.shutdown();
+	}
+}
+
+impl fmt::Debug for ShieldedVerifier {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ShieldedVerifier")
+			.field("threads", &self.threads)
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn runs_work_with_and_without_threads() {
+		for threads in 0..3 {
+			let verifier = ShieldedVerifier::new(threads);
+			assert_eq!(verifier.threads(), threads);
+
+			let results: Vec<_> = (0..8u64).map(|i| verifier.execute(move || i * 2)).collect();
+			let results: Vec<_> = results.into_iter().map(|r| r.recv().ok()).collect();
+			assert_eq!(results, (0..8u64).map(|i| Some(i * 2)).collect::<Vec<_>>());
+
+			let panicking = verifier.execute(|| -> u64 { panic!("test panic") });
+			assert!(panicking.recv().is_err());
+		}
+	}
+
+	#[test]
+	fn runs_work_inline_after_shutdown() {
+		let verifier = ShieldedVerifier::new(2);
+		verifier.shutdown();
+		let caller = thread::current().id();
+		assert_eq!(verifier.execute(move || thread::current().id() == caller).recv(), Ok(true));
+	}
+}
@@ -0,0 +1,52 @@
+// Copyright 2018-2020 Origo Foundation.
+// This file is part of Origo Network.
+
+// Origo Network is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Origo Network is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Origo Network.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sources of the transactions pushed into the blocks being prepared.
+
+use std::sync::Arc;
+
+use ethereum_types::{U256, Address};
+
+use pool::{client, PendingSettings, TransactionQueue, VerifiedTransaction};
+
+/// Chain state a `TransactionSource` checks the readiness of its transactions against.
+pub trait SourceClient: client::NonceClient + client::NullifierClient {}
+
+impl<T: client::NonceClient + client::NullifierClient> SourceClient for T {}
+
+/// A source of the transactions for the blocks the miner prepares.
+pub trait TransactionSource: Send + Sync {
+	/// Get the transactions ready to be included in the next block, in the order to push them.
+	fn pending(&self, client: &SourceClient, settings: PendingSettings) -> Vec<Arc<VerifiedTransaction>>;
+}
+
+impl TransactionSource for TransactionQueue {
+	fn pending(&self, client: &SourceClient, settings: PendingSettings) -> Vec<Arc<VerifiedTransaction>> {
+		TransactionQueue::pending(self, client, settings)
+	}
+}
+
+impl<'a> client::NonceClient for &'a SourceClient {
+	fn account_nonce(&self, address: &Address) -> U256 {
+		(**self).account_nonce(address)
+	}
+}
+
+impl<'a> client::NullifierClient for &'a SourceClient {
+	fn nullifier_exists(&self, nullifier: &U256) -> bool {
+		(**self).nullifier_exists(nullifier)
+	}
+}
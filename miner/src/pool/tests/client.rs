@@ -20,6 +20,7 @@ use std::sync::{atomic, Arc};
 use ethereum_types::{U256, H256, Address};
 use rlp::Rlp;
 use types::transaction::{self, Transaction, SignedTransaction, UnverifiedTransaction};
+use zcash_primitives::sapling::Node;
 use std::collections::HashSet;
 
 use pool;
@@ -36,6 +37,7 @@ pub struct TestClient {
 	local_address: Address,
 	max_transaction_size: usize,
 	nullifier_set: HashSet<U256>,
+	anchors_valid: bool,
 }
 
 impl Default for TestClient {
@@ -52,6 +54,7 @@ impl Default for TestClient {
 			local_address: Default::default(),
 			max_transaction_size: MAX_TRANSACTION_SIZE,
 			nullifier_set: HashSet::new(),
+			anchors_valid: true,
 		}
 	}
 }
@@ -76,6 +79,11 @@ impl TestClient {
 		self
 	}
 
+	pub fn with_expired_anchors(mut self) -> Self {
+		self.anchors_valid = false;
+		self
+	}
+
 	pub fn with_gas_required<T: Into<U256>>(mut self, gas_required: T) -> Self {
 		self.gas_required = gas_required.into();
 		self
@@ -119,6 +127,10 @@ impl pool::client::Client for TestClient {
 		Ok(())
 	}
 
+	fn is_commitment_anchor_valid(&self, _anchor: &Node) -> bool {
+		self.anchors_valid
+	}
+
 	fn verify_transaction(&self, tx: UnverifiedTransaction)
 		-> Result<SignedTransaction, transaction::Error>
 	{
@@ -14,10 +14,15 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity Ethereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex as StdMutex};
+
 use ethereum_types::{U256, Address};
+use types::compact_block::{CompactBlock, CompactOutput, CompactSpend, CompactTx};
 use types::transaction::{self, PendingTransaction};
 use txpool;
 
+use pool::shielded_verifier::ShieldedVerifier;
 use pool::{verifier, TransactionQueue, PrioritizationStrategy, PendingSettings, PendingOrdering};
 
 pub mod tx;
@@ -31,6 +36,8 @@ use ethcore_blockchain::wallet::wallet_types::{
 	SaplingExtendedFullViewingKey, SaplingExtendedSpendingKey,
 };
 use ethcore_blockchain::wallet::transaction_builder::TransactionBuilder;
+use ethcore_blockchain::wallet::zkp::{OUTPUT_VK_ID, SPEND_VK_ID};
+use zcash_proofs::sapling::{ProofCache, ShieldedError};
 use sapling_crypto::{
 	jubjub::fs::Fs,
 	redjubjub::Signature,
@@ -126,6 +133,17 @@ fn private_to_public_transaction(gas_price: U256, value: u64) -> SignedTransacti
 	result.unwrap().sign_for_private(TEST_CHAIN_ID)
 }
 
+fn spends_only_transaction() -> Transaction {
+	// The spent note covers the public output and the 21000 * 1_000_000 wei fee
+	// exactly, so no change output is added.
+	let mut builder = TransactionBuilder::new(U256::from(1_000_000), U256::from(0), U256::from(21000), vec![], TEST_CHAIN_ID);
+	add_private_input(&mut builder, 5 + 21);
+	builder.set_public_output(Address::from("0000000000000000000000000000000000000005"), &(U256::from(5) * CONVERSION_FACTOR));
+	let result = builder.build();
+	assert!(result.is_ok());
+	result.unwrap()
+}
+
 fn create_private(gas_price: u64) -> SignedTransaction {
 	let mut rng = OsRng::new().expect("should be able to construct RNG");
 
@@ -532,6 +550,24 @@ fn check_transaction_nullifier_remove_for_private() {
 	assert_eq!(txq.status().status.transaction_count, 1);
 }
 
+#[test]
+fn cull_releases_nullifiers_of_culled_private_transactions() {
+	// given
+	let txq = new_queue();
+	let tx1 = create_private(2_000_000);
+	let nullifiers = tx1.get_nullifier_set();
+	let res = txq.import(TestClient::new(), vec![tx1].local());
+	assert_eq!(res, vec![Ok(())]);
+	assert_eq!(txq.pending_nullifiers(), nullifiers.len());
+
+	// when the spent nullifiers are mined
+	txq.cull(TestClient::new().with_nullifier(nullifiers.into_iter().collect()));
+
+	// then
+	assert_eq!(txq.status().status.transaction_count, 0);
+	assert_eq!(txq.pending_nullifiers(), 0);
+}
+
 #[test]
 fn should_return_correct_nonces_when_dropped_because_of_limit_for_unshield() {
 	// given
@@ -724,6 +760,357 @@ fn should_move_all_transactions_from_future_for_private() {
 	assert_eq!(top.len(), 2);
 }
 
+#[test]
+fn should_record_shielded_verification_timings() {
+	// given
+	let txq = new_queue();
+	assert_eq!(txq.shielded_timings(), Default::default());
+
+	// when
+	let res = txq.import(TestClient::new(), vec![create_private(1_000_000)].local());
+	assert_eq!(res, vec![Ok(())]);
+
+	// then
+	let timings = txq.shielded_timings();
+	assert_eq!(timings.transactions, 1);
+	assert!(timings.total() > Default::default());
+}
+
+#[test]
+fn should_not_verify_proofs_of_a_returning_transaction_again() {
+	// given
+	let txq = new_queue();
+	let tx = create_private(1_000_000);
+	let hash = tx.hash();
+	assert_eq!(txq.import(TestClient::new(), vec![tx.clone()].local()), vec![Ok(())]);
+	assert_eq!(txq.shielded_timings().transactions, 1);
+	assert!(txq.is_shielded_verified(&hash));
+
+	// when
+	// mined, then back to the pool with its block retracted.
+	txq.remove(vec![&hash], false);
+	let res = txq.import(TestClient::new(), vec![tx.clone()].retracted());
+
+	// then
+	assert_eq!(res, vec![Ok(())]);
+	assert_eq!(txq.shielded_timings().transactions, 1);
+
+	// the anchor is still checked.
+	txq.remove(vec![&hash], false);
+	let res = txq.import(TestClient::new().with_expired_anchors(), vec![tx].retracted());
+	assert_eq!(res, vec![Err(transaction::Error::InvalidCommitmentAnchor)]);
+	assert_eq!(txq.shielded_timings().transactions, 1);
+}
+
+#[test]
+fn should_precheck_shielded_transactions() {
+	// given
+	let tx = create_private(1_000_000);
+	let nullifier = U256::from(tx.v_shielded_spend()[0].nullifier);
+	let no_pending = HashSet::new();
+	let valid_anchor = |_: &Node| true;
+
+	// then
+	assert_eq!(verifier::precheck_shielded_tx(&tx, &valid_anchor, &TestClient::new(), &no_pending), Ok(()));
+	assert_eq!(
+		verifier::precheck_shielded_tx(&tx, &|_: &Node| false, &TestClient::new(), &no_pending),
+		Err(ShieldedError::UnknownAnchor)
+	);
+	assert_eq!(
+		verifier::precheck_shielded_tx(&tx, &valid_anchor, &TestClient::new().with_nullifier(vec![nullifier]), &no_pending),
+		Err(ShieldedError::DoubleSpend)
+	);
+	let pending = vec![nullifier].into_iter().collect();
+	assert_eq!(
+		verifier::precheck_shielded_tx(&tx, &valid_anchor, &TestClient::new(), &pending),
+		Err(ShieldedError::DoubleSpend)
+	);
+
+	// the queue rejects it before verifying its proofs.
+	let txq = new_queue();
+	let res = txq.import(TestClient::new().with_nullifier(vec![nullifier]), vec![tx].local());
+	assert_eq!(res, vec![Err(transaction::Error::ConflictNullifier)]);
+	assert_eq!(txq.shielded_timings().transactions, 0);
+}
+
+#[test]
+fn should_reject_duplicate_nullifiers_within_transaction() {
+	// given
+	let tx = Transaction::create_private(vec![7, 7], 0.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+	let nullifier = U256::from([7u8; 32]);
+	let client = TestClient::new().with_nullifier(vec![nullifier]);
+
+	// when
+	let res = verifier::precheck_shielded_tx(&tx, &|_: &Node| true, &client, &HashSet::new());
+
+	// then
+	// reported before the nullifier is looked up in the set.
+	assert_eq!(res, Err(ShieldedError::DoubleSpend));
+}
+
+#[test]
+fn should_skip_verification_without_shielded_components() {
+	// given
+	let mut tx = Transaction::create_private(vec![], 0.into(), Action::PrivateToPrivate);
+	tx.private.as_mut().unwrap().outputs.clear();
+	let mut minting = tx.clone();
+	minting.private.as_mut().unwrap().balancing_value = 100;
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// then
+	assert_eq!(verifier::verify_private_transaction(&tx.sign_for_private(TEST_CHAIN_ID), &mut timings), Ok(()));
+	assert_eq!(
+		verifier::verify_private_transaction(&minting.sign_for_private(TEST_CHAIN_ID), &mut timings),
+		Err(ShieldedError::ValueBalance)
+	);
+	assert_eq!(timings.transactions, 2);
+}
+
+#[test]
+fn should_check_binding_signature_of_spends_only_transaction() {
+	// given
+	let tx = spends_only_transaction();
+	assert!(tx.v_shielded_spend().len() > 0);
+	assert!(tx.v_shielded_output().is_empty());
+	let mut tampered = tx.clone();
+	tampered.private.as_mut().unwrap().binding_sig[32] ^= 1;
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// then
+	assert_eq!(verifier::verify_private_transaction(&tx.sign_for_private(TEST_CHAIN_ID), &mut timings), Ok(()));
+	assert_eq!(
+		verifier::verify_private_transaction(&tampered.sign_for_private(TEST_CHAIN_ID), &mut timings),
+		Err(ShieldedError::ValueBalance)
+	);
+}
+
+#[test]
+fn should_check_binding_signature_of_outputs_only_transaction() {
+	// given
+	let tx = public_to_private_transaction(U256::from(1_000_000), U256::from(0));
+	assert!(tx.v_shielded_spend().is_empty());
+	assert!(tx.v_shielded_output().len() > 0);
+	let mut tampered = tx.as_unsigned().clone();
+	tampered.private.as_mut().unwrap().binding_sig[32] ^= 1;
+	let tampered = tampered.sign(Random.generate().unwrap().secret(), Some(TEST_CHAIN_ID));
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// then
+	assert_eq!(verifier::verify_private_transaction(&tx, &mut timings), Ok(()));
+	assert_eq!(
+		verifier::verify_private_transaction(&tampered, &mut timings),
+		Err(ShieldedError::ValueBalance)
+	);
+}
+
+#[test]
+fn should_return_cached_proof_verification_results() {
+	// given
+	let tx = public_to_private_transaction(U256::from(1_000_000), U256::from(0));
+	let mut tampered = tx.as_unsigned().clone();
+	// the proof no longer matches its public inputs.
+	tampered.private.as_mut().unwrap().outputs[0].cmu = Fr::from_str("1").unwrap();
+	let tampered = tampered.sign(Random.generate().unwrap().secret(), Some(TEST_CHAIN_ID));
+	let cache = Arc::new(StdMutex::new(ProofCache::new(16, *SPEND_VK_ID, *OUTPUT_VK_ID)));
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// when
+	let fresh = verifier::verify_private_transaction_with_cache(&tx, &mut timings, Some(&cache));
+	let fresh_tampered = verifier::verify_private_transaction_with_cache(&tampered, &mut timings, Some(&cache));
+	let cached = cache.lock().unwrap().len();
+
+	// then
+	assert_eq!(fresh, Ok(()));
+	assert_eq!(fresh_tampered, Err(ShieldedError::InvalidProof));
+	assert_eq!(cached, tx.v_shielded_output().len() + 1);
+	assert_eq!(verifier::verify_private_transaction_with_cache(&tx, &mut timings, Some(&cache)), fresh);
+	assert_eq!(verifier::verify_private_transaction_with_cache(&tampered, &mut timings, Some(&cache)), fresh_tampered);
+	assert_eq!(cache.lock().unwrap().len(), cached);
+}
+
+#[test]
+fn should_verify_proofs_on_shielded_verifier_threads() {
+	// given
+	let tx = public_to_private_transaction(U256::from(1_000_000), U256::from(0));
+	let spends = spends_only_transaction().sign_for_private(TEST_CHAIN_ID);
+	let mut tampered = tx.as_unsigned().clone();
+	tampered.private.as_mut().unwrap().outputs[0].cmu = Fr::from_str("1").unwrap();
+	let tampered = tampered.sign(Random.generate().unwrap().secret(), Some(TEST_CHAIN_ID));
+	let shielded_verifier = ShieldedVerifier::new(2);
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// then
+	for transaction in &[&tx, &spends, &tampered] {
+		assert_eq!(
+			verifier::verify_private_transaction_on(transaction, &mut timings, None, &shielded_verifier),
+			verifier::verify_private_transaction(transaction, &mut timings)
+		);
+	}
+	assert_eq!(
+		verifier::verify_private_transaction_on(&tampered, &mut timings, None, &shielded_verifier),
+		Err(ShieldedError::InvalidProof)
+	);
+	assert_eq!(timings.transactions, 7);
+}
+
+#[test]
+fn should_verify_no_proof_of_a_block_with_a_bad_anchor() {
+	// given
+	let first = create_private(1_000_000);
+	let second = create_private(1_000_000);
+	let bad_anchor = Node::new(first.v_shielded_spend()[0].anchor.into_repr());
+	let anchor_valid = |anchor: &Node| *anchor != bad_anchor;
+	let cache = Arc::new(StdMutex::new(ProofCache::new(16, *SPEND_VK_ID, *OUTPUT_VK_ID)));
+	let shielded_verifier = ShieldedVerifier::new(0);
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// when
+	let res = verifier::verify_block_shielded(
+		&[second.clone(), first.clone()],
+		&anchor_valid,
+		&mut timings,
+		Some(&cache),
+		&shielded_verifier,
+	);
+
+	// then
+	assert_eq!(res, Err(ShieldedError::UnknownAnchor));
+	assert_eq!(timings.transactions, 0);
+	assert!(cache.lock().unwrap().is_empty());
+
+	// a nullifier revealed twice in the block is caught before the proofs, too.
+	let res = verifier::verify_block_shielded(
+		&[second.clone(), second.clone()],
+		&anchor_valid,
+		&mut timings,
+		Some(&cache),
+		&shielded_verifier,
+	);
+	assert_eq!(res, Err(ShieldedError::DoubleSpend));
+	assert_eq!(timings.transactions, 0);
+
+	let res = verifier::verify_block_shielded(&[second], &anchor_valid, &mut timings, Some(&cache), &shielded_verifier);
+	assert_eq!(res, Ok(()));
+	assert_eq!(timings.transactions, 1);
+}
+
+#[test]
+fn should_not_verify_proofs_of_a_block_transaction_verified_before() {
+	// given
+	let tx = create_private(1_000_000);
+	let anchor = Node::new(tx.v_shielded_spend()[0].anchor.into_repr());
+	let cache = Arc::new(StdMutex::new(ProofCache::new(16, *SPEND_VK_ID, *OUTPUT_VK_ID)));
+	let shielded_verifier = ShieldedVerifier::new(0);
+	let mut timings = verifier::ShieldedTimings::default();
+	// as on admission to the pool.
+	assert_eq!(verifier::verify_private_transaction_with_cache(&tx, &mut timings, Some(&cache)), Ok(()));
+	verifier::record_verified_transaction(&cache, &tx.hash());
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// when
+	let res = verifier::verify_block_shielded(&[tx.clone()], &|_: &Node| true, &mut timings, Some(&cache), &shielded_verifier);
+
+	// then
+	assert_eq!(res, Ok(()));
+	assert_eq!(timings.transactions, 0);
+
+	// the anchor is still checked.
+	let expired = |root: &Node| *root != anchor;
+	let res = verifier::verify_block_shielded(&[tx], &expired, &mut timings, Some(&cache), &shielded_verifier);
+	assert_eq!(res, Err(ShieldedError::UnknownAnchor));
+}
+
+#[test]
+fn should_verify_a_transaction_of_a_compact_block_for_a_light_client() {
+	// given
+	let tx = create_private(1_000_000);
+	let compact_tx = CompactTx {
+		index: 0,
+		hash: tx.hash(),
+		spends: tx.v_shielded_spend().iter().map(CompactSpend::from).collect(),
+		outputs: tx.v_shielded_output().iter().map(CompactOutput::from).collect(),
+	};
+	let block = CompactBlock {
+		height: 1,
+		hash: 1.into(),
+		prev_hash: 0.into(),
+		time: 0,
+		vtx: vec![compact_tx.clone()],
+	};
+
+	let mut tree = CommitmentTree::new();
+	tree.append(Node::new(tx.v_shielded_output()[0].cmu.into_repr())).unwrap();
+	let witness = IncrementalWitness::from_tree(&tree);
+	let output_paths = vec![(0, witness.path().unwrap())];
+	let spend_anchor = Node::new(tx.v_shielded_spend()[0].anchor.into_repr());
+	let tree_root = witness.root();
+	let anchor_valid = |anchor: &Node| *anchor == spend_anchor || *anchor == tree_root;
+	let client = TestClient::new();
+	let light = verifier::LightShieldedVerifier::new(&anchor_valid, &client);
+
+	// when
+	let res = light.verify(&block, &tx, &output_paths);
+
+	// then
+	assert_eq!(res, Ok(()));
+
+	// a transaction the block does not list, or lists with other commitments.
+	let other = CompactBlock { vtx: vec![], ..block.clone() };
+	assert_eq!(light.verify(&other, &tx, &output_paths), Err(ShieldedError::NotInBlock));
+	let mut forged = compact_tx.clone();
+	forged.outputs[0].cmu = 0.into();
+	let other = CompactBlock { vtx: vec![forged], ..block.clone() };
+	assert_eq!(light.verify(&other, &tx, &output_paths), Err(ShieldedError::NotInBlock));
+
+	// a path to a root the client does not trust, or for a missing output.
+	let mut later = witness.clone();
+	later.append(Node::new(FrRepr::from(1))).unwrap();
+	let paths = vec![(0, later.path().unwrap())];
+	assert_eq!(light.verify(&block, &tx, &paths), Err(ShieldedError::UnknownCommitment));
+	let paths = vec![(1, witness.path().unwrap())];
+	assert_eq!(light.verify(&block, &tx, &paths), Err(ShieldedError::UnknownCommitment));
+
+	// an anchor or a nullifier the client was not given a proof for.
+	let anchor_unknown = |anchor: &Node| *anchor == tree_root;
+	let light_unknown = verifier::LightShieldedVerifier::new(&anchor_unknown, &client);
+	assert_eq!(light_unknown.verify(&block, &tx, &output_paths), Err(ShieldedError::UnknownAnchor));
+	let spent = TestClient::new().with_nullifier(vec![U256::from(tx.v_shielded_spend()[0].nullifier)]);
+	let light_spent = verifier::LightShieldedVerifier::new(&anchor_valid, &spent);
+	assert_eq!(light_spent.verify(&block, &tx, &output_paths), Err(ShieldedError::DoubleSpend));
+}
+
+#[test]
+fn should_reject_malformed_spend_without_panicking() {
+	// given
+	let tx = Transaction::create_private(vec![1], 0.into(), Action::PrivateToPrivate).sign_for_private(TEST_CHAIN_ID);
+	let mut timings = verifier::ShieldedTimings::default();
+
+	// then
+	assert_eq!(
+		verifier::verify_private_transaction(&tx, &mut timings),
+		Err(ShieldedError::CanonicalEncoding)
+	);
+}
+
+#[test]
+fn should_order_private_transactions_by_fee() {
+	// given
+	let txq = new_queue();
+	let low = create_private(1_000_000);
+	let high = create_private(2_000_000);
+	let high2 = create_private(2_000_000);
+	let hashes = vec![high.hash(), high2.hash(), low.hash()];
+
+	// when
+	let res = txq.import(TestClient::new(), vec![low, high, high2].retracted());
+	assert_eq!(res, vec![Ok(()), Ok(()), Ok(())]);
+
+	// then
+	// higher fee goes first, equal fees keep their arrival order.
+	let top = txq.pending(TestClient::new(), PendingSettings::all_prioritized(0, 0));
+	assert_eq!(top.iter().map(|tx| tx.hash).collect::<Vec<_>>(), hashes);
+}
+
 #[test]
 fn should_move_all_transactions_from_future() {
 	// given
@@ -1070,6 +1457,37 @@ fn should_prefer_current_transactions_when_hitting_the_limit() {
 	assert_eq!(txq.next_nonce(TestClient::new(), &sender), Some(124.into()));
 }
 
+#[test]
+fn should_return_min_gas_price_only_when_full() {
+	// given
+	let txq = TransactionQueue::new(
+		txpool::Options {
+			max_count: 2,
+			max_per_sender: 2,
+			max_mem_usage: 1 << 20,
+		},
+		verifier::Options {
+			minimal_gas_price: 1.into(),
+			block_gas_limit: 1_000_000.into(),
+			tx_gas_limit: 1_000_000.into(),
+			no_early_reject: false,
+		},
+		PrioritizationStrategy::GasPriceOnly,
+		None,
+	);
+	assert_eq!(txq.full_queue_min_gas_price(), None);
+
+	// when
+	let res = txq.import(TestClient::new(), vec![Tx::gas_price(3).signed().unverified()]);
+	assert_eq!(res, vec![Ok(())]);
+	assert_eq!(txq.full_queue_min_gas_price(), None);
+	let res = txq.import(TestClient::new(), vec![Tx::gas_price(2).signed().unverified()]);
+	assert_eq!(res, vec![Ok(())]);
+
+	// then
+	assert_eq!(txq.full_queue_min_gas_price(), Some(2.into()));
+}
+
 #[test]
 fn should_drop_transactions_with_old_nonces() {
 	let txq = new_queue();
@@ -23,27 +23,34 @@
 //! stalled transactions.
 
 use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
 use std::sync::Arc;
 use std::sync::atomic::{self, AtomicUsize};
+use std::time::{Duration, Instant};
 
 use ethereum_types::{U256, H256};
+use parking_lot::Mutex;
 use rlp::Encodable;
 use txpool;
+use types::compact_block::{CompactBlock, CompactOutput, CompactSpend};
 use types::transaction;
-use zcash_proofs::sapling::SaplingVerificationContext;
+use zcash_proofs::sapling::{SaplingVerificationContext, SharedProofCache, ShieldedError};
+use super::shielded_verifier::ShieldedVerifier;
 use bellman::groth16::Proof;
-use ethcore_blockchain::wallet::zkp::{OUTPUT_VK, SPEND_VK};
+use ethcore_blockchain::wallet::zkp::{OUTPUT_VK, OUTPUT_VK_ID, SPEND_VK, SPEND_VK_ID};
 use sapling_crypto::redjubjub::Signature;
 use zcash_primitives::{
 	merkle_tree::{CommitmentTreeWitness, IncrementalWitness},
 	note_encryption::{Memo, SaplingNoteEncryption},
 	sapling::{spend_sig, Node},
-	transaction::components::GROTH_PROOF_SIZE,
+	transaction::components::{OutputDescription, SpendDescription, GROTH_PROOF_SIZE},
 	JUBJUB,
 };
 use pairing::bls12_381::{Bls12, Fr};
+use ff::PrimeField;
 
-use super::client::{Client, TransactionType};
+use super::client::{Client, NullifierClient, TransactionType};
 use super::VerifiedTransaction;
 
 /// Verification options.
@@ -71,6 +78,33 @@ impl Default for Options {
 	}
 }
 
+/// Time spent in each phase of shielded transaction verification.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ShieldedTimings {
+	/// Number of shielded transactions verified.
+	pub transactions: u64,
+	/// Time spent checking spend proofs and signatures.
+	pub spends: Duration,
+	/// Time spent checking output proofs.
+	pub outputs: Duration,
+	/// Time spent checking the value balance and binding signature.
+	pub binding: Duration,
+}
+
+impl ShieldedTimings {
+	/// Total time spent verifying.
+	pub fn total(&self) -> Duration {
+		self.spends + self.outputs + self.binding
+	}
+
+	fn accrue(&mut self, other: &ShieldedTimings) {
+		self.transactions += other.transactions;
+		self.spends += other.spends;
+		self.outputs += other.outputs;
+		self.binding += other.binding;
+	}
+}
+
 /// Transaction to verify.
 #[cfg_attr(test, derive(Clone))]
 pub enum Transaction {
@@ -117,6 +151,15 @@ impl Transaction {
 		}
 	}
 
+	/// Return the transaction as received, before any verification.
+	pub fn unverified(&self) -> &transaction::UnverifiedTransaction {
+		match *self {
+			Transaction::Unverified(ref tx) => tx,
+			Transaction::Retracted(ref tx) => tx,
+			Transaction::Local(ref tx) => &tx.transaction.transaction,
+		}
+	}
+
 	fn transaction(&self) -> &transaction::Transaction {
 		match *self {
 			Transaction::Unverified(ref tx) => &*tx,
@@ -149,6 +192,9 @@ pub struct Verifier<C, S, V> {
 	options: Options,
 	id: Arc<AtomicUsize>,
 	transaction_to_replace: Option<(S, Arc<V>)>,
+	shielded_timings: Arc<Mutex<ShieldedTimings>>,
+	proof_cache: SharedProofCache,
+	shielded_verifier: Arc<ShieldedVerifier>,
 }
 
 impl<C, S, V> Verifier<C, S, V> {
@@ -158,12 +204,18 @@ impl<C, S, V> Verifier<C, S, V> {
 		options: Options,
 		id: Arc<AtomicUsize>,
 		transaction_to_replace: Option<(S, Arc<V>)>,
+		shielded_timings: Arc<Mutex<ShieldedTimings>>,
+		proof_cache: SharedProofCache,
+		shielded_verifier: Arc<ShieldedVerifier>,
 	) -> Self {
 		Verifier {
 			client,
 			options,
 			id,
 			transaction_to_replace,
+			shielded_timings,
+			proof_cache,
+			shielded_verifier,
 		}
 	}
 }
@@ -311,7 +363,25 @@ impl<C: Client> txpool::Verifier<Transaction> for Verifier<C, ::pool::scoring::N
 
 
 		if transaction.is_private() {
-			verify_private_transaction(&transaction.transaction)?;
+			// the anchors and nullifiers were checked again by the queue before.
+			if is_transaction_verified(&self.proof_cache, &hash) {
+				trace!(target: "txqueue", "[{:?}] Proofs already verified, skipping", hash);
+			} else {
+				let mut timings = ShieldedTimings::default();
+				// the pool tells which part of the transaction is invalid.
+				let result = check_private_transaction_on(
+					&transaction.transaction,
+					&mut timings,
+					Some(&self.proof_cache),
+					&self.shielded_verifier,
+				);
+				self.shielded_timings.lock().accrue(&timings);
+				result.map_err(|rejection| {
+					debug!(target: "txqueue", "[{:?}] Invalid shielded part: {:?}", hash, rejection);
+					transaction::Error::from(rejection)
+				})?;
+				record_verified_transaction(&self.proof_cache, &hash);
+			}
 			self.client.verify_private_transaction_basic(&transaction)?;
 		}
 		// For PrivateToPrivate and PrivateToPublic transaction without public sender,
@@ -360,51 +430,477 @@ impl<C: Client> txpool::Verifier<Transaction> for Verifier<C, ::pool::scoring::N
 	}
 }
 
-pub fn verify_private_transaction(transaction: &transaction::SignedTransaction) -> Result<(), transaction::Error> {
-	let mut ctx = SaplingVerificationContext::new();
-	let chain_id = transaction.transaction.chain_id();
-	let sighash = transaction.transaction.unsigned.hash(chain_id).into();
-	let spends = transaction.v_shielded_spend();
+/// Reason why a shielded transaction is refused by `precheck_shielded_tx`.
+#[derive(Debug, Clone, PartialEq)]
+enum AdmissionError {
+	/// The transaction reveals the same nullifier in two of its spends.
+	DuplicateNullifierInTx(U256),
+	/// The nullifier was already revealed on chain.
+	SpentNullifier(U256),
+	/// The nullifier is revealed by a queued transaction which would not be replaced.
+	PendingNullifier(U256),
+	/// A spend anchor is unknown or out of the anchor window.
+	InvalidAnchor,
+}
+
+impl fmt::Display for AdmissionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			AdmissionError::DuplicateNullifierInTx(ref n) => write!(f, "Nullifier {:x} revealed twice", n),
+			AdmissionError::SpentNullifier(ref n) => write!(f, "Nullifier {:x} already spent", n),
+			AdmissionError::PendingNullifier(ref n) => write!(f, "Nullifier {:x} spent by a queued transaction", n),
+			AdmissionError::InvalidAnchor => write!(f, "Invalid commitment anchor"),
+		}
+	}
+}
+
+impl From<AdmissionError> for ShieldedError {
+	fn from(err: AdmissionError) -> Self {
+		match err {
+			AdmissionError::DuplicateNullifierInTx(_)
+			| AdmissionError::SpentNullifier(_)
+			| AdmissionError::PendingNullifier(_) => ShieldedError::DoubleSpend,
+			AdmissionError::InvalidAnchor => ShieldedError::UnknownAnchor,
+		}
+	}
+}
+
+impl From<AdmissionError> for transaction::Error {
+	fn from(err: AdmissionError) -> Self {
+		match err {
+			AdmissionError::DuplicateNullifierInTx(_) =>
+				transaction::Error::InvalidPrivateTx(transaction::PrivateTxError::DuplicatedNullifier),
+			AdmissionError::SpentNullifier(_) | AdmissionError::PendingNullifier(_) =>
+				transaction::Error::ConflictNullifier,
+			AdmissionError::InvalidAnchor => transaction::Error::InvalidCommitmentAnchor,
+		}
+	}
+}
+
+/// Cheap admission checks of a shielded transaction, done before verifying its proofs.
+///
+/// Checks that its nullifiers are pairwise distinct, that none of them is in
+/// `nullifier_set` or in `pending_nullifiers` and that its anchors pass `anchor_history`.
+/// Its descriptions are decoded, and its proofs and signatures verified, only by
+/// `verify_private_transaction`.
+pub fn precheck_shielded_tx(
+	tx: &transaction::UnverifiedTransaction,
+	anchor_history: &Fn(&Node) -> bool,
+	nullifier_set: &NullifierClient,
+	pending_nullifiers: &HashSet<U256>,
+) -> Result<(), ShieldedError> {
+	check_shielded_admission(tx, anchor_history, nullifier_set, pending_nullifiers).map_err(|err| {
+		debug!(target: "txqueue", "[{:?}] Rejected private tx: {}", tx.hash(), err);
+		err.into()
+	})
+}
+
+fn check_shielded_admission(
+	tx: &transaction::UnverifiedTransaction,
+	anchor_history: &Fn(&Node) -> bool,
+	nullifier_set: &NullifierClient,
+	pending_nullifiers: &HashSet<U256>,
+) -> Result<(), AdmissionError> {
+	let spends = tx.v_shielded_spend();
+	let nullifiers = distinct_nullifiers(&spends)?;
+
+	for nullifier in nullifiers {
+		if nullifier_set.nullifier_exists(&nullifier) {
+			return Err(AdmissionError::SpentNullifier(nullifier));
+		}
+		if pending_nullifiers.contains(&nullifier) {
+			return Err(AdmissionError::PendingNullifier(nullifier));
+		}
+	}
+
 	for spend in spends.iter() {
-		let spend_proof = Proof::<Bls12>::read(&spend.zkproof[..]).unwrap();
-		if !(ctx.check_spend(
-			spend.cv,
-			spend.anchor,
-			&spend.nullifier,
-			spend.rk.clone(),
-			&sighash,
-			spend.spend_auth_sig.unwrap(),
-			spend_proof,
-			&SPEND_VK,
-			&JUBJUB,
-		)) {
-			bail!(transaction::Error::InvalidSpendDescription);
+		if !anchor_history(&Node::new(spend.anchor.into_repr())) {
+			return Err(AdmissionError::InvalidAnchor);
+		}
+	}
+
+	Ok(())
+}
+
+/// Collect the nullifiers of the given spends, in order, failing if one is repeated.
+fn distinct_nullifiers(spends: &[SpendDescription]) -> Result<Vec<U256>, AdmissionError> {
+	let mut seen = HashSet::with_capacity(spends.len());
+	let mut nullifiers = Vec::with_capacity(spends.len());
+	for spend in spends {
+		let nullifier = U256::from(spend.nullifier);
+		if !seen.insert(nullifier) {
+			return Err(AdmissionError::DuplicateNullifierInTx(nullifier));
+		}
+		nullifiers.push(nullifier);
+	}
+	Ok(nullifiers)
+}
+
+/// Verify the shielded part of a private transaction.
+///
+/// A transaction without spends or outputs has nothing to prove and skips the
+/// verification, but it must not move any value in or out of the pool. One with
+/// only spends or only outputs is still bound to its balancing value by the
+/// binding signature. Malformed proofs and signatures fail the verification of
+/// the description they belong to.
+///
+/// The time spent in each phase is added to `timings`, and logged under the
+/// `shielded::timing` target.
+pub fn verify_private_transaction(transaction: &transaction::SignedTransaction, timings: &mut ShieldedTimings) -> Result<(), ShieldedError> {
+	verify_private_transaction_with_cache(transaction, timings, None)
+}
+
+/// Verify the shielded part of a private transaction like `verify_private_transaction`,
+/// looking up and recording the result of each proof verification in `proof_cache`.
+///
+/// The cache is first switched to the current verifying keys, so results recorded
+/// for other keys are never returned.
+pub fn verify_private_transaction_with_cache(
+	transaction: &transaction::SignedTransaction,
+	timings: &mut ShieldedTimings,
+	proof_cache: Option<&SharedProofCache>,
+) -> Result<(), ShieldedError> {
+	verify_private_transaction_on(transaction, timings, proof_cache, &ShieldedVerifier::new(0))
+}
+
+/// Whether every proof and signature of the transaction `hash` passed verification
+/// already, according to `proof_cache`.
+pub fn is_transaction_verified(proof_cache: &SharedProofCache, hash: &H256) -> bool {
+	proof_cache.lock().expect("proof cache lock poisoned").is_transaction_verified(&hash.0)
+}
+
+/// Record in `proof_cache` that every proof and signature of the transaction `hash`
+/// passed verification.
+pub fn record_verified_transaction(proof_cache: &SharedProofCache, hash: &H256) {
+	proof_cache.lock().expect("proof cache lock poisoned").insert_verified_transaction(hash.0);
+}
+
+/// A context checking the descriptions of a transaction, recording in `cache` if any.
+fn verification_context(cache: Option<SharedProofCache>) -> SaplingVerificationContext {
+	match cache {
+		Some(cache) => SaplingVerificationContext::new().with_cache(cache),
+		None => SaplingVerificationContext::new(),
+	}
+}
+
+/// A shielded verification failure and the part of the transaction it was found in.
+#[derive(Debug)]
+enum Rejection {
+	Nullifiers(AdmissionError),
+	Spend(ShieldedError),
+	Output(ShieldedError),
+	Binding(ShieldedError),
+}
+
+impl From<Rejection> for transaction::Error {
+	fn from(rejection: Rejection) -> Self {
+		match rejection {
+			Rejection::Nullifiers(err) => err.into(),
+			Rejection::Spend(_) => transaction::Error::InvalidSpendDescription,
+			Rejection::Output(_) => transaction::Error::InvalidOutputDescription,
+			Rejection::Binding(_) => transaction::Error::InvalidBalancingValueOrBindingSig,
+		}
+	}
+}
+
+impl From<Rejection> for ShieldedError {
+	fn from(rejection: Rejection) -> Self {
+		match rejection {
+			Rejection::Nullifiers(err) => err.into(),
+			Rejection::Spend(err) | Rejection::Output(err) | Rejection::Binding(err) => err,
 		}
 	}
+}
+
+/// Verify the shielded part of a private transaction like `verify_private_transaction_with_cache`,
+/// checking the spends, and then the outputs, in parallel on the threads of `shielded_verifier`.
+///
+/// This blocks until every description is checked, and the binding signature is checked
+/// on the calling thread against the value commitments accumulated by all of them.
+pub fn verify_private_transaction_on(
+	transaction: &transaction::SignedTransaction,
+	timings: &mut ShieldedTimings,
+	proof_cache: Option<&SharedProofCache>,
+	shielded_verifier: &ShieldedVerifier,
+) -> Result<(), ShieldedError> {
+	check_private_transaction_on(transaction, timings, proof_cache, shielded_verifier).map_err(|rejection| {
+		debug!(target: "txqueue", "[{:?}] Invalid shielded part: {:?}", transaction.hash(), rejection);
+		rejection.into()
+	})
+}
+
+fn check_private_transaction_on(
+	transaction: &transaction::SignedTransaction,
+	timings: &mut ShieldedTimings,
+	proof_cache: Option<&SharedProofCache>,
+	shielded_verifier: &ShieldedVerifier,
+) -> Result<(), Rejection> {
+	timings.transactions += 1;
+	match decode_shielded(transaction)? {
+		Some(decoded) => {
+			distinct_nullifiers(&decoded.spends).map_err(Rejection::Nullifiers)?;
+			verify_decoded(transaction, decoded, timings, proof_cache, shielded_verifier)
+		},
+		None => Ok(()),
+	}
+}
+
+/// The shielded part of a private transaction, with its proofs and signatures decoded.
+struct DecodedShielded {
+	spends: Vec<SpendDescription>,
+	spend_proofs: Vec<Proof<Bls12>>,
+	outputs: Vec<OutputDescription>,
+	output_proofs: Vec<Proof<Bls12>>,
+	binding_sig: Signature,
+}
 
+// Decode the proofs and signatures of the shielded part of `transaction`, or `None` if it
+// has neither spends nor outputs, and so nothing to prove.
+fn decode_shielded(transaction: &transaction::SignedTransaction) -> Result<Option<DecodedShielded>, Rejection> {
+	let spends = transaction.v_shielded_spend();
 	let outputs = transaction.v_shielded_output();
-	for output in outputs.iter() {
-		let zkproof = Proof::<Bls12>::read(&output.zkproof[..]).unwrap();
-		if !(ctx.check_output(
-			output.cv,
-			output.cmu,
-			output.ephemeral_key,
-			zkproof,
-			&OUTPUT_VK,
-			&JUBJUB,
-		)) {
-			bail!(transaction::Error::InvalidOutputDescription);
+	if spends.is_empty() && outputs.is_empty() {
+		trace!(target: "shielded::timing", "[{:?}] no shielded components to verify", transaction.hash());
+		if transaction.balancing_value() != 0 {
+			return Err(Rejection::Binding(ShieldedError::ValueBalance));
+		}
+		return Ok(None);
+	}
+
+	let spend_proofs = spends.iter().map(|spend| {
+		if spend.spend_auth_sig.is_none() {
+			return Err(Rejection::Spend(ShieldedError::MalformedDescription));
+		}
+		Proof::<Bls12>::read(&spend.zkproof[..]).map_err(|_| Rejection::Spend(ShieldedError::CanonicalEncoding))
+	}).collect::<Result<Vec<_>, _>>()?;
+	let output_proofs = outputs.iter().map(|output| {
+		Proof::<Bls12>::read(&output.zkproof[..]).map_err(|_| Rejection::Output(ShieldedError::CanonicalEncoding))
+	}).collect::<Result<Vec<_>, _>>()?;
+	let binding_sig = Signature::read(&transaction.binding_sig()[..])
+		.map_err(|_| Rejection::Binding(ShieldedError::CanonicalEncoding))?;
+
+	Ok(Some(DecodedShielded { spends, spend_proofs, outputs, output_proofs, binding_sig }))
+}
+
+// Verify the decoded proofs and signatures of `transaction`.
+fn verify_decoded(
+	transaction: &transaction::SignedTransaction,
+	decoded: DecodedShielded,
+	timings: &mut ShieldedTimings,
+	proof_cache: Option<&SharedProofCache>,
+	shielded_verifier: &ShieldedVerifier,
+) -> Result<(), Rejection> {
+	let hash = transaction.hash();
+	if let Some(cache) = proof_cache {
+		cache.lock().expect("proof cache lock poisoned").set_verifying_keys(*SPEND_VK_ID, *OUTPUT_VK_ID);
+	}
+	let mut ctx = SaplingVerificationContext::new();
+	let chain_id = transaction.transaction.chain_id();
+	let sighash = transaction.transaction.unsigned.shielded_sighash(chain_id);
+	let DecodedShielded { spends, spend_proofs, outputs, output_proofs, binding_sig } = decoded;
+
+	let start = Instant::now();
+	let pending: Vec<_> = spends.into_iter().zip(spend_proofs).map(|(spend, spend_proof)| {
+		let cache = proof_cache.cloned();
+		shielded_verifier.execute(move || {
+			let spend_auth_sig = spend.spend_auth_sig.ok_or(ShieldedError::MalformedDescription)?;
+			let mut ctx = verification_context(cache);
+			ctx.verify_spend(
+				spend.cv,
+				spend.anchor,
+				&spend.nullifier,
+				spend.rk.clone(),
+				&sighash,
+				spend_auth_sig,
+				spend_proof,
+				&SPEND_VK,
+				&JUBJUB,
+			).map(|_| ctx)
+		})
+	}).collect();
+	let spends_count = pending.len();
+	for result in pending {
+		match result.recv() {
+			Ok(Ok(spend_ctx)) => ctx.merge(&spend_ctx, &JUBJUB),
+			Ok(Err(e)) => return Err(Rejection::Spend(e)),
+			// the verification panicked.
+			Err(_) => return Err(Rejection::Spend(ShieldedError::InvalidProof)),
 		}
 	}
+	let spends_time = start.elapsed();
+	timings.spends += spends_time;
+	trace!(target: "shielded::timing", "[{:?}] {} spends verified in {:?}", hash, spends_count, spends_time);
+
+	let start = Instant::now();
+	let pending: Vec<_> = outputs.into_iter().zip(output_proofs).map(|(output, zkproof)| {
+		let cache = proof_cache.cloned();
+		shielded_verifier.execute(move || {
+			let mut ctx = verification_context(cache);
+			ctx.verify_output(
+				output.cv,
+				output.cmu,
+				output.ephemeral_key,
+				zkproof,
+				&OUTPUT_VK,
+				&JUBJUB,
+			).map(|_| ctx)
+		})
+	}).collect();
+	let outputs_count = pending.len();
+	for result in pending {
+		match result.recv() {
+			Ok(Ok(output_ctx)) => ctx.merge(&output_ctx, &JUBJUB),
+			Ok(Err(e)) => return Err(Rejection::Output(e)),
+			Err(_) => return Err(Rejection::Output(ShieldedError::InvalidProof)),
+		}
+	}
+	let outputs_time = start.elapsed();
+	timings.outputs += outputs_time;
+	trace!(target: "shielded::timing", "[{:?}] {} outputs verified in {:?}", hash, outputs_count, outputs_time);
 
-	if !(ctx.final_check(
+	let start = Instant::now();
+	let binding = ctx.verify_final(
 		transaction.balancing_value(),
 		&sighash,
-		Signature::read(&transaction.binding_sig()[..]).unwrap(),
+		binding_sig,
 		&JUBJUB,
-	)) {
-		bail!(transaction::Error::InvalidBalancingValueOrBindingSig);
+	);
+	let binding_time = start.elapsed();
+	timings.binding += binding_time;
+	trace!(target: "shielded::timing", "[{:?}] binding signature verified in {:?}", hash, binding_time);
+
+	binding.map_err(Rejection::Binding)
+}
+
+/// Verify the shielded components of the transactions of a block, in order, stopping at
+/// the first failure.
+///
+/// The cheap checks of every transaction come first: that its proofs and signatures
+/// decode, that its anchors pass `anchor_valid` and that none of its nullifiers is
+/// revealed twice in the block. Only then are the decoded proofs and signatures verified,
+/// like `verify_private_transaction_on`, so a block failing a cheap check costs no pairing.
+///
+/// A transaction which `proof_cache` knows passed, as on admission to the pool, only has
+/// its cheap checks run, and the transactions which pass are recorded there.
+pub fn verify_block_shielded(
+	transactions: &[transaction::SignedTransaction],
+	anchor_valid: &Fn(&Node) -> bool,
+	timings: &mut ShieldedTimings,
+	proof_cache: Option<&SharedProofCache>,
+	shielded_verifier: &ShieldedVerifier,
+) -> Result<(), ShieldedError> {
+	let mut decoded = Vec::new();
+	let mut nullifiers = HashSet::new();
+	for tx in transactions.iter().filter(|tx| tx.is_private()) {
+		let shielded = decode_shielded(tx)?;
+		if let Some(ref shielded) = shielded {
+			if shielded.spends.iter().any(|spend| !anchor_valid(&Node::new(spend.anchor.into_repr()))) {
+				return Err(ShieldedError::UnknownAnchor);
+			}
+			if !shielded.spends.iter().all(|spend| nullifiers.insert(U256::from(spend.nullifier))) {
+				return Err(ShieldedError::DoubleSpend);
+			}
+		}
+		decoded.push((tx, shielded));
 	}
 
+	for (tx, shielded) in decoded {
+		// the anchors and nullifiers were checked above.
+		if proof_cache.map_or(false, |cache| is_transaction_verified(cache, &tx.hash())) {
+			continue;
+		}
+		timings.transactions += 1;
+		if let Some(shielded) = shielded {
+			verify_decoded(tx, shielded, timings, proof_cache, shielded_verifier).map_err(|rejection| {
+				debug!(target: "txqueue", "[{:?}] Invalid shielded part in block: {:?}", tx.hash(), rejection);
+				ShieldedError::from(rejection)
+			})?;
+			if let Some(cache) = proof_cache {
+				record_verified_transaction(cache, &tx.hash());
+			}
+		}
+	}
 	Ok(())
 }
+
+/// Verifies the shielded part of a single transaction for a light client, which follows
+/// compact blocks and holds neither the nullifier set nor the commitment tree.
+///
+/// The proofs, the spend authorization signatures, the binding signature and the value
+/// balance are checked exactly as `verify_private_transaction` checks them, so a
+/// transaction passing verification spends notes under `anchor_valid` with their keys
+/// and creates no value. What a full node checks against its own chain is supplied
+/// instead, and is only as trustworthy as its source:
+///
+/// - a spend anchor passes if `anchor_valid` accepts it, where a full node requires the
+///   root of its own commitment tree within the anchor window. The client must learn
+///   the roots it accepts from a source it trusts, as the headers do not commit to them.
+/// - a nullifier is unspent if `nullifiers` says so, from the non-membership proof the
+///   client was given, where a full node looks it up in the nullifier set of its chain.
+///   Nothing in the chain commits to the nullifier set, so a source lying about a
+///   nullifier goes unnoticed.
+/// - the transaction is in the block if the compact block lists it, with the same
+///   nullifiers and note commitments. The compact block is not checked against the
+///   transactions root of the header, so it is trusted as a whole.
+/// - the output paths given prove that the note commitments of those outputs are in a
+///   tree whose root `anchor_valid` accepts, which a full node knows from appending them.
+///
+/// The public part of the transaction, its nonce, gas and transfers, is not checked.
+pub struct LightShieldedVerifier<'a> {
+	anchor_valid: &'a Fn(&Node) -> bool,
+	nullifiers: &'a NullifierClient,
+}
+
+impl<'a> LightShieldedVerifier<'a> {
+	/// Create a verifier accepting the commitment tree roots which pass `anchor_valid` and
+	/// the nullifiers which `nullifiers` does not know.
+	pub fn new(anchor_valid: &'a Fn(&Node) -> bool, nullifiers: &'a NullifierClient) -> Self {
+		LightShieldedVerifier {
+			anchor_valid,
+			nullifiers,
+		}
+	}
+
+	/// Verify the shielded part of `transaction`, which `block` is claimed to contain, with
+	/// `output_paths` proving that the outputs at the given indices are in the tree.
+	///
+	/// The cheap checks come first, so the proofs are only verified for a transaction which
+	/// is in the block and whose anchors, nullifiers and paths pass.
+	pub fn verify(
+		&self,
+		block: &CompactBlock,
+		transaction: &transaction::SignedTransaction,
+		output_paths: &[(usize, CommitmentTreeWitness<Node>)],
+	) -> Result<(), ShieldedError> {
+		let spends = transaction.v_shielded_spend();
+		let outputs = transaction.v_shielded_output();
+
+		let compact = block.vtx.iter()
+			.find(|compact| compact.hash == transaction.hash())
+			.ok_or(ShieldedError::NotInBlock)?;
+		if compact.spends != spends.iter().map(CompactSpend::from).collect::<Vec<_>>()
+			|| compact.outputs != outputs.iter().map(CompactOutput::from).collect::<Vec<_>>()
+		{
+			return Err(ShieldedError::NotInBlock);
+		}
+
+		for &(index, ref path) in output_paths {
+			let cmu = outputs.get(index).ok_or(ShieldedError::UnknownCommitment)?.cmu;
+			match path.root(Node::new(cmu.into_repr())) {
+				Some(ref root) if (self.anchor_valid)(root) => {},
+				_ => return Err(ShieldedError::UnknownCommitment),
+			}
+		}
+
+		precheck_shielded_tx(transaction, self.anchor_valid, self.nullifiers, &HashSet::new())?;
+		if Signature::read(&transaction.binding_sig()[..]).is_err() {
+			return Err(ShieldedError::CanonicalEncoding);
+		}
+
+		check_private_transaction_on(transaction, &mut ShieldedTimings::default(), None, &ShieldedVerifier::new(0))
+			.map_err(|rejection| {
+				debug!(target: "txqueue", "[{:?}] Invalid shielded part for a light client: {:?}", transaction.hash(), rejection);
+				ShieldedError::from(rejection)
+			})
+	}
+}
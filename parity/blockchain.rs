@@ -116,6 +116,7 @@ pub struct ImportBlockchain {
 	pub verifier_settings: VerifierSettings,
 	pub light: bool,
 	pub max_round_blocks_to_import: usize,
+	pub trusted: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -402,6 +403,7 @@ fn execute_import(cmd: ImportBlockchain) -> Result<(), String> {
 	drop(spec);
 
 	let client = service.client();
+	let trusted = cmd.trusted;
 
 	let mut instream: Box<io::Read> = match cmd.file_path {
 		Some(f) => Box::new(fs::File::open(&f).map_err(|_| format!("Cannot open given file: {}", f))?),
@@ -440,7 +442,12 @@ fn execute_import(cmd: ImportBlockchain) -> Result<(), String> {
 	let do_import = |bytes| {
 		let block = Unverified::from_rlp(bytes).map_err(|_| "Invalid block rlp")?;
 		while client.queue_info().is_full() { sleep(Duration::from_secs(1)); }
-		match client.import_block(block) {
+		let imported = if trusted {
+			client.import_trusted_block(block)
+		} else {
+			client.import_block(block)
+		};
+		match imported {
 			Err(EthcoreError(EthcoreErrorKind::Import(ImportErrorKind::AlreadyInChain), _)) => {
 				trace!("Skipping block already in chain.");
 			}
@@ -85,6 +85,10 @@ usage! {
 			ARG arg_import_file: (Option<String>) = None,
 			"[FILE]",
 			"Path to the file to import from",
+
+			FLAG flag_import_trusted: (bool) = false,
+			"--trusted",
+			"Do not verify the shielded proofs of the imported blocks again. Only use it for a file exported by a node which verified them.",
 		}
 
 		CMD cmd_export
@@ -310,6 +314,14 @@ usage! {
 			"--wallet-path=[PATH]",
 			"Specify the path for wallet files to be found",
 
+			ARG arg_max_concurrent_rescans: (usize) = 1usize, or |c: &Config| c.parity.as_ref()?.max_concurrent_rescans.clone(),
+			"--max-concurrent-rescans=[NUM]",
+			"Specify the number of wallet rescans which may walk the chain at the same time.",
+
+			ARG arg_max_commitment_tree_lag: (u64) = 8u64, or |c: &Config| c.parity.as_ref()?.max_commitment_tree_lag.clone(),
+			"--max-commitment-tree-lag=[NUM]",
+			"Specify the number of blocks the commitment tree may fall behind the best block before the node reports it as stale.",
+
 			ARG arg_identity: (String) = "", or |c: &Config| c.parity.as_ref()?.identity.clone(),
 			"--identity=[NAME]",
 			"Specify your node's name.",
@@ -386,6 +398,10 @@ usage! {
 			"--no-serve-light",
 			"Disable serving of light peers.",
 
+			ARG arg_max_peer_transactions_per_sec: (Option<usize>) = None, or |c: &Config| c.network.as_ref()?.max_peer_transactions_per_sec.clone(),
+			"--max-peer-transactions-per-sec=[NUM]",
+			"Relay at most NUM transactions per second to a single peer. Unlimited if not set.",
+
 			ARG arg_warp_barrier: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.warp_barrier.clone(),
 			"--warp-barrier=[NUM]",
 			"When warp enabled never attempt regular sync before warping to block NUM.",
@@ -789,6 +805,14 @@ usage! {
 			"--tx-time-limit=[MS]",
 			"Maximal time for processing single transaction. If enabled senders of transactions offending the limit will get other transactions penalized.",
 
+			ARG arg_shielded_verifier_threads: (Option<usize>) = None, or |c: &Config| c.mining.as_ref()?.shielded_verifier_threads.clone(),
+			"--shielded-verifier-threads=[NUM]",
+			"Number of threads verifying the proofs of shielded transactions imported to the queue, 0 to verify them on the importing thread. Defaults to the number of CPUs minus one.",
+
+			ARG arg_max_block_bytes: (Option<usize>) = None, or |c: &Config| c.mining.as_ref()?.max_block_bytes.clone(),
+			"--max-block-bytes=[BYTES]",
+			"Maximal total size in bytes of the transactions of an authored block. Unlimited by default.",
+
 			ARG arg_extra_data: (Option<String>) = None, or |c: &Config| c.mining.as_ref()?.extra_data.clone(),
 			"--extra-data=[STRING]",
 			"Specify a custom extra-data for authored blocks, no more than 32 characters.",
@@ -836,6 +860,14 @@ usage! {
 			"--scale-verifiers",
 			"Automatically scale amount of verifier threads based on workload. Not guaranteed to be faster.",
 
+			FLAG flag_catch_verification_panics: (bool) = false, or |c: &Config| c.footprint.as_ref()?.catch_verification_panics.clone(),
+			"--catch-verification-panics",
+			"Reject a block whose transaction verification panics instead of stalling the block queue behind it.",
+
+			FLAG flag_shielded_benchmark: (bool) = false, or |c: &Config| c.footprint.as_ref()?.shielded_benchmark.clone(),
+			"--shielded-benchmark",
+			"Time the Pedersen hash and the shielded proof verification at startup and log their throughput, to check that this hardware keeps up with the shielded transactions of the chain.",
+
 			ARG arg_tracing: (String) = "auto", or |c: &Config| c.footprint.as_ref()?.tracing.clone(),
 			"--tracing=[BOOL]",
 			"Indicates if full transaction tracing should be enabled. Works only if client had been fully synced with tracing enabled. BOOL may be one of auto, on, off. auto uses last used value of this option (off if it does not exist).", // footprint option
@@ -884,6 +916,14 @@ usage! {
 			"--num-verifiers=[INT]",
 			"Amount of verifier threads to use or to begin with, if verifier auto-scaling is enabled.",
 
+			ARG arg_tree_checkpoint_interval: (u64) = 10000u64, or |c: &Config| c.footprint.as_ref()?.tree_checkpoint_interval.clone(),
+			"--tree-checkpoint-interval=[NUM]",
+			"Specify the number of blocks between two stored commitment tree checkpoints, 0 to store none.",
+
+			ARG arg_anchor_history_margin: (u64) = 1024u64, or |c: &Config| c.footprint.as_ref()?.anchor_history_margin.clone(),
+			"--anchor-history-margin=[NUM]",
+			"Specify the number of blocks older than the anchor window whose commitment roots are kept before they are pruned.",
+
 		["Import/export Options"]
 			FLAG flag_no_seal_check: (bool) = false, or |_| None,
 			"--no-seal-check",
@@ -898,6 +938,14 @@ usage! {
 			"--snapshot-threads=[NUM]",
 			"Enables multiple threads for snapshots creation.",
 
+			ARG arg_snapshot_compression: (String) = "snappy", or |c: &Config| c.snapshots.as_ref()?.compression.clone(),
+			"--snapshot-compression=[METHOD]",
+			"Compression of the chunks of the snapshots taken. METHOD may be none, snappy, zstd (level 3) or zstd:LEVEL, with LEVEL from 1 to 22.",
+
+			ARG arg_snapshot_startup_grace: (u64) = 300u64, or |c: &Config| c.snapshots.as_ref()?.startup_grace.clone(),
+			"--snapshot-startup-grace=[SECS]",
+			"Specify the time after startup during which no periodic snapshot is taken, so that it does not compete with the initial sync.",
+
 		["Whisper Options"]
 			FLAG flag_whisper: (bool) = false, or |c: &Config| c.whisper.as_ref()?.enabled,
 			"--whisper",
@@ -1152,6 +1200,8 @@ struct Operating {
 	db_path: Option<String>,
 	keys_path: Option<String>,
 	wallet_path: Option<String>,
+	max_concurrent_rescans: Option<usize>,
+	max_commitment_tree_lag: Option<u64>,
 	identity: Option<String>,
 	light: Option<bool>,
 	no_persistent_txqueue: Option<bool>,
@@ -1221,6 +1271,7 @@ struct Network {
 	reserved_peers: Option<String>,
 	reserved_only: Option<bool>,
 	no_serve_light: Option<bool>,
+	max_peer_transactions_per_sec: Option<usize>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -1328,6 +1379,8 @@ struct Mining {
 	work_queue_size: Option<usize>,
 	tx_gas_limit: Option<String>,
 	tx_time_limit: Option<u64>,
+	shielded_verifier_threads: Option<usize>,
+	max_block_bytes: Option<usize>,
 	relay_set: Option<String>,
 	min_gas_price: Option<u64>,
 	gas_price_percentile: Option<usize>,
@@ -1378,7 +1431,11 @@ struct Footprint {
 	db_compaction: Option<String>,
 	fat_db: Option<String>,
 	scale_verifiers: Option<bool>,
+	catch_verification_panics: Option<bool>,
+	shielded_benchmark: Option<bool>,
 	num_verifiers: Option<usize>,
+	tree_checkpoint_interval: Option<u64>,
+	anchor_history_margin: Option<u64>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -1386,6 +1443,8 @@ struct Footprint {
 struct Snapshots {
 	disable_periodic: Option<bool>,
 	processing_threads: Option<usize>,
+	compression: Option<String>,
+	startup_grace: Option<u64>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -1691,6 +1750,7 @@ mod tests {
 			arg_daemon_pid_file: None,
 			arg_import_file: None,
 			arg_import_format: None,
+			flag_import_trusted: false,
 			arg_export_blocks_file: None,
 			arg_export_blocks_format: None,
 			arg_export_state_file: None,
@@ -1722,6 +1782,8 @@ mod tests {
 			arg_db_path: Some("$HOME/.origo/chains".into()),
 			arg_keys_path: "$HOME/.origo/keys".into(),
 			arg_wallet_path: "$HOME/.origo/wallet".into(),
+			arg_max_concurrent_rescans: 1usize,
+			arg_max_commitment_tree_lag: 8u64,
 			arg_identity: "".into(),
 			flag_light: false,
 			flag_no_hardcoded_sync: false,
@@ -1767,6 +1829,7 @@ mod tests {
 			flag_reserved_only: false,
 			flag_no_ancient_blocks: false,
 			flag_no_serve_light: false,
+			arg_max_peer_transactions_per_sec: None,
 
 			// -- API and Console Options
 			// RPC
@@ -1840,6 +1903,8 @@ mod tests {
 			arg_work_queue_size: 20usize,
 			arg_tx_gas_limit: Some("10000000".into()),
 			arg_tx_time_limit: Some(100u64),
+			arg_shielded_verifier_threads: None,
+			arg_max_block_bytes: None,
 			arg_relay_set: "cheap".into(),
 			arg_min_gas_price: Some(0u64),
 			arg_usd_per_tx: "0.0001".into(),
@@ -1883,7 +1948,11 @@ mod tests {
 			arg_db_compaction: "ssd".into(),
 			arg_fat_db: "auto".into(),
 			flag_scale_verifiers: true,
+			flag_catch_verification_panics: false,
+			flag_shielded_benchmark: false,
 			arg_num_verifiers: Some(6),
+			arg_tree_checkpoint_interval: 10000u64,
+			arg_anchor_history_margin: 1024u64,
 
 			// -- Import/Export Options
 			arg_export_blocks_from: "1".into(),
@@ -1899,6 +1968,8 @@ mod tests {
 			arg_snapshot_at: "latest".into(),
 			flag_no_periodic_snapshot: false,
 			arg_snapshot_threads: None,
+			arg_snapshot_compression: "snappy".into(),
+			arg_snapshot_startup_grace: 300u64,
 
 			// -- Light options.
 			arg_on_demand_response_time_window: Some(2),
@@ -2001,6 +2072,8 @@ mod tests {
 				db_path: None,
 				keys_path: None,
 				wallet_path: None,
+				max_concurrent_rescans: None,
+				max_commitment_tree_lag: None,
 				identity: None,
 				light: None,
 				no_hardcoded_sync: None,
@@ -2041,6 +2114,7 @@ mod tests {
 				reserved_peers: Some("./path/to/reserved_peers".into()),
 				reserved_only: Some(true),
 				no_serve_light: None,
+				max_peer_transactions_per_sec: None,
 			}),
 			websockets: Some(Ws {
 				disable: Some(true),
@@ -2136,6 +2210,8 @@ mod tests {
 				tx_queue_no_early_reject: None,
 				tx_gas_limit: None,
 				tx_time_limit: None,
+				shielded_verifier_threads: None,
+				max_block_bytes: None,
 				extra_data: None,
 				remove_solved: None,
 				notify_work: None,
@@ -2157,7 +2233,11 @@ mod tests {
 				db_compaction: Some("ssd".into()),
 				fat_db: Some("off".into()),
 				scale_verifiers: Some(false),
+				catch_verification_panics: None,
+				shielded_benchmark: None,
 				num_verifiers: None,
+				tree_checkpoint_interval: None,
+				anchor_history_margin: None,
 			}),
 			light: Some(Light {
 				on_demand_response_time_window: Some(2),
@@ -2169,6 +2249,8 @@ mod tests {
 			snapshots: Some(Snapshots {
 				disable_periodic: Some(true),
 				processing_threads: None,
+				compression: None,
+				startup_grace: None,
 			}),
 			misc: Some(Misc {
 				logging: Some("own_tx=trace".into()),
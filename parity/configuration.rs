@@ -50,7 +50,7 @@ use export_hardcoded_sync::ExportHsyncCmd;
 use helpers::{
 	geth_ipc_path, parity_ipc_path, to_address, to_addresses, to_block_id, to_bootnodes,
 	to_duration, to_mode, to_pending_set, to_price, to_queue_penalization, to_queue_strategy,
-	to_u256,
+	to_snapshot_compression, to_u256,
 };
 use ipfs::Configuration as IpfsConfiguration;
 use network::IpFilter;
@@ -290,6 +290,7 @@ impl Configuration {
 				verifier_settings: self.verifier_settings(),
 				light: self.args.flag_light,
 				max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
+				trusted: self.args.flag_import_trusted,
 			};
 			Cmd::Blockchain(BlockchainCmd::Import(import_cmd))
 		} else if self.args.cmd_export {
@@ -443,7 +444,14 @@ impl Configuration {
 				check_seal: !self.args.flag_no_seal_check,
 				download_old_blocks: !self.args.flag_no_ancient_blocks,
 				verifier_settings: verifier_settings,
+				catch_verification_panics: self.args.flag_catch_verification_panics,
+				shielded_benchmark_at_startup: self.args.flag_shielded_benchmark,
+				max_concurrent_rescans: self.args.arg_max_concurrent_rescans,
+				max_commitment_tree_lag: self.args.arg_max_commitment_tree_lag,
+				tree_checkpoint_interval: self.args.arg_tree_checkpoint_interval,
+				anchor_history_margin: self.args.arg_anchor_history_margin,
 				serve_light: !self.args.flag_no_serve_light,
+				max_peer_transactions_per_sec: self.args.arg_max_peer_transactions_per_sec,
 				light: self.args.flag_light,
 				no_persistent_txqueue: self.args.flag_no_persistent_txqueue,
 				whisper: whisper_config,
@@ -656,6 +664,9 @@ impl Configuration {
 
 			pool_limits: self.pool_limits()?,
 			pool_verification_options: self.pool_verification_options()?,
+			shielded_verifier_threads: self.args.arg_shielded_verifier_threads
+				.unwrap_or_else(pool::shielded_verifier::default_threads),
+			max_block_bytes: self.args.arg_max_block_bytes.unwrap_or_else(usize::max_value),
 		};
 
 		Ok(options)
@@ -1038,6 +1049,8 @@ impl Configuration {
 				Some(threads) if threads > 0 => threads,
 				_ => ::std::cmp::max(1, num_cpus::get() / 2),
 			},
+			compression: to_snapshot_compression(&self.args.arg_snapshot_compression)?,
+			startup_grace: Duration::from_secs(self.args.arg_snapshot_startup_grace),
 		};
 
 		Ok(conf)
@@ -1501,6 +1514,7 @@ mod tests {
 				verifier_settings: Default::default(),
 				light: false,
 				max_round_blocks_to_import: 12,
+				trusted: false,
 			}))
 		);
 	}
@@ -1692,7 +1706,14 @@ mod tests {
 			check_seal: true,
 			download_old_blocks: true,
 			verifier_settings: Default::default(),
+			catch_verification_panics: false,
+			shielded_benchmark_at_startup: false,
+			max_concurrent_rescans: 1,
+			max_commitment_tree_lag: 8,
+			tree_checkpoint_interval: 10000,
+			anchor_history_margin: 1024,
 			serve_light: true,
+			max_peer_transactions_per_sec: None,
 			light: false,
 			no_hardcoded_sync: false,
 			no_persistent_txqueue: false,
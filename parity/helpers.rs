@@ -23,6 +23,7 @@ use ethereum_types::{U256, clean_0x, Address};
 use journaldb::Algorithm;
 use ethcore::client::{Mode, BlockId, VMType, DatabaseCompactionProfile, ClientConfig, VerifierType};
 use ethcore::miner::{PendingSet, Penalization};
+use ethcore::snapshot::SnapshotCompression;
 use miner::pool::PrioritizationStrategy;
 use cache::CacheConfig;
 use dir::DatabaseDirectories;
@@ -98,6 +99,22 @@ pub fn to_pending_set(s: &str) -> Result<PendingSet, String> {
 	}
 }
 
+/// Default zstd level of `to_snapshot_compression`.
+const DEFAULT_ZSTD_LEVEL: u8 = 3;
+
+pub fn to_snapshot_compression(s: &str) -> Result<SnapshotCompression, String> {
+	match s {
+		"none" => Ok(SnapshotCompression::None),
+		"snappy" => Ok(SnapshotCompression::Snappy),
+		"zstd" => Ok(SnapshotCompression::Zstd { level: DEFAULT_ZSTD_LEVEL }),
+		other if other.starts_with("zstd:") => other["zstd:".len()..].parse()
+			.ok()
+			.and_then(SnapshotCompression::zstd)
+			.ok_or_else(|| format!("Invalid zstd level: {:?}, must be between 1 and 22", &other["zstd:".len()..])),
+		other => Err(format!("Invalid snapshot compression: {:?}", other)),
+	}
+}
+
 pub fn to_queue_strategy(s: &str) -> Result<PrioritizationStrategy, String> {
 	match s {
 		"gas_price" => Ok(PrioritizationStrategy::GasPriceOnly),
@@ -340,8 +357,9 @@ mod tests {
 	use ethereum_types::U256;
 	use ethcore::client::{Mode, BlockId};
 	use ethcore::miner::PendingSet;
+	use ethcore::snapshot::SnapshotCompression;
 	use ethkey::Password;
-	use super::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_address, to_addresses, to_price, geth_ipc_path, to_bootnodes, join_set, password_from_file};
+	use super::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_snapshot_compression, to_address, to_addresses, to_price, geth_ipc_path, to_bootnodes, join_set, password_from_file};
 
 	#[test]
 	fn test_to_duration() {
@@ -373,6 +391,18 @@ mod tests {
 		assert!(to_mode("other", 20, 30).is_err());
 	}
 
+	#[test]
+	fn test_to_snapshot_compression() {
+		assert_eq!(to_snapshot_compression("none").unwrap(), SnapshotCompression::None);
+		assert_eq!(to_snapshot_compression("snappy").unwrap(), SnapshotCompression::Snappy);
+		assert_eq!(to_snapshot_compression("zstd").unwrap(), SnapshotCompression::Zstd { level: 3 });
+		assert_eq!(to_snapshot_compression("zstd:22").unwrap(), SnapshotCompression::Zstd { level: 22 });
+		assert!(to_snapshot_compression("zstd:23").is_err());
+		assert!(to_snapshot_compression("zstd:0").is_err());
+		assert!(to_snapshot_compression("zstd:x").is_err());
+		assert!(to_snapshot_compression("lz4").is_err());
+	}
+
 	#[test]
 	fn test_to_block_id() {
 		assert_eq!(to_block_id("latest").unwrap(), BlockId::Latest);
@@ -140,6 +140,7 @@ impl InformantData for FullNodeInformantData {
 		cache_sizes.insert("db", client_report.state_db_mem);
 		cache_sizes.insert("queue", queue_info.mem_used);
 		cache_sizes.insert("chain", blockchain_cache_info.total());
+		cache_sizes.insert("witnesses", self.client.wallet_witnesses_mem());
 
 		let importing = self.is_major_importing();
 		let sync_info = match (self.sync.as_ref(), self.net.as_ref()) {
@@ -126,7 +126,14 @@ pub struct RunCmd {
 	pub allow_missing_blocks: bool,
 	pub download_old_blocks: bool,
 	pub verifier_settings: VerifierSettings,
+	pub catch_verification_panics: bool,
+	pub shielded_benchmark_at_startup: bool,
+	pub max_concurrent_rescans: usize,
+	pub max_commitment_tree_lag: u64,
+	pub tree_checkpoint_interval: u64,
+	pub anchor_history_margin: u64,
 	pub serve_light: bool,
+	pub max_peer_transactions_per_sec: Option<usize>,
 	pub light: bool,
 	pub no_persistent_txqueue: bool,
 	pub whisper: ::whisper::Config,
@@ -472,6 +479,7 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 	};
 	sync_config.download_old_blocks = cmd.download_old_blocks;
 	sync_config.serve_light = cmd.serve_light;
+	sync_config.max_peer_transactions_per_sec = cmd.max_peer_transactions_per_sec;
 
 	let passwords = passwords_from_files(&cmd.acc_conf.password_files)?;
 
@@ -541,6 +549,12 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 	);
 
 	client_config.queue.verifier_settings = cmd.verifier_settings;
+	client_config.queue.catch_verification_panics = cmd.catch_verification_panics;
+	client_config.shielded_benchmark_at_startup = cmd.shielded_benchmark_at_startup;
+	client_config.blockchain.max_concurrent_rescans = cmd.max_concurrent_rescans;
+	client_config.max_commitment_tree_lag = cmd.max_commitment_tree_lag;
+	client_config.blockchain.tree_checkpoint_interval = cmd.tree_checkpoint_interval;
+	client_config.anchor_history_margin = cmd.anchor_history_margin;
 	client_config.transaction_verification_queue_size = ::std::cmp::max(2048, txpool_size / 4);
 	client_config.snapshot = cmd.snapshot_conf.clone();
 
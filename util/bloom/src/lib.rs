@@ -151,6 +151,11 @@ impl Bloom {
 		true
 	}
 
+	/// Return the bitmap of the filter, in the layout expected by `from_parts`.
+	pub fn parts(&self) -> &[u64] {
+		&self.bitmap.elems
+	}
+
 	/// Return the number of bits in the filter
 	pub fn number_of_bits(&self) -> u64 {
 		self.bitmap_bits
@@ -459,8 +459,8 @@ impl<Message> IoService<Message> where Message: Send + Sync + 'static {
 		// Clear handlers so that shared pointers are not stuck on stack
 		// in Channel::send_sync
 		self.handlers.write().clear();
-		self.host_channel.lock().send(IoMessage::Shutdown).unwrap_or_else(|e| warn!("Error on IO service shutdown: {:?}", e));
 		if let Some(thread) = self.thread.take() {
+			self.host_channel.lock().send(IoMessage::Shutdown).unwrap_or_else(|e| warn!("Error on IO service shutdown: {:?}", e));
 			thread.join().unwrap_or_else(|e| {
 				debug!(target: "shutdown", "Error joining IO service event loop thread: {:?}", e);
 			});
@@ -468,6 +468,11 @@ impl<Message> IoService<Message> where Message: Send + Sync + 'static {
 		trace!(target: "shutdown", "[IoService] Closed.");
 	}
 
+	/// Drop all registered IO handlers without stopping the event loop.
+	pub fn deregister_handlers(&self) {
+		self.handlers.write().clear();
+	}
+
 	/// Regiter an IO handler with the event loop.
 	pub fn register_handler(&self, handler: Arc<IoHandler<Message>+Send>) -> Result<(), IoError> {
 		self.host_channel.lock().send(IoMessage::AddHandler {
@@ -272,6 +272,11 @@ impl<Message> IoService<Message> where Message: Send + Sync + 'static {
 		trace!(target: "shutdown", "[IoService] Closed.");
 	}
 
+	/// Drop all registered IO handlers without stopping the worker threads.
+	pub fn deregister_handlers(&self) {
+		self.shared.handlers.write().clear();
+	}
+
 	/// Register an IO handler with the event loop.
 	pub fn register_handler(&self, handler: Arc<IoHandler<Message>+Send>) -> Result<(), IoError> {
 		let id = self.shared.handlers.write().insert(handler.clone());